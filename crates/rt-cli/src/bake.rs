@@ -0,0 +1,121 @@
+//! The bake mode: instead of an image, the lighting baked on the vertices of the meshes of the
+//! scene, written in a PLY file with vertex colors.
+use std::{io::Write, path::Path};
+
+use anyhow::Result;
+use clap::ValueEnum;
+use rt::{
+    bake::{vertex_normals, AmbientOcclusion, BakedMesh, MeshCollector},
+    shape::Shape,
+};
+
+use crate::{insert_scene, Args};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum AvailableBake {
+    /// Ambient occlusion
+    Ao,
+}
+
+/// Bakes the meshes of the scene against `objects`, the built scene, to `output/bake/`
+pub fn bake(args: &Args, bake: AvailableBake, objects: &dyn Shape) -> Result<()> {
+    let mut collector = MeshCollector::default();
+    insert_scene(args, &mut collector);
+    let (name, ao) = match bake {
+        AvailableBake::Ao => (
+            "ao",
+            AmbientOcclusion {
+                samples: args.bake_samples,
+                max_distance: args.bake_distance.unwrap_or(f32::INFINITY),
+            },
+        ),
+    };
+
+    log::info!("baking {} meshes", collector.meshes.len());
+    let values = collector
+        .meshes
+        .iter()
+        .map(|mesh| {
+            let normals = vertex_normals(&mesh.positions, &mesh.indices);
+            ao.bake_vertices(objects, &mesh.positions, &normals, args.seed)
+        })
+        .collect::<Vec<_>>();
+
+    let outdir = Path::new("output/bake/");
+    std::fs::create_dir_all(outdir)?;
+    let path = outdir.join(format!("{name}.ply"));
+    log::info!("saving the bake to {}", path.display());
+    let mut file = std::io::BufWriter::new(std::fs::File::create(path)?);
+    write_ply(&mut file, &collector.meshes, &values)?;
+    file.flush()?;
+    Ok(())
+}
+
+/// An ASCII PLY of all the meshes, the value of each vertex as a gray color
+fn write_ply(w: &mut impl Write, meshes: &[BakedMesh], values: &[Vec<f32>]) -> Result<()> {
+    let vertices: usize = meshes.iter().map(|mesh| mesh.positions.len()).sum();
+    let faces: usize = meshes.iter().map(|mesh| mesh.indices.len()).sum();
+    writeln!(w, "ply\nformat ascii 1.0")?;
+    writeln!(w, "element vertex {vertices}")?;
+    writeln!(w, "property float x\nproperty float y\nproperty float z")?;
+    writeln!(
+        w,
+        "property uchar red\nproperty uchar green\nproperty uchar blue"
+    )?;
+    writeln!(w, "element face {faces}")?;
+    writeln!(w, "property list uchar uint vertex_indices\nend_header")?;
+
+    for (mesh, values) in meshes.iter().zip(values) {
+        for (p, &value) in mesh.positions.iter().zip(values) {
+            let gray = (value.clamp(0.0, 1.0) * 255.0).round() as u8;
+            writeln!(w, "{} {} {} {gray} {gray} {gray}", p.x, p.y, p.z)?;
+        }
+    }
+    // The indices of a mesh follow the vertices of the previous ones
+    let mut offset = 0;
+    for mesh in meshes {
+        for [a, b, c] in mesh.indices.iter().map(|t| t.map(|i| i + offset)) {
+            writeln!(w, "3 {a} {b} {c}")?;
+        }
+        offset += mesh.positions.len() as u32;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use rt::{bake::BakedMesh, math::vec::Vec3};
+
+    use super::write_ply;
+
+    #[test]
+    fn ply() {
+        let triangle = BakedMesh {
+            positions: vec![Vec3::ZERO, Vec3::X, Vec3::Y],
+            indices: vec![[0, 1, 2]],
+        };
+        let mut out = Vec::new();
+        write_ply(
+            &mut out,
+            &[triangle.clone(), triangle],
+            &[vec![1.0; 3], vec![0.0, 0.5, 2.0]],
+        )
+        .unwrap();
+        let out = String::from_utf8(out).unwrap();
+        let body = out.split("end_header\n").nth(1).unwrap();
+        assert!(out.contains("element vertex 6\n") && out.contains("element face 2\n"));
+        assert_eq!(
+            body.lines().collect::<Vec<_>>(),
+            [
+                "0 0 0 255 255 255",
+                "1 0 0 255 255 255",
+                "0 1 0 255 255 255",
+                "0 0 0 0 0 0",
+                "1 0 0 128 128 128",
+                "0 1 0 255 255 255",
+                "3 0 1 2",
+                "3 3 4 5",
+            ]
+        );
+    }
+}
@@ -0,0 +1,264 @@
+//! Contact sheets: the same scene rendered under several configurations, the integrators in rows
+//! and the numbers of samples in columns, composited in a single labeled image.
+use std::sync::{Arc, Mutex};
+
+use anyhow::Result;
+use clap::ValueEnum;
+use image::{Rgb, Rgb32FImage, RgbImage};
+use rt::renderer::{Channel, RgbChannel, World};
+
+use crate::{
+    output::{FinalOutput, OutputBuffers},
+    renderer::Renderer,
+    utils::{AvailableIntegrator, Framing},
+    Args,
+};
+
+/// Size of the pixels of the glyphs of the labels
+const LABEL_SCALE: u32 = 2;
+/// Height of the band over each image where its label is written
+pub const LABEL_HEIGHT: u32 = 7 * LABEL_SCALE;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Configuration {
+    pub integrator: AvailableIntegrator,
+    pub spp: u32,
+}
+
+impl Configuration {
+    /// Every integrator of `--compare` with every spp of `--compare-spp`, integrator after
+    /// integrator. A missing list is the integrator or the spp of the render
+    pub fn all(args: &Args) -> Vec<Self> {
+        let integrators = match &args.compare[..] {
+            [] => &[args.integrator][..],
+            integrators => integrators,
+        };
+        let spps = match &args.compare_spp[..] {
+            [] => &[args.spp][..],
+            spps => spps,
+        };
+        integrators
+            .iter()
+            .flat_map(|&integrator| spps.iter().map(move |&spp| Self { integrator, spp }))
+            .collect()
+    }
+
+    pub fn label(&self) -> String {
+        let integrator = self
+            .integrator
+            .to_possible_value()
+            .map_or_else(String::new, |value| value.get_name().to_string());
+        format!("{integrator} {}spp", self.spp)
+    }
+}
+
+/// Keeps the color of the render
+struct CaptureColor(Arc<Mutex<Option<Rgb32FImage>>>);
+
+impl FinalOutput for CaptureColor {
+    fn commit(&self, output_buffers: &OutputBuffers) -> Result<()> {
+        *self.0.lock().unwrap() =
+            output_buffers
+                .channels
+                .iter()
+                .find_map(|channel| match channel {
+                    Channel::RgbChannel(RgbChannel::Color, image) => Some(image.clone()),
+                    _ => None,
+                });
+        Ok(())
+    }
+}
+
+/// Renders the world under each configuration, the other settings come from `args`
+pub fn render_contact_sheet(args: &Args, world: &World, framing: Framing) -> Result<RgbImage> {
+    let configurations = Configuration::all(args);
+    let mut cells = Vec::with_capacity(configurations.len());
+    for configuration in &configurations {
+        log::info!("rendering {}", configuration.label());
+        let mut args = args.clone();
+        args.integrator = configuration.integrator;
+        args.spp = configuration.spp;
+        args.sample_range = None;
+        // Only the contact sheet is saved
+        args.output.clear();
+
+        let color = Arc::new(Mutex::new(None));
+        let mut renderer = Renderer::new(&args, None, framing)?;
+        renderer
+            .final_outputs
+            .push(Box::new(CaptureColor(color.clone())));
+        renderer.run(world)?;
+
+        let color = color.lock().unwrap().take();
+        let color = color
+            .unwrap_or_else(|| Rgb32FImage::new(args.dimensions.width, args.dimensions.height));
+        cells.push((configuration.label(), color));
+    }
+    Ok(compose(&cells, args.compare_spp.len().max(1) as u32))
+}
+
+/// The images in a grid of the given number of columns, each one under its label. The images
+/// must all have the same size
+pub fn compose(cells: &[(String, Rgb32FImage)], columns: u32) -> RgbImage {
+    let Some((_, first)) = cells.first() else {
+        return RgbImage::new(0, 0);
+    };
+    let (width, height) = first.dimensions();
+    let rows = (cells.len() as u32).div_ceil(columns);
+    let cell_height = LABEL_HEIGHT + height;
+
+    let mut sheet = RgbImage::new(columns * width, rows * cell_height);
+    for (index, (label, image)) in cells.iter().enumerate() {
+        assert_eq!(image.dimensions(), (width, height));
+        let (x0, y0) = (
+            index as u32 % columns * width,
+            index as u32 / columns * cell_height,
+        );
+        draw_text(
+            &mut sheet,
+            label,
+            x0 + LABEL_SCALE,
+            y0 + LABEL_SCALE,
+            x0 + width,
+        );
+        for (x, y, pixel) in image.enumerate_pixels() {
+            let (sx, sy) = (x0 + x, y0 + LABEL_HEIGHT + y);
+            let color = pixel
+                .0
+                .map(|c| rt::color::dither_to_byte(c.clamp(0.0, 1.0), sx, sy));
+            sheet.put_pixel(sx, sy, Rgb(color));
+        }
+    }
+    sheet
+}
+
+/// Writes `text` in white with its top left corner at `(x, y)`, the text past `x_end` is cut
+fn draw_text(image: &mut RgbImage, text: &str, x: u32, y: u32, x_end: u32) {
+    let advance = 4 * LABEL_SCALE;
+    for (index, c) in text.chars().enumerate() {
+        let glyph_x = x + index as u32 * advance;
+        for (row, bits) in glyph(c).into_iter().enumerate() {
+            for column in 0..3 {
+                if bits >> (2 - column) & 1 == 0 {
+                    continue;
+                }
+                for (dx, dy) in
+                    (0..LABEL_SCALE).flat_map(|dx| (0..LABEL_SCALE).map(move |dy| (dx, dy)))
+                {
+                    let (px, py) = (
+                        glyph_x + column * LABEL_SCALE + dx,
+                        y + row as u32 * LABEL_SCALE + dy,
+                    );
+                    if px < x_end.min(image.width()) && py < image.height() {
+                        image.put_pixel(px, py, Rgb([255; 3]));
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// A 3x5 glyph, each row in 3 bits from left to right. The letters are all uppercase
+fn glyph(c: char) -> [u8; 5] {
+    match c.to_ascii_uppercase() {
+        'A' => [0b010, 0b101, 0b111, 0b101, 0b101],
+        'B' => [0b110, 0b101, 0b110, 0b101, 0b110],
+        'C' => [0b011, 0b100, 0b100, 0b100, 0b011],
+        'D' => [0b110, 0b101, 0b101, 0b101, 0b110],
+        'E' => [0b111, 0b100, 0b110, 0b100, 0b111],
+        'F' => [0b111, 0b100, 0b110, 0b100, 0b100],
+        'G' => [0b011, 0b100, 0b101, 0b101, 0b011],
+        'H' => [0b101, 0b101, 0b111, 0b101, 0b101],
+        'I' => [0b111, 0b010, 0b010, 0b010, 0b111],
+        'J' => [0b001, 0b001, 0b001, 0b101, 0b010],
+        'K' => [0b101, 0b101, 0b110, 0b101, 0b101],
+        'L' => [0b100, 0b100, 0b100, 0b100, 0b111],
+        'M' => [0b101, 0b111, 0b111, 0b101, 0b101],
+        'N' => [0b110, 0b101, 0b101, 0b101, 0b101],
+        'O' => [0b010, 0b101, 0b101, 0b101, 0b010],
+        'P' => [0b110, 0b101, 0b110, 0b100, 0b100],
+        'Q' => [0b010, 0b101, 0b101, 0b110, 0b011],
+        'R' => [0b110, 0b101, 0b110, 0b101, 0b101],
+        'S' => [0b011, 0b100, 0b010, 0b001, 0b110],
+        'T' => [0b111, 0b010, 0b010, 0b010, 0b010],
+        'U' => [0b101, 0b101, 0b101, 0b101, 0b111],
+        'V' => [0b101, 0b101, 0b101, 0b101, 0b010],
+        'W' => [0b101, 0b101, 0b111, 0b111, 0b101],
+        'X' => [0b101, 0b101, 0b010, 0b101, 0b101],
+        'Y' => [0b101, 0b101, 0b010, 0b010, 0b010],
+        'Z' => [0b111, 0b001, 0b010, 0b100, 0b111],
+        '0' => [0b111, 0b101, 0b101, 0b101, 0b111],
+        '1' => [0b010, 0b110, 0b010, 0b010, 0b111],
+        '2' => [0b110, 0b001, 0b010, 0b100, 0b111],
+        '3' => [0b110, 0b001, 0b010, 0b001, 0b110],
+        '4' => [0b101, 0b101, 0b111, 0b001, 0b001],
+        '5' => [0b111, 0b100, 0b110, 0b001, 0b110],
+        '6' => [0b011, 0b100, 0b111, 0b101, 0b111],
+        '7' => [0b111, 0b001, 0b010, 0b010, 0b010],
+        '8' => [0b111, 0b101, 0b111, 0b101, 0b111],
+        '9' => [0b111, 0b101, 0b111, 0b001, 0b110],
+        '-' => [0b000, 0b000, 0b111, 0b000, 0b000],
+        '.' => [0b000, 0b000, 0b000, 0b000, 0b010],
+        ' ' => [0b000; 5],
+        _ => [0b111, 0b001, 0b010, 0b000, 0b010],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+    use image::{Rgb, Rgb32FImage};
+
+    use crate::{utils::AvailableIntegrator, Args};
+
+    use super::{compose, Configuration, LABEL_HEIGHT};
+
+    #[test]
+    fn contact_sheet_grid() {
+        let args = Args::parse_from([
+            "rt",
+            "--compare",
+            "basic,path-tracer",
+            "--compare-spp",
+            "4,16,64",
+        ]);
+        let configurations = Configuration::all(&args);
+        assert_eq!(configurations.len(), 6);
+        assert_eq!(
+            configurations[4],
+            Configuration {
+                integrator: AvailableIntegrator::PathTracer,
+                spp: 16
+            }
+        );
+        assert_eq!(configurations[4].label(), "path-tracer 16spp");
+        assert_eq!(Configuration::all(&Args::parse_from(["rt"])).len(), 1);
+
+        let cells = configurations
+            .iter()
+            .enumerate()
+            .map(|(index, configuration)| {
+                let gray = index as f32 / 8.0;
+                (
+                    configuration.label(),
+                    Rgb32FImage::from_pixel(10, 6, Rgb([gray; 3])),
+                )
+            })
+            .collect::<Vec<_>>();
+        let sheet = compose(&cells, args.compare_spp.len() as u32);
+        assert_eq!(sheet.dimensions(), (3 * 10, 2 * (LABEL_HEIGHT + 6)));
+
+        // The images are in their cell, under their label
+        let cell_height = LABEL_HEIGHT + 6;
+        for (index, _) in cells.iter().enumerate() {
+            let (x, y) = (index as u32 % 3 * 10, index as u32 / 3 * cell_height);
+            let expected = (index as f32 / 8.0 * 255.0).round();
+            let value = sheet.get_pixel(x + 5, y + LABEL_HEIGHT + 3).0[0] as f32;
+            assert!((value - expected).abs() <= 1.0, "{value} != {expected}");
+            let label = (y..y + LABEL_HEIGHT)
+                .flat_map(|y| (x..x + 10).map(move |x| (x, y)))
+                .any(|(x, y)| sheet.get_pixel(x, y).0 == [255; 3]);
+            assert!(label, "no label over the image {index}");
+        }
+    }
+}
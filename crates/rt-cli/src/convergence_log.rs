@@ -0,0 +1,40 @@
+//! A CSV log of the convergence of a render, one row per batch of samples, to plot how fast the
+//! samplers and the integrators converge.
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+    time::Instant,
+};
+
+use anyhow::Result;
+
+pub struct ConvergenceLog {
+    file: BufWriter<File>,
+    start: Instant,
+}
+
+impl ConvergenceLog {
+    /// The elapsed times are counted from now
+    pub fn create(path: &Path) -> Result<Self> {
+        let mut file = BufWriter::new(File::create(path)?);
+        writeln!(file, "samples,elapsed_ms,mean_luminance,rel_variance")?;
+        file.flush()?;
+        Ok(Self {
+            file,
+            start: Instant::now(),
+        })
+    }
+
+    /// Adds the row of the batch ending at `samples` per pixel. It is written at once, the log
+    /// of an interrupted render is complete
+    pub fn record(&mut self, samples: u32, mean_luminance: f32, rel_variance: f32) -> Result<()> {
+        writeln!(
+            self.file,
+            "{samples},{},{mean_luminance},{rel_variance}",
+            self.start.elapsed().as_millis()
+        )?;
+        self.file.flush()?;
+        Ok(())
+    }
+}
@@ -1,34 +1,40 @@
 use rt::{
-    filter::{BoxFilter, Filter},
+    filter::Filter,
     math::vec::Vec2,
-    sampler::{Sampler, StratifiedSampler},
+    sampler::{draw_2d, AntitheticSampler, Dimension, Sampler, SobolSampler, StratifiedSampler},
     Seed,
 };
 use std::{
     io::Write,
     ops::Range,
-    sync::mpsc::{channel, Receiver},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{channel, Receiver},
+        Arc,
+    },
+    time::{Duration, Instant},
 };
 
 use crate::{
-    tile::{Tile, Tiler},
-    utils::{FromArgs, RenderRange},
+    convergence_log::ConvergenceLog,
+    foveation::Foveation,
+    profiler::{self, Profiler, TileProfile, TimedShape},
+    tile::{Tile, TileOrder, Tiler},
+    utils::{AvailableSampler, FromArgs, Pixel, RenderMask, RenderRange},
     Args, Dimensions, Spp,
 };
 
 use super::progress;
 
-use rayon::{
-    iter::{
-        IndexedParallelIterator, IntoParallelIterator, IntoParallelRefMutIterator, ParallelIterator,
-    },
-    Scope,
-};
+use rayon::iter::{ParallelBridge, ParallelIterator};
 use rt::{
-    camera::Camera,
-    integrators::Integrator,
+    camera::{skip_back_faces, Camera, Exposure},
+    color::soft_clamp,
+    integrators::{Integrator, WavefrontIntegrator, WavefrontRay},
+    math::stat::Convergence,
     memory::{Arena, ArenaInner},
-    renderer::{PixelRenderResult, RaySeries, World},
+    ray::Ray,
+    renderer::{PixelRenderResult, RayResult, RaySeries, World},
     utils::counter::counter,
     Ctx,
 };
@@ -41,38 +47,123 @@ enum Message {
 pub struct TileMsg {
     pub tile: Tile,
     pub data: Vec<PixelRenderResult>,
+    /// All the samples of the tile are in, it won't be sent again
+    pub complete: bool,
 }
 
 pub struct Executor {
     pub dimension: Dimensions,
     pub tile_size: u32,
+    pub tile_order: TileOrder,
 
-    pub allowed_error: Option<f32>,
+    /// Adaptive sampling: a pixel stops being sampled once its color has converged
+    pub convergence: Option<Convergence>,
+    /// The render stops after the batch of samples where the mean over the image of the
+    /// relative variance of the pixels gets below this
+    pub target_variance: Option<f32>,
+    /// Records the convergence after each batch of samples, if there is one
+    pub convergence_log: Option<ConvergenceLog>,
 
     // TODO: make a pool of materials
     pub integrator: Box<dyn Integrator>,
     pub camera: Camera,
+    /// The camera rays go through the back faces they hit first, see [skip_back_faces]
+    pub skip_back_faces: bool,
+    /// Reconstruction filter, it spreads the samples around the center of their pixel
+    pub filter: Box<dyn Filter>,
     pub spp: u32,
+    /// Once a pixel has this many samples, they only go to the color and not to the AOVs
+    pub aov_spp: Option<u32>,
+    /// Scale of the color of the samples, see [rt::camera::Exposure]
+    pub exposure: f32,
+    /// The colors of the samples are rolled off above this luminance, see [soft_clamp]
+    pub highlight_knee: Option<f32>,
 
     pub seed: u64,
+    pub wavefront: bool,
+    pub sampler: AvailableSampler,
+    /// Take the samples by antithetic pairs
+    pub antithetic: bool,
+    /// Only the pixels in the mask are rendered, if there is one
+    pub mask: Option<RenderMask>,
+    /// Only the pixels of the foveation lattice are rendered, if there is one
+    pub foveation: Option<Foveation>,
+    pub transparent_background: bool,
+    /// Stop rendering once the time is up, even if all the samples are not done
+    pub render_time: Option<Duration>,
+    /// Stop rendering as soon as it is set
+    pub interrupt: Option<Arc<AtomicBool>>,
+    /// Size of the thread pool of the multithreaded mode, all the cores by default
+    pub threads: Option<usize>,
+    /// Records the time spent by each tile, if there is one
+    pub profiler: Option<Arc<Profiler>>,
+    /// The paths of this pixel are logged, only the one of the given sample if there is one
+    pub debug: Option<(Pixel, Option<u32>)>,
 }
 
-impl FromArgs for Executor {
-    fn from_args(args: &Args) -> Self {
-        Executor {
+impl Executor {
+    /// Build the executor of the arguments, the files it reads and writes are opened
+    pub fn new(args: &Args) -> anyhow::Result<Self> {
+        let integrator: Box<dyn Integrator> = FromArgs::from_args(args);
+        if args.wavefront && integrator.as_wavefront().is_none() {
+            log::warn!(
+                "the integrator can't be used in wavefront mode, falling back to recursive mode"
+            );
+        }
+
+        Ok(Executor {
             dimension: args.dimensions,
             tile_size: args.tile_size,
-            allowed_error: args.allowed_error,
+            tile_order: args.tile_order,
+            convergence: args.allowed_error.map(|allowed_error| Convergence {
+                allowed_error,
+                min_samples: args.min_samples as usize,
+            }),
+            target_variance: args.target_variance,
+            convergence_log: args
+                .convergence_log
+                .as_deref()
+                .map(ConvergenceLog::create)
+                .transpose()?,
             spp: args.spp,
-            integrator: FromArgs::from_args(args),
+            aov_spp: args.aov_spp,
+            exposure: Exposure {
+                shutter_seconds: args.shutter_seconds,
+                iso: args.iso,
+                fstop: args.fstop,
+            }
+            .scale(),
+            highlight_knee: args.highlight_knee,
+            integrator,
             camera: FromArgs::from_args(args),
+            skip_back_faces: args.skip_back_faces,
+            filter: FromArgs::from_args(args),
             seed: args.seed,
-        }
+            wavefront: args.wavefront,
+            sampler: args.sampler,
+            antithetic: args.antithetic,
+            mask: RenderMask::from_args(args)?,
+            foveation: FromArgs::from_args(args),
+            transparent_background: args.transparent_background,
+            render_time: args.render_time.map(|t| t.0),
+            interrupt: None,
+            threads: args.threads,
+            profiler: args.profile.then(Default::default),
+            debug: args.debug_pixel.map(|pixel| (pixel, args.debug_sample)),
+        })
     }
 }
 
 const SCRATCH_MEMORY_SIZE: usize = 1024 * 1024; // 1 MB
 
+/// Added to the squared brightness of the pixels when their variance is made relative, so that
+/// the darkest ones don't hold the render back forever
+const RELATIVE_VARIANCE_EPSILON: f32 = 1e-2;
+
+/// Side of the grid of strata of the stratified sampler when the number of samples is unbounded:
+/// the samples are stratified by windows of this squared, one after the other
+const UNBOUNDED_STRATA: u32 = 16;
+
 impl Executor {
     pub fn run_multithreaded<F: FnMut(&TileMsg) + Send>(
         self,
@@ -81,7 +172,10 @@ impl Executor {
         pixel_range: RenderRange,
         sample_range: Spp,
     ) -> anyhow::Result<()> {
-        log::debug!("Monothreaded");
+        let deadline = self.render_time.map(|t| Instant::now() + t);
+        let interrupt = self.interrupt.clone();
+        let batch_size = self.batch_size();
+        let threads = self.threads;
 
         let (tx, rx) = channel();
         let mut dispatcher_ = self.build_dispatcher(
@@ -94,21 +188,28 @@ impl Executor {
         let dispatcher = &mut dispatcher_;
 
         let progress = match &sample_range {
-            Spp::Spp(s) => progress::Progress::new(s.len() * dispatcher.tiler.tile_count()),
+            _ if deadline.is_some() => progress::Progress::new_inf(),
+            Spp::Spp(s) => progress::Progress::new(s.len() * dispatcher.tiles.len()),
         };
         progress.print();
 
-        let generation_result = rayon::scope(|s: &Scope<'_>| {
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(threads.unwrap_or(0))
+            .build()?;
+        log::debug!(
+            "Multithreaded on {} threads, by chunks of {batch_size} samples",
+            pool.current_num_threads()
+        );
+
+        // The tiles are received out of the pool, so that it can't be starved by the receiver
+        let generation_result = std::thread::scope(|s| {
             log::info!("Generating image...");
-            s.spawn(|_| {
+            s.spawn(|| {
                 let progress = &progress;
                 let rx: Receiver<Message> = rx; // Force move without moving anything else
                 let mut last_progress_update = std::time::Instant::now();
 
-                loop {
-                    let Some(msg) = rx.try_recv().ok() else {
-                        continue;
-                    };
+                while let Ok(msg) = rx.recv() {
                     match msg {
                         Message::Tile(msg) => {
                             on_tile_rendered(&msg);
@@ -129,9 +230,26 @@ impl Executor {
                 let _ = std::io::stdout().flush();
             });
 
-            for samples in SampleCounter::new(32, sample_range) {
-                dispatcher.dispatch_async(world, samples, &progress);
-            }
+            pool.install(|| {
+                let end = sample_range.end();
+                for samples in SampleCounter::new(batch_size, sample_range) {
+                    if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                        log::info!("Render time is up");
+                        break;
+                    }
+                    if interrupt.as_ref().is_some_and(|i| i.load(Ordering::SeqCst)) {
+                        log::info!("Render interrupted");
+                        break;
+                    }
+                    let complete = samples.end == end;
+                    let last = samples.end;
+                    dispatcher.dispatch_async(world, samples, complete, &progress);
+                    dispatcher.log_convergence(last);
+                    if dispatcher.reached_target_variance(last) {
+                        break;
+                    }
+                }
+            });
             tx.send(Message::Stop)
         });
 
@@ -153,10 +271,14 @@ impl Executor {
         samples_range: Spp,
     ) -> anyhow::Result<()> {
         log::debug!("Monothreaded");
+        let deadline = self.render_time.map(|t| Instant::now() + t);
+        let interrupt = self.interrupt.clone();
+        let batch_size = self.batch_size();
 
         let mut dispatcher = self.build_dispatcher(on_tile_rendered, pixel_range.x, pixel_range.y);
         let progress = match &samples_range {
-            Spp::Spp(s) => progress::Progress::new(s.len() * dispatcher.tiler.tile_count()),
+            _ if deadline.is_some() => progress::Progress::new_inf(),
+            Spp::Spp(s) => progress::Progress::new(s.len() * dispatcher.tiles.len()),
         };
         progress.print();
 
@@ -164,8 +286,23 @@ impl Executor {
 
         let mut arena = ArenaInner::new(SCRATCH_MEMORY_SIZE);
 
-        for samples in SampleCounter::new(32, samples_range) {
-            dispatcher.dispatch_sync(world, &mut arena, samples, &progress);
+        let end = samples_range.end();
+        for samples in SampleCounter::new(batch_size, samples_range) {
+            if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                log::info!("Render time is up");
+                break;
+            }
+            if interrupt.as_ref().is_some_and(|i| i.load(Ordering::SeqCst)) {
+                log::info!("Render interrupted");
+                break;
+            }
+            let complete = samples.end == end;
+            let last = samples.end;
+            dispatcher.dispatch_sync(world, &mut arena, samples, complete, &progress);
+            dispatcher.log_convergence(last);
+            if dispatcher.reached_target_variance(last) {
+                break;
+            }
         }
         println!();
 
@@ -174,6 +311,18 @@ impl Executor {
         Ok(())
     }
 
+    /// Number of samples dispatched at once. The render time and the interruption are only
+    /// checked between batches, so they are kept small when needed
+    fn batch_size(&self) -> u32 {
+        if self.render_time.is_some() || self.interrupt.is_some() {
+            1
+        } else if self.target_variance.is_some() {
+            8
+        } else {
+            32
+        }
+    }
+
     fn build_dispatcher<F>(
         self,
         on_tile_rendered: F,
@@ -189,16 +338,22 @@ impl Executor {
             y_grainsize: self.tile_size,
         };
 
+        let tiles = tiler
+            .ordered_indices(self.tile_order)
+            .into_iter()
+            .map(|idx| tiler.tile(idx).unwrap())
+            .collect::<Vec<_>>();
+
         Dispatcher {
-            tiler,
-            tiles_data: tiler
-                .into_iter()
+            tiles_data: tiles
+                .iter()
                 .map(|tile| {
                     let mut c = Vec::new();
                     c.resize_with(tile.width() * tile.height(), Default::default);
                     c
                 })
                 .collect::<Vec<Vec<RaySeries>>>(),
+            tiles,
             on_tile_rendered,
             executor: self,
         }
@@ -215,10 +370,47 @@ impl Executor {
         assert_eq!(data.len(), tile.len());
 
         log::trace!("working on tile {tile:?}");
-        let sqr_sample = f32::sqrt(self.spp as f32).floor() as u32;
+        let Some(profiler) = &self.profiler else {
+            return self.render_tile(world, arena, tile, data, samples);
+        };
+
+        let objects = TimedShape(world.objects);
+        let world = World {
+            objects: &objects,
+            lights: world.lights,
+            materials: world.materials,
+            world_material: world.world_material,
+            fog: world.fog,
+        };
+        let start = Instant::now();
+        let intersection_start = profiler::intersection_time();
+        self.render_tile(&world, arena, tile, data, samples);
+        profiler.record(TileProfile {
+            tile,
+            intersection: profiler::intersection_time() - intersection_start,
+            total: start.elapsed(),
+        });
+    }
+
+    fn render_tile(
+        &self,
+        world: &World,
+        arena: &mut ArenaInner,
+        tile: Tile,
+        data: &mut [RaySeries],
+        samples: &Range<u32>,
+    ) {
+        if self.wavefront {
+            if let Some(integrator) = self.integrator.as_wavefront() {
+                return self.tile_worker_wavefront(integrator, world, arena, tile, data, samples);
+            }
+        }
 
         for (index, (x, y)) in tile.into_iter().enumerate() {
-            let mut sampler = StratifiedSampler::new(x, y, sqr_sample, sqr_sample);
+            if self.is_masked_out(x, y) {
+                continue;
+            }
+            let mut sampler = self.pixel_sampler(x, y);
             // let mut sampler = UniformSampler::new(x, y);
 
             for sample_idx in samples.clone() {
@@ -233,16 +425,17 @@ impl Executor {
                 };
                 let mut ctx = Ctx {
                     seed,
-                    sampler: &mut sampler,
+                    sampler: sampler.as_mut(),
                     world,
                     rng: seed.into_rng(0),
                     arena: Arena::new(arena),
+                    debug: self.is_debugged(seed),
                 };
 
                 self.pixel_worker(&mut ctx, &mut data[index]);
 
-                if let Some(allowed_error) = self.allowed_error {
-                    if data[index].color.is_precise_enough(allowed_error).is_some() {
+                if let Some(ref convergence) = self.convergence {
+                    if data[index].color.is_precise_enough(convergence).is_some() {
                         counter!("Adaptative sampling break");
                         break;
                     }
@@ -251,41 +444,247 @@ impl Executor {
         }
     }
 
+    /// Same as [Self::render_tile] but each sample of the whole tile is traced at once
+    fn tile_worker_wavefront(
+        &self,
+        integrator: &dyn WavefrontIntegrator,
+        world: &World,
+        arena: &mut ArenaInner,
+        tile: Tile,
+        data: &mut [RaySeries],
+        samples: &Range<u32>,
+    ) {
+        let pixels = tile.into_iter().collect::<Vec<_>>();
+
+        // The sampler of each pixel that still needs samples
+        let mut samplers = pixels
+            .iter()
+            .map(|&(x, y)| (!self.is_masked_out(x, y)).then(|| self.pixel_sampler(x, y)))
+            .collect::<Vec<_>>();
+        for sample_idx in samples.clone() {
+            arena.reuse();
+
+            let (rays, (indices, weights)): (Vec<_>, (Vec<_>, Vec<_>)) = samplers
+                .iter_mut()
+                .enumerate()
+                .filter_map(|(index, sampler)| {
+                    let sampler = sampler.as_mut()?;
+                    sampler.with_sample(sample_idx);
+
+                    let (x, y) = pixels[index];
+                    let seed = Seed {
+                        x,
+                        y,
+                        sample_idx,
+                        seed: self.seed,
+                    };
+                    let mut ctx = Ctx {
+                        seed,
+                        sampler: sampler.as_mut(),
+                        world,
+                        rng: seed.into_rng(0),
+                        arena: Arena::new(arena),
+                        debug: self.is_debugged(seed),
+                    };
+
+                    let (ray, weight) = self.camera_ray(&mut ctx);
+                    Some((WavefrontRay { ray, ctx }, (index, weight)))
+                })
+                .unzip();
+            if rays.is_empty() {
+                break;
+            }
+
+            let results = integrator.ray_cast_wavefront(world, rays);
+
+            for ((index, weight), sample) in indices.into_iter().zip(weights).zip(results) {
+                self.accumulate(&mut data[index], sample, weight);
+
+                if let Some(ref convergence) = self.convergence {
+                    if data[index].color.is_precise_enough(convergence).is_some() {
+                        counter!("Adaptative sampling break");
+                        samplers[index] = None;
+                    }
+                }
+            }
+        }
+    }
+
+    /// With the stratified sampler, the image plane is stratified with a stratum per sample, or
+    /// per pair of samples if they are antithetic. Without a bound on the number of samples, see
+    /// [UNBOUNDED_STRATA]
+    fn pixel_sampler(&self, x: u32, y: u32) -> Box<dyn Sampler> {
+        let strata = |samples: u32| {
+            if self.spp == u32::MAX {
+                UNBOUNDED_STRATA
+            } else {
+                f32::sqrt(samples as f32).floor() as u32
+            }
+        };
+        match (self.sampler, self.antithetic) {
+            (AvailableSampler::Stratified, true) => {
+                let sqr_sample = strata((self.spp / 2).max(1));
+                Box::new(AntitheticSampler::new(StratifiedSampler::new(
+                    x, y, sqr_sample, sqr_sample,
+                )))
+            }
+            (AvailableSampler::Stratified, false) => {
+                let sqr_sample = strata(self.spp);
+                Box::new(StratifiedSampler::new(x, y, sqr_sample, sqr_sample))
+            }
+            (AvailableSampler::Sobol, true) => {
+                Box::new(AntitheticSampler::new(SobolSampler::new(x, y)))
+            }
+            (AvailableSampler::Sobol, false) => Box::new(SobolSampler::new(x, y)),
+        }
+    }
+
+    fn is_masked_out(&self, x: u32, y: u32) -> bool {
+        self.mask.as_ref().is_some_and(|mask| !mask.contains(x, y))
+            || self
+                .foveation
+                .is_some_and(|foveation| !foveation.is_rendered(x, y, self.dimension))
+    }
+
+    fn is_debugged(&self, seed: Seed) -> bool {
+        self.debug.is_some_and(|(pixel, sample)| {
+            (pixel.x, pixel.y) == (seed.x, seed.y) && sample.is_none_or(|s| s == seed.sample_idx)
+        })
+    }
+
     fn pixel_worker(&self, ctx: &mut Ctx, res: &mut RaySeries) {
-        let pcoords = ctx.sampler.sample_2d();
+        let (camera_ray, weight) = self.camera_ray(ctx);
+        let sample = self.integrator.ray_cast(ctx, camera_ray, 0);
+        self.accumulate(res, sample, weight);
+    }
 
-        let filtered_sample = BoxFilter {
-            radius: Vec2::splat(0.7),
+    fn accumulate(&self, res: &mut RaySeries, sample: RayResult, weight: f32) {
+        let mut color = self.exposure * sample.color;
+        if let Some(knee) = self.highlight_knee {
+            color = soft_clamp(color, knee);
+        }
+        let sample = RayResult { color, ..sample };
+        if self
+            .aov_spp
+            .is_some_and(|aov_spp| res.aov_samples >= aov_spp)
+        {
+            res.add_color_sample(sample, weight);
+        } else {
+            res.add_sample(sample, weight);
         }
-        .sample(pcoords);
+    }
+
+    /// Generate a ray from the camera for the current sample, along with the weight of the sample
+    fn camera_ray(&self, ctx: &mut Ctx) -> (Ray, f32) {
+        counter!("Primary rays");
+        let pcoords = Vec2::from(draw_2d(ctx.sampler, &mut ctx.rng, Dimension::PixelOffset));
+
+        let filtered_sample = self.filter.sample(pcoords);
 
         let coords = Vec2 {
             x: ctx.seed.x as f32 + 0.5,
             y: ctx.seed.y as f32 + 0.5,
         } + filtered_sample.coords;
 
-        let camera_ray = self.camera.ray(ctx, coords);
-        let sample = self.integrator.ray_cast(ctx, camera_ray, 0);
-        res.add_sample(sample, filtered_sample.weight);
+        let mut ray = self.camera.ray(ctx, coords);
+        if self.skip_back_faces {
+            ray = skip_back_faces(ctx.world.objects, ray);
+        }
+        if ctx.debug {
+            log::info!(
+                "debug pixel ({}, {}) sample {}: offset {pcoords}, camera {ray:?}, weight {}",
+                ctx.seed.x,
+                ctx.seed.y,
+                ctx.seed.sample_idx,
+                filtered_sample.weight
+            );
+        }
+        (ray, filtered_sample.weight)
     }
 }
 
 struct Dispatcher<F> {
-    tiler: Tiler,
+    /// In the order they are rendered
+    tiles: Vec<Tile>,
     tiles_data: Vec<Vec<RaySeries>>,
     on_tile_rendered: F,
     executor: Executor,
 }
 
+impl<F> Dispatcher<F> {
+    /// Mean over the rendered pixels of the relative variance of their color
+    fn mean_relative_variance(&self) -> f32 {
+        let (sum, count) = self
+            .tiles_data
+            .iter()
+            .flatten()
+            .filter(|series| series.samples_accumulated > 0)
+            .fold((0.0, 0), |(sum, count), series| {
+                let variance = series
+                    .color
+                    .relative_variance_of_mean(RELATIVE_VARIANCE_EPSILON);
+                (sum + variance, count + 1)
+            });
+        if count == 0 {
+            f32::INFINITY
+        } else {
+            sum / count as f32
+        }
+    }
+
+    /// Mean over the rendered pixels of the luminance of their color
+    fn mean_luminance(&self) -> f32 {
+        let (sum, count) = self
+            .tiles_data
+            .iter()
+            .flatten()
+            .filter(|series| series.samples_accumulated > 0)
+            .fold((0.0, 0), |(sum, count), series| {
+                (sum + series.color.mean().luminance(), count + 1)
+            });
+        sum / count.max(1) as f32
+    }
+
+    /// Records the row of the batch ending at `samples_end` in the convergence log
+    fn log_convergence(&mut self, samples_end: u32) {
+        if self.executor.convergence_log.is_none() {
+            return;
+        }
+        let (luminance, variance) = (self.mean_luminance(), self.mean_relative_variance());
+        let convergence_log = self.executor.convergence_log.as_mut().unwrap();
+        if let Err(err) = convergence_log.record(samples_end, luminance, variance) {
+            log::warn!("can't write the convergence log: {err}");
+        }
+    }
+
+    /// Whether the render can stop, after the samples up to `samples_end` are in
+    fn reached_target_variance(&self, samples_end: u32) -> bool {
+        let Some(target) = self.executor.target_variance else {
+            return false;
+        };
+        let variance = self.mean_relative_variance();
+        log::debug!("mean relative variance {variance} after {samples_end} samples");
+        if variance > target {
+            return false;
+        }
+        log::info!(
+            "Target variance reached after {samples_end} samples per pixel, the mean relative \
+            variance is {variance}"
+        );
+        true
+    }
+}
+
 impl<F: FnMut(&TileMsg)> Dispatcher<F> {
     fn dispatch_sync(
         &mut self,
         world: &World,
         arena: &mut ArenaInner,
         samples: Range<u32>,
+        complete: bool,
         progress: &progress::Progress,
     ) {
-        for (tile, data) in self.tiler.into_iter().zip(self.tiles_data.iter_mut()) {
+        for (&tile, data) in self.tiles.iter().zip(self.tiles_data.iter_mut()) {
             self.executor
                 .tile_worker(world, arena, tile, data, &samples);
 
@@ -295,7 +694,11 @@ impl<F: FnMut(&TileMsg)> Dispatcher<F> {
 
             let msg = TileMsg {
                 tile,
-                data: data.iter().map(|x| x.as_pixelresult()).collect::<Vec<_>>(),
+                data: data
+                    .iter()
+                    .map(|x| x.as_pixelresult(self.executor.transparent_background))
+                    .collect::<Vec<_>>(),
+                complete,
             };
 
             (self.on_tile_rendered)(&msg);
@@ -308,25 +711,37 @@ impl<F: Fn(TileMsg) + Sync> Dispatcher<F> {
         &mut self,
         world: &World,
         samples: Range<u32>,
+        complete: bool,
         progress: &progress::Progress,
     ) {
-        self.tiler
-            .into_par_iter()
-            .zip(self.tiles_data.par_iter_mut())
-            .map_init(
+        // Each worker gets a run of consecutive tiles at once. The runs are bridged to the workers
+        // without any order: they start about in the tile order but end in any order, which is
+        // fine as each message carries its tile
+        let chunk_size = self.tiles.len().div_ceil(4 * rayon::current_num_threads());
+        let executor = &self.executor;
+        let on_tile_rendered = &self.on_tile_rendered;
+        self.tiles
+            .chunks(chunk_size.max(1))
+            .zip(self.tiles_data.chunks_mut(chunk_size.max(1)))
+            .par_bridge()
+            .for_each_init(
                 || ArenaInner::new(SCRATCH_MEMORY_SIZE),
-                |arena, (tile, data)| {
-                    self.executor
-                        .tile_worker(world, arena, tile, data, &samples);
-                    progress.add(samples.len() as _);
-
-                    TileMsg {
-                        tile,
-                        data: data.iter().map(|x| x.as_pixelresult()).collect::<Vec<_>>(),
+                |arena, (tiles, tiles_data)| {
+                    for (&tile, data) in tiles.iter().zip(tiles_data) {
+                        executor.tile_worker(world, arena, tile, data, &samples);
+                        progress.add(samples.len() as _);
+
+                        on_tile_rendered(TileMsg {
+                            tile,
+                            data: data
+                                .iter()
+                                .map(|x| x.as_pixelresult(executor.transparent_background))
+                                .collect::<Vec<_>>(),
+                            complete,
+                        });
                     }
                 },
             )
-            .for_each(&self.on_tile_rendered)
     }
 }
 
@@ -368,3 +783,700 @@ impl Iterator for SampleCounter {
         res
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use rt::{
+        camera::Camera,
+        filter::BoxFilter,
+        integrators::PathTracer,
+        light::Lights,
+        material::{DiffuseBxDF, EmitBxDF, MaterialDescriptor, MaterialId},
+        math::{
+            bounds::Bounds,
+            point::Point,
+            quaternion::LookAt,
+            vec::{Vec2, Vec3},
+        },
+        ray::Ray,
+        renderer::{Channel, LumaChannel, RaySeries, RgbChannel, World},
+        shape::{
+            local_info, FullIntersectionResult, MinIntersectionResult, RayIntersection, Shape,
+        },
+    };
+
+    use crate::{
+        convergence_log::ConvergenceLog,
+        foveation::Foveation,
+        output::OutputBuffers,
+        profiler::Profiler,
+        tile::TileOrder,
+        utils::{
+            turntable_camera, AvailableSampler, Dimensions, ExecutionMode, Frame, Framing, Pixel,
+            RenderMask, RenderRange, Spp,
+        },
+        Args,
+    };
+    use clap::Parser;
+
+    use std::{
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc,
+        },
+        time::{Duration, Instant},
+    };
+
+    use super::{Executor, TileMsg, UNBOUNDED_STRATA};
+
+    struct Nothing;
+    impl Shape for Nothing {
+        fn intersection_full(&self, _ray: Ray) -> FullIntersectionResult {
+            FullIntersectionResult::NoIntersection
+        }
+        fn intersect_bare(&self, _ray: Ray) -> MinIntersectionResult {
+            MinIntersectionResult::NoIntersection
+        }
+        fn bounding_box(&self) -> Bounds {
+            unimplemented!()
+        }
+    }
+
+    struct Sphere(Point, f32);
+    impl Shape for Sphere {
+        fn intersection_full(&self, ray: Ray) -> FullIntersectionResult {
+            let Sphere(center, radius) = *self;
+            let oc = ray.origin - center;
+            let b = oc.dot(ray.direction);
+            let delta = b * b - oc.length_squared() + radius * radius;
+            let t = -b - delta.sqrt();
+            if delta < 0.0 || !ray.range().contains(&t) {
+                return FullIntersectionResult::NoIntersection;
+            }
+
+            FullIntersectionResult::Intersection(RayIntersection {
+                t,
+                local_info: local_info::Full {
+                    pos: ray.at(t),
+                    normal: (ray.at(t) - center) / radius,
+                    shading_normal: (ray.at(t) - center) / radius,
+                    material: MaterialId(0),
+                    uv: [0.0, 0.0],
+                    object: 0,
+                },
+            })
+        }
+        fn intersect_bare(&self, _ray: Ray) -> MinIntersectionResult {
+            unimplemented!()
+        }
+        fn bounding_box(&self) -> Bounds {
+            unimplemented!()
+        }
+    }
+
+    /// Many copies of a sphere, all of them are intersected by the rays going through their bounds
+    struct Cluster(Vec<Sphere>, Bounds);
+    impl Shape for Cluster {
+        fn intersection_full(&self, ray: Ray) -> FullIntersectionResult {
+            if self.1.ray_intersect(&ray).is_none() {
+                return FullIntersectionResult::NoIntersection;
+            }
+            self.0
+                .iter()
+                .map(|sphere| sphere.intersection_full(ray))
+                .fold(FullIntersectionResult::NoIntersection, |a, b| a.min(b))
+        }
+        fn intersect_bare(&self, _ray: Ray) -> MinIntersectionResult {
+            unimplemented!()
+        }
+        fn bounding_box(&self) -> Bounds {
+            self.1
+        }
+    }
+
+    const DIMENSION: Dimensions = Dimensions {
+        width: 16,
+        height: 8,
+    };
+
+    fn executor() -> Executor {
+        let dimension = DIMENSION;
+        Executor {
+            dimension,
+            tile_size: 4,
+            tile_order: TileOrder::Scan,
+            convergence: None,
+            target_variance: None,
+            convergence_log: None,
+            integrator: Box::new(PathTracer::new(4)),
+            camera: Camera::new(
+                dimension.width,
+                dimension.height,
+                f32::to_radians(70.),
+                1.0,
+                Point::ORIGIN,
+                LookAt {
+                    direction: Vec3::NEG_Z,
+                    forward: Vec3::NEG_Z,
+                }
+                .into(),
+                0.0,
+            ),
+            skip_back_faces: false,
+            filter: Box::new(BoxFilter {
+                radius: Vec2::splat(0.7),
+            }),
+            spp: 4,
+            aov_spp: None,
+            exposure: 1.0,
+            highlight_knee: None,
+            seed: 0,
+            wavefront: false,
+            sampler: AvailableSampler::Stratified,
+            antithetic: false,
+            mask: None,
+            foveation: None,
+            transparent_background: false,
+            render_time: None,
+            interrupt: None,
+            threads: None,
+            profiler: None,
+            debug: None,
+        }
+    }
+
+    /// Render the whole image, returns the channels of each pixel flattened after each batch of
+    /// samples
+    fn render(
+        executor: Executor,
+        objects: &dyn Shape,
+        sample_range: Spp,
+    ) -> Vec<((u32, u32), Vec<f32>)> {
+        render_with_mode(executor, objects, sample_range, ExecutionMode::Monothreaded)
+    }
+
+    fn render_with_mode(
+        executor: Executor,
+        objects: &dyn Shape,
+        sample_range: Spp,
+        execution_mode: ExecutionMode,
+    ) -> Vec<((u32, u32), Vec<f32>)> {
+        let materials = [MaterialDescriptor {
+            label: None,
+            material: Box::new(DiffuseBxDF {
+                albedo: [0.5, 0.5, 0.5].into(),
+                ..Default::default()
+            }),
+            alpha: None,
+        }];
+        let world = World {
+            objects,
+            lights: &Lights::default(),
+            materials: &materials,
+            world_material: MaterialId(0),
+            fog: None,
+        };
+
+        let mut pixels = std::collections::BTreeMap::new();
+        let on_tile_rendered = |msg: &TileMsg| {
+            for (coords, pixel) in msg.tile.into_iter().zip(&msg.data) {
+                let values = pixel
+                    .channels
+                    .iter()
+                    .flat_map(|chan| match chan {
+                        Channel::RgbChannel(_, c) => c.0.to_vec(),
+                        Channel::LumaChannel(_, c) => vec![c.0],
+                    })
+                    .collect();
+                pixels.insert(coords, values);
+            }
+        };
+        let pixel_range = RenderRange {
+            x: 0..DIMENSION.width,
+            y: 0..DIMENSION.height,
+        };
+        match execution_mode {
+            ExecutionMode::Multithreaded => {
+                executor.run_multithreaded(&world, on_tile_rendered, pixel_range, sample_range)
+            }
+            ExecutionMode::Monothreaded => {
+                executor.run_monothreaded(&world, on_tile_rendered, pixel_range, sample_range)
+            }
+        }
+        .unwrap();
+        pixels.into_iter().collect()
+    }
+
+    /// Compare the bits, as NaN != NaN
+    fn bits(pixels: &[((u32, u32), Vec<f32>)]) -> Vec<((u32, u32), Vec<u32>)> {
+        pixels
+            .iter()
+            .map(|(coords, values)| (*coords, values.iter().map(|x| x.to_bits()).collect()))
+            .collect()
+    }
+
+    #[test]
+    fn single_thread_is_monothreaded() {
+        let sphere = Sphere(Point::new(0.0, 0.0, -3.0), 1.0);
+        let mono = render(executor(), &sphere, Spp::Spp(0..4));
+        let multi = render_with_mode(
+            Executor {
+                threads: Some(1),
+                ..executor()
+            },
+            &sphere,
+            Spp::Spp(0..4),
+            ExecutionMode::Multithreaded,
+        );
+        assert_eq!(bits(&mono), bits(&multi));
+    }
+
+    #[test]
+    fn full_mask_is_full_render() {
+        let full = render(executor(), &Nothing, Spp::Spp(0..4));
+        let masked = render(
+            Executor {
+                mask: Some(RenderMask {
+                    width: 16,
+                    height: 8,
+                    pixels: vec![true; 16 * 8],
+                }),
+                ..executor()
+            },
+            &Nothing,
+            Spp::Spp(0..4),
+        );
+
+        assert_eq!(full.len(), masked.len());
+        for (a, b) in full.iter().zip(&masked) {
+            assert_eq!(a.0, b.0);
+            // NaN != NaN, so compare the bits
+            let bits = |v: &Vec<f32>| v.iter().map(|x| x.to_bits()).collect::<Vec<_>>();
+            assert_eq!(bits(&a.1), bits(&b.1));
+        }
+    }
+
+    #[test]
+    fn uniform_foveation_is_full_render() {
+        let sphere = Sphere(Point::new(0.0, 0.0, -3.0), 1.0);
+        let full = render(executor(), &sphere, Spp::Spp(0..4));
+        let foveated = |falloff| {
+            let executor = Executor {
+                foveation: Some(Foveation {
+                    center: Vec2::new(0.5, 0.5),
+                    falloff,
+                }),
+                ..executor()
+            };
+            render(executor, &sphere, Spp::Spp(0..4))
+        };
+        assert_eq!(bits(&full), bits(&foveated(0.0)));
+
+        // The corners are skipped with a steep falloff, they are left transparent until upsampled
+        let steep = foveated(8.0);
+        let alpha = |pixels: &[((u32, u32), Vec<f32>)], coords| {
+            pixels.iter().find(|(c, _)| *c == coords).unwrap().1[alpha_index()]
+        };
+        assert_eq!(alpha(&steep, (15, 7)), 0.0);
+        assert_eq!(alpha(&steep, (8, 4)), 1.0);
+    }
+
+    #[test]
+    fn debug_pixel_does_not_change_the_render() {
+        let sphere = Sphere(Point::new(0.0, 0.0, -3.0), 1.0);
+        let full = render(executor(), &sphere, Spp::Spp(0..4));
+        for sample in [None, Some(2)] {
+            let debugged = render(
+                Executor {
+                    debug: Some((Pixel { x: 8, y: 4 }, sample)),
+                    ..executor()
+                },
+                &sphere,
+                Spp::Spp(0..4),
+            );
+            assert_eq!(bits(&full), bits(&debugged));
+        }
+
+        let executor = Executor {
+            debug: Some((Pixel { x: 8, y: 4 }, Some(2))),
+            ..executor()
+        };
+        let debugged = |x, y, sample_idx| {
+            executor.is_debugged(rt::Seed {
+                seed: 0,
+                x,
+                y,
+                sample_idx,
+            })
+        };
+        assert!(debugged(8, 4, 2));
+        assert!(!debugged(8, 4, 1) && !debugged(4, 8, 2));
+    }
+
+    #[test]
+    fn masked_out_pixels_are_transparent() {
+        let mask = RenderMask {
+            width: 16,
+            height: 8,
+            pixels: (0..16 * 8).map(|i| i % 3 == 0).collect(),
+        };
+        for ((x, y), values) in render(
+            Executor {
+                mask: Some(mask.clone()),
+                ..executor()
+            },
+            &Nothing,
+            Spp::Spp(0..4),
+        ) {
+            let expected = if mask.contains(x, y) { 1.0 } else { 0.0 };
+            assert_eq!(values[alpha_index()], expected);
+        }
+    }
+
+    #[test]
+    fn transparent_background() {
+        let sphere = Sphere(Point::new(0.0, 0.0, -3.0), 1.0);
+        let alpha = |transparent_background| {
+            let executor = Executor {
+                transparent_background,
+                ..executor()
+            };
+            render(executor, &sphere, Spp::Spp(0..4))
+                .into_iter()
+                .map(|(coords, values)| (coords, values[alpha_index()]))
+                .collect::<std::collections::HashMap<_, _>>()
+        };
+
+        assert!(alpha(false).values().all(|&alpha| alpha == 1.0));
+
+        let alpha = alpha(true);
+        // The sphere is in the middle of the image
+        assert_eq!(alpha[&(8, 4)], 1.0);
+        for corner in [(0, 0), (15, 0), (0, 7), (15, 7)] {
+            assert_eq!(alpha[&corner], 0.0);
+        }
+    }
+
+    #[test]
+    fn aov_spp() {
+        let sphere = Sphere(Point::new(0.0, 0.0, -3.0), 1.0);
+        // The alpha is the proportion of the samples hitting the sphere
+        let executor = || Executor {
+            spp: 8,
+            aov_spp: Some(2),
+            transparent_background: true,
+            ..executor()
+        };
+        let two = render(executor(), &sphere, Spp::Spp(0..2));
+        let eight = render(executor(), &sphere, Spp::Spp(0..8));
+
+        // Whether each of the flattened channels is an AOV, or is accumulated over all the samples
+        let (aovs, colors): (Vec<_>, Vec<_>) = RaySeries::default()
+            .as_pixelresult(false)
+            .channels
+            .iter()
+            .flat_map(|chan| {
+                let kind = match chan {
+                    Channel::RgbChannel(RgbChannel::Color, _)
+                    | Channel::LumaChannel(LumaChannel::Alpha, _) => Some(false),
+                    Channel::RgbChannel(
+                        RgbChannel::Normal | RgbChannel::Position | RgbChannel::Albedo,
+                        _,
+                    )
+                    | Channel::LumaChannel(LumaChannel::Z | LumaChannel::ObjectId, _) => Some(true),
+                    _ => None,
+                };
+                let len = match chan {
+                    Channel::RgbChannel(_, _) => 3,
+                    Channel::LumaChannel(_, _) => 1,
+                };
+                vec![kind; len]
+            })
+            .enumerate()
+            .filter_map(|(index, kind)| Some((index, kind?)))
+            .partition(|(_, is_aov)| *is_aov);
+
+        let mut color_changed = false;
+        for ((_, two), (_, eight)) in two.iter().zip(&eight) {
+            for &(index, _) in &aovs {
+                assert_eq!(two[index].to_bits(), eight[index].to_bits());
+            }
+            color_changed |= colors.iter().any(|&(index, _)| two[index] != eight[index]);
+        }
+        assert!(color_changed);
+    }
+
+    #[test]
+    fn unbounded_spp_is_stratified() {
+        let executor = Executor {
+            spp: u32::MAX,
+            ..executor()
+        };
+        // Each window of samples visits every stratum of the pixel once
+        let mut sampler = executor.pixel_sampler(1, 2);
+        let mut strata = std::collections::BTreeSet::new();
+        for sample in 0..UNBOUNDED_STRATA * UNBOUNDED_STRATA {
+            sampler.with_sample(sample);
+            let offset = sampler.sample_2d() * UNBOUNDED_STRATA as f32;
+            strata.insert((offset.x as u32, offset.y as u32));
+        }
+        assert_eq!(strata.len() as u32, UNBOUNDED_STRATA * UNBOUNDED_STRATA);
+    }
+
+    /// Position of the alpha in the flattened channels
+    fn alpha_index() -> usize {
+        RaySeries::default()
+            .as_pixelresult(false)
+            .channels
+            .iter()
+            .flat_map(|chan| match chan {
+                Channel::RgbChannel(_, _) => vec![false; 3],
+                Channel::LumaChannel(name, _) => vec![*name == LumaChannel::Alpha],
+            })
+            .position(|is_alpha| is_alpha)
+            .unwrap()
+    }
+
+    #[test]
+    fn target_variance() {
+        let materials = [
+            MaterialDescriptor {
+                label: None,
+                material: Box::new(DiffuseBxDF {
+                    albedo: [0.5, 0.5, 0.5].into(),
+                    ..Default::default()
+                }),
+                alpha: None,
+            },
+            MaterialDescriptor {
+                label: None,
+                material: Box::new(EmitBxDF {
+                    le: [1.0, 1.0, 1.0].into(),
+                    two_sided: true,
+                }),
+                alpha: None,
+            },
+        ];
+        // The edges of the sphere are noisy
+        let sphere = Sphere(Point::new(0.0, 0.0, -3.0), 1.5);
+        let world = World {
+            objects: &sphere,
+            lights: &Lights::default(),
+            materials: &materials,
+            world_material: MaterialId(1),
+            fog: None,
+        };
+        let spp = 256;
+        let target = 1e-3;
+        let executor = Executor {
+            spp,
+            target_variance: Some(target),
+            ..executor()
+        };
+        // Every tile is sent after each batch
+        let batch_size = executor.batch_size();
+        let tiles = DIMENSION.width.div_ceil(executor.tile_size)
+            * DIMENSION.height.div_ceil(executor.tile_size);
+
+        // The brightness and the variance of the color of each pixel, and the mean relative
+        // variance of the pixels after each batch
+        let mut messages = 0;
+        let mut pixels = std::collections::BTreeMap::new();
+        let mut variances = Vec::new();
+        executor
+            .run_monothreaded(
+                &world,
+                |msg: &TileMsg| {
+                    messages += 1;
+                    for (coords, pixel) in msg.tile.into_iter().zip(&msg.data) {
+                        let (mut brightness, mut variance) = (0.0f32, 0.0);
+                        for channel in &pixel.channels {
+                            match channel {
+                                Channel::RgbChannel(RgbChannel::Color, c) => {
+                                    brightness = c.0.into_iter().fold(0.0, f32::max)
+                                }
+                                Channel::LumaChannel(LumaChannel::Variance, v) => variance = v.0,
+                                _ => (),
+                            }
+                        }
+                        pixels.insert(coords, (brightness, variance));
+                    }
+                    if messages % tiles != 0 {
+                        return;
+                    }
+                    let samples = messages / tiles * batch_size;
+                    let mean_relative_variance = pixels
+                        .values()
+                        .map(|(brightness, variance)| {
+                            variance
+                                / samples as f32
+                                / (brightness * brightness + super::RELATIVE_VARIANCE_EPSILON)
+                        })
+                        .sum::<f32>()
+                        / pixels.len() as f32;
+                    variances.push(mean_relative_variance);
+                },
+                RenderRange {
+                    x: 0..DIMENSION.width,
+                    y: 0..DIMENSION.height,
+                },
+                Spp::Spp(0..spp),
+            )
+            .unwrap();
+
+        // The render stops at the first batch below the target, before all the samples are in
+        assert_eq!(messages % tiles, 0);
+        let samples = variances.len() as u32 * batch_size;
+        assert!(samples < spp, "{samples}");
+        let (&last, before) = variances.split_last().unwrap();
+        assert!(!before.is_empty(), "{variances:?}");
+        assert!(last <= target * 1.01, "{last}");
+        assert!(last > 0.0);
+        for variance in before {
+            assert!(*variance > target * 0.99, "stopped late: {variances:?}");
+        }
+    }
+
+    #[test]
+    fn convergence_log() {
+        let path = std::env::temp_dir().join(format!("rt-convergence-{}.csv", std::process::id()));
+        let executor = Executor {
+            convergence_log: Some(ConvergenceLog::create(&path).unwrap()),
+            // Batches of a single sample
+            interrupt: Some(Default::default()),
+            ..executor()
+        };
+        render(
+            executor,
+            &Sphere(Point::new(0.0, 0.0, -3.0), 1.5),
+            Spp::Spp(0..6),
+        );
+
+        let log = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let mut lines = log.lines();
+        assert_eq!(
+            lines.next(),
+            Some("samples,elapsed_ms,mean_luminance,rel_variance")
+        );
+        let rows = lines
+            .map(|line| line.split(',').collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+        assert!(rows.iter().all(|row| row.len() == 4), "{rows:?}");
+        let samples = rows
+            .iter()
+            .map(|row| row[0].parse::<u32>().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(samples, [1, 2, 3, 4, 5, 6]);
+        let elapsed = rows.iter().map(|row| row[1].parse::<u128>().unwrap());
+        assert!(elapsed.clone().zip(elapsed.skip(1)).all(|(a, b)| a <= b));
+        assert!(rows.iter().all(|row| row[2].parse::<f32>().unwrap() >= 0.0));
+    }
+
+    #[test]
+    fn render_time() {
+        let executor = Executor {
+            render_time: Some(Duration::from_millis(300)),
+            ..executor()
+        };
+
+        let begin = Instant::now();
+        let pixels = render(executor, &Nothing, Spp::Spp(0..u32::MAX));
+        let elapsed = begin.elapsed();
+
+        assert!(!pixels.is_empty());
+        assert!(elapsed >= Duration::from_millis(300));
+        assert!(
+            elapsed < Duration::from_millis(600),
+            "rendered for {elapsed:?}"
+        );
+    }
+
+    #[test]
+    fn interrupt() {
+        let interrupt = Arc::new(AtomicBool::new(false));
+        let executor = Executor {
+            interrupt: Some(interrupt.clone()),
+            ..executor()
+        };
+
+        let handle = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(100));
+            interrupt.store(true, Ordering::SeqCst);
+        });
+        let begin = Instant::now();
+        let pixels = render(executor, &Nothing, Spp::Spp(0..u32::MAX));
+        let elapsed = begin.elapsed();
+        handle.join().unwrap();
+
+        assert!(!pixels.is_empty());
+        assert!(
+            elapsed < Duration::from_millis(400),
+            "rendered for {elapsed:?}"
+        );
+    }
+
+    #[test]
+    fn profiler_heatmap() {
+        // Seen on the left half of the image
+        let (center, radius) = (Point::new(1.2, 0.0, -3.0), 1.0);
+        let cluster = Cluster(
+            (0..400).map(|_| Sphere(center, radius)).collect(),
+            Bounds::new(center - Vec3::splat(radius), center + Vec3::splat(radius)),
+        );
+        let profiler = Arc::new(Profiler::default());
+        render(
+            Executor {
+                profiler: Some(profiler.clone()),
+                ..executor()
+            },
+            &cluster,
+            Spp::Spp(0..4),
+        );
+
+        let mut output_buffers = OutputBuffers {
+            channels: Vec::new(),
+        };
+        profiler.add_heatmaps(&mut output_buffers, DIMENSION);
+        let Some(Channel::LumaChannel(LumaChannel::IntersectionTime, heatmap)) =
+            output_buffers.channels.first()
+        else {
+            panic!("there is no intersection time");
+        };
+        let (left, right): (Vec<_>, Vec<_>) = heatmap
+            .enumerate_pixels()
+            .partition(|(x, _, _)| *x < DIMENSION.width / 2);
+        let mean = |pixels: Vec<(u32, u32, &image::Luma<f32>)>| {
+            pixels.iter().map(|(_, _, p)| p.0[0]).sum::<f32>() / pixels.len() as f32
+        };
+        let (left, right) = (mean(left), mean(right));
+        assert!(left > 2.0 * right, "{left} <= 2 * {right}");
+        assert_eq!(output_buffers.channels.len(), 2);
+    }
+
+    #[test]
+    fn turntable() {
+        let args = Args::parse_from(["rt", "-d", "16x8", "--frames", "2", "--turntable"]);
+        let sphere = Sphere(Point::new(0.3, 0.0, -1.0), 0.2);
+        let hits = |index| {
+            let executor = Executor {
+                camera: turntable_camera(&args, Framing::default(), Frame { index, count: 2 }),
+                transparent_background: true,
+                ..executor()
+            };
+            let (left, right): (Vec<_>, Vec<_>) = render(executor, &sphere, Spp::Spp(0..1))
+                .into_iter()
+                .filter(|(_, values)| values[alpha_index()] > 0.0)
+                .partition(|((x, _), _)| *x < DIMENSION.width / 2);
+            (left.len(), right.len())
+        };
+
+        // The sphere is seen from the front then from the back, on the other side of the image
+        let sides = |(left, right)| {
+            assert!(left + right > 0);
+            assert!(left == 0 || right == 0);
+            left > 0
+        };
+        assert_ne!(sides(hits(0)), sides(hits(1)));
+    }
+}
@@ -0,0 +1,214 @@
+//! Foveated rendering: the pixels are rendered densely around a focus point and sparsely in the
+//! periphery, the skipped pixels are interpolated from the rendered ones afterwards.
+//!
+//! Away from the focus, only the pixels on a lattice of stride 2, 4, ... up to
+//! [Foveation::MAX_STRIDE] are rendered. The strides are powers of 2 so the lattices are nested:
+//! the pixels of a coarse lattice are rendered whatever the stride around them.
+use std::str::FromStr;
+
+use rt::{
+    math::vec::Vec2,
+    renderer::{Channel, LumaChannel},
+};
+
+use crate::{
+    output::OutputBuffers,
+    utils::{Dimensions, FromArgs},
+    Args,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Foveation {
+    /// The focus point, as a fraction of the width and the height of the image
+    pub center: Vec2,
+    /// Growth of the stride of the lattice with the distance to the focus, in half diagonals of
+    /// the image. With 0, every pixel is rendered
+    pub falloff: f32,
+}
+
+impl Foveation {
+    pub const MAX_STRIDE: u32 = 16;
+
+    /// Stride of the lattice of rendered pixels around the pixel `(x, y)`
+    pub fn stride(&self, x: u32, y: u32, dimension: Dimensions) -> u32 {
+        let size = Vec2::new(dimension.width as f32, dimension.height as f32);
+        let distance = (Vec2::new(x as f32 + 0.5, y as f32 + 0.5) - self.center * size).length()
+            / (size.length() / 2.0);
+        let stride = (1.0 + self.falloff.max(0.0) * distance).min(Self::MAX_STRIDE as f32) as u32;
+        // The largest power of 2 below
+        1 << stride.ilog2()
+    }
+
+    pub fn is_rendered(&self, x: u32, y: u32, dimension: Dimensions) -> bool {
+        let stride = self.stride(x, y, dimension);
+        x % stride == 0 && y % stride == 0
+    }
+
+    /// The rendered pixels to interpolate the pixel `(x, y)` from, with their weights: the
+    /// corners of the cell of the lattice around it, the first lattice whose corners are all
+    /// rendered
+    fn neighbours(&self, x: u32, y: u32, dimension: Dimensions) -> [((u32, u32), f32); 4] {
+        let mut stride = self.stride(x, y, dimension);
+        loop {
+            let (x0, y0) = (x - x % stride, y - y % stride);
+            // Past the last line of the lattice, the cell is clamped to its first line
+            let x1 = if x0 + stride < dimension.width {
+                x0 + stride
+            } else {
+                x0
+            };
+            let y1 = if y0 + stride < dimension.height {
+                y0 + stride
+            } else {
+                y0
+            };
+            let corners = [(x0, y0), (x1, y0), (x0, y1), (x1, y1)];
+
+            if stride >= Self::MAX_STRIDE
+                || corners
+                    .iter()
+                    .all(|&(cx, cy)| self.is_rendered(cx, cy, dimension))
+            {
+                let fx = if x1 > x0 {
+                    (x - x0) as f32 / stride as f32
+                } else {
+                    0.0
+                };
+                let fy = if y1 > y0 {
+                    (y - y0) as f32 / stride as f32
+                } else {
+                    0.0
+                };
+                let weights = [
+                    (1.0 - fx) * (1.0 - fy),
+                    fx * (1.0 - fy),
+                    (1.0 - fx) * fy,
+                    fx * fy,
+                ];
+                return [0, 1, 2, 3].map(|i| (corners[i], weights[i]));
+            }
+            stride *= 2;
+        }
+    }
+
+    /// Fills the pixels that were not rendered by a bilinear interpolation of the rendered ones.
+    /// The object ids are not interpolated, the one of the nearest rendered pixel is taken
+    pub fn upsample(&self, output_buffers: &mut OutputBuffers, dimension: Dimensions) {
+        let skipped = (0..dimension.height)
+            .flat_map(|y| (0..dimension.width).map(move |x| (x, y)))
+            .filter(|&(x, y)| !self.is_rendered(x, y, dimension))
+            .map(|(x, y)| ((x, y), self.neighbours(x, y, dimension)))
+            .collect::<Vec<_>>();
+
+        for channel in &mut output_buffers.channels {
+            match channel {
+                Channel::RgbChannel(_, image) => {
+                    for &((x, y), neighbours) in &skipped {
+                        let mut value = [0.0; 3];
+                        for ((nx, ny), weight) in neighbours {
+                            let pixel = image.get_pixel(nx, ny).0;
+                            for (v, p) in value.iter_mut().zip(pixel) {
+                                *v += weight * p;
+                            }
+                        }
+                        image.get_pixel_mut(x, y).0 = value;
+                    }
+                }
+                Channel::LumaChannel(LumaChannel::ObjectId, image) => {
+                    for &((x, y), neighbours) in &skipped {
+                        let (nearest, _) = neighbours
+                            .into_iter()
+                            .max_by(|a, b| a.1.total_cmp(&b.1))
+                            .unwrap();
+                        *image.get_pixel_mut(x, y) = *image.get_pixel(nearest.0, nearest.1);
+                    }
+                }
+                Channel::LumaChannel(_, image) => {
+                    for &((x, y), neighbours) in &skipped {
+                        let value = neighbours
+                            .into_iter()
+                            .map(|((nx, ny), weight)| weight * image.get_pixel(nx, ny).0[0])
+                            .sum();
+                        image.get_pixel_mut(x, y).0 = [value];
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl FromStr for Foveation {
+    type Err = anyhow::Error;
+
+    /// In format `cx,cy,falloff`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let values = s
+            .split(',')
+            .map(|c| c.trim().parse())
+            .collect::<Result<Vec<f32>, _>>()?;
+        let [cx, cy, falloff] = values[..] else {
+            anyhow::bail!("Incorrect format, see help");
+        };
+        Ok(Self {
+            center: Vec2::new(cx, cy),
+            falloff,
+        })
+    }
+}
+
+impl FromArgs for Option<Foveation> {
+    fn from_args(args: &Args) -> Self {
+        args.foveate
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{ImageBuffer, Rgb};
+    use rt::renderer::{Channel, RgbChannel};
+
+    use crate::{output::OutputBuffers, utils::Dimensions};
+
+    use super::Foveation;
+
+    #[test]
+    fn foveated_lattice() {
+        let dimension = Dimensions {
+            width: 64,
+            height: 32,
+        };
+        let foveation: Foveation = "0.5,0.5,12".parse().unwrap();
+        assert_eq!(foveation.stride(32, 16, dimension), 1);
+        assert!(foveation.stride(0, 0, dimension) > 4);
+        let rendered = (0..32)
+            .flat_map(|y| (0..64).map(move |x| (x, y)))
+            .filter(|&(x, y)| foveation.is_rendered(x, y, dimension))
+            .count();
+        assert!(rendered < 64 * 32 / 2, "{rendered}");
+
+        // A gradient is rebuilt exactly from the rendered pixels
+        let gradient = |x: u32, y: u32| Rgb([x as f32, 2.0 * y as f32, 1.0]);
+        let mut output_buffers = OutputBuffers {
+            channels: vec![Channel::RgbChannel(
+                RgbChannel::Color,
+                ImageBuffer::from_fn(64, 32, |x, y| {
+                    if foveation.is_rendered(x, y, dimension) {
+                        gradient(x, y)
+                    } else {
+                        Rgb([0.0; 3])
+                    }
+                }),
+            )],
+        };
+        foveation.upsample(&mut output_buffers, dimension);
+        let Channel::RgbChannel(_, image) = &output_buffers.channels[0] else {
+            unreachable!()
+        };
+        // Away from the last lines, where the lattice is clamped
+        for y in 0..16 {
+            for x in 0..48 {
+                assert_eq!(image.get_pixel(x, y).0, gradient(x, y).0, "at {x}, {y}");
+            }
+        }
+    }
+}
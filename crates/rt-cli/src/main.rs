@@ -1,27 +1,55 @@
 #![feature(new_uninit)]
 #![feature(maybe_uninit_slice)]
 
+mod bake;
+mod contact_sheet;
+mod convergence_log;
 mod executor;
+mod foveation;
 mod output;
+mod profiler;
 mod progress;
 mod renderer;
 mod tile;
 mod utils;
+mod watcher;
+
+use std::{
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
 
 use anyhow::Result;
 use clap::Parser;
 use progress::PercentBar;
 use renderer::Renderer;
-use rt::aggregate::embree::EmbreeScene;
+use rt::{
+    aggregate::{
+        clipped::ClippedShape,
+        embree::{BuildCancelled, EmbreeScene},
+    },
+    color::Rgb,
+    loader::ObjLoaderExt,
+    material::{DiffuseBxDF, MaterialDescriptor},
+    math::transform::Transform,
+    scene::{info::SceneInfo, SceneT},
+    utils::counter,
+};
+use tile::TileOrder;
 use utils::{
-    AvailableIntegrator, AvailableOutput, AvailableScene, Dimensions, ExecutionMode, FromArgs,
-    RenderRange, Spp,
+    AvailableFilter, AvailableIntegrator, AvailableOutput, AvailableSampler, AvailableScene,
+    Dimensions, ExecutionMode, Frame, Framing, FromArgs, LensShift, MaterialOverride, Pixel,
+    RenderRange, RenderTime, Spp,
 };
+use watcher::FileWatcher;
 
-#[derive(Parser, Debug)]
+#[derive(Parser, Debug, Clone)]
 pub struct Args {
     tev_path: Option<String>,
-    #[arg(long = "spp", default_value = "32")]
+    #[arg(long = "spp", default_value = "32", value_parser = utils::parse_spp)]
     /// Samples per pixel. To render a pixel using 5 samples use "5" to render a pixel with samples
     /// 7..84 use "7..84" to render a pixel with as much sample as possible (it will render until
     ///   interuption) use "inf"
@@ -30,10 +58,62 @@ pub struct Args {
     #[arg(long)]
     sample_range: Option<Spp>,
 
+    #[arg(long, value_name = "SECONDS")]
+    /// Save the outputs with the samples accumulated so far every given number of seconds, so
+    /// that a long or infinite render that is interrupted still leaves an image
+    autosave_interval: Option<u64>,
+
+    #[arg(long, value_name = "PATH")]
+    /// Write a CSV with a row per batch of samples: the number of samples per pixel, the elapsed
+    /// milliseconds, the mean luminance of the image and the mean relative variance of its pixels
+    convergence_log: Option<PathBuf>,
+
+    #[arg(long)]
+    /// Number of samples of the AOVs, the normal, albedo, depth... The next samples of a pixel
+    /// only go to its color. The AOVs converge in a few samples
+    aov_spp: Option<u32>,
+
+    #[arg(long)]
+    /// Keep rendering samples until the given time is up, eg "30s" or "2m30s". Unless a sample
+    /// range is given, the number of samples is then unbounded
+    render_time: Option<RenderTime>,
+
     #[arg(long, value_enum, default_value_t)]
     /// Scene selector
     scene: AvailableScene,
 
+    #[arg(long)]
+    /// An OBJ file to render instead of the selected scene
+    scene_file: Option<PathBuf>,
+
+    #[arg(long, requires = "scene_file", value_name = "NAME=MATERIAL")]
+    /// Give a material to the meshes of the scene file of the given object or group name, eg
+    /// `windows=thin-glass`, instead of their own one. The material is one of diffuse, glass,
+    /// thin-glass and emit
+    override_material: Vec<MaterialOverride>,
+
+    #[arg(long, requires = "scene_file")]
+    /// Restart the render each time the scene file changes
+    watch: bool,
+
+    #[arg(long)]
+    /// Print the numbers of primitives, lights and materials of the scene, its bounds and an
+    /// estimate of its memory, and exit without rendering
+    scene_info: bool,
+
+    #[arg(long, value_enum)]
+    /// Bake the given lighting on the vertices of the meshes of the scene instead of rendering,
+    /// to `output/bake/<bake>.ply` as vertex colors
+    bake: Option<bake::AvailableBake>,
+
+    #[arg(long, default_value_t = 256)]
+    /// Number of rays per vertex of the bake
+    bake_samples: u32,
+
+    #[arg(long)]
+    /// Only the occluders within this distance darken the ambient occlusion bake
+    bake_distance: Option<f32>,
+
     #[arg(short, long, default_value = "800x600")]
     /// Screen dimension in format `width`x`height`
     dimensions: Dimensions,
@@ -41,27 +121,130 @@ pub struct Args {
     #[arg(short, long, value_enum)]
     output: Vec<AvailableOutput>,
 
+    #[arg(long)]
+    /// Write the HDR images of the file output in tiled EXR files as the tiles are completed,
+    /// instead of keeping them in memory. The whole image must be rendered for a number of samples
+    stream_exr: bool,
+
+    #[arg(long)]
+    /// Path of the images of the file output, with placeholders for the `{scene}`, the
+    /// `{integrator}`, the `{spp}` and the `{seed}`: `out/{scene}_{spp}spp_{seed}`. The channel is
+    /// added to the name unless there is a `{channel}` placeholder, the extension depends on the
+    /// format of the image
+    output_template: Option<String>,
+
+    #[arg(long)]
+    /// An EXR image of the same scene rendered with many samples
+    reference: Option<PathBuf>,
+
+    #[arg(long, requires = "reference")]
+    /// Once rendered, log the error of the color against the reference
+    report_error: bool,
+
     #[arg(short, long, value_enum, default_value_t)]
     integrator: AvailableIntegrator,
 
+    #[arg(long, value_enum, value_delimiter = ',', conflicts_with = "frames")]
+    /// Render the scene with each of these integrators and composite the renders in a labeled
+    /// grid, saved to `output/ldr/contact-sheet.png`, one row per integrator
+    compare: Vec<AvailableIntegrator>,
+
+    #[arg(long, value_delimiter = ',', conflicts_with = "frames")]
+    /// Render the scene with each of these numbers of samples in the contact sheet, one column per
+    /// number of samples
+    compare_spp: Vec<u32>,
+
     #[arg(long)]
     tev_hostname: Option<String>,
 
-    /// If provided, allow for a kind of adaptative sampling by estimating the error of a pixel until the error if less than the given value
+    /// If provided, allow for a kind of adaptative sampling: a pixel is sampled until the 95%
+    /// confidence interval of its color is within the given error relative to its brightness
     #[arg(long)]
     allowed_error: Option<f32>,
 
+    /// Keep adding batches of samples until the mean over the image of the variance of the
+    /// pixels, relative to their squared brightness, gets below this. The number of samples it
+    /// took is logged, it is at most the spp
+    #[arg(long)]
+    target_variance: Option<f32>,
+
+    /// Number of samples a pixel takes before adaptative sampling can stop it
+    #[arg(long, default_value_t = 16)]
+    min_samples: u32,
+
     #[arg(long, default_value_t = 32)]
     tile_size: u32,
 
+    #[arg(long, value_enum, default_value_t)]
+    /// The order in which the tiles are rendered
+    tile_order: TileOrder,
+
     #[arg(short, long, value_enum, default_value_t=ExecutionMode::Multithreaded)]
     execution_mode: ExecutionMode,
 
+    #[arg(long)]
+    /// Number of threads of the multithreaded mode, defaults to the number of cores
+    threads: Option<usize>,
+
     #[arg(short, long)]
     /// The range to render. To render pixel (1,4) use "1x4",to render range (1,4)..(7,45) use
     /// "1..7x4..45".
     range: Option<RenderRange>,
 
+    #[arg(long)]
+    /// A black and white image of the size of the render, only the white pixels get rendered.
+    /// The others are transparent in the output
+    mask: Option<PathBuf>,
+
+    #[arg(long, value_name = "CX,CY,FALLOFF")]
+    /// Foveated rendering: the pixels are rendered densely around the focus point (cx, cy), as
+    /// fractions of the width and the height, and sparsely away from it, then interpolated. The
+    /// falloff is how fast the density drops with the distance to the focus point
+    foveate: Option<foveation::Foveation>,
+
+    #[arg(long)]
+    /// Make the background transparent: the alpha of the pixels is the proportion of the camera
+    /// rays hitting the scene
+    transparent_background: bool,
+
+    #[arg(long)]
+    /// Render an animation of the given number of frames, each one written to numbered files
+    frames: Option<u32>,
+
+    #[arg(long, requires = "frames")]
+    /// Make the camera orbit around the point it looks at, by a full turn over the animation
+    turntable: bool,
+
+    #[arg(long, requires = "frames", conflicts_with = "turntable")]
+    /// Move the camera along a path through the keyframes over the animation, each one in format
+    /// `x,y,z:x,y,z`: where the camera looks from, then where it looks at
+    keyframe: Vec<Framing>,
+
+    #[arg(long)]
+    /// Let the camera rays through the back faces they hit first, to see out of a closed mesh
+    /// the camera is in
+    skip_back_faces: bool,
+
+    #[arg(long)]
+    /// Place the camera so that it sees the whole scene, looking at its center
+    auto_frame: bool,
+
+    #[arg(long, default_value_t = 1.0)]
+    /// Exposure time of the camera. Along with the ISO and the f-stop, it gives the exposure
+    /// value, the defaults are at EV 0 where the radiance is not scaled
+    shutter_seconds: f32,
+
+    #[arg(long, default_value_t = 100.0)]
+    iso: f32,
+
+    #[arg(long, default_value_t = 1.0)]
+    fstop: f32,
+
+    #[arg(long, value_name = "LUMINANCE")]
+    /// Roll off the highlights of the samples smoothly above this luminance, after the exposure,
+    /// to tame the fireflies: a sample is at most twice as bright as the knee
+    highlight_knee: Option<f32>,
+
     #[arg(long, default_value_t)]
     /// Seed to use for all the random stuff.
     /// Given a seed, the rendering is deterministic (the output only depends on x, y, sample and seed).
@@ -69,6 +252,101 @@ pub struct Args {
 
     #[arg(long)]
     max_ray_depth: Option<u32>,
+
+    #[arg(long)]
+    /// Density of a fog filling the whole scene
+    fog_density: Option<f32>,
+
+    #[arg(long)]
+    /// Hits closer than this to the origin of a ray are ignored, on every ray
+    ray_tmin: Option<f32>,
+
+    #[arg(long)]
+    /// Hits farther than this from the origin of a ray are ignored, on every ray
+    ray_tmax: Option<f32>,
+
+    #[arg(long)]
+    /// Maximum number of diffuse bounces, defaults to the max ray depth
+    max_diffuse_depth: Option<u32>,
+
+    #[arg(long)]
+    /// Maximum number of glossy bounces, defaults to the max ray depth
+    max_glossy_depth: Option<u32>,
+
+    #[arg(long)]
+    /// Maximum number of specular bounces (perfect reflections and refractions), defaults to the
+    /// max ray depth
+    max_specular_depth: Option<u32>,
+
+    #[arg(long, default_value_t = 0)]
+    /// Number of specular bounces on which the paths are split, tracing both the reflection and
+    /// the refraction of the glass. It can't be done in wavefront mode
+    split_depth: u32,
+
+    #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u32).range(1..))]
+    /// Number of light samples, each with its shadow ray, averaged at each hit of the path
+    /// tracer. More of them give cleaner penumbrae for the cost of the shadow rays
+    shadow_samples: u32,
+
+    #[arg(long)]
+    /// Count the rays, intersections and BxDF evaluations and print them after the render
+    stats: bool,
+
+    #[arg(long, value_name = "X,Y", allow_hyphen_values = true)]
+    /// Shift the sensor in its plane, in units of half the size of the image, as a tilt-shift
+    /// lens does: the image center then sees what the point (X, Y) of the unshifted image sees,
+    /// (-1, -1) being its top left corner. The vertical lines stay parallel, eg 0,-0.5 frames a
+    /// building higher
+    lens_shift: Option<LensShift>,
+
+    #[arg(long, value_name = "X,Y")]
+    /// Log every step of the paths of this pixel: the camera ray, the hits, the sampled
+    /// directions and their pdfs
+    debug_pixel: Option<Pixel>,
+
+    #[arg(long, requires = "debug_pixel")]
+    /// Only log the path of this sample of the debugged pixel, instead of all of them
+    debug_sample: Option<u32>,
+
+    #[arg(long)]
+    /// Time the intersections and the shading of each tile, the times are logged and given as
+    /// AOVs
+    profile: bool,
+
+    #[arg(long)]
+    /// Draw black outlines on the saved color where the normals or the depth change abruptly
+    outline: bool,
+
+    #[arg(long, default_value_t = 0.5)]
+    /// Gradient of the normals above which an outline is drawn
+    outline_normal_threshold: f32,
+
+    #[arg(long, default_value_t = 0.1)]
+    /// Gradient of the depth, relative to the depth, above which an outline is drawn
+    outline_depth_threshold: f32,
+
+    #[arg(long)]
+    /// Trace all the pixels of a tile at once, bounce after bounce, instead of one path at a time.
+    /// Only some integrators support it.
+    wavefront: bool,
+
+    #[arg(long, value_enum, default_value_t)]
+    /// Sampler of the pixels
+    sampler: AvailableSampler,
+
+    #[arg(long, value_enum, default_value_t)]
+    /// Reconstruction filter of the pixels
+    filter: AvailableFilter,
+
+    #[arg(long)]
+    /// Radius of the filter in pixels, 0.7 for the box, 1 for the triangle and 2 for the
+    /// Mitchell filter by default
+    filter_radius: Option<f32>,
+
+    #[arg(long)]
+    /// Take the samples by pairs, the second one mirroring the first in its stratum of the pixel.
+    /// This reduces the variance of smooth regions of the image without biasing it
+    antithetic: bool,
 }
 
 fn build_embree_device() -> Result<embree4_rs::device::Device> {
@@ -89,19 +367,16 @@ fn build_embree_device() -> Result<embree4_rs::device::Device> {
     Ok(device)
 }
 
-fn main() -> anyhow::Result<()> {
-    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
-    let args = Args::parse();
-
-    let device = build_embree_device()?;
-
-    log::info!("loading scene");
-    let mut scene = EmbreeScene::new(&device);
-    args.scene.insert_into(&mut scene);
-
-    log::info!("building scene");
-    let commited_scene = scene.commit_with_progress(|amount| {
-        // log::info!(target:"embree::scene", "progress: {}%", amount*100);
+/// Prints the progress of the build of the scene until the channel is closed, at most once per
+/// percent
+fn print_progress(receiver: std::sync::mpsc::Receiver<f64>) {
+    let mut printed = None;
+    for amount in receiver {
+        let percent = (amount * 100.0) as u32;
+        if printed.is_some_and(|printed| printed >= percent) {
+            continue;
+        }
+        printed = Some(percent);
         print!(
             "\r{}",
             PercentBar {
@@ -109,11 +384,145 @@ fn main() -> anyhow::Result<()> {
                 width: 50
             }
         );
-        true
-    })?;
-    println!();
+        let _ = std::io::Write::flush(&mut std::io::stdout());
+    }
+    if printed.is_some() {
+        println!();
+    }
+}
+
+/// Inserts the scene file, or else the selected scene
+fn insert_scene(args: &Args, scene: &mut impl SceneT) {
+    match &args.scene_file {
+        Some(path) => {
+            let default_material = scene.insert_material(MaterialDescriptor {
+                label: None,
+                material: Box::new(DiffuseBxDF {
+                    albedo: Rgb::from_array([0.8, 0.8, 0.8]),
+                    ..Default::default()
+                }),
+                alpha: None,
+            });
+            let overrides = args
+                .override_material
+                .iter()
+                .map(|o| {
+                    (
+                        o.name.clone(),
+                        scene.insert_material(o.material.descriptor()),
+                    )
+                })
+                .collect::<Vec<_>>();
+            scene.load_obj_with_overrides(path, Transform::default(), default_material, &overrides);
+        }
+        None => args.scene.insert_into(scene),
+    }
+}
+
+/// Build the scene and render it, until the end or until interrupted
+fn render(
+    args: &Args,
+    device: &embree4_rs::device::Device,
+    interrupt: Option<Arc<AtomicBool>>,
+) -> Result<()> {
+    log::info!("loading scene");
+    let mut scene = EmbreeScene::new(device);
+    insert_scene(args, &mut scene);
+
+    log::info!("building scene");
+    let (progress, receiver) = std::sync::mpsc::channel();
+    let cancel = interrupt.clone().unwrap_or_default();
+    let commited_scene = std::thread::scope(|scope| {
+        // The progress comes from the build threads, it is printed from this one only
+        scope.spawn(move || print_progress(receiver));
+        scene.commit_with_progress(progress, cancel)
+    });
+    let commited_scene = match commited_scene {
+        Err(err) if err.is::<BuildCancelled>() => {
+            log::info!("{err}");
+            return Ok(());
+        }
+        commited_scene => commited_scene?,
+    };
+
+    let mut world = commited_scene.into_world()?;
+    world.fog = FromArgs::from_args(args);
+    let clipped;
+    if args.ray_tmin.is_some() || args.ray_tmax.is_some() {
+        clipped = ClippedShape {
+            inner: world.objects,
+            t_min: args.ray_tmin.unwrap_or(0.0),
+            t_max: args.ray_tmax.unwrap_or(f32::INFINITY),
+        };
+        world.objects = &clipped;
+    }
+    let framing = if args.auto_frame {
+        Framing::fit(&world.objects.bounding_box(), args.dimensions)
+    } else {
+        Framing::default()
+    };
+
+    if let Some(bake) = args.bake {
+        return bake::bake(args, bake, world.objects);
+    }
+
+    if !args.compare.is_empty() || !args.compare_spp.is_empty() {
+        let sheet = contact_sheet::render_contact_sheet(args, &world, framing)?;
+        let outdir = PathBuf::from("output/ldr/");
+        std::fs::create_dir_all(&outdir)?;
+        let path = outdir.join("contact-sheet.png");
+        log::info!("saving the contact sheet to {}", path.display());
+        sheet.save(path)?;
+        return Ok(());
+    }
+
+    let frames = args.frames.map_or(vec![None], |count| {
+        (0..count)
+            .map(|index| Some(Frame { index, count }))
+            .collect()
+    });
+    for frame in frames {
+        if let Some(frame) = frame {
+            log::info!("rendering frame {}/{}", frame.index + 1, frame.count);
+        }
+        let mut renderer = Renderer::new(args, frame, framing)?;
+        renderer.executor.interrupt = interrupt.clone();
+        renderer.run(&world)?;
+
+        if interrupt.as_ref().is_some_and(|i| i.load(Ordering::SeqCst)) {
+            break;
+        }
+    }
+    Ok(())
+}
+
+fn main() -> anyhow::Result<()> {
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
+    let args = Args::parse();
+    if args.stats {
+        counter::enable_counters();
+    }
+
+    if args.scene_info {
+        let mut info = SceneInfo::default();
+        insert_scene(&args, &mut info);
+        println!("{info}");
+        return Ok(());
+    }
+
+    let device = build_embree_device()?;
+
+    if !args.watch {
+        return render(&args, &device, None);
+    }
 
-    let world = commited_scene.into_world()?;
+    let mut watcher = FileWatcher::new(args.scene_file.clone().unwrap());
+    loop {
+        let (changed, handle) = watcher.spawn();
+        render(&args, &device, Some(changed))?;
 
-    Renderer::from_args(&args).run(&world)
+        // Once the render is done, keep waiting for the next change
+        watcher = handle.join().expect("the watcher thread panicked");
+        log::info!("restarting the render");
+    }
 }
@@ -0,0 +1,113 @@
+use std::path::PathBuf;
+
+use anyhow::Result;
+use image::Rgb32FImage;
+use rt::renderer::{Channel, RgbChannel};
+
+use super::{FinalOutput, OutputBuffers};
+
+/// Error of the color of an image against a reference, averaged over the pixels and the channels
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ImageError {
+    pub mse: f64,
+    /// Squared error relative to the squared reference, so that the dark areas count as much as
+    /// the bright ones
+    pub rel_mse: f64,
+}
+
+impl ImageError {
+    /// Added to the squared reference in the relative error, so that black pixels don't divide by 0
+    const EPSILON: f64 = 1e-2;
+
+    pub fn between(image: &Rgb32FImage, reference: &Rgb32FImage) -> Result<Self> {
+        if image.dimensions() != reference.dimensions() {
+            anyhow::bail!(
+                "the reference is {}x{} but the render is {}x{}",
+                reference.width(),
+                reference.height(),
+                image.width(),
+                image.height()
+            );
+        }
+
+        let (mut se, mut rel_se) = (0.0, 0.0);
+        for (p, q) in image.pixels().zip(reference.pixels()) {
+            for (&c, &r) in p.0.iter().zip(&q.0) {
+                let (c, r) = (c as f64, r as f64);
+                se += (c - r).powi(2);
+                rel_se += (c - r).powi(2) / (r * r + Self::EPSILON);
+            }
+        }
+        // The number of values, 3 per pixel
+        let n = image.len() as f64;
+        Ok(Self {
+            mse: se / n,
+            rel_mse: rel_se / n,
+        })
+    }
+
+    pub fn rmse(&self) -> f64 {
+        self.mse.sqrt()
+    }
+}
+
+/// Logs the error of the color of the render against a reference image
+pub struct ErrorReport {
+    pub reference: PathBuf,
+}
+
+impl FinalOutput for ErrorReport {
+    fn commit(&self, output_buffers: &OutputBuffers) -> Result<()> {
+        let Some(color) = output_buffers.channels.iter().find_map(|c| match c {
+            Channel::RgbChannel(RgbChannel::Color, color) => Some(color),
+            _ => None,
+        }) else {
+            anyhow::bail!("there is no color to compare to the reference");
+        };
+        let reference = image::open(&self.reference)?.into_rgb32f();
+
+        let error = ImageError::between(color, &reference)?;
+        log::info!(
+            "error against {}: RMSE {:.6}, MSE {:.6}, relMSE {:.6}",
+            self.reference.display(),
+            error.rmse(),
+            error.mse,
+            error.rel_mse
+        );
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{ImageBuffer, Rgb};
+
+    use super::ImageError;
+
+    #[test]
+    fn image_error() {
+        let reference = ImageBuffer::from_fn(8, 4, |x, y| Rgb([x as f32, y as f32, 0.5]));
+        let error = ImageError::between(&reference, &reference).unwrap();
+        assert_eq!(error.mse, 0.0);
+        assert_eq!(error.rel_mse, 0.0);
+
+        // Twice as bright, the error of each value is the value
+        let scaled = ImageBuffer::from_fn(8, 4, |x, y| {
+            Rgb(reference.get_pixel(x, y).0.map(|c| 2.0 * c))
+        });
+        let values = reference.pixels().flat_map(|p| p.0).map(|c| c as f64);
+        let expected = values.clone().map(|c| c * c).sum::<f64>() / 96.0;
+        let expected_rel = values.map(|c| c * c / (c * c + 1e-2)).sum::<f64>() / 96.0;
+        let error = ImageError::between(&scaled, &reference).unwrap();
+        assert!(
+            (error.mse - expected).abs() < 1e-9,
+            "{} != {expected}",
+            error.mse
+        );
+        assert!((error.rel_mse - expected_rel).abs() < 1e-9);
+        assert!((error.rmse() - expected.sqrt()).abs() < 1e-9);
+
+        let smaller = ImageBuffer::from_pixel(4, 4, Rgb([0.0; 3]));
+        assert!(ImageError::between(&smaller, &reference).is_err());
+    }
+}
@@ -1,12 +1,24 @@
 use anyhow::Result;
-use image::{buffer::ConvertBuffer, ImageBuffer, Rgb};
-use std::path::PathBuf;
+use exr::prelude::WritableImage;
+use exr::prelude::{AnyChannel, AnyChannels, Encoding, FlatSamples, Image, Layer, LayerAttributes};
+use image::{buffer::ConvertBuffer, ImageBuffer, Pixel, Rgb, Rgb32FImage, Rgba, Rgba32FImage};
+use rt::renderer::{Channel, LumaChannel, RgbChannel};
+use std::{
+    fmt::Display,
+    path::{Path, PathBuf},
+};
 
-use super::{FinalOutput, OutputBuffers};
+use super::{FinalOutput, OutputBuffers, RenderMetadata};
 
 pub struct FileOutput {
     pub hdr_outdir: Option<PathBuf>,
     pub ldr_outdir: Option<PathBuf>,
+    /// Prefix of the file names, followed by the channel unless it has a `{channel}` placeholder
+    pub name: Option<String>,
+    /// When rendering an animation, the index of the frame is added to the file names
+    pub frame: Option<u32>,
+    /// Saved in the HDR images
+    pub metadata: Option<RenderMetadata>,
 }
 
 impl FileOutput {
@@ -14,8 +26,62 @@ impl FileOutput {
         Self {
             hdr_outdir: Some("output/hdr/".into()),
             ldr_outdir: Some("output/ldr/".into()),
+            name: None,
+            frame: None,
+            metadata: None,
         }
     }
+
+    fn file_name(&self, channel: impl Display, extension: &str) -> String {
+        file_name(self.name.as_deref(), self.frame, channel, extension)
+    }
+}
+
+/// Name of the image of a channel, see [FileOutput::name]. When rendering an animation the index
+/// of the frame is added
+pub(super) fn file_name(
+    name: Option<&str>,
+    frame: Option<u32>,
+    channel: impl Display,
+    extension: &str,
+) -> String {
+    let stem = match name {
+        Some(name) if name.contains("{channel}") => {
+            expand_template(name, &[("channel", channel.to_string())])
+        }
+        Some(name) => format!("{name}-{channel}"),
+        None => channel.to_string(),
+    };
+    match frame {
+        Some(frame) => format!("{stem}-{frame:04}.{extension}"),
+        None => format!("{stem}.{extension}"),
+    }
+}
+
+/// Replaces the `{key}` placeholders of `template` by their values. The placeholders without a
+/// value are left as they are
+pub fn expand_template(template: &str, values: &[(&str, String)]) -> String {
+    let mut expanded = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        expanded += &rest[..start];
+        rest = &rest[start..];
+        let value = rest.find('}').and_then(|end| {
+            let (_, value) = values.iter().find(|(key, _)| *key == &rest[1..end])?;
+            Some((value, end))
+        });
+        match value {
+            Some((value, end)) => {
+                expanded += value;
+                rest = &rest[end + 1..];
+            }
+            None => {
+                expanded.push('{');
+                rest = &rest[1..];
+            }
+        }
+    }
+    expanded + rest
 }
 
 impl FinalOutput for FileOutput {
@@ -25,32 +91,65 @@ impl FinalOutput for FileOutput {
             let hdr_path = hdr_output.as_path();
             std::fs::create_dir_all(hdr_output)?;
 
+            let alpha = output_buffers.channels.iter().find_map(|buff| match buff {
+                Channel::LumaChannel(LumaChannel::Alpha, c) => Some(c),
+                _ => None,
+            });
+
             log::info!("Saving HDR images...");
+            let metadata = self.metadata.as_ref();
             for buff in &output_buffers.channels {
                 match buff {
-                    rt::renderer::Channel::RgbChannel(chan, c) => {
-                        c.save(hdr_path.join(chan.to_string() + ".exr"))
+                    // The color is saved along with the alpha in an RGBA image
+                    rt::renderer::Channel::RgbChannel(chan @ RgbChannel::Color, c)
+                        if alpha.is_some() =>
+                    {
+                        save_exr(
+                            &with_alpha(c, alpha.unwrap()),
+                            &hdr_path.join(self.file_name(chan, "exr")),
+                            metadata,
+                        )
                     }
-                    rt::renderer::Channel::LumaChannel(chan, c) => {
-                        convert_luma(c).save(hdr_path.join(chan.to_string() + ".exr"))
+                    rt::renderer::Channel::RgbChannel(chan, c) => {
+                        save_exr(c, &hdr_path.join(self.file_name(chan, "exr")), metadata)
                     }
+                    rt::renderer::Channel::LumaChannel(chan, c) => save_exr(
+                        &convert_luma(c),
+                        &hdr_path.join(self.file_name(chan, "exr")),
+                        metadata,
+                    ),
                 }?
             }
         }
         if let Some(ref ldr_output) = self.ldr_outdir {
-            let convert_luma = ConvertBuffer::<ImageBuffer<Rgb<u8>, Vec<u8>>>::convert;
-            let convert_rgb = ConvertBuffer::<ImageBuffer<Rgb<u8>, Vec<u8>>>::convert;
+            let convert_luma = |c: &ImageBuffer<image::Luma<f32>, Vec<f32>>| {
+                dithered::<_, Rgb<u8>>(&ConvertBuffer::<Rgb32FImage>::convert(c))
+            };
+            let convert_rgb = dithered::<_, Rgb<u8>>;
+            let convert_rgba = dithered::<_, Rgba<u8>>;
             let ldr_path = ldr_output.as_path();
             std::fs::create_dir_all(ldr_output)?;
 
+            let alpha = output_buffers.channels.iter().find_map(|buff| match buff {
+                Channel::LumaChannel(LumaChannel::Alpha, c) => Some(c),
+                _ => None,
+            });
+
             log::info!("Saving LDR images...");
             for buff in &output_buffers.channels {
                 match buff {
+                    // jpeg has no alpha, so the color goes to a png
+                    rt::renderer::Channel::RgbChannel(chan @ RgbChannel::Color, c)
+                        if alpha.is_some() =>
+                    {
+                        convert_rgba(&with_alpha(c, alpha.unwrap()))
+                            .save(ldr_path.join(self.file_name(chan, "png")))
+                    }
                     rt::renderer::Channel::RgbChannel(chan, c) => {
-                        convert_rgb(c).save(ldr_path.join(chan.to_string() + ".jpeg"))
+                        convert_rgb(c).save(ldr_path.join(self.file_name(chan, "jpeg")))
                     }
                     rt::renderer::Channel::LumaChannel(chan, c) => {
-                        convert_luma(c).save(ldr_path.join(chan.to_string() + ".jpeg"))
+                        convert_luma(c).save(ldr_path.join(self.file_name(chan, "jpeg")))
                     }
                 }?
             }
@@ -58,3 +157,88 @@ impl FinalOutput for FileOutput {
         Ok(())
     }
 }
+
+/// Saves an RGB or RGBA image in an EXR file, along with the metadata of the render
+fn save_exr<P: Pixel<Subpixel = f32>>(
+    image: &ImageBuffer<P, Vec<f32>>,
+    path: &Path,
+    metadata: Option<&RenderMetadata>,
+) -> Result<()> {
+    let names = &["R", "G", "B", "A"][..P::CHANNEL_COUNT as usize];
+    let channels = names
+        .iter()
+        .enumerate()
+        .map(|(index, &name)| {
+            let samples = image.pixels().map(|p| p.channels()[index]).collect();
+            AnyChannel::new(name, FlatSamples::F32(samples))
+        })
+        .collect();
+    let mut layer = Layer::new(
+        (image.width() as usize, image.height() as usize),
+        LayerAttributes::default(),
+        Encoding::FAST_LOSSLESS,
+        AnyChannels::sort(channels),
+    );
+    if let Some(metadata) = metadata {
+        layer.attributes.other.extend(metadata.attributes());
+    }
+    Image::from_layer(layer).write().to_file(path)?;
+    Ok(())
+}
+
+/// The 8 bits version of an image, dithered so that the smooth gradients don't get banded. `P`
+/// and `Q` must have the same channels
+fn dithered<P: Pixel<Subpixel = f32>, Q: Pixel<Subpixel = u8>>(
+    image: &ImageBuffer<P, Vec<f32>>,
+) -> ImageBuffer<Q, Vec<u8>> {
+    ImageBuffer::from_fn(image.width(), image.height(), |x, y| {
+        let values = image
+            .get_pixel(x, y)
+            .channels()
+            .iter()
+            .map(|&c| rt::color::dither_to_byte(c, x, y))
+            .collect::<Vec<_>>();
+        *Q::from_slice(&values)
+    })
+}
+
+fn with_alpha(rgb: &Rgb32FImage, alpha: &ImageBuffer<image::Luma<f32>, Vec<f32>>) -> Rgba32FImage {
+    ImageBuffer::from_fn(rgb.width(), rgb.height(), |x, y| {
+        let [r, g, b] = rgb.get_pixel(x, y).0;
+        Rgba([r, g, b, alpha.get_pixel(x, y).0[0]])
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{expand_template, file_name};
+
+    #[test]
+    fn templates() {
+        let values = [
+            ("scene", "cornell-box".to_string()),
+            ("spp", "64".to_string()),
+            ("seed", "3".to_string()),
+        ];
+        assert_eq!(
+            expand_template("out/{scene}_{spp}spp_{seed}", &values),
+            "out/cornell-box_64spp_3"
+        );
+        // Unknown and unclosed placeholders are kept
+        assert_eq!(
+            expand_template("{scene}_{integrator}_{spp", &values),
+            "cornell-box_{integrator}_{spp"
+        );
+        assert_eq!(expand_template("{{seed}}", &values), "{3}");
+
+        assert_eq!(file_name(None, None, "color", "exr"), "color.exr");
+        assert_eq!(
+            file_name(Some("run_{seed}"), Some(2), "normal", "png"),
+            "run_{seed}-normal-0002.png"
+        );
+        assert_eq!(
+            file_name(Some("{channel}_run"), None, "albedo", "exr"),
+            "albedo_run.exr"
+        );
+    }
+}
@@ -0,0 +1,135 @@
+use std::hash::{Hash, Hasher};
+
+use exr::meta::attribute::{AttributeValue, Text};
+use rt::{
+    camera::Camera, loader::hash_obj, math::transform::Transform, utils::stable_hash::StableHasher,
+};
+
+use crate::Args;
+
+/// What an image is rendered from, saved in the EXR images to render them again
+#[derive(Debug, Clone)]
+pub struct RenderMetadata {
+    pub seed: u64,
+    /// The range of samples that was rendered
+    pub samples: String,
+    pub integrator: String,
+    /// See [scene_hash]
+    pub scene_hash: u64,
+}
+
+impl RenderMetadata {
+    pub fn new(args: &Args, seed: u64, samples: String, camera: &Camera) -> Self {
+        let scene_hash = scene_hash(args, camera).unwrap_or_else(|err| {
+            log::warn!("can't hash the scene file: {err}");
+            0
+        });
+        Self {
+            seed,
+            samples,
+            integrator: format!("{:?}", args.integrator),
+            scene_hash,
+        }
+    }
+
+    /// The attributes of the EXR headers
+    pub fn attributes(&self) -> impl Iterator<Item = (Text, AttributeValue)> {
+        [
+            ("rt.seed", self.seed.to_string()),
+            ("rt.samples", self.samples.clone()),
+            ("rt.integrator", self.integrator.clone()),
+            ("rt.sceneHash", format!("{:016x}", self.scene_hash)),
+        ]
+        .into_iter()
+        .map(|(name, value)| {
+            (
+                Text::from(name),
+                AttributeValue::Text(Text::from(value.as_str())),
+            )
+        })
+    }
+}
+
+/// Hash of the scene and of the camera, it only changes with them. A scene file is hashed along
+/// with its MTL files, the built-in scenes are only known by their name
+pub fn scene_hash(args: &Args, camera: &Camera) -> std::io::Result<u64> {
+    let mut hasher = StableHasher::default();
+    match &args.scene_file {
+        Some(path) => hash_obj(path, &Transform::default(), &mut hasher)?,
+        None => format!("{:?}", args.scene).hash(&mut hasher),
+    }
+    format!("{:?}", args.override_material).hash(&mut hasher);
+    args.fog_density.map(f32::to_bits).hash(&mut hasher);
+
+    let Camera {
+        aperture,
+        focal_length,
+        width,
+        height,
+        viewport_half_height,
+        viewport_half_width,
+        center_of_lens,
+        rotation,
+        shift,
+    } = camera;
+    (width, height).hash(&mut hasher);
+    for c in [
+        *aperture,
+        *focal_length,
+        *viewport_half_height,
+        *viewport_half_width,
+    ]
+    .into_iter()
+    .chain(center_of_lens.vec().to_array())
+    .chain(rotation.to_array())
+    .chain(shift.to_array())
+    {
+        c.to_bits().hash(&mut hasher);
+    }
+    Ok(hasher.finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+    use rt::camera::Camera;
+
+    use crate::{utils::FromArgs, Args};
+
+    use super::scene_hash;
+
+    #[test]
+    fn scene_hash_changes_with_the_scene() {
+        let dir = std::env::temp_dir().join(format!("rt-scene-hash-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let obj = dir.join("triangle.obj");
+        std::fs::write(
+            &obj,
+            "mtllib triangle.mtl\nusemtl red\nv 0 0 -1\nv 1 0 -1\nv 0 1 -1\nf 1 2 3\n",
+        )
+        .unwrap();
+        let mtl = dir.join("triangle.mtl");
+        std::fs::write(&mtl, "newmtl red\nKd 0.8 0.1 0.1\n").unwrap();
+
+        let hash = |args: &[&str]| {
+            let args = Args::parse_from(
+                ["rt", "--scene-file", obj.to_str().unwrap()]
+                    .iter()
+                    .chain(args),
+            );
+            scene_hash(&args, &Camera::from_args(&args)).unwrap()
+        };
+
+        let reference = hash(&[]);
+        assert_eq!(reference, hash(&[]));
+        // The samples are not part of the scene
+        assert_eq!(reference, hash(&["--seed", "3", "--spp", "4"]));
+        assert_ne!(reference, hash(&["-d", "400x300"]));
+        assert_ne!(reference, hash(&["--override-material", "red=glass"]));
+
+        std::fs::write(&mtl, "newmtl red\nKd 0.1 0.8 0.1\n").unwrap();
+        assert_ne!(reference, hash(&[]));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
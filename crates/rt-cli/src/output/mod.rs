@@ -1,16 +1,24 @@
+mod error_report;
 mod file_output;
+mod metadata;
+mod outline;
 mod tev_streaming;
+mod tiled_exr;
 
 use core::panic;
 
 use anyhow::Result;
-pub use file_output::FileOutput;
+pub use error_report::ErrorReport;
+pub use file_output::{expand_template, FileOutput};
 use image::{ImageBuffer, Rgb32FImage};
+pub use metadata::RenderMetadata;
+pub use outline::Outline;
 use rt::{
     color::{ColorspaceConversion, Luma, Rgb},
     renderer::{Channel, GenericRenderResult, PixelRenderResult},
 };
 pub use tev_streaming::TevStreaming;
+pub use tiled_exr::TiledExrOutput;
 
 use crate::{executor::TileMsg, utils::Dimensions};
 
@@ -61,6 +69,11 @@ impl OutputBuffersExt<Rgb, Luma> for OutputBuffers {
 
 pub trait StreamingOutput: Send {
     fn send_msg(&mut self, msg: &TileMsg) -> Result<()>;
+
+    /// Called once the render is over
+    fn finish(&mut self) -> Result<()> {
+        Ok(())
+    }
 }
 
 pub struct DummyOutput {}
@@ -0,0 +1,177 @@
+use image::{ImageBuffer, Rgb};
+use rt::renderer::{Channel, LumaChannel, RgbChannel};
+
+use crate::{utils::FromArgs, Args};
+
+use super::OutputBuffers;
+
+/// Draws black outlines on the color where the normal or the depth of the image change abruptly,
+/// found with a Sobel filter. It only applies to the final outputs
+#[derive(Debug, Clone, Copy)]
+pub struct Outline {
+    /// Magnitude of the gradient of the normal above which there is an edge
+    pub normal_threshold: f32,
+    /// Magnitude of the gradient of the depth relative to the depth above which there is an edge
+    pub depth_threshold: f32,
+}
+
+impl FromArgs for Option<Outline> {
+    fn from_args(args: &Args) -> Self {
+        args.outline.then_some(Outline {
+            normal_threshold: args.outline_normal_threshold,
+            depth_threshold: args.outline_depth_threshold,
+        })
+    }
+}
+
+/// Sobel gradient of `f` at (x, y), the pixels outside of the image are clamped to the border
+fn sobel<const N: usize>(
+    x: u32,
+    y: u32,
+    (width, height): (u32, u32),
+    f: impl Fn(u32, u32) -> [f32; N],
+) -> [[f32; 2]; N] {
+    let at = |dx: i32, dy: i32| {
+        let x = (x as i32 + dx).clamp(0, width as i32 - 1) as u32;
+        let y = (y as i32 + dy).clamp(0, height as i32 - 1) as u32;
+        f(x, y)
+    };
+    const KERNEL: [(i32, f32); 3] = [(-1, 1.0), (0, 2.0), (1, 1.0)];
+
+    let mut gradient = [[0.0; 2]; N];
+    for (d, w) in KERNEL {
+        let (left, right) = (at(-1, d), at(1, d));
+        let (top, bottom) = (at(d, -1), at(d, 1));
+        for i in 0..N {
+            gradient[i][0] += w * (right[i] - left[i]);
+            gradient[i][1] += w * (bottom[i] - top[i]);
+        }
+    }
+    gradient
+}
+
+fn magnitude<const N: usize>(gradient: [[f32; 2]; N]) -> f32 {
+    gradient
+        .iter()
+        .map(|[gx, gy]| gx * gx + gy * gy)
+        .sum::<f32>()
+        .sqrt()
+}
+
+impl Outline {
+    /// The mask of the pixels on an edge of the normals or of the depth
+    fn edges(&self, buffers: &OutputBuffers) -> Option<ImageBuffer<image::Luma<u8>, Vec<u8>>> {
+        let normal = buffers.channels.iter().find_map(|c| match c {
+            Channel::RgbChannel(RgbChannel::Normal, c) => Some(c),
+            _ => None,
+        });
+        let z = buffers.channels.iter().find_map(|c| match c {
+            Channel::LumaChannel(LumaChannel::Z, c) => Some(c),
+            _ => None,
+        });
+        let dimensions = normal
+            .map(|c| c.dimensions())
+            .or(z.map(|c| c.dimensions()))?;
+
+        Some(ImageBuffer::from_fn(dimensions.0, dimensions.1, |x, y| {
+            let normal_edge = normal.is_some_and(|normal| {
+                let gradient = sobel(x, y, dimensions, |x, y| normal.get_pixel(x, y).0);
+                magnitude(gradient) > self.normal_threshold
+            });
+            let depth_edge = z.is_some_and(|z| {
+                let gradient = sobel(x, y, dimensions, |x, y| z.get_pixel(x, y).0);
+                let depth = z.get_pixel(x, y).0[0].abs().max(f32::EPSILON);
+                magnitude(gradient) > self.depth_threshold * depth
+            });
+            image::Luma([(normal_edge || depth_edge) as u8])
+        }))
+    }
+
+    pub fn apply(&self, buffers: &mut OutputBuffers) {
+        let Some(edges) = self.edges(buffers) else {
+            log::warn!("no normal or depth to outline");
+            return;
+        };
+        for channel in &mut buffers.channels {
+            if let Channel::RgbChannel(RgbChannel::Color, color) = channel {
+                for (x, y, edge) in edges.enumerate_pixels() {
+                    if edge.0[0] != 0 {
+                        *color.get_pixel_mut(x, y) = Rgb([0.0; 3]);
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{ImageBuffer, Luma, Rgb};
+    use rt::renderer::{Channel, LumaChannel, RgbChannel};
+
+    use crate::output::OutputBuffers;
+
+    use super::Outline;
+
+    fn buffers(
+        normal: impl Fn(u32, u32) -> [f32; 3],
+        z: impl Fn(u32, u32) -> f32,
+    ) -> OutputBuffers {
+        OutputBuffers {
+            channels: vec![
+                Channel::RgbChannel(
+                    RgbChannel::Color,
+                    ImageBuffer::from_pixel(16, 16, Rgb([0.5, 0.6, 0.7])),
+                ),
+                Channel::RgbChannel(
+                    RgbChannel::Normal,
+                    ImageBuffer::from_fn(16, 16, |x, y| Rgb(normal(x, y))),
+                ),
+                Channel::LumaChannel(
+                    LumaChannel::Z,
+                    ImageBuffer::from_fn(16, 16, |x, y| Luma([z(x, y)])),
+                ),
+            ],
+        }
+    }
+
+    fn outlined(buffers: &OutputBuffers) -> usize {
+        let Channel::RgbChannel(_, color) = &buffers.channels[0] else {
+            unreachable!()
+        };
+        color.pixels().filter(|p| p.0 == [0.0; 3]).count()
+    }
+
+    #[test]
+    fn outline() {
+        let outline = Outline {
+            normal_threshold: 0.5,
+            depth_threshold: 0.1,
+        };
+
+        // A plane seen from the front, then tilted so that its depth changes smoothly
+        let mut flat = buffers(|_, _| [0.0, 0.0, 1.0], |_, _| 2.0);
+        outline.apply(&mut flat);
+        assert_eq!(outlined(&flat), 0);
+        let mut tilted = buffers(|_, _| [0.0, 0.6, 0.8], |_, y| 2.0 + 0.01 * y as f32);
+        outline.apply(&mut tilted);
+        assert_eq!(outlined(&tilted), 0);
+
+        // A fold in the middle, then a step in the depth
+        let mut fold = buffers(
+            |x, _| {
+                if x < 8 {
+                    [0.0, 0.0, 1.0]
+                } else {
+                    [1.0, 0.0, 0.0]
+                }
+            },
+            |_, _| 2.0,
+        );
+        outline.apply(&mut fold);
+        assert_eq!(outlined(&fold), 2 * 16);
+        let mut step = buffers(|_, _| [0.0, 0.0, 1.0], |_, y| if y < 8 { 2.0 } else { 4.0 });
+        outline.apply(&mut step);
+        assert!(outlined(&step) > 0);
+    }
+}
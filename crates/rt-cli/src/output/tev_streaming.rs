@@ -1,19 +1,150 @@
-use std::path::PathBuf;
+use std::{
+    collections::BTreeMap,
+    io,
+    path::PathBuf,
+    time::{Duration, Instant},
+};
 
-use anyhow::{Context, Result};
+use anyhow::Result;
 use rand::{distributions::Alphanumeric, Rng};
-use rt::renderer::RgbChannel;
-use tev_client::{PacketCreateImage, PacketUpdateImage, TevClient};
+use rt::renderer::{LumaChannel, RgbChannel};
+use tev_client::{PacketCloseImage, PacketCreateImage, PacketUpdateImage, TevClient};
 
-use crate::{executor::TileMsg, Dimensions};
+use crate::{executor::TileMsg, tile::Tile, Dimensions};
 
 use super::StreamingOutput;
 
+/// The values of the channels of the pixels of a tile, interleaved
+pub struct TileData {
+    pub tile: Tile,
+    pub data: Vec<f32>,
+}
+
+/// Where the images go, a [TevClient] or a mock in the tests
+pub trait TevConnection: Send {
+    fn close_image(&mut self, image_name: &str) -> io::Result<()>;
+    fn create_image(
+        &mut self,
+        image_name: &str,
+        channel_names: &[String],
+        dimension: Dimensions,
+    ) -> io::Result<()>;
+    fn update_image(
+        &mut self,
+        image_name: &str,
+        channel_names: &[String],
+        tile: &TileData,
+    ) -> io::Result<()>;
+}
+
+impl TevConnection for TevClient {
+    fn close_image(&mut self, image_name: &str) -> io::Result<()> {
+        self.send(PacketCloseImage { image_name })
+    }
+
+    fn create_image(
+        &mut self,
+        image_name: &str,
+        channel_names: &[String],
+        dimension: Dimensions,
+    ) -> io::Result<()> {
+        self.send(PacketCreateImage {
+            image_name,
+            grab_focus: true,
+            channel_names,
+            width: dimension.width,
+            height: dimension.height,
+        })
+    }
+
+    fn update_image(
+        &mut self,
+        image_name: &str,
+        channel_names: &[String],
+        TileData { tile, data }: &TileData,
+    ) -> io::Result<()> {
+        let channel_offsets = (0..channel_names.len() as u64).collect::<Vec<_>>();
+        let channel_strides = vec![channel_names.len() as u64; channel_names.len()];
+        self.send(PacketUpdateImage {
+            image_name,
+            grab_focus: false,
+            channel_names,
+            channel_offsets: &channel_offsets,
+            channel_strides: &channel_strides,
+            x: tile.x_start,
+            y: tile.y_start,
+            width: tile.width() as u32,
+            height: tile.height() as u32,
+            data,
+        })
+    }
+}
+
+/// Exponential backoff between the attempts to reconnect
+#[derive(Debug, Clone, Copy)]
+pub struct Backoff {
+    /// Delay after the first failure, it doubles after each of the next ones
+    pub base: Duration,
+    pub max: Duration,
+    failures: u32,
+    next_try: Option<Instant>,
+}
+
+impl Backoff {
+    pub fn new(base: Duration, max: Duration) -> Self {
+        Self {
+            base,
+            max,
+            failures: 0,
+            next_try: None,
+        }
+    }
+
+    pub fn ready(&self, now: Instant) -> bool {
+        self.next_try.is_none_or(|next_try| now >= next_try)
+    }
+
+    /// Delay before the next attempt, given the failures so far
+    pub fn delay(&self) -> Duration {
+        match self.failures {
+            0 => Duration::ZERO,
+            failures => self
+                .base
+                .saturating_mul(1 << (failures - 1).min(16))
+                .min(self.max),
+        }
+    }
+
+    pub fn failed(&mut self, now: Instant) {
+        self.failures += 1;
+        self.next_try = Some(now + self.delay());
+    }
+
+    pub fn succeeded(&mut self) {
+        self.failures = 0;
+        self.next_try = None;
+    }
+}
+
+type Connector = Box<dyn FnMut() -> Result<Box<dyn TevConnection>> + Send>;
+
+/// Streams the tiles to tev as they are rendered.
+///
+/// When tev goes away, the tiles are kept and the connection is tried again with an exponential
+/// backoff. Once reconnected, the image is closed and created again, so that no stale layer of
+/// the same name lingers, and all the tiles so far are sent back.
 pub struct TevStreaming {
-    client: TevClient,
+    connection: Option<Box<dyn TevConnection>>,
+    connect: Connector,
+    backoff: Backoff,
     image_name: String,
+    /// The image is created on the current connection
     opened: bool,
     dimension: Dimensions,
+    /// Known from the first tile
+    channel_names: Vec<String>,
+    /// The last data of each tile, by its start
+    tiles: BTreeMap<(u32, u32), TileData>,
 }
 
 impl TevStreaming {
@@ -38,15 +169,16 @@ impl TevStreaming {
             std::thread::sleep(std::time::Duration::from_secs(2));
             Ok(())
         };
-        let try_connect = || -> Result<TevClient> {
-            Ok(TevClient::wrap(std::net::TcpStream::connect(
-                &tev_hostname,
-            )?))
+        let hostname = tev_hostname.clone();
+        let try_connect = move || -> Result<Box<dyn TevConnection>> {
+            Ok(Box::new(TevClient::wrap(std::net::TcpStream::connect(
+                &hostname,
+            )?)))
         };
 
         log::debug!("Trying tev direct connection");
-        let client = match try_connect() {
-            Ok(client) => client,
+        let connection = match try_connect() {
+            Ok(connection) => connection,
             Err(_) => {
                 log::warn!("Can't find tev client, trying to spawn tev");
                 try_spawn(tev_path.into())?;
@@ -64,12 +196,83 @@ impl TevStreaming {
         }
         let image_name = format!("raytraced-{}", get_id());
 
-        Ok(Self {
-            client,
+        Ok(Self::with_connection(
+            dimension,
+            image_name,
+            connection,
+            Box::new(try_connect),
+            Backoff::new(Duration::from_millis(250), Duration::from_secs(10)),
+        ))
+    }
+
+    /// `connect` opens a new connection once `connection` is lost
+    pub fn with_connection(
+        dimension: Dimensions,
+        image_name: String,
+        connection: Box<dyn TevConnection>,
+        connect: Connector,
+        backoff: Backoff,
+    ) -> Self {
+        Self {
+            connection: Some(connection),
+            connect,
+            backoff,
             image_name,
             opened: false,
             dimension,
-        })
+            channel_names: Vec::new(),
+            tiles: BTreeMap::new(),
+        }
+    }
+
+    /// Whether there is a connection, a new one is tried if the backoff allows it
+    fn reconnect(&mut self) -> bool {
+        if self.connection.is_some() {
+            return true;
+        }
+        let now = Instant::now();
+        if !self.backoff.ready(now) {
+            return false;
+        }
+
+        match (self.connect)() {
+            Ok(connection) => {
+                log::info!("Reconnected to tev");
+                self.connection = Some(connection);
+                self.opened = false;
+                self.backoff.succeeded();
+                true
+            }
+            Err(err) => {
+                self.backoff.failed(now);
+                log::debug!(
+                    "Can't reconnect to tev: {err}, next try in {:?}",
+                    self.backoff.delay()
+                );
+                false
+            }
+        }
+    }
+
+    /// Sends the tile, or the whole image if it is not created yet on this connection
+    fn send_tile(&mut self, key: (u32, u32)) -> io::Result<()> {
+        let connection = self.connection.as_mut().unwrap();
+        if self.opened {
+            return connection.update_image(
+                &self.image_name,
+                &self.channel_names,
+                &self.tiles[&key],
+            );
+        }
+
+        // Tev may still have the image of a previous connection
+        connection.close_image(&self.image_name)?;
+        connection.create_image(&self.image_name, &self.channel_names, self.dimension)?;
+        for tile in self.tiles.values() {
+            connection.update_image(&self.image_name, &self.channel_names, tile)?;
+        }
+        self.opened = true;
+        Ok(())
     }
 }
 
@@ -81,31 +284,30 @@ impl StreamingOutput for TevStreaming {
 
         assert!(msg.data.len() == msg.tile.len());
 
-        let mut channel_names = Vec::new();
-        let mut channel_offsets = Vec::<u64>::new();
-        for channel in &msg.data[0].channels {
-            match channel {
-                rt::renderer::Channel::RgbChannel(name, _) => {
-                    if *name != RgbChannel::Color {
-                        channel_names.push(name.to_string() + ".X");
-                        channel_names.push(name.to_string() + ".Y");
-                        channel_names.push(name.to_string() + ".Z");
-                    } else {
-                        channel_names.push("R".into());
-                        channel_names.push("G".into());
-                        channel_names.push("B".into());
+        if self.channel_names.is_empty() {
+            for channel in &msg.data[0].channels {
+                match channel {
+                    rt::renderer::Channel::RgbChannel(name, _) => {
+                        if *name != RgbChannel::Color {
+                            self.channel_names.push(name.to_string() + ".X");
+                            self.channel_names.push(name.to_string() + ".Y");
+                            self.channel_names.push(name.to_string() + ".Z");
+                        } else {
+                            self.channel_names.push("R".into());
+                            self.channel_names.push("G".into());
+                            self.channel_names.push("B".into());
+                        }
+                    }
+                    rt::renderer::Channel::LumaChannel(name, _) => {
+                        if *name != LumaChannel::Alpha {
+                            self.channel_names.push(name.to_string());
+                        } else {
+                            self.channel_names.push("A".into());
+                        }
                     }
-                    channel_offsets.push(channel_offsets.len() as _);
-                    channel_offsets.push(channel_offsets.len() as _);
-                    channel_offsets.push(channel_offsets.len() as _);
-                }
-                rt::renderer::Channel::LumaChannel(name, _) => {
-                    channel_names.push(name.to_string());
-                    channel_offsets.push(channel_offsets.len() as _);
                 }
             }
         }
-        let channel_strides = vec![channel_offsets.len() as u64; channel_offsets.len()];
 
         let mut data = Vec::new();
         for p in &msg.data {
@@ -117,31 +319,190 @@ impl StreamingOutput for TevStreaming {
                 }
             }
         }
+        let key = (msg.tile.x_start, msg.tile.y_start);
+        self.tiles.insert(
+            key,
+            TileData {
+                tile: msg.tile,
+                data,
+            },
+        );
 
-        if !self.opened {
-            self.client.send(PacketCreateImage {
-                image_name: &self.image_name,
-                grab_focus: true,
-                channel_names: &channel_names,
-                width: self.dimension.width,
-                height: self.dimension.height,
-            })?;
-            self.opened = true;
-        }
-
-        self.client
-            .send(PacketUpdateImage {
-                image_name: &self.image_name,
-                grab_focus: false,
-                channel_names: &channel_names,
-                channel_offsets: &channel_offsets,
-                channel_strides: &channel_strides,
-                x: msg.tile.x_start,
-                y: msg.tile.y_start,
-                width: msg.tile.width() as u32,
-                height: msg.tile.height() as u32,
-                data: &data,
+        if !self.reconnect() {
+            return Ok(());
+        }
+        if let Err(err) = self.send_tile(key) {
+            log::warn!("Lost the connection to tev: {err}, reconnecting");
+            self.connection = None;
+            self.opened = false;
+            self.backoff.failed(Instant::now());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io,
+        sync::{
+            atomic::{AtomicBool, AtomicUsize, Ordering},
+            Arc, Mutex,
+        },
+        time::{Duration, Instant},
+    };
+
+    use rt::{
+        color::{Luma, Rgb},
+        renderer::{LumaChannel, PixelRenderResult, RgbChannel},
+    };
+
+    use crate::{executor::TileMsg, output::StreamingOutput, tile::Tile, utils::Dimensions};
+
+    use super::{Backoff, TevConnection, TevStreaming, TileData};
+
+    /// Logs the packets, or fails to send them once `broken` is set
+    struct MockConnection {
+        log: Arc<Mutex<Vec<String>>>,
+        broken: Arc<AtomicBool>,
+    }
+
+    impl MockConnection {
+        fn push(&mut self, packet: String) -> io::Result<()> {
+            if self.broken.load(Ordering::SeqCst) {
+                return Err(io::ErrorKind::BrokenPipe.into());
+            }
+            self.log.lock().unwrap().push(packet);
+            Ok(())
+        }
+    }
+
+    impl TevConnection for MockConnection {
+        fn close_image(&mut self, _image_name: &str) -> io::Result<()> {
+            self.push("close".into())
+        }
+        fn create_image(
+            &mut self,
+            _image_name: &str,
+            channel_names: &[String],
+            _dimension: Dimensions,
+        ) -> io::Result<()> {
+            self.push(format!("create {}", channel_names.join(",")))
+        }
+        fn update_image(
+            &mut self,
+            _image_name: &str,
+            _channel_names: &[String],
+            TileData { tile, data }: &TileData,
+        ) -> io::Result<()> {
+            self.push(format!(
+                "update {} {} {}",
+                tile.x_start, tile.y_start, data[0]
+            ))
+        }
+    }
+
+    fn tile(x: u32, value: f32) -> TileMsg {
+        TileMsg {
+            tile: Tile {
+                x_start: x,
+                x_end: x + 2,
+                y_start: 0,
+                y_end: 2,
+            },
+            data: (0..4)
+                .map(|_| PixelRenderResult {
+                    channels: vec![
+                        RgbChannel::Color.channel(Rgb::from_array([value; 3])),
+                        LumaChannel::Alpha.channel(Luma(1.0)),
+                    ],
+                })
+                .collect(),
+            complete: false,
+        }
+    }
+
+    #[test]
+    fn reconnection() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let broken = Arc::new(AtomicBool::new(false));
+        // Number of connection attempts to refuse
+        let refused = Arc::new(AtomicUsize::new(0));
+        let connection = |log: &Arc<Mutex<Vec<String>>>, broken: &Arc<AtomicBool>| {
+            Box::new(MockConnection {
+                log: log.clone(),
+                broken: broken.clone(),
             })
-            .context("Can't send Packet to tev client. It may be closed")
+        };
+
+        let (connect_log, connect_broken, connect_refused) =
+            (log.clone(), broken.clone(), refused.clone());
+        let mut tev = TevStreaming::with_connection(
+            Dimensions {
+                width: 8,
+                height: 2,
+            },
+            "test".into(),
+            connection(&log, &broken),
+            Box::new(move || {
+                if connect_refused.load(Ordering::SeqCst) > 0 {
+                    connect_refused.fetch_sub(1, Ordering::SeqCst);
+                    anyhow::bail!("connection refused");
+                }
+                connect_broken.store(false, Ordering::SeqCst);
+                Ok(connection(&connect_log, &connect_broken))
+            }),
+            Backoff::new(Duration::ZERO, Duration::ZERO),
+        );
+        let take = || std::mem::take(&mut *log.lock().unwrap());
+
+        tev.send_msg(&tile(0, 1.0)).unwrap();
+        tev.send_msg(&tile(2, 2.0)).unwrap();
+        assert_eq!(
+            take(),
+            ["close", "create R,G,B,A", "update 0 0 1", "update 2 0 2"]
+        );
+
+        // Tev goes away, then refuses the first connection
+        broken.store(true, Ordering::SeqCst);
+        refused.store(1, Ordering::SeqCst);
+        tev.send_msg(&tile(0, 3.0)).unwrap();
+        tev.send_msg(&tile(4, 4.0)).unwrap();
+        assert!(take().is_empty());
+
+        // The image is replaced, with the last data of each tile
+        tev.send_msg(&tile(6, 5.0)).unwrap();
+        assert_eq!(
+            take(),
+            [
+                "close",
+                "create R,G,B,A",
+                "update 0 0 3",
+                "update 2 0 2",
+                "update 4 0 4",
+                "update 6 0 5"
+            ]
+        );
+        tev.send_msg(&tile(2, 6.0)).unwrap();
+        assert_eq!(take(), ["update 2 0 6"]);
+    }
+
+    #[test]
+    fn exponential_backoff() {
+        let mut backoff = Backoff::new(Duration::from_millis(100), Duration::from_secs(1));
+        let now = Instant::now();
+        assert!(backoff.ready(now));
+
+        let mut delays = Vec::new();
+        for _ in 0..6 {
+            backoff.failed(now);
+            delays.push(backoff.delay().as_millis());
+        }
+        assert_eq!(delays, [100, 200, 400, 800, 1000, 1000]);
+        assert!(!backoff.ready(now + Duration::from_millis(999)));
+        assert!(backoff.ready(now + Duration::from_secs(1)));
+
+        backoff.succeeded();
+        assert!(backoff.ready(now));
     }
 }
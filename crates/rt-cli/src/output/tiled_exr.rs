@@ -0,0 +1,355 @@
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::BufWriter,
+    path::{Path, PathBuf},
+    sync::mpsc::{self, Sender},
+    thread::JoinHandle,
+};
+
+use anyhow::{anyhow, Result};
+use exr::{
+    block::{self, writer::ChunksWriter, UncompressedBlock},
+    compression::Compression,
+    error::Error,
+    math::{RoundingMode, Vec2},
+    meta::{
+        attribute::{ChannelDescription, LevelMode, LineOrder, SampleType, TileDescription},
+        header::Header,
+        BlockDescription,
+    },
+};
+use rt::{
+    color::{colorspace, ColorspaceConversion},
+    renderer::{Channel, LumaChannel, RgbChannel},
+};
+
+use crate::{executor::TileMsg, tile::Tile, Dimensions};
+
+use super::{file_output::file_name, RenderMetadata, StreamingOutput};
+
+/// Writes the HDR images in tiled OpenEXR files as the tiles are completed, instead of keeping
+/// the whole image in memory. The files hold the same values as the ones of [super::FileOutput].
+///
+/// The tiles of the render must be aligned on the grid of the EXR tiles and cover the whole
+/// image: every tile must be sent exactly once as complete.
+pub struct TiledExrOutput {
+    outdir: PathBuf,
+    /// See [super::FileOutput::name]
+    name: Option<String>,
+    frame: Option<u32>,
+    dimension: Dimensions,
+    tile_size: u32,
+    /// Saved in the headers of the images
+    metadata: Option<RenderMetadata>,
+    /// Opened on the first tile, once the channels are known
+    writers: Vec<TiledExrWriter>,
+}
+
+impl TiledExrOutput {
+    pub fn new(
+        outdir: PathBuf,
+        name: Option<String>,
+        frame: Option<u32>,
+        dimension: Dimensions,
+        tile_size: u32,
+        metadata: Option<RenderMetadata>,
+    ) -> Result<Self> {
+        std::fs::create_dir_all(&outdir)?;
+        Ok(Self {
+            outdir,
+            name,
+            frame,
+            dimension,
+            tile_size,
+            metadata,
+            writers: Vec::new(),
+        })
+    }
+
+    fn open(&mut self, msg: &TileMsg) -> Result<()> {
+        let channels = &msg.data[0].channels;
+        let with_alpha = channels
+            .iter()
+            .any(|c| matches!(c, Channel::LumaChannel(LumaChannel::Alpha, _)));
+
+        for (index, channel) in channels.iter().enumerate() {
+            let (name, samples) = match channel {
+                // The color is saved along with the alpha in an RGBA image
+                Channel::RgbChannel(chan @ RgbChannel::Color, _) if with_alpha => {
+                    (chan.to_string(), 4)
+                }
+                Channel::RgbChannel(chan, _) => (chan.to_string(), 3),
+                Channel::LumaChannel(chan, _) => (chan.to_string(), 3),
+            };
+            let path = self
+                .outdir
+                .join(file_name(self.name.as_deref(), self.frame, name, "exr"));
+            self.writers.push(TiledExrWriter::new(
+                &path,
+                index,
+                samples,
+                self.dimension,
+                self.tile_size,
+                self.metadata.as_ref(),
+            )?);
+        }
+        Ok(())
+    }
+
+    /// Wait for the files to be written
+    fn close(&mut self) -> Result<()> {
+        for writer in self.writers.drain(..) {
+            drop(writer.tiles);
+            writer
+                .thread
+                .join()
+                .map_err(|_| anyhow!("the EXR writer panicked"))??;
+        }
+        Ok(())
+    }
+}
+
+impl StreamingOutput for TiledExrOutput {
+    fn send_msg(&mut self, msg: &TileMsg) -> Result<()> {
+        if !msg.complete {
+            return Ok(());
+        }
+        if self.writers.is_empty() {
+            self.open(msg)?;
+        }
+
+        let alpha = msg.data[0]
+            .channels
+            .iter()
+            .position(|c| matches!(c, Channel::LumaChannel(LumaChannel::Alpha, _)));
+        let mut stopped = false;
+        for writer in &self.writers {
+            let values = msg
+                .data
+                .iter()
+                .flat_map(|pixel| {
+                    let value = pixel_value(&pixel.channels[writer.channel]);
+                    let alpha = alpha.map(|alpha| pixel_value(&pixel.channels[alpha])[0]);
+                    value.into_iter().chain(alpha).take(writer.samples)
+                })
+                .collect();
+            stopped |= writer.tiles.send((msg.tile, values)).is_err();
+        }
+        if stopped {
+            // The writer has failed, get its error back
+            self.close()?;
+            return Err(anyhow!("the EXR writer stopped"));
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        self.close()
+    }
+}
+
+/// The values stored in the images, the colors are in sRGB as in [super::OutputBuffers]
+fn pixel_value(channel: &Channel<rt::color::Rgb, rt::color::Luma>) -> [f32; 3] {
+    match channel {
+        Channel::RgbChannel(_, c) => {
+            ColorspaceConversion::<colorspace::sRGB>::convert(*c).to_array()
+        }
+        Channel::LumaChannel(_, c) => [c.0; 3],
+    }
+}
+
+/// A tiled EXR file being written by its own thread
+struct TiledExrWriter {
+    /// Index of the channel of the render in the file
+    channel: usize,
+    /// Number of samples of each pixel, 3 for RGB or 4 for RGBA
+    samples: usize,
+    /// The tiles with their values, pixel after pixel
+    tiles: Sender<(Tile, Vec<f32>)>,
+    thread: JoinHandle<Result<()>>,
+}
+
+impl TiledExrWriter {
+    fn new(
+        path: &Path,
+        channel: usize,
+        samples: usize,
+        dimension: Dimensions,
+        tile_size: u32,
+        metadata: Option<&RenderMetadata>,
+    ) -> Result<Self> {
+        let file = BufWriter::new(File::create(path)?);
+        let names = &["R", "G", "B", "A"][..samples];
+        // The channels must be sorted by name in the file
+        let mut sorted_names = names.to_vec();
+        sorted_names.sort();
+        let mut header = Header::new(
+            "rt".into(),
+            (dimension.width as usize, dimension.height as usize),
+            sorted_names
+                .iter()
+                .map(|&name| ChannelDescription::named(name, SampleType::F32))
+                .collect(),
+        )
+        .with_encoding(
+            Compression::ZIP16,
+            BlockDescription::Tiles(TileDescription {
+                tile_size: Vec2(tile_size as usize, tile_size as usize),
+                level_mode: LevelMode::Singular,
+                rounding_mode: RoundingMode::Down,
+            }),
+            LineOrder::Unspecified,
+        );
+        if let Some(metadata) = metadata {
+            header.own_attributes.other.extend(metadata.attributes());
+        }
+        let sample_of_channel = header
+            .channels
+            .list
+            .iter()
+            .map(|c| names.iter().position(|&name| c.name == *name).unwrap())
+            .collect::<Vec<_>>();
+
+        let (tiles, rx) = mpsc::channel::<(Tile, Vec<f32>)>();
+        let thread = std::thread::spawn(move || {
+            block::write(
+                file,
+                std::iter::once(header).collect(),
+                true,
+                |meta, writer| {
+                    let blocks = block::enumerate_ordered_header_block_indices(&meta.headers)
+                        .map(|(index, block)| (block.pixel_position, (index, block)))
+                        .collect::<HashMap<_, _>>();
+
+                    for (tile, values) in rx {
+                        let position = Vec2(tile.x_start as usize, tile.y_start as usize);
+                        let Some(&(index, block_index)) = blocks.get(&position) else {
+                            return Err(Error::Invalid(
+                                "the tile is not aligned on the grid".into(),
+                            ));
+                        };
+                        if block_index.pixel_size != Vec2(tile.width(), tile.height()) {
+                            return Err(Error::Invalid("the tile has the wrong size".into()));
+                        }
+
+                        let block = UncompressedBlock::from_lines(
+                            &meta.headers[0].channels,
+                            block_index,
+                            |line| {
+                                let sample = sample_of_channel[line.location.channel];
+                                let row =
+                                    (line.location.position.y() - position.y()) * tile.width();
+                                line.write_samples(|x| values[(row + x) * samples + sample])
+                                    .expect("the line has the width of the tile");
+                            },
+                        );
+                        writer.write_chunk(index, block.compress_to_chunk(&meta.headers)?)?;
+                    }
+                    Ok(())
+                },
+            )?;
+            Ok(())
+        });
+
+        Ok(Self {
+            channel,
+            samples,
+            tiles,
+            thread,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rt::{
+        color::{Luma, Rgb},
+        renderer::{Channel, LumaChannel, PixelRenderResult, RgbChannel},
+    };
+
+    use crate::{
+        executor::TileMsg,
+        output::{FileOutput, FinalOutput, OutputBuffers, OutputBuffersExt, StreamingOutput},
+        tile::Tile,
+        Dimensions,
+    };
+
+    use super::TiledExrOutput;
+
+    fn pixel(x: u32, y: u32) -> PixelRenderResult {
+        let (x, y) = (x as f32, y as f32);
+        PixelRenderResult {
+            channels: vec![
+                Channel::RgbChannel(RgbChannel::Color, Rgb::from_array([x, y, x * y / 7.0])),
+                Channel::RgbChannel(RgbChannel::Normal, Rgb::from_array([0.1, -x, 0.3])),
+                Channel::LumaChannel(LumaChannel::Z, Luma(x + 0.5 * y)),
+                Channel::LumaChannel(LumaChannel::Alpha, Luma(y / 8.0)),
+            ],
+        }
+    }
+
+    #[test]
+    fn streamed_matches_buffered() {
+        let dimension = Dimensions {
+            width: 16,
+            height: 8,
+        };
+        let tile_size = 4;
+        let outdir = std::env::temp_dir().join(format!("rt-tiled-exr-{}", std::process::id()));
+        let (buffered_dir, streamed_dir) = (outdir.join("buffered"), outdir.join("streamed"));
+
+        let mut buffers = OutputBuffers {
+            channels: Vec::new(),
+        };
+        let mut streamed =
+            TiledExrOutput::new(streamed_dir.clone(), None, None, dimension, tile_size, None)
+                .unwrap();
+        // The tiles are sent out of order
+        for y_start in (0..dimension.height).step_by(tile_size as usize).rev() {
+            for x_start in (0..dimension.width).step_by(tile_size as usize) {
+                let tile = Tile {
+                    x_start,
+                    x_end: x_start + tile_size,
+                    y_start,
+                    y_end: y_start + tile_size,
+                };
+                let data = tile.into_iter().map(|(x, y)| pixel(x, y)).collect();
+                let msg = TileMsg {
+                    tile,
+                    data,
+                    complete: true,
+                };
+                for ((x, y), pixel) in tile.into_iter().zip(&msg.data) {
+                    buffers.convert(pixel, x, y, dimension);
+                }
+                streamed.send_msg(&msg).unwrap();
+            }
+        }
+        streamed.finish().unwrap();
+        FileOutput {
+            hdr_outdir: Some(buffered_dir.clone()),
+            ldr_outdir: None,
+            name: None,
+            frame: None,
+            metadata: None,
+        }
+        .commit(&buffers)
+        .unwrap();
+
+        for name in ["Color", "Normal", "Z", "Alpha"] {
+            let file = format!("{name}.exr");
+            let buffered = image::open(buffered_dir.join(&file))
+                .unwrap()
+                .into_rgba32f();
+            let streamed = image::open(streamed_dir.join(&file))
+                .unwrap()
+                .into_rgba32f();
+            assert_eq!(buffered.dimensions(), streamed.dimensions());
+            for (b, s) in buffered.pixels().zip(streamed.pixels()) {
+                assert_eq!(b.0.map(f32::to_bits), s.0.map(f32::to_bits), "{file}");
+            }
+        }
+        std::fs::remove_dir_all(outdir).unwrap();
+    }
+}
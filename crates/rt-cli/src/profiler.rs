@@ -0,0 +1,120 @@
+//! Opt-in profiler of the time spent by each tile in the intersection of the rays and in the
+//! rest of the work, the shading.
+//!
+//! The objects of the world are wrapped in a [TimedShape] that sums the time of the intersections
+//! of the thread rendering the tile. Nothing is wrapped when the profiler is disabled.
+use std::{
+    cell::Cell,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use image::{ImageBuffer, Luma};
+use rt::{
+    math::bounds::Bounds,
+    ray::Ray,
+    renderer::LumaChannel,
+    shape::{FullIntersectionResult, MinIntersectionResult, Shape},
+};
+
+use crate::{output::OutputBuffers, tile::Tile, utils::Dimensions};
+
+thread_local! {
+    static INTERSECTION_TIME: Cell<Duration> = const { Cell::new(Duration::ZERO) };
+}
+
+/// Time spent in the intersections by the current thread so far
+pub fn intersection_time() -> Duration {
+    INTERSECTION_TIME.get()
+}
+
+fn timed<T>(f: impl FnOnce() -> T) -> T {
+    let start = Instant::now();
+    let res = f();
+    INTERSECTION_TIME.set(INTERSECTION_TIME.get() + start.elapsed());
+    res
+}
+
+pub struct TimedShape<'a>(pub &'a dyn Shape);
+
+impl Shape for TimedShape<'_> {
+    fn intersection_full(&self, ray: Ray) -> FullIntersectionResult {
+        timed(|| self.0.intersection_full(ray))
+    }
+
+    fn intersection_stream(&self, rays: &[Ray]) -> Vec<FullIntersectionResult> {
+        timed(|| self.0.intersection_stream(rays))
+    }
+
+    fn intersect_bare(&self, ray: Ray) -> MinIntersectionResult {
+        timed(|| self.0.intersect_bare(ray))
+    }
+
+    fn bounding_box(&self) -> Bounds {
+        self.0.bounding_box()
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct TileProfile {
+    pub tile: Tile,
+    pub intersection: Duration,
+    pub total: Duration,
+}
+
+impl TileProfile {
+    pub fn shading(&self) -> Duration {
+        self.total.saturating_sub(self.intersection)
+    }
+}
+
+/// The profiles of the tiles, a tile gets one for each batch of samples
+#[derive(Default)]
+pub struct Profiler {
+    tiles: Mutex<Vec<TileProfile>>,
+}
+
+impl Profiler {
+    pub fn record(&self, profile: TileProfile) {
+        self.tiles.lock().unwrap().push(profile);
+    }
+
+    /// Adds the intersection and shading times of each pixel, in seconds, as AOVs. The time of a
+    /// tile is spread over its pixels
+    pub fn add_heatmaps(&self, output_buffers: &mut OutputBuffers, dimension: Dimensions) {
+        let mut intersection = ImageBuffer::<Luma<f32>, _>::new(dimension.width, dimension.height);
+        let mut shading = ImageBuffer::<Luma<f32>, _>::new(dimension.width, dimension.height);
+        for profile in self.tiles.lock().unwrap().iter() {
+            let pixels = profile.tile.len() as f32;
+            for (x, y) in profile.tile {
+                intersection.get_pixel_mut(x, y).0[0] +=
+                    profile.intersection.as_secs_f32() / pixels;
+                shading.get_pixel_mut(x, y).0[0] += profile.shading().as_secs_f32() / pixels;
+            }
+        }
+        output_buffers.channels.extend([
+            LumaChannel::IntersectionTime.channel(intersection),
+            LumaChannel::ShadingTime.channel(shading),
+        ]);
+    }
+
+    pub fn log_summary(&self) {
+        let tiles = self.tiles.lock().unwrap();
+        let intersection = tiles.iter().map(|p| p.intersection).sum::<Duration>();
+        let total = tiles.iter().map(|p| p.total).sum::<Duration>();
+        let fraction = intersection.as_secs_f64() / total.as_secs_f64().max(f64::EPSILON);
+        log::info!(
+            "profile: {total:.2?} in the tiles, {intersection:.2?} ({:.1}%) intersecting, {:.2?} \
+            shading",
+            100.0 * fraction,
+            total.saturating_sub(intersection)
+        );
+        if let Some(slowest) = tiles.iter().max_by_key(|p| p.total) {
+            log::info!(
+                "profile: slowest batch of a tile {:?}, {:.2?}",
+                slowest.tile,
+                slowest.total
+            );
+        }
+    }
+}
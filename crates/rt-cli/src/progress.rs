@@ -26,7 +26,6 @@ impl Progress {
             ..Default::default()
         }
     }
-    #[allow(dead_code)]
     pub fn new_inf() -> Self {
         Self {
             max: MaxProgress::Inf,
@@ -1,4 +1,10 @@
+use std::{
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
 use anyhow::Result;
+use clap::{builder::PossibleValue, ValueEnum};
 use itertools::Itertools;
 use rt::utils::timer::timed_scope_log;
 use rt::{renderer::World, utils::counter};
@@ -6,23 +12,109 @@ use rt::{renderer::World, utils::counter};
 use crate::output::{OutputBuffers, OutputBuffersExt};
 use crate::{
     executor::{Executor, TileMsg},
-    output::{FileOutput, FinalOutput, StreamingOutput, TevStreaming},
-    utils::{ExecutionMode, FromArgs, RenderRange},
+    output::{
+        expand_template, ErrorReport, FileOutput, FinalOutput, Outline, RenderMetadata,
+        StreamingOutput, TevStreaming, TiledExrOutput,
+    },
+    utils::{
+        keyframe_camera, turntable_camera, ExecutionMode, Frame, Framing, FromArgs, RenderRange,
+        Spp,
+    },
     Args, AvailableOutput,
 };
 
 pub struct Renderer {
     pub streaming_outputs: Vec<Box<dyn StreamingOutput>>,
     pub final_outputs: Vec<Box<dyn FinalOutput>>,
+    /// Applied to the images of the final outputs
+    pub outline: Option<Outline>,
     pub executor: Executor,
     pub execution_mode: ExecutionMode,
     pub pixel_range: RenderRange,
-    pub sample_range: crate::utils::Spp,
+    pub sample_range: Spp,
+    /// Commits the final outputs during the render, if there is one
+    pub autosave: Option<Autosave>,
+}
+
+/// Periodic commits of the final outputs with the samples accumulated so far, so that an
+/// interrupted render still leaves an image. The images are saved as they are, without the
+/// outline nor the upsampling of a foveated render
+#[derive(Debug, Clone, Copy)]
+pub struct Autosave {
+    pub interval: Duration,
+    last: Instant,
+}
+
+impl Autosave {
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            last: Instant::now(),
+        }
+    }
+
+    /// Whether the outputs are to be saved at `now`, the next save is then an interval later
+    pub fn is_due(&mut self, now: Instant) -> bool {
+        if now.duration_since(self.last) < self.interval {
+            return false;
+        }
+        self.last = now;
+        true
+    }
+}
+
+/// The values of the placeholders of `--output-template`
+fn template_values(args: &Args, seed: u64) -> [(&'static str, String); 4] {
+    let name =
+        |value: Option<PossibleValue>| value.map_or_else(String::new, |v| v.get_name().into());
+    let scene = match &args.scene_file {
+        Some(path) => path
+            .file_stem()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .into_owned(),
+        None => name(args.scene.to_possible_value()),
+    };
+    [
+        ("spp", display_spp(args.spp)),
+        ("seed", seed.to_string()),
+        ("integrator", name(args.integrator.to_possible_value())),
+        ("scene", scene),
+    ]
+}
+
+fn display_spp(spp: u32) -> String {
+    if spp == u32::MAX {
+        "inf".into()
+    } else {
+        spp.to_string()
+    }
 }
 
-impl FromArgs for Renderer {
-    fn from_args(args: &Args) -> Self {
+impl Renderer {
+    /// Build the renderer of the given frame of an animation, or of a still image
+    pub fn new(args: &Args, frame: Option<Frame>, framing: Framing) -> Result<Self> {
         log::info!("building renderer");
+        let mut executor = Executor::new(args)?;
+        if let Some(frame) = frame {
+            executor.seed = frame.seed(args.seed);
+        }
+        executor.camera = match frame {
+            Some(frame) if args.turntable => turntable_camera(args, framing, frame),
+            Some(frame) if !args.keyframe.is_empty() => {
+                keyframe_camera(args, &args.keyframe, frame)
+            }
+            _ => framing.camera(args),
+        };
+
+        let sample_range: Spp = FromArgs::from_args(args);
+        let Spp::Spp(samples) = &sample_range;
+        let metadata = RenderMetadata::new(
+            args,
+            executor.seed,
+            format!("{samples:?}"),
+            &executor.camera,
+        );
         let mut streaming_outputs = Vec::<Box<dyn StreamingOutput>>::new();
         let mut final_outputs = Vec::<Box<dyn FinalOutput>>::new();
 
@@ -39,34 +131,101 @@ impl FromArgs for Renderer {
                     ));
                 }
                 AvailableOutput::File => {
-                    final_outputs.push(Box::new(FileOutput::new()));
+                    let mut output = FileOutput::new();
+                    output.frame = frame.map(|frame| frame.index);
+                    output.metadata = Some(metadata.clone());
+                    if let Some(template) = &args.output_template {
+                        let values = template_values(args, executor.seed);
+                        let path = PathBuf::from(expand_template(template, &values));
+                        let outdir = path.parent().map(Path::to_path_buf).unwrap_or_default();
+                        output.hdr_outdir = Some(outdir.clone());
+                        output.ldr_outdir = Some(outdir);
+                        output.name = path
+                            .file_stem()
+                            .map(|name| name.to_string_lossy().into_owned());
+                    }
+                    // Each tile must be completed exactly once, which an unbounded number of
+                    // samples never does
+                    let streamable = args.spp != u32::MAX
+                        && args.range.is_none()
+                        && args.render_time.is_none()
+                        && args.target_variance.is_none()
+                        && !args.watch;
+                    if args.stream_exr && !streamable {
+                        log::warn!("the EXR images can't be streamed, they are kept in memory");
+                    } else if args.stream_exr {
+                        let hdr_outdir = output.hdr_outdir.take().unwrap();
+                        streaming_outputs.push(Box::new(
+                            TiledExrOutput::new(
+                                hdr_outdir,
+                                output.name.clone(),
+                                output.frame,
+                                args.dimensions,
+                                args.tile_size,
+                                Some(metadata.clone()),
+                            )
+                            .expect("can't create EXR output"),
+                        ));
+                    }
+                    final_outputs.push(Box::new(output));
                 }
             }
         }
+        if let Some(reference) = args.reference.clone().filter(|_| args.report_error) {
+            final_outputs.push(Box::new(ErrorReport { reference }));
+        }
 
-        Renderer {
+        Ok(Renderer {
             streaming_outputs,
             final_outputs,
-            executor: FromArgs::from_args(args),
+            outline: FromArgs::from_args(args),
+            executor,
             execution_mode: args.execution_mode,
-            sample_range: FromArgs::from_args(args),
+            sample_range,
             pixel_range: FromArgs::from_args(args),
-        }
+            autosave: args
+                .autosave_interval
+                .map(|seconds| Autosave::new(Duration::from_secs(seconds))),
+        })
     }
-}
 
-impl Renderer {
     pub fn run(mut self, world: &World) -> Result<()> {
+        if !self.executor.skip_back_faces && self.executor.camera.inside_geometry(world.objects) {
+            log::warn!(
+                "the camera seems to be inside a closed geometry, it only sees back faces: \
+                 --skip-back-faces lets it see through them"
+            );
+        }
         log::info!("rendering");
         let mut output_buffers = OutputBuffers {
             channels: Vec::new(),
         };
 
+        let profiler = self.executor.profiler.clone();
+        let foveation = self.executor.foveation;
+        let dimension = self.executor.dimension;
         timed_scope_log("run tile renderer", || {
             let dim = self.executor.dimension;
+            let buffered = !self.final_outputs.is_empty();
+            let final_outputs = &mut self.final_outputs;
+            let mut autosave = self.autosave.filter(|_| buffered);
             let f = |msg: &TileMsg| {
-                for (index, (x, y)) in msg.tile.into_iter().enumerate() {
-                    output_buffers.convert(&msg.data[index], x, y, dim);
+                if buffered {
+                    for (index, (x, y)) in msg.tile.into_iter().enumerate() {
+                        output_buffers.convert(&msg.data[index], x, y, dim);
+                    }
+                }
+                let now = Instant::now();
+                if autosave
+                    .as_mut()
+                    .is_some_and(|autosave| autosave.is_due(now))
+                {
+                    log::info!("autosaving the render");
+                    for final_output in final_outputs.iter_mut() {
+                        if let Err(err) = final_output.commit(&output_buffers) {
+                            log::warn!("the autosave failed: {err}");
+                        }
+                    }
                 }
                 self.streaming_outputs
                     .iter_mut()
@@ -88,11 +247,142 @@ impl Renderer {
         })
         .res?;
 
+        for streaming_output in &mut self.streaming_outputs {
+            streaming_output.finish()?;
+        }
+        if let Some(profiler) = profiler {
+            profiler.log_summary();
+            if !output_buffers.channels.is_empty() {
+                profiler.add_heatmaps(&mut output_buffers, dimension);
+            }
+        }
+        if let Some(foveation) = foveation.filter(|_| !output_buffers.channels.is_empty()) {
+            foveation.upsample(&mut output_buffers, dimension);
+        }
+        if let Some(outline) = self.outline {
+            outline.apply(&mut output_buffers);
+        }
         for final_output in self.final_outputs {
             final_output.commit(&output_buffers)?;
         }
 
-        counter::report_counters();
+        if counter::counters_enabled() {
+            counter::report_counters();
+        }
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+
+    use anyhow::Result;
+    use clap::Parser;
+    use rt::{
+        light::Lights,
+        material::{DiffuseBxDF, MaterialDescriptor, MaterialId},
+        math::bounds::Bounds,
+        ray::Ray,
+        renderer::{Channel, LumaChannel, World},
+        shape::{FullIntersectionResult, MinIntersectionResult, Shape},
+    };
+
+    use crate::{
+        output::{FinalOutput, OutputBuffers},
+        utils::Framing,
+        Args,
+    };
+
+    use super::{Autosave, Renderer};
+
+    struct Nothing;
+    impl Shape for Nothing {
+        fn intersection_full(&self, _ray: Ray) -> FullIntersectionResult {
+            FullIntersectionResult::NoIntersection
+        }
+        fn intersect_bare(&self, _ray: Ray) -> MinIntersectionResult {
+            MinIntersectionResult::NoIntersection
+        }
+        fn bounding_box(&self) -> Bounds {
+            Bounds::EMPTY
+        }
+    }
+
+    /// Keeps the number of rendered pixels of each commit
+    struct RenderedPixels(Arc<Mutex<Vec<usize>>>);
+
+    impl FinalOutput for RenderedPixels {
+        fn commit(&self, output_buffers: &OutputBuffers) -> Result<()> {
+            let rendered = output_buffers
+                .channels
+                .iter()
+                .find_map(|channel| match channel {
+                    Channel::LumaChannel(LumaChannel::Alpha, alpha) => {
+                        Some(alpha.pixels().filter(|alpha| alpha.0[0] > 0.0).count())
+                    }
+                    _ => None,
+                })
+                .unwrap();
+            self.0.lock().unwrap().push(rendered);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn autosave() {
+        let mut autosave = Autosave::new(Duration::from_secs(60));
+        let start = autosave.last;
+        assert!(!autosave.is_due(start + Duration::from_secs(30)));
+        assert!(autosave.is_due(start + Duration::from_secs(60)));
+        assert!(!autosave.is_due(start + Duration::from_secs(90)));
+        assert!(autosave.is_due(start + Duration::from_secs(125)));
+        assert_eq!(Args::parse_from(["rt", "--spp", "inf"]).spp, u32::MAX);
+
+        // Saved after each tile, the 8 tiles of 4x4 pixels are rendered one after the other
+        let args = Args::parse_from([
+            "rt",
+            "-d",
+            "16x8",
+            "--tile-size",
+            "4",
+            "-e",
+            "monothreaded",
+            "--spp",
+            "2",
+            "--autosave-interval",
+            "0",
+        ]);
+        let mut renderer = Renderer::new(&args, None, Framing::default()).unwrap();
+        let commits = Arc::new(Mutex::new(Vec::new()));
+        renderer
+            .final_outputs
+            .push(Box::new(RenderedPixels(commits.clone())));
+        let materials = [MaterialDescriptor {
+            label: None,
+            material: Box::new(DiffuseBxDF::default()),
+            alpha: None,
+        }];
+        let world = World {
+            objects: &Nothing,
+            lights: &Lights::default(),
+            materials: &materials,
+            world_material: MaterialId(0),
+            fog: None,
+        };
+        renderer.run(&world).unwrap();
+
+        // The autosaves then the final commit
+        let expected = (1..=8).map(|tiles| 16 * tiles).chain([128]);
+        assert_eq!(*commits.lock().unwrap(), expected.collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn missing_mask() {
+        let args = Args::parse_from(["rt", "--mask", "does/not/exist.png"]);
+        assert!(Renderer::new(&args, None, Framing::default()).is_err());
+    }
+}
@@ -1,3 +1,4 @@
+use clap::ValueEnum;
 use rayon::iter::plumbing::bridge;
 
 #[derive(Debug, Clone, Copy)]
@@ -62,6 +63,20 @@ pub struct Tiler {
     pub y_grainsize: u32,
 }
 
+/// The order in which the tiles are rendered
+#[derive(Debug, Default, Clone, Copy, ValueEnum, PartialEq, Eq)]
+pub enum TileOrder {
+    /// Row after row
+    #[default]
+    Scan,
+    /// Ring after ring around the center of the image
+    Spiral,
+    /// From the closest tiles to the center to the farthest
+    CenterOut,
+    /// Along a Hilbert curve, consecutive tiles are neighbours
+    Hilbert,
+}
+
 fn div_ceil(x: u32, y: u32) -> u32 {
     x / y + if x % y != 0 { 1 } else { 0 }
 }
@@ -95,6 +110,70 @@ impl Tiler {
             y_end: self.offset_y + u32::min(self.height, (y + 1) * self.y_grainsize),
         })
     }
+
+    /// The indices of all the tiles, in the given order
+    pub fn ordered_indices(&self, order: TileOrder) -> Vec<usize> {
+        let (col_count, row_count) = self.tile_dimensions();
+        let index = |(x, y): (usize, usize)| x + y * col_count;
+        let mut indices = (0..self.tile_count()).collect::<Vec<_>>();
+
+        // Offset from the center, in half tiles to stay integral
+        let offset = |idx: usize| {
+            let x = 2 * (idx % col_count) as i64 + 1 - col_count as i64;
+            let y = 2 * (idx / col_count) as i64 + 1 - row_count as i64;
+            (x, y)
+        };
+
+        match order {
+            TileOrder::Scan => {}
+            TileOrder::Spiral => indices.sort_by(|&a, &b| {
+                let key = |idx| {
+                    let (x, y) = offset(idx);
+                    (x.abs().max(y.abs()), (y as f32).atan2(x as f32))
+                };
+                let (ring_a, angle_a) = key(a);
+                let (ring_b, angle_b) = key(b);
+                ring_a.cmp(&ring_b).then(angle_a.total_cmp(&angle_b))
+            }),
+            TileOrder::CenterOut => indices.sort_by_key(|&idx| {
+                let (x, y) = offset(idx);
+                x * x + y * y
+            }),
+            TileOrder::Hilbert => {
+                let size = col_count.max(row_count).next_power_of_two();
+                indices = (0..size * size)
+                    .map(|d| hilbert_curve(size, d))
+                    .filter(|&(x, y)| x < col_count && y < row_count)
+                    .map(index)
+                    .collect();
+            }
+        }
+        indices
+    }
+}
+
+/// The position of the `d`-th point of the Hilbert curve covering a `size`x`size` square, `size`
+/// being a power of two
+fn hilbert_curve(size: usize, mut d: usize) -> (usize, usize) {
+    let (mut x, mut y) = (0, 0);
+    let mut s = 1;
+    while s < size {
+        let rx = 1 & (d / 2);
+        let ry = 1 & (d ^ rx);
+        // Rotate the quadrant
+        if ry == 0 {
+            if rx == 1 {
+                x = s - 1 - x;
+                y = s - 1 - y;
+            }
+            std::mem::swap(&mut x, &mut y);
+        }
+        x += s * rx;
+        y += s * ry;
+        d /= 4;
+        s *= 2;
+    }
+    (x, y)
 }
 impl IntoIterator for Tiler {
     type Item = Tile;
@@ -218,3 +297,60 @@ impl rayon::iter::plumbing::Producer for TileProducer {
         )
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use clap::ValueEnum;
+
+    use super::{TileOrder, Tiler};
+
+    fn tiler(width: u32, height: u32) -> Tiler {
+        Tiler {
+            offset_x: 0,
+            offset_y: 0,
+            width,
+            height,
+            x_grainsize: 8,
+            y_grainsize: 8,
+        }
+    }
+
+    #[test]
+    fn every_tile_once() {
+        for order in TileOrder::value_variants() {
+            for tiler in [tiler(64, 64), tiler(100, 30), tiler(8, 50)] {
+                let mut indices = tiler.ordered_indices(*order);
+                indices.sort();
+                assert_eq!(
+                    indices,
+                    (0..tiler.tile_count()).collect::<Vec<_>>(),
+                    "{order:?}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn hilbert_is_continuous() {
+        let tiler = tiler(64, 64);
+        let tiles = tiler
+            .ordered_indices(TileOrder::Hilbert)
+            .into_iter()
+            .map(|idx| tiler.tile(idx).unwrap())
+            .collect::<Vec<_>>();
+        for pair in tiles.windows(2) {
+            let dx = pair[0].x_start.abs_diff(pair[1].x_start);
+            let dy = pair[0].y_start.abs_diff(pair[1].y_start);
+            assert_eq!(dx + dy, 8, "{pair:?}");
+        }
+    }
+
+    #[test]
+    fn center_first() {
+        let tiler = tiler(40, 40);
+        for order in [TileOrder::Spiral, TileOrder::CenterOut] {
+            let first = tiler.tile(tiler.ordered_indices(order)[0]).unwrap();
+            assert_eq!((first.x_start, first.y_start), (16, 16), "{order:?}");
+        }
+    }
+}
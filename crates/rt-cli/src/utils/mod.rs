@@ -1,14 +1,28 @@
 use core::fmt::Display;
-use std::{ops::Range, str::FromStr};
+use std::{ops::Range, path::Path, str::FromStr, time::Duration};
 
 use crate::Args;
 use clap::ValueEnum;
 use rt::{
-    camera::Camera,
-    integrators::{Integrator, PathTracer, RandomWalkIntegrator},
-    math::{point::Point, quaternion::LookAt, vec::Vec3},
+    camera::{Camera, CameraKeyframe},
+    filter::{BoxFilter, Filter, MitchellFilter, TriangleFilter},
+    integrators::{
+        Integrator, PathTracer, RandomWalkIntegrator, ToonIntegrator, WhittedIntegrator,
+    },
+    material::{
+        DielectricBxDF, DiffuseBxDF, EmitBxDF, Material, MaterialDescriptor, ThinDielectricBxDF,
+    },
+    math::{
+        bounds::Bounds,
+        point::Point,
+        quaternion::{LookAt, Quat},
+        vec::{Vec2, Vec3},
+    },
+    renderer::GlobalFog,
     scene::{
-        examples::{CornellBoxScene, DebugScene, DragonScene, SpheresScene, StandfordBunnyScene},
+        examples::{
+            CornellBoxScene, DebugScene, DragonScene, PrismScene, SpheresScene, StandfordBunnyScene,
+        },
         SceneT,
     },
 };
@@ -23,27 +37,156 @@ pub enum ExecutionMode {
     Monothreaded,
 }
 
+const LOOK_AT: Point = Point(Vec3::NEG_Z);
+/// Vertical field of view of the camera, in degrees
+const VFOV: f32 = 70.;
+
 impl FromArgs for Camera {
     fn from_args(args: &Args) -> Self {
-        let look_at = Point::new(0.0, 0.0, -1.0);
-        let look_from = Point::ORIGIN;
-        let look_direction = look_at - look_from;
-        Camera::new(
-            args.dimensions.width,
-            args.dimensions.height,
-            f32::to_radians(70.),
-            look_direction.length(),
-            look_from,
-            LookAt {
+        Framing::default().camera(args)
+    }
+}
+
+/// Where the camera looks at, and where it looks from when it doesn't move
+#[derive(Debug, Clone, Copy)]
+pub struct Framing {
+    pub look_at: Point,
+    pub look_from: Point,
+}
+
+impl Default for Framing {
+    fn default() -> Self {
+        Self {
+            look_at: LOOK_AT,
+            look_from: Point::ORIGIN,
+        }
+    }
+}
+
+impl Framing {
+    /// Looking at the center of `bounds` along -Z, from close enough for its bounding sphere to
+    /// fit in the field of view of a render of the given dimensions
+    pub fn fit(bounds: &Bounds, dimensions: Dimensions) -> Self {
+        let radius = bounds.diag().length() / 2.0;
+        if !radius.is_finite() || radius <= 0.0 {
+            log::warn!("the scene is empty, it can't be framed");
+            return Self::default();
+        }
+
+        let half_vfov = f32::to_radians(VFOV) / 2.0;
+        let aspect_ratio = dimensions.width as f32 / dimensions.height as f32;
+        let half_hfov = f32::atan(f32::tan(half_vfov) * aspect_ratio);
+        let distance = radius / f32::sin(half_vfov.min(half_hfov));
+        let look_at = bounds.centroid();
+        Self {
+            look_at,
+            look_from: look_at + distance * Vec3::Z,
+        }
+    }
+
+    pub fn camera(self, args: &Args) -> Camera {
+        camera(args, self.look_at, self.look_from)
+    }
+
+    fn keyframe(self) -> CameraKeyframe {
+        let look_direction = self.look_at - self.look_from;
+        CameraKeyframe {
+            center_of_lens: self.look_from,
+            rotation: LookAt {
                 direction: look_direction,
                 forward: Vec3::NEG_Z,
             }
             .into(),
-            0.0,
-        )
+            focal_length: look_direction.length(),
+        }
     }
 }
 
+impl FromStr for Framing {
+    type Err = anyhow::Error;
+
+    /// In format `x,y,z:x,y,z`, where the camera looks from then where it looks at
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let point = |s: &str| -> anyhow::Result<Point> {
+            let coords = s
+                .split(',')
+                .map(|c| c.trim().parse())
+                .collect::<Result<Vec<f32>, _>>()?;
+            let [x, y, z] = coords[..] else {
+                anyhow::bail!("Incorrect format, see help");
+            };
+            Ok(Point::new(x, y, z))
+        };
+        let Some((look_from, look_at)) = s.split_once(':') else {
+            anyhow::bail!("Incorrect format, see help");
+        };
+        Ok(Self {
+            look_at: point(look_at)?,
+            look_from: point(look_from)?,
+        })
+    }
+}
+
+/// A frame of an animation
+#[derive(Debug, Clone, Copy)]
+pub struct Frame {
+    pub index: u32,
+    pub count: u32,
+}
+
+impl Frame {
+    /// Each frame gets its own seed so that the noise is not the same in all of them
+    pub fn seed(self, seed: u64) -> u64 {
+        seed.wrapping_add(self.index as u64)
+    }
+}
+
+/// The camera orbiting around the point it looks at, making a full turn over all the frames
+pub fn turntable_camera(args: &Args, framing: Framing, frame: Frame) -> Camera {
+    let angle = f32::to_radians(360.) * frame.index as f32 / frame.count as f32;
+    let look_from =
+        framing.look_at + Quat::from_rotation_y(angle) * (framing.look_from - framing.look_at);
+    camera(args, framing.look_at, look_from)
+}
+
+/// The camera moving along the keyframes, going through the first one on the first frame and
+/// through the last one on the last frame
+pub fn keyframe_camera(args: &Args, keyframes: &[Framing], frame: Frame) -> Camera {
+    let keyframes = keyframes.iter().map(|k| k.keyframe()).collect::<Vec<_>>();
+    let t = frame.index as f32 / frame.count.saturating_sub(1).max(1) as f32;
+    let keyframe = CameraKeyframe::interpolate(&keyframes, t);
+    let mut camera = Camera::new(
+        args.dimensions.width,
+        args.dimensions.height,
+        f32::to_radians(VFOV),
+        keyframe.focal_length,
+        keyframe.center_of_lens,
+        keyframe.rotation,
+        0.0,
+    );
+    camera.shift = args.lens_shift.map_or(Vec2::ZERO, |shift| shift.0);
+    camera
+}
+
+fn camera(args: &Args, look_at: Point, look_from: Point) -> Camera {
+    let look_direction = look_at - look_from;
+    let mut camera = Camera::new(
+        args.dimensions.width,
+        args.dimensions.height,
+        f32::to_radians(VFOV),
+        look_direction.length(),
+        look_from,
+        LookAt {
+            direction: look_direction,
+            forward: Vec3::NEG_Z,
+        }
+        .into(),
+        0.0,
+    );
+    camera.shift = args.lens_shift.map_or(Vec2::ZERO, |shift| shift.0);
+    camera
+}
+
 #[derive(Debug, Clone)]
 pub struct RenderRange {
     pub x: Range<u32>,
@@ -86,13 +229,214 @@ impl std::str::FromStr for RenderRange {
     }
 }
 
+/// A 1-bit image telling which pixels should be rendered
+#[derive(Debug, Clone)]
+pub struct RenderMask {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<bool>,
+}
+
+impl RenderMask {
+    /// Pixels are rendered where the image is white, in fact where its luma is more than 50%
+    pub fn open(path: &Path, dimensions: Dimensions) -> anyhow::Result<Self> {
+        let image = image::open(path)?.into_luma8();
+        if image.dimensions() != (dimensions.width, dimensions.height) {
+            anyhow::bail!(
+                "the mask is {}x{} but the render is {dimensions}",
+                image.width(),
+                image.height()
+            );
+        }
+
+        Ok(RenderMask {
+            width: image.width(),
+            height: image.height(),
+            pixels: image.pixels().map(|p| p.0[0] > 127).collect(),
+        })
+    }
+
+    pub fn contains(&self, x: u32, y: u32) -> bool {
+        x < self.width && y < self.height && self.pixels[(y * self.width + x) as usize]
+    }
+
+    /// The mask given in the arguments, if any
+    pub fn from_args(args: &Args) -> anyhow::Result<Option<Self>> {
+        args.mask
+            .as_ref()
+            .map(|path| RenderMask::open(path, args.dimensions))
+            .transpose()
+    }
+}
+
+/// A pixel given as `x,y`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Pixel {
+    pub x: u32,
+    pub y: u32,
+}
+
+impl FromStr for Pixel {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let Some((x, y)) = s.split_once(',') else {
+            anyhow::bail!("Incorrect format, see help");
+        };
+        Ok(Self {
+            x: x.trim().parse()?,
+            y: y.trim().parse()?,
+        })
+    }
+}
+
+/// A lens shift given as `x,y`, in viewport units
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LensShift(pub Vec2);
+
+impl FromStr for LensShift {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let Some((x, y)) = s.split_once(',') else {
+            anyhow::bail!("Incorrect format, see help");
+        };
+        Ok(Self(Vec2::new(x.trim().parse()?, y.trim().parse()?)))
+    }
+}
+
+/// A material to give to the meshes of the scene file by name
+#[derive(Debug, Clone, Copy, ValueEnum, PartialEq, Eq)]
+pub enum AvailableMaterial {
+    Diffuse,
+    Glass,
+    /// A glass without thickness, for the windows
+    ThinGlass,
+    Emit,
+}
+
+impl AvailableMaterial {
+    pub fn descriptor(self) -> MaterialDescriptor {
+        let material: Box<dyn Material> = match self {
+            AvailableMaterial::Diffuse => Box::new(DiffuseBxDF {
+                albedo: [0.8, 0.8, 0.8].into(),
+                ..Default::default()
+            }),
+            AvailableMaterial::Glass => Box::new(DielectricBxDF {
+                ior: 1.5,
+                roughness: 0.0,
+                transmittance_color: [1.0, 1.0, 1.0].into(),
+            }),
+            AvailableMaterial::ThinGlass => Box::new(ThinDielectricBxDF { ior: 1.5 }),
+            AvailableMaterial::Emit => Box::new(EmitBxDF {
+                le: [1.0, 1.0, 1.0].into(),
+                two_sided: false,
+            }),
+        };
+        MaterialDescriptor {
+            label: self
+                .to_possible_value()
+                .map(|value| value.get_name().to_string()),
+            material,
+            alpha: None,
+        }
+    }
+}
+
+/// The material of the meshes of a name, given as `name=material`
+#[derive(Debug, Clone, PartialEq)]
+pub struct MaterialOverride {
+    pub name: String,
+    pub material: AvailableMaterial,
+}
+
+impl FromStr for MaterialOverride {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let Some((name, material)) = s.rsplit_once('=') else {
+            anyhow::bail!("Incorrect format, see help");
+        };
+        Ok(Self {
+            name: name.to_string(),
+            material: AvailableMaterial::from_str(material.trim(), true)
+                .map_err(anyhow::Error::msg)?,
+        })
+    }
+}
+
+/// A duration given as a sequence of amounts with a unit, eg "1h30m", "90s" or "500ms"
+#[derive(Debug, Clone, Copy)]
+pub struct RenderTime(pub Duration);
+
+impl FromStr for RenderTime {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut total = Duration::ZERO;
+        let mut rest = s.trim();
+        if rest.is_empty() {
+            anyhow::bail!("empty duration");
+        }
+
+        while !rest.is_empty() {
+            let split = rest
+                .find(|c: char| !c.is_ascii_digit() && c != '.')
+                .ok_or_else(|| anyhow::anyhow!("missing unit in {s:?}, use h, m, s or ms"))?;
+            let (amount, tail) = rest.split_at(split);
+            let amount: f64 = amount.parse()?;
+
+            let unit_len = tail
+                .find(|c: char| c.is_ascii_digit())
+                .unwrap_or(tail.len());
+            let (unit, tail) = tail.split_at(unit_len);
+            let seconds = match unit {
+                "h" => 3600.0,
+                "m" | "min" => 60.0,
+                "s" => 1.0,
+                "ms" => 0.001,
+                _ => anyhow::bail!("unknown unit {unit:?}, use h, m, s or ms"),
+            };
+
+            total += Duration::from_secs_f64(amount * seconds);
+            rest = tail;
+        }
+
+        Ok(RenderTime(total))
+    }
+}
+
 #[derive(Debug, Clone)]
 pub enum Spp {
     Spp(Range<u32>),
 }
+
+impl Spp {
+    /// The sample after the last one
+    pub fn end(&self) -> u32 {
+        match self {
+            Spp::Spp(r) => r.end,
+        }
+    }
+}
+
 impl FromArgs for Spp {
     fn from_args(args: &Args) -> Self {
-        args.sample_range.clone().unwrap_or(Spp::Spp(0..args.spp))
+        let default_range = if args.render_time.is_some() {
+            // Render until the time is up
+            Spp::Spp(0..u32::MAX)
+        } else {
+            Spp::Spp(0..args.spp)
+        };
+        args.sample_range.clone().unwrap_or(default_range)
+    }
+}
+
+/// A number of samples per pixel, "inf" rendering until the render is interrupted
+pub fn parse_spp(s: &str) -> anyhow::Result<u32> {
+    match s.trim() {
+        "inf" => Ok(u32::MAX),
+        s => Ok(s.parse()?),
     }
 }
 
@@ -116,6 +460,7 @@ pub enum AvailableScene {
     Spheres,
     Debug,
     Dragon,
+    Prism,
 }
 
 impl AvailableScene {
@@ -126,6 +471,7 @@ impl AvailableScene {
             AvailableScene::Spheres => SpheresScene::insert_into(scene),
             AvailableScene::Debug => DebugScene::insert_into(scene),
             AvailableScene::Dragon => DragonScene::insert_into(scene),
+            AvailableScene::Prism => PrismScene::insert_into(scene),
         }
     }
 }
@@ -137,11 +483,25 @@ pub enum AvailableOutput {
     File,
 }
 
+#[derive(Default, Debug, Clone, Copy, ValueEnum, PartialEq, Eq, Hash)]
+pub enum AvailableSampler {
+    /// One stratum per sample in each dimension of the path, visited in a shuffled order
+    #[default]
+    Stratified,
+    /// Owen scrambled Sobol sequence over the whole path
+    Sobol,
+}
+
 #[derive(Default, Debug, Clone, Copy, ValueEnum, PartialEq, Eq, Hash)]
 pub enum AvailableIntegrator {
     Basic,
     #[default]
     PathTracer,
+    /// Gooch and cel shading of the first hit, without global illumination
+    Toon,
+    /// Direct lighting by the point lights and perfect specular reflections and refractions,
+    /// without noise nor global illumination
+    Whitted,
 }
 
 impl FromArgs for Box<dyn Integrator> {
@@ -149,11 +509,68 @@ impl FromArgs for Box<dyn Integrator> {
         let max_depth = args.max_ray_depth.unwrap_or(64);
         match args.integrator {
             AvailableIntegrator::Basic => Box::new(RandomWalkIntegrator { max_depth }),
-            AvailableIntegrator::PathTracer => Box::new(PathTracer { max_depth }),
+            AvailableIntegrator::PathTracer => Box::new(PathTracer {
+                max_depth,
+                max_diffuse_depth: args.max_diffuse_depth.unwrap_or(max_depth),
+                max_glossy_depth: args.max_glossy_depth.unwrap_or(max_depth),
+                max_specular_depth: args.max_specular_depth.unwrap_or(max_depth),
+                split_depth: args.split_depth,
+                shadow_samples: args.shadow_samples,
+            }),
+            AvailableIntegrator::Toon => Box::new(ToonIntegrator::default()),
+            AvailableIntegrator::Whitted => Box::new(WhittedIntegrator::new(max_depth)),
+        }
+    }
+}
+
+/// Reconstruction filter of the pixels
+#[derive(Default, Debug, Clone, Copy, ValueEnum, PartialEq, Eq, Hash)]
+pub enum AvailableFilter {
+    /// The samples are spread uniformly, with the same weight. A radius of 0.7 reproduces the
+    /// renders made before the filter could be chosen
+    Box,
+    Triangle,
+    /// Sharper, its negative lobes giving negative weights to some samples
+    #[default]
+    Mitchell,
+}
+
+impl AvailableFilter {
+    /// Radius of the filter when none is given, in pixels
+    pub fn default_radius(self) -> f32 {
+        match self {
+            AvailableFilter::Box => 0.7,
+            AvailableFilter::Triangle => 1.0,
+            AvailableFilter::Mitchell => 2.0,
+        }
+    }
+}
+
+impl FromArgs for Box<dyn Filter> {
+    fn from_args(args: &Args) -> Self {
+        let radius = Vec2::splat(
+            args.filter_radius
+                .unwrap_or_else(|| args.filter.default_radius()),
+        );
+        match args.filter {
+            AvailableFilter::Box => Box::new(BoxFilter { radius }),
+            AvailableFilter::Triangle => Box::new(TriangleFilter { radius }),
+            AvailableFilter::Mitchell => Box::new(MitchellFilter::new(radius)),
         }
     }
 }
 
+impl FromArgs for Option<GlobalFog> {
+    fn from_args(args: &Args) -> Self {
+        args.fog_density
+            .filter(|&density| density > 0.0)
+            .map(|sigma_t| GlobalFog {
+                sigma_t,
+                color: [0.7, 0.7, 0.8].into(),
+            })
+    }
+}
+
 #[derive(Copy, Clone, Debug)]
 pub struct Dimensions {
     pub width: u32,
@@ -180,3 +597,92 @@ impl Display for Dimensions {
         f.write_fmt(format_args!("{}x{}", self.width, self.height))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+    use rt::{
+        filter::Filter,
+        math::{bounds::Bounds, float::FloatAsExt, point::Point, vec::Vec2},
+    };
+
+    use crate::Args;
+
+    use super::{AvailableMaterial, Dimensions, Framing, FromArgs, MaterialOverride, VFOV};
+
+    #[test]
+    fn material_override() {
+        let args = Args::parse_from([
+            "rt",
+            "--scene-file",
+            "house.obj",
+            "--override-material",
+            "windows=thin-glass",
+            "--override-material",
+            "roof=Glass",
+        ]);
+        assert_eq!(
+            args.override_material,
+            [
+                MaterialOverride {
+                    name: "windows".to_string(),
+                    material: AvailableMaterial::ThinGlass,
+                },
+                MaterialOverride {
+                    name: "roof".to_string(),
+                    material: AvailableMaterial::Glass,
+                },
+            ]
+        );
+        assert!("windows".parse::<MaterialOverride>().is_err());
+        assert!("windows=wood".parse::<MaterialOverride>().is_err());
+        assert_eq!(
+            AvailableMaterial::ThinGlass.descriptor().label.as_deref(),
+            Some("thin-glass")
+        );
+    }
+
+    #[test]
+    fn box_filter_offsets() {
+        let filter: Box<dyn Filter> =
+            FromArgs::from_args(&Args::parse_from(["rt", "--filter", "box"]));
+        let explicit: Box<dyn Filter> = FromArgs::from_args(&Args::parse_from([
+            "rt",
+            "--filter",
+            "box",
+            "--filter-radius",
+            "0.7",
+        ]));
+        for sample in [
+            Vec2::ZERO,
+            Vec2::new(0.25, 0.9),
+            Vec2::new(0.5, 0.1),
+            Vec2::new(0.999, 0.3),
+        ] {
+            // The offsets of the box filter of radius 0.7 that was hardcoded
+            let expected = Vec2::new(sample.x.lerp(-0.7, 0.7), sample.y.lerp(-0.7, 0.7));
+            for filter in [&filter, &explicit] {
+                let filtered = filter.sample(sample);
+                assert_eq!(filtered.coords, expected);
+                assert_eq!(filtered.weight, 1.0);
+            }
+        }
+    }
+
+    #[test]
+    fn auto_frame() {
+        let sphere = Bounds::new(Point::new(-1.0, -1.0, -1.0), Point::new(1.0, 1.0, 1.0));
+        for (width, height) in [(800, 600), (600, 800)] {
+            let framing = Framing::fit(&sphere, Dimensions { width, height });
+            assert_eq!(framing.look_at.vec(), Point::ORIGIN.vec());
+
+            // Fraction of the narrowest side of the image covered by the sphere
+            let distance = (framing.look_from - framing.look_at).length();
+            let half_vfov = f32::to_radians(VFOV) / 2.0;
+            let half_fov =
+                f32::atan(f32::tan(half_vfov) * width.min(height) as f32 / height as f32);
+            let covered = f32::tan(f32::asin(1.0 / distance)) / f32::tan(half_fov);
+            assert!((0.4..0.9).contains(&covered), "{covered}");
+        }
+    }
+}
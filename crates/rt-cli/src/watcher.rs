@@ -0,0 +1,59 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread::JoinHandle,
+    time::{Duration, SystemTime},
+};
+
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Polls the modification time of a file
+pub struct FileWatcher {
+    path: PathBuf,
+    last_modified: Option<SystemTime>,
+}
+
+impl FileWatcher {
+    pub fn new(path: PathBuf) -> Self {
+        let last_modified = modified(&path);
+        Self {
+            path,
+            last_modified,
+        }
+    }
+
+    /// Block until the file is modified, a missing file is a change once it reappears
+    pub fn wait_for_change(&mut self) {
+        loop {
+            std::thread::sleep(POLL_INTERVAL);
+            let modified = modified(&self.path);
+            if modified.is_some() && modified != self.last_modified {
+                self.last_modified = modified;
+                log::info!("{} changed", self.path.display());
+                return;
+            }
+        }
+    }
+
+    /// Wait for a change in another thread, setting the returned flag once it happens.
+    /// The thread gives back the watcher when joined
+    pub fn spawn(mut self) -> (Arc<AtomicBool>, JoinHandle<Self>) {
+        let changed = Arc::new(AtomicBool::new(false));
+        let handle = std::thread::spawn({
+            let changed = changed.clone();
+            move || {
+                self.wait_for_change();
+                changed.store(true, Ordering::SeqCst);
+                self
+            }
+        });
+        (changed, handle)
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
@@ -0,0 +1,82 @@
+//! The camera rays of the dragon scene intersected one by one and by packets, as the recursive and
+//! the wavefront loops do.
+//!
+//! Run from anywhere with `cargo +nightly bench -p rt`
+#![feature(test)]
+
+extern crate test;
+
+use embree4_rs::device::Device;
+use glam::Vec3;
+use rt::{
+    aggregate::embree::EmbreeScene,
+    math::point::Point,
+    ray::Ray,
+    scene::examples::DragonScene,
+    shape::{IntersectionResult, Shape},
+};
+use test::Bencher;
+
+/// The rays of a 256x256 image looking at the dragon
+fn camera_rays() -> Vec<Ray> {
+    let size = 256;
+    (0..size * size)
+        .map(|i| {
+            let (x, y) = (i % size, i / size);
+            let target = Vec3::new(
+                (x as f32 + 0.5) / size as f32 - 0.5,
+                0.5 - (y as f32 + 0.5) / size as f32,
+                -1.0,
+            );
+            Ray::new(Point::ORIGIN, target.normalize())
+        })
+        .collect()
+}
+
+/// Bench `intersect` on the committed dragon scene
+fn with_dragon(b: &mut Bencher, intersect: impl Fn(&dyn Shape, &[Ray]) -> usize) {
+    // The paths of the models are relative to the root of the repository
+    std::env::set_current_dir(concat!(env!("CARGO_MANIFEST_DIR"), "/../..")).unwrap();
+    let device = Device::try_new(None).unwrap();
+    let mut scene = EmbreeScene::new(&device);
+    DragonScene::insert_into(&mut scene);
+    let scene = scene.commit().unwrap();
+
+    let rays = camera_rays();
+    // Both ways must see the same hits
+    let scalar = rays
+        .iter()
+        .map(|&ray| scene.intersection_full(ray))
+        .collect::<Vec<_>>();
+    for (a, b) in scalar.iter().zip(scene.intersection_stream(&rays)) {
+        match (a, b) {
+            (IntersectionResult::Intersection(a), IntersectionResult::Intersection(b)) => {
+                assert_eq!((a.t, a.local_info.object), (b.t, b.local_info.object));
+            }
+            (IntersectionResult::NoIntersection, IntersectionResult::NoIntersection) => (),
+            _ => panic!("stream and scalar intersections disagree"),
+        }
+    }
+
+    b.iter(|| intersect(&scene, &rays));
+}
+
+#[bench]
+fn dragon_scalar(b: &mut Bencher) {
+    with_dragon(b, |scene, rays| {
+        rays.iter()
+            .filter(|&&ray| scene.intersection_full(ray).is_intersection())
+            .count()
+    });
+}
+
+#[bench]
+fn dragon_stream(b: &mut Bencher) {
+    with_dragon(b, |scene, rays| {
+        scene
+            .intersection_stream(rays)
+            .iter()
+            .filter(|isect| isect.is_intersection())
+            .count()
+    });
+}
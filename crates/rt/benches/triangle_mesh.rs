@@ -0,0 +1,156 @@
+//! The camera rays of the bunny intersected with its triangles stored in one [TriangleMesh], and
+//! with a boxed shape per triangle.
+//!
+//! Run from anywhere with `cargo +nightly bench -p rt`
+#![feature(test)]
+
+extern crate test;
+
+use glam::Vec3;
+use rt::{
+    aggregate::{nested::Aggregate, triangle_mesh::TriangleMesh},
+    material::{LightDescriptor, MaterialDescriptor, MaterialId},
+    math::point::Point,
+    ray::Ray,
+    scene::{examples::StandfordBunnyScene, SceneT},
+    shape::{IntersectionResult, Shape},
+};
+use test::Bencher;
+
+/// A mesh of a scene, with the normals of its vertices if it has some
+struct Mesh {
+    positions: Vec<Vec3>,
+    normals: Option<Vec<Vec3>>,
+    indices: Vec<[u32; 3]>,
+}
+
+/// The meshes of a scene
+#[derive(Default)]
+struct Meshes(Vec<Mesh>);
+
+impl SceneT for Meshes {
+    type GeometryHandle = ();
+
+    fn insert_material(&mut self, _mat: MaterialDescriptor) -> MaterialId {
+        MaterialId(0)
+    }
+
+    fn insert_light(&mut self, _light: LightDescriptor) {}
+
+    fn insert_mesh(&mut self, _material: MaterialId, vertices: &[[f32; 3]], indices: &[[u32; 3]]) {
+        self.0.push(Mesh {
+            positions: vertices.iter().map(|&p| Vec3::from_array(p)).collect(),
+            normals: None,
+            indices: indices.to_vec(),
+        });
+    }
+
+    fn insert_mesh_with_normals(
+        &mut self,
+        _material: MaterialId,
+        vertices: &[[f32; 3]],
+        normals: &[[f32; 3]],
+        indices: &[[u32; 3]],
+    ) {
+        self.0.push(Mesh {
+            positions: vertices.iter().map(|&p| Vec3::from_array(p)).collect(),
+            normals: Some(normals.iter().map(|&n| Vec3::from_array(n)).collect()),
+            indices: indices.to_vec(),
+        });
+    }
+
+    fn insert_sphere(&mut self, _material: MaterialId, _origin: Point, _radius: f32) {}
+
+    fn insert_curve(&mut self, _material: MaterialId, _: &[[f32; 3]], _widths: &[f32]) {}
+}
+
+/// The rays of a 32x32 image looking at the bunny
+fn camera_rays() -> Vec<Ray> {
+    let size = 32;
+    (0..size * size)
+        .map(|i| {
+            let (x, y) = (i % size, i / size);
+            let target = Vec3::new(
+                (x as f32 + 0.5) / size as f32 - 0.5,
+                0.5 - (y as f32 + 0.5) / size as f32,
+                -1.0,
+            );
+            Ray::new(Point::ORIGIN, target.normalize())
+        })
+        .collect()
+}
+
+/// The meshes of the bunny, built into a shape by `build`
+fn bunny(build: impl Fn(Mesh) -> Box<dyn Shape>) -> Aggregate {
+    // The paths of the models are relative to the root of the repository
+    std::env::set_current_dir(concat!(env!("CARGO_MANIFEST_DIR"), "/../..")).unwrap();
+    let mut meshes = Meshes::default();
+    StandfordBunnyScene::insert_into(&mut meshes);
+
+    let mut aggregate = Aggregate::new();
+    for mesh in meshes.0 {
+        aggregate.push(build(mesh));
+    }
+    aggregate
+}
+
+/// One mesh per mesh of the scene
+fn bunny_mesh() -> Aggregate {
+    bunny(|mesh| {
+        Box::new(TriangleMesh::new(
+            MaterialId(0),
+            mesh.positions,
+            mesh.normals,
+            mesh.indices,
+        ))
+    })
+}
+
+/// One mesh per triangle, boxed on its own
+fn bunny_boxed() -> Aggregate {
+    bunny(|mesh| {
+        let mut triangles = Aggregate::new();
+        for triangle in mesh.indices {
+            let vertices = |attribute: &[Vec3]| triangle.map(|i| attribute[i as usize]).to_vec();
+            triangles.push(Box::new(TriangleMesh::new(
+                MaterialId(0),
+                vertices(&mesh.positions),
+                mesh.normals.as_deref().map(vertices),
+                vec![[0, 1, 2]],
+            )));
+        }
+        Box::new(triangles)
+    })
+}
+
+fn hits(shape: &dyn Shape, rays: &[Ray]) -> usize {
+    rays.iter()
+        .filter(|&&ray| shape.intersection_full(ray).is_intersection())
+        .count()
+}
+
+#[bench]
+fn bunny_triangle_mesh(b: &mut Bencher) {
+    let (mesh, boxed) = (bunny_mesh(), bunny_boxed());
+    let rays = camera_rays();
+    // Both ways must see the same hits
+    for &ray in &rays {
+        match (mesh.intersection_full(ray), boxed.intersection_full(ray)) {
+            (IntersectionResult::Intersection(a), IntersectionResult::Intersection(b)) => {
+                assert_eq!((a.t, a.local_info.normal), (b.t, b.local_info.normal));
+            }
+            (IntersectionResult::NoIntersection, IntersectionResult::NoIntersection) => (),
+            _ => panic!("the mesh and the boxed triangles disagree"),
+        }
+    }
+    assert!(hits(&mesh, &rays) > 0);
+
+    b.iter(|| hits(&mesh, &rays));
+}
+
+#[bench]
+fn bunny_boxed_triangles(b: &mut Bencher) {
+    let boxed = bunny_boxed();
+    let rays = camera_rays();
+    b.iter(|| hits(&boxed, &rays));
+}
@@ -0,0 +1,249 @@
+use glam::Vec3;
+
+use crate::{
+    material::MaterialId,
+    math::{bounds::Bounds, point::Point},
+    ray::Ray,
+    shape::{local_info, FullIntersectionResult, MinIntersectionResult, RayIntersection, Shape},
+};
+
+/// A bicubic Bezier patch, a smooth surface described by a grid of 4x4 control points.
+///
+/// It is intersected with Newton iterations in the parameter space of the patch, the `uv` of a
+/// hit are its parameters in [0, 1]²
+pub struct BezierPatch {
+    pub material: MaterialId,
+    /// Indexed by `[v][u]`, the corner ones are on the surface
+    pub control_points: [[Vec3; 4]; 4],
+    bounds: Bounds,
+}
+
+/// A hit on the patch
+#[derive(Debug, Clone, Copy)]
+struct PatchHit {
+    t: f32,
+    uv: [f32; 2],
+    normal: Vec3,
+}
+
+impl BezierPatch {
+    /// Newton iterations from each starting point
+    const ITERATIONS: usize = 16;
+    /// The starting points are on a grid of `SEEDS`² points over the patch, so that most of the
+    /// hits of a folded patch are found
+    const SEEDS: usize = 3;
+
+    pub fn new(material: MaterialId, control_points: [[Vec3; 4]; 4]) -> Self {
+        // The patch is in the convex hull of its control points
+        let bounds = control_points
+            .iter()
+            .flatten()
+            .fold(Bounds::EMPTY, |b, &p| b.union_point(Point(p)));
+
+        Self {
+            material,
+            control_points,
+            bounds,
+        }
+    }
+
+    /// The patch going through the 2x2 inner points of a grid of 4x4 Catmull-Rom control points
+    pub fn from_catmull_rom(material: MaterialId, points: [[Vec3; 4]; 4]) -> Self {
+        let to_bezier =
+            |[p0, p1, p2, p3]: [Vec3; 4]| [p1, p1 + (p2 - p0) / 6.0, p2 - (p3 - p1) / 6.0, p2];
+
+        let rows = points.map(to_bezier);
+        let columns: [[Vec3; 4]; 4] = std::array::from_fn(|i| to_bezier(rows.map(|row| row[i])));
+        Self::new(
+            material,
+            std::array::from_fn(|v| std::array::from_fn(|u| columns[u][v])),
+        )
+    }
+
+    /// The cubic Bernstein polynomials and their derivatives at `t`
+    fn bernstein(t: f32) -> ([f32; 4], [f32; 4]) {
+        let s = 1.0 - t;
+        (
+            [s * s * s, 3.0 * t * s * s, 3.0 * t * t * s, t * t * t],
+            [
+                -3.0 * s * s,
+                3.0 * s * (s - 2.0 * t),
+                3.0 * t * (2.0 * s - t),
+                3.0 * t * t,
+            ],
+        )
+    }
+
+    /// The point of the patch at `(u, v)` and its derivatives along u and v
+    pub fn eval(&self, u: f32, v: f32) -> (Vec3, Vec3, Vec3) {
+        let (bu, dbu) = Self::bernstein(u);
+        let (bv, dbv) = Self::bernstein(v);
+
+        let (mut p, mut dpdu, mut dpdv) = (Vec3::ZERO, Vec3::ZERO, Vec3::ZERO);
+        for (j, row) in self.control_points.iter().enumerate() {
+            for (i, &cp) in row.iter().enumerate() {
+                p += bu[i] * bv[j] * cp;
+                dpdu += dbu[i] * bv[j] * cp;
+                dpdv += bu[i] * dbv[j] * cp;
+            }
+        }
+        (p, dpdu, dpdv)
+    }
+
+    /// The ray is the intersection of two planes containing it, the hits are the `(u, v)` where
+    /// the patch is on both of them. They are found by Newton iterations, from every starting
+    /// point, keeping the closest one
+    fn closest_hit(&self, ray: &Ray) -> Option<PatchHit> {
+        self.bounds.intersect_ray(ray, ray.direction.recip())?;
+
+        let n1 = ray.direction.any_orthonormal_vector();
+        let n2 = ray.direction.cross(n1).normalize();
+        let origin = ray.origin.vec();
+        let tolerance = 1e-5 * (1.0 + self.bounds.diag().max_element());
+
+        let mut closest: Option<PatchHit> = None;
+        for seed in 0..Self::SEEDS * Self::SEEDS {
+            let start = |i: usize| (i as f32 + 0.5) / Self::SEEDS as f32;
+            let (mut u, mut v) = (start(seed % Self::SEEDS), start(seed / Self::SEEDS));
+
+            for _ in 0..Self::ITERATIONS {
+                let (p, dpdu, dpdv) = self.eval(u, v);
+                let (f1, f2) = (n1.dot(p - origin), n2.dot(p - origin));
+                if f1.abs() < tolerance && f2.abs() < tolerance {
+                    let t = ray.direction.dot(p - origin);
+                    let inside = (0.0..=1.0).contains(&u) && (0.0..=1.0).contains(&v);
+                    if inside && ray.range().contains(&t) && closest.is_none_or(|hit| t < hit.t) {
+                        closest = Some(PatchHit {
+                            t,
+                            uv: [u, v],
+                            normal: dpdu.cross(dpdv).normalize_or_zero(),
+                        });
+                    }
+                    break;
+                }
+
+                let (a, b) = (n1.dot(dpdu), n1.dot(dpdv));
+                let (c, d) = (n2.dot(dpdu), n2.dot(dpdv));
+                let det = a * d - b * c;
+                if det.abs() < f32::EPSILON {
+                    break;
+                }
+                u -= (d * f1 - b * f2) / det;
+                v -= (a * f2 - c * f1) / det;
+                // Far outside of the patch, it won't come back
+                if !(-1.0..=2.0).contains(&u) || !(-1.0..=2.0).contains(&v) {
+                    break;
+                }
+            }
+        }
+        closest
+    }
+}
+
+impl Shape for BezierPatch {
+    fn intersection_full(&self, ray: Ray) -> FullIntersectionResult {
+        match self.closest_hit(&ray) {
+            Some(hit) => FullIntersectionResult::Intersection(RayIntersection {
+                t: hit.t,
+                local_info: local_info::Full {
+                    pos: ray.at(hit.t),
+                    normal: hit.normal,
+                    shading_normal: hit.normal,
+                    material: self.material,
+                    uv: hit.uv,
+                    object: 0,
+                },
+            }),
+            None => FullIntersectionResult::NoIntersection,
+        }
+    }
+
+    fn intersect_bare(&self, ray: Ray) -> MinIntersectionResult {
+        match self.closest_hit(&ray) {
+            Some(hit) => MinIntersectionResult::Intersection(RayIntersection {
+                t: hit.t,
+                local_info: local_info::Minimum { pos: ray.at(hit.t) },
+            }),
+            None => MinIntersectionResult::NoIntersection,
+        }
+    }
+
+    fn bounding_box(&self) -> Bounds {
+        self.bounds
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::Vec3;
+
+    use crate::{
+        material::MaterialId,
+        math::point::Point,
+        ray::Ray,
+        shape::{FullIntersectionResult, Shape},
+    };
+
+    use super::BezierPatch;
+
+    #[test]
+    fn flat_patch_is_a_plane() {
+        // The square [-1, 1]² at z = -1, facing the origin, with evenly spaced control points
+        let control_points = std::array::from_fn(|v| {
+            std::array::from_fn(|u| {
+                Vec3::new(2.0 * u as f32 / 3.0 - 1.0, 2.0 * v as f32 / 3.0 - 1.0, -1.0)
+            })
+        });
+        let patch = BezierPatch::new(MaterialId(0), control_points);
+
+        for i in 0..64 {
+            let target = Vec3::new((i % 8) as f32 / 4.0 - 0.9, (i / 8) as f32 / 4.0 - 0.9, -1.0);
+            let origin = Point::new(0.1 * (i % 3) as f32, 0.0, 0.5);
+            let ray = Ray::new(origin, (target - origin.vec()).normalize());
+            let FullIntersectionResult::Intersection(hit) = patch.intersection_full(ray) else {
+                panic!("the ray to {target} misses the patch");
+            };
+
+            // The hit of the plane z = -1
+            let t = (-1.0 - origin.0.z) / ray.direction.z;
+            assert!((hit.t - t).abs() < 1e-4, "{} != {t}", hit.t);
+            assert!((hit.local_info.pos.vec() - target).length() < 1e-4);
+            assert!((hit.local_info.normal - Vec3::Z).length() < 1e-5);
+            let [u, v] = hit.local_info.uv;
+            assert!((u - (target.x + 1.0) / 2.0).abs() < 1e-4);
+            assert!((v - (target.y + 1.0) / 2.0).abs() < 1e-4);
+        }
+
+        assert!(!patch
+            .intersection_full(Ray::new(Point::ORIGIN, Vec3::Z))
+            .is_intersection());
+        let outside = Ray::new(Point::ORIGIN, Vec3::new(2.0, 0.0, -1.0).normalize());
+        assert!(!patch.intersect_bare(outside).is_intersection());
+    }
+
+    #[test]
+    fn catmull_rom_interpolates() {
+        // A bump, its inner points are on the surface
+        let points = std::array::from_fn(|v| {
+            std::array::from_fn(|u| {
+                let bump = if (1..3).contains(&u) && (1..3).contains(&v) {
+                    0.5
+                } else {
+                    0.0
+                };
+                Vec3::new(u as f32, v as f32, bump)
+            })
+        });
+        let patch = BezierPatch::from_catmull_rom(MaterialId(0), points);
+        for (u, v) in [(0.0, 0.0), (1.0, 0.0), (0.0, 1.0), (1.0, 1.0)] {
+            let (p, _, _) = patch.eval(u, v);
+            let expected = points[1 + v as usize][1 + u as usize];
+            assert!((p - expected).length() < 1e-5, "{p} != {expected}");
+        }
+
+        let ray = Ray::new(Point::new(1.5, 1.5, 2.0), -Vec3::Z);
+        let hit = patch.intersection_full(ray).unwrap();
+        assert!(hit.local_info.pos.0.z >= 0.5);
+        assert!((hit.local_info.uv[0] - 0.5).abs() < 1e-4);
+    }
+}
@@ -0,0 +1,90 @@
+use crate::{
+    math::bounds::Bounds,
+    ray::Ray,
+    shape::{FullIntersectionResult, MinIntersectionResult, Shape},
+};
+
+/// Clips the bounds of every ray to `[t_min, t_max]` before intersecting the inner shape.
+///
+/// Every ray is concerned, the ones from the camera as well as the ones leaving a surface: `t_min`
+/// pushes the hits away from the origin of the rays to fix acne, `t_max` cuts off the far
+/// geometry
+pub struct ClippedShape<'a> {
+    pub inner: &'a dyn Shape,
+    pub t_min: f32,
+    pub t_max: f32,
+}
+
+impl ClippedShape<'_> {
+    fn clip(&self, mut ray: Ray) -> Ray {
+        let (start, end) = ray.bounds;
+        ray.bounds = (start.max(self.t_min), end.min(self.t_max));
+        ray
+    }
+}
+
+impl Shape for ClippedShape<'_> {
+    fn intersection_full(&self, ray: Ray) -> FullIntersectionResult {
+        self.inner.intersection_full(self.clip(ray))
+    }
+
+    fn intersection_stream(&self, rays: &[Ray]) -> Vec<FullIntersectionResult> {
+        let rays = rays.iter().map(|&ray| self.clip(ray)).collect::<Vec<_>>();
+        self.inner.intersection_stream(&rays)
+    }
+
+    fn intersect_bare(&self, ray: Ray) -> MinIntersectionResult {
+        self.inner.intersect_bare(self.clip(ray))
+    }
+
+    fn bounding_box(&self) -> Bounds {
+        self.inner.bounding_box()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        integrators::pathtracing::tests::Spheres,
+        material::MaterialId,
+        math::{point::Point, vec::Vec3},
+        ray::Ray,
+        shape::{IntersectionResult, Shape},
+    };
+
+    use super::ClippedShape;
+
+    #[test]
+    fn clipped_rays() {
+        let spheres = Spheres(vec![
+            (Point::new(-1.0, 0.0, -3.0), 0.5, MaterialId(0)),
+            (Point::new(3.0, 0.0, -20.0), 1.0, MaterialId(1)),
+        ]);
+        let clipped = ClippedShape {
+            inner: &spheres,
+            t_min: 0.0,
+            t_max: 10.0,
+        };
+        let hit = |shape: &dyn Shape, target: Vec3| match shape
+            .intersection_full(Ray::new(Point::ORIGIN, target.normalize()))
+        {
+            IntersectionResult::Intersection(hit) => Some(hit.local_info.material.0),
+            IntersectionResult::NoIntersection => None,
+        };
+
+        let (near, far) = (Vec3::new(-1.0, 0.0, -3.0), Vec3::new(3.0, 0.0, -20.0));
+        assert_eq!(hit(&clipped, near), Some(0));
+        assert_eq!(hit(&spheres, far), Some(1));
+        assert_eq!(hit(&clipped, far), None);
+
+        // Past the front of the near sphere, the ray hits its back
+        let t_min = ClippedShape {
+            inner: &spheres,
+            t_min: 2.6,
+            t_max: f32::INFINITY,
+        };
+        let ray = Ray::new(Point::new(-1.0, 0.0, 0.0), Vec3::NEG_Z);
+        let t = t_min.intersection_full(ray).unwrap().t;
+        assert!((t - 3.5).abs() < 1e-5, "{t}");
+    }
+}
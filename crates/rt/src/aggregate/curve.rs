@@ -0,0 +1,255 @@
+use glam::Vec3;
+
+use crate::{
+    material::MaterialId,
+    math::{bounds::Bounds, point::Point},
+    ray::Ray,
+    shape::{local_info, FullIntersectionResult, MinIntersectionResult, RayIntersection, Shape},
+};
+
+/// A round cubic B-spline curve, a tube whose width follows the curve, for hair and grass.
+///
+/// Each segment is approximated by a few straight pieces, each one a cylinder whose radius is the
+/// one of the curve at the closest point to the ray. The `uv` of a hit are its parameter along
+/// the curve, in [0, 1], and 0
+pub struct Curve {
+    pub material: MaterialId,
+    pub control_points: Vec<Vec3>,
+    /// Width of the curve at each control point, interpolated like the points
+    pub widths: Vec<f32>,
+    bounds: Bounds,
+}
+
+/// A hit on the curve
+#[derive(Debug, Clone, Copy)]
+struct CurveHit {
+    t: f32,
+    /// Parameter along the whole curve
+    u: f32,
+    normal: Vec3,
+}
+
+impl Curve {
+    /// Straight pieces a segment is cut into
+    const PIECES: usize = 8;
+
+    pub fn new(material: MaterialId, control_points: Vec<Vec3>, widths: Vec<f32>) -> Self {
+        assert_eq!(control_points.len(), widths.len());
+        assert!(control_points.len() >= 4, "a curve has at least 4 points");
+
+        // The curve is in the convex hull of its control points, the tube is at most as wide as
+        // the widest one
+        let radius = widths.iter().copied().fold(0.0, f32::max) / 2.0;
+        let bounds = control_points.iter().fold(Bounds::EMPTY, |b, &p| {
+            b.union_point(Point(p - radius))
+                .union_point(Point(p + radius))
+        });
+
+        Self {
+            material,
+            control_points,
+            widths,
+            bounds,
+        }
+    }
+
+    pub fn segments(&self) -> usize {
+        self.control_points.len() - 3
+    }
+
+    /// The point of the segment, and the radius of the curve there, at `t` in [0, 1]
+    pub fn eval(&self, segment: usize, t: f32) -> (Vec3, f32) {
+        let s = 1.0 - t;
+        let basis = [
+            s * s * s / 6.0,
+            (3.0 * t * t * t - 6.0 * t * t + 4.0) / 6.0,
+            (-3.0 * t * t * t + 3.0 * t * t + 3.0 * t + 1.0) / 6.0,
+            t * t * t / 6.0,
+        ];
+
+        let (mut p, mut width) = (Vec3::ZERO, 0.0);
+        for (i, b) in basis.into_iter().enumerate() {
+            p += b * self.control_points[segment + i];
+            width += b * self.widths[segment + i];
+        }
+        (p, width / 2.0)
+    }
+
+    /// The hit of the ray on the straight piece from `a` to `b` of radii `ra` and `rb`, as the
+    /// time and the position along the piece
+    fn intersect_piece(
+        ray: &Ray,
+        (a, ra): (Vec3, f32),
+        (b, rb): (Vec3, f32),
+    ) -> Option<(f32, f32)> {
+        let axis = b - a;
+        let length2 = axis.length_squared();
+        if length2 == 0.0 {
+            return None;
+        }
+
+        // The closest points of the ray and of the line of the piece
+        let w = ray.origin.vec() - a;
+        let d = ray.direction;
+        let (dd, da, aa) = (d.dot(d), d.dot(axis), length2);
+        let (dw, aw) = (d.dot(w), axis.dot(w));
+        let denom = dd * aa - da * da;
+        let s = if denom.abs() < f32::EPSILON * dd * aa {
+            aw / aa
+        } else {
+            (dd * aw - da * dw) / denom
+        }
+        .clamp(0.0, 1.0);
+        let radius = ra + s * (rb - ra);
+
+        // The cylinder of that radius around the line: |(o + td - a) x axis|² = r² |axis|²
+        let (dc, wc) = (d.cross(axis), w.cross(axis));
+        let qa = dc.length_squared();
+        let qb = 2.0 * dc.dot(wc);
+        let qc = wc.length_squared() - radius * radius * length2;
+        if qa == 0.0 {
+            return None;
+        }
+        let discriminant = qb * qb - 4.0 * qa * qc;
+        if discriminant < 0.0 {
+            return None;
+        }
+
+        let sqrt = discriminant.sqrt();
+        let (t_min, t_max) = ray.bounds;
+        [(-qb - sqrt) / (2.0 * qa), (-qb + sqrt) / (2.0 * qa)]
+            .into_iter()
+            .filter(|&t| t_min <= t && t <= t_max)
+            .map(|t| (t, (w + t * d).dot(axis) / length2))
+            .find(|(_, s)| (0.0..=1.0).contains(s))
+    }
+
+    fn closest_hit(&self, ray: &Ray) -> Option<CurveHit> {
+        self.bounds.intersect_ray(ray, ray.direction.recip())?;
+
+        let mut closest: Option<CurveHit> = None;
+        for segment in 0..self.segments() {
+            let mut start = self.eval(segment, 0.0);
+            for piece in 0..Self::PIECES {
+                let end = self.eval(segment, (piece + 1) as f32 / Self::PIECES as f32);
+                if let Some((t, s)) = Self::intersect_piece(ray, start, end) {
+                    if closest.is_none_or(|hit| t < hit.t) {
+                        // The normal is the direction from the axis, orthogonal to the tangent
+                        let tangent = (end.0 - start.0).normalize();
+                        let from_axis = ray.at(t).vec() - (start.0 + s * (end.0 - start.0));
+                        let normal = from_axis - from_axis.dot(tangent) * tangent;
+                        let u = (segment as f32 + (piece as f32 + s) / Self::PIECES as f32)
+                            / self.segments() as f32;
+                        closest = Some(CurveHit {
+                            t,
+                            u,
+                            normal: normal.normalize_or_zero(),
+                        });
+                    }
+                }
+                start = end;
+            }
+        }
+        closest
+    }
+}
+
+impl Shape for Curve {
+    fn intersection_full(&self, ray: Ray) -> FullIntersectionResult {
+        match self.closest_hit(&ray) {
+            Some(hit) => FullIntersectionResult::Intersection(RayIntersection {
+                t: hit.t,
+                local_info: local_info::Full {
+                    pos: ray.at(hit.t),
+                    normal: hit.normal,
+                    shading_normal: hit.normal,
+                    material: self.material,
+                    uv: [hit.u, 0.0],
+                    object: 0,
+                },
+            }),
+            None => FullIntersectionResult::NoIntersection,
+        }
+    }
+
+    fn intersect_bare(&self, ray: Ray) -> MinIntersectionResult {
+        match self.closest_hit(&ray) {
+            Some(hit) => MinIntersectionResult::Intersection(RayIntersection {
+                t: hit.t,
+                local_info: local_info::Minimum { pos: ray.at(hit.t) },
+            }),
+            None => MinIntersectionResult::NoIntersection,
+        }
+    }
+
+    fn bounding_box(&self) -> Bounds {
+        self.bounds
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::Vec3;
+
+    use crate::{
+        material::MaterialId,
+        math::point::Point,
+        ray::Ray,
+        shape::{FullIntersectionResult, Shape},
+    };
+
+    use super::Curve;
+
+    #[test]
+    fn straight_curve_is_a_cylinder() {
+        // Evenly spaced points on the x axis, the segment goes from x = -1 to x = 1
+        let curve = Curve::new(
+            MaterialId(0),
+            [-3.0, -1.0, 1.0, 3.0]
+                .map(|x| Vec3::new(x, 0.0, 0.0))
+                .to_vec(),
+            vec![1.0; 4],
+        );
+        // The cylinder of radius 0.5 around the x axis
+        for y in [0.0, 0.2, -0.3, 0.45] {
+            let ray = Ray::new(Point::new(0.3, y, 5.0), -Vec3::Z);
+            let FullIntersectionResult::Intersection(hit) = curve.intersection_full(ray) else {
+                panic!("the ray at {y} misses the curve");
+            };
+            let z = (0.25f32 - y * y).sqrt();
+            assert!((hit.t - (5.0 - z)).abs() < 1e-4, "{} != {}", hit.t, 5.0 - z);
+            let normal = Vec3::new(0.0, y, z) / 0.5;
+            assert!((hit.local_info.normal - normal).length() < 1e-4);
+            assert!((hit.local_info.uv[0] - 0.65).abs() < 1e-4);
+        }
+
+        assert!(!curve
+            .intersect_bare(Ray::new(Point::new(0.3, 0.6, 5.0), -Vec3::Z))
+            .is_intersection());
+        // Past the end of the segment
+        assert!(!curve
+            .intersect_bare(Ray::new(Point::new(1.2, 0.0, 5.0), -Vec3::Z))
+            .is_intersection());
+    }
+
+    #[test]
+    fn tapered_curve() {
+        let curve = Curve::new(
+            MaterialId(0),
+            [-3.0, -1.0, 1.0, 3.0]
+                .map(|x| Vec3::new(x, 0.0, 0.0))
+                .to_vec(),
+            vec![1.0, 1.0, 0.2, 0.2],
+        );
+        let (_, root) = curve.eval(0, 0.0);
+        let (_, tip) = curve.eval(0, 1.0);
+        assert!(root > tip);
+
+        let hits = |x: f32| {
+            let ray = Ray::new(Point::new(x, 0.3, 5.0), -Vec3::Z);
+            curve.intersect_bare(ray).is_intersection()
+        };
+        assert!(hits(-0.9));
+        assert!(!hits(0.9));
+    }
+}
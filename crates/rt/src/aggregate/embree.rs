@@ -1,4 +1,12 @@
-use std::{collections::BTreeMap, mem::size_of};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    mem::size_of,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::Sender,
+        Arc, Mutex,
+    },
+};
 
 use anyhow::Result;
 use embree4_rs::{
@@ -9,22 +17,36 @@ use embree4_rs::{
 use embree4_sys::{RTCGeometry, RTCSceneFlags};
 
 use crate::{
-    material::{DiffuseBxDF, MaterialDescriptor, MaterialId},
-    math::point::Point,
+    aggregate::triangle_mesh::skip_degenerate_triangles,
+    color::Rgb,
+    light::{Light, Lights, QuadEmitter, SphereEmitter, SphereLight},
+    material::{texture::Uniform, EmitBxDF, MaterialDescriptor, MaterialId, TexturedEmit},
+    math::{distributions::sphere_uv_from_direction, point::Point},
     renderer::World,
-    scene::SceneT,
-    shape::{local_info, FullIntersectionResult, Shape},
+    scene::{emissive_quad_material, emissive_sphere_material, quad_mesh, SceneT},
+    shape::{local_info, FullIntersectionResult, MinIntersectionResult, Shape},
+    utils::counter::counter,
 };
 
 pub struct EmbreeScene<'a> {
     device: &'a Device,
     scene: Scene<'a>,
     pub materials: Vec<MaterialDescriptor>,
-    pub lights: Vec<Point>,
+    pub lights: Vec<Box<dyn Light>>,
     pub geometry_material: BTreeMap<<Self as SceneT>::GeometryHandle, MaterialId>,
+    /// The sphere geometries, their uv are given by [sphere_uv_from_direction]
+    spheres: BTreeSet<<Self as SceneT>::GeometryHandle>,
+    /// The meshes inserted with normals
+    normals: BTreeMap<<Self as SceneT>::GeometryHandle, VertexNormals>,
     sky_material: MaterialId,
 }
 
+/// The normals of the vertices of a mesh, along with its triangles as Embree numbers them
+struct VertexNormals {
+    normals: Vec<glam::Vec3>,
+    triangles: Vec<[u32; 3]>,
+}
+
 impl<'a> EmbreeScene<'a> {
     pub fn new(device: &'a Device) -> Self {
         let scene = Scene::try_new(
@@ -40,12 +62,16 @@ impl<'a> EmbreeScene<'a> {
             scene,
             materials: vec![MaterialDescriptor {
                 label: Some("Sky".into()),
-                material: Box::new(DiffuseBxDF {
-                    albedo: [0.0, 0.0, 0.0].into(),
+                material: Box::new(EmitBxDF {
+                    le: [0.5, 0.3, 1.0].into(),
+                    two_sided: true,
                 }),
+                alpha: None,
             }],
             lights: Default::default(),
             geometry_material: Default::default(),
+            spheres: Default::default(),
+            normals: Default::default(),
             sky_material: MaterialId(0),
         }
     }
@@ -62,25 +88,101 @@ impl<'a> EmbreeScene<'a> {
 
     pub fn commit<'c>(&'c mut self) -> Result<CommittedEmbreeScene<'c, 'a>> {
         let commited = self.scene.commit()?;
+        let scene: &'c Self = self;
         Ok(CommittedEmbreeScene {
-            scene: self,
+            lights: Lights::new(&scene.lights),
+            scene,
             commited,
         })
     }
-    pub fn commit_with_progress<'c, F: FnMut(f64) -> bool>(
+
+    /// Commit the scene, sending the progress of the build in [0, 1] to `progress`. The build
+    /// stops with a [BuildCancelled] error once `cancel` is set
+    pub fn commit_with_progress<'c>(
         &'c mut self,
-        progress_callback: F,
+        progress: Sender<f64>,
+        cancel: Arc<AtomicBool>,
     ) -> Result<CommittedEmbreeScene<'c, 'a>> {
-        let _p = self
-            .scene
-            .register_scene_progress_monitor_callback(progress_callback);
-        self.commit()
+        let forwarder = Arc::new(ProgressForwarder::new(progress, cancel.clone()));
+        let callback = {
+            let forwarder = forwarder.clone();
+            move |amount| forwarder.report(amount)
+        };
+        let commited = {
+            let _guard = self
+                .scene
+                .register_scene_progress_monitor_callback(callback);
+            self.scene.commit()
+        };
+        // Embree may keep the callback, the channel is closed anyway
+        forwarder.close();
+
+        if cancel.load(Ordering::SeqCst) {
+            return Err(BuildCancelled.into());
+        }
+        let scene: &'c Self = self;
+        Ok(CommittedEmbreeScene {
+            lights: Lights::new(&scene.lights),
+            scene,
+            commited: commited?,
+        })
+    }
+}
+
+/// The build of a scene was cancelled before the end
+#[derive(Debug, Clone, Copy)]
+pub struct BuildCancelled;
+
+impl std::fmt::Display for BuildCancelled {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "the build of the scene was cancelled")
+    }
+}
+
+impl std::error::Error for BuildCancelled {}
+
+/// Forwards the progress reported by the build threads of Embree to a channel.
+///
+/// The threads report in any order, only the progress above the last one sent is forwarded so
+/// that the receiver sees it increase
+pub struct ProgressForwarder {
+    /// The sender, None once closed, along with the last progress sent
+    sender: Mutex<(Option<Sender<f64>>, f64)>,
+    cancel: Arc<AtomicBool>,
+}
+
+impl ProgressForwarder {
+    pub fn new(sender: Sender<f64>, cancel: Arc<AtomicBool>) -> Self {
+        Self {
+            sender: Mutex::new((Some(sender), f64::NEG_INFINITY)),
+            cancel,
+        }
+    }
+
+    /// The callback of the build, returns whether it should go on
+    pub fn report(&self, amount: f64) -> bool {
+        let mut sender = self.sender.lock().unwrap();
+        let (channel, last) = &mut *sender;
+        if amount > *last {
+            *last = amount;
+            if let Some(channel) = channel {
+                // The receiver may not care anymore
+                let _ = channel.send(amount);
+            }
+        }
+        !self.cancel.load(Ordering::SeqCst)
+    }
+
+    /// Nothing is sent anymore, the receiver sees the channel closed
+    pub fn close(&self) {
+        self.sender.lock().unwrap().0 = None;
     }
 }
 
 pub struct CommittedEmbreeScene<'a, 'b> {
     scene: &'a EmbreeScene<'b>,
     commited: CommittedScene<'b>,
+    lights: Lights<'a>,
 }
 
 unsafe impl Send for CommittedEmbreeScene<'_, '_> {}
@@ -90,15 +192,68 @@ impl<'a, 'b> CommittedEmbreeScene<'a, 'b> {
     pub fn into_world(&self) -> Result<World> {
         Ok(World {
             objects: self,
-            lights: &self.scene.lights,
+            lights: &self.lights,
             materials: &self.scene.materials,
             world_material: self.scene.sky_material,
+            fog: None,
+        })
+    }
+}
+
+impl CommittedEmbreeScene<'_, '_> {
+    fn intersection_from_hit(
+        &self,
+        t: f32,
+        pos: Point,
+        normal: glam::Vec3,
+        geom_id: u32,
+        prim_id: u32,
+        uv: [f32; 2],
+    ) -> FullIntersectionResult {
+        let normal = normal.normalize_or_zero();
+        let shading_normal = match self.scene.normals.get(&geom_id) {
+            Some(VertexNormals { normals, triangles }) => {
+                let [u, v] = uv;
+                let [n0, n1, n2] = triangles[prim_id as usize].map(|i| normals[i as usize]);
+                ((1.0 - u - v) * n0 + u * n1 + v * n2).normalize_or_zero()
+            }
+            None => normal,
+        };
+        // Embree's uv of the spheres are not a mapping of the sphere
+        let uv = if self.scene.spheres.contains(&geom_id) {
+            sphere_uv_from_direction(normal)
+        } else {
+            uv
+        };
+        FullIntersectionResult::Intersection(crate::shape::RayIntersection {
+            t,
+            local_info: local_info::Full {
+                pos,
+                normal,
+                shading_normal,
+                material: self
+                    .scene
+                    .geometry_material
+                    .get(&geom_id)
+                    .copied()
+                    .unwrap_or(MaterialId(0)),
+                uv,
+                object: geom_id,
+            },
         })
     }
 }
 
+/// Size of the ray packets used by [Shape::intersection_stream]
+const PACKET_SIZE: usize = 16;
+
+/// The valid mask of a ray packet, Embree requires it to be aligned as the packet
+#[repr(C, align(64))]
+struct PacketMask([i32; PACKET_SIZE]);
+
 impl Shape for CommittedEmbreeScene<'_, '_> {
     fn intersection_full(&self, ray: crate::ray::Ray) -> crate::shape::FullIntersectionResult {
+        counter!("Intersection rays");
         let r = embree4_sys::RTCRay {
             org_x: ray.origin.0.x,
             org_y: ray.origin.0.y,
@@ -112,35 +267,127 @@ impl Shape for CommittedEmbreeScene<'_, '_> {
         };
 
         match self.commited.intersect_1(r).unwrap() {
-            Some(res) => FullIntersectionResult::Intersection(crate::shape::RayIntersection {
-                t: res.ray.tfar,
-                local_info: local_info::Full {
-                    pos: Point::new(
-                        res.ray.org_x + res.ray.tfar * res.ray.dir_x,
-                        res.ray.org_y + res.ray.tfar * res.ray.dir_y,
-                        res.ray.org_z + res.ray.tfar * res.ray.dir_z,
-                    ),
-                    normal: glam::Vec3 {
-                        x: res.hit.Ng_x,
-                        y: res.hit.Ng_y,
-                        z: res.hit.Ng_z,
-                    }
-                    .normalize_or_zero(),
-                    material: self
-                        .scene
-                        .geometry_material
-                        .get(&res.hit.geomID)
-                        .copied()
-                        .unwrap_or(MaterialId(0)),
-                    uv: [res.hit.u, res.hit.v],
+            Some(res) => self.intersection_from_hit(
+                res.ray.tfar,
+                Point::new(
+                    res.ray.org_x + res.ray.tfar * res.ray.dir_x,
+                    res.ray.org_y + res.ray.tfar * res.ray.dir_y,
+                    res.ray.org_z + res.ray.tfar * res.ray.dir_z,
+                ),
+                glam::Vec3 {
+                    x: res.hit.Ng_x,
+                    y: res.hit.Ng_y,
+                    z: res.hit.Ng_z,
                 },
-            }),
+                res.hit.geomID,
+                res.hit.primID,
+                [res.hit.u, res.hit.v],
+            ),
             None => FullIntersectionResult::NoIntersection,
         }
     }
 
-    fn intersect_bare(&self, _ray: crate::ray::Ray) -> crate::shape::MinIntersectionResult {
-        todo!()
+    /// Rays are intersected by packets of [PACKET_SIZE]. Embree 4 dropped the stream functions of
+    /// Embree 3 (`rtcIntersect1M` and the like): the packets are what is left to trace many rays
+    /// at once
+    fn intersection_stream(&self, rays: &[crate::ray::Ray]) -> Vec<FullIntersectionResult> {
+        let mut results = Vec::with_capacity(rays.len());
+
+        for packet in rays.chunks(PACKET_SIZE) {
+            let mut valid = PacketMask([0; PACKET_SIZE]);
+            let mut rayhit = embree4_sys::RTCRayHit16::default();
+            for (i, ray) in packet.iter().enumerate() {
+                counter!("Intersection rays");
+                valid.0[i] = -1;
+                rayhit.ray.org_x[i] = ray.origin.0.x;
+                rayhit.ray.org_y[i] = ray.origin.0.y;
+                rayhit.ray.org_z[i] = ray.origin.0.z;
+                rayhit.ray.dir_x[i] = ray.direction.x;
+                rayhit.ray.dir_y[i] = ray.direction.y;
+                rayhit.ray.dir_z[i] = ray.direction.z;
+                rayhit.ray.tnear[i] = ray.bounds.0;
+                rayhit.ray.tfar[i] = ray.bounds.1;
+                // The defaults of the scalar rays, the ones of the packets are zeroed
+                rayhit.ray.mask[i] = u32::MAX;
+                rayhit.hit.geomID[i] = embree4_sys::RTC_INVALID_GEOMETRY_ID;
+                rayhit.hit.instID[0][i] = embree4_sys::RTC_INVALID_GEOMETRY_ID;
+            }
+
+            unsafe {
+                embree4_sys::rtcIntersect16(
+                    valid.0.as_ptr(),
+                    self.commited.as_raw_handle(),
+                    &mut rayhit,
+                    std::ptr::null_mut(),
+                );
+            }
+            if let Some(err) = self.scene.device.error() {
+                panic!("Failed to intersect ray packet: {:?}", err);
+            }
+
+            let (r, h) = (&rayhit.ray, &rayhit.hit);
+            results.extend((0..packet.len()).map(|i| {
+                if h.geomID[i] == embree4_sys::RTC_INVALID_GEOMETRY_ID {
+                    return FullIntersectionResult::NoIntersection;
+                }
+
+                self.intersection_from_hit(
+                    r.tfar[i],
+                    Point::new(
+                        r.org_x[i] + r.tfar[i] * r.dir_x[i],
+                        r.org_y[i] + r.tfar[i] * r.dir_y[i],
+                        r.org_z[i] + r.tfar[i] * r.dir_z[i],
+                    ),
+                    glam::Vec3 {
+                        x: h.Ng_x[i],
+                        y: h.Ng_y[i],
+                        z: h.Ng_z[i],
+                    },
+                    h.geomID[i],
+                    h.primID[i],
+                    [h.u[i], h.v[i]],
+                )
+            }));
+        }
+
+        results
+    }
+
+    /// Embree's occlusion query only tells whether something lies within the bounds of the ray,
+    /// not where: the intersection is reported at the far end of the ray.
+    fn intersect_bare(&self, ray: crate::ray::Ray) -> MinIntersectionResult {
+        counter!("Shadow rays");
+        let mut r = embree4_sys::RTCRay {
+            org_x: ray.origin.0.x,
+            org_y: ray.origin.0.y,
+            org_z: ray.origin.0.z,
+            dir_x: ray.direction.x,
+            dir_y: ray.direction.y,
+            dir_z: ray.direction.z,
+            tnear: ray.bounds.0,
+            tfar: ray.bounds.1,
+            ..Default::default()
+        };
+
+        unsafe {
+            embree4_sys::rtcOccluded1(self.commited.as_raw_handle(), &mut r, std::ptr::null_mut());
+        }
+        if let Some(err) = self.scene.device.error() {
+            panic!("Failed to intersect occlusion ray: {:?}", err);
+        }
+
+        // tfar is set to -inf when the ray is occluded
+        if r.tfar == f32::NEG_INFINITY {
+            let t = ray.bounds.1;
+            MinIntersectionResult::Intersection(crate::shape::RayIntersection {
+                t,
+                local_info: local_info::Minimum {
+                    pos: ray.at_unchecked(t),
+                },
+            })
+        } else {
+            MinIntersectionResult::NoIntersection
+        }
     }
 
     fn bounding_box(&self) -> crate::math::bounds::Bounds {
@@ -171,7 +418,7 @@ impl SceneT for EmbreeScene<'_> {
     }
 
     fn insert_light(&mut self, light: crate::material::LightDescriptor) {
-        self.lights.push(light.light_pos);
+        self.lights.push(light.light);
     }
 
     fn insert_mesh(
@@ -180,6 +427,14 @@ impl SceneT for EmbreeScene<'_> {
         vertices: &[[f32; 3]],
         indices: &[[u32; 3]],
     ) -> Self::GeometryHandle {
+        let positions = vertices
+            .iter()
+            .map(|&p| glam::Vec3::from_array(p))
+            .collect::<Vec<_>>();
+        let mut valid_indices = indices.to_vec();
+        skip_degenerate_triangles(&positions, &mut valid_indices);
+        let indices = &valid_indices;
+
         let geometry = {
             let geometry = unsafe {
                 embree4_sys::rtcNewGeometry(
@@ -252,6 +507,33 @@ impl SceneT for EmbreeScene<'_> {
         self.insert_geometry(material, &geometry)
     }
 
+    fn insert_mesh_with_normals(
+        &mut self,
+        material: MaterialId,
+        vertices: &[[f32; 3]],
+        normals: &[[f32; 3]],
+        indices: &[[u32; 3]],
+    ) -> Self::GeometryHandle {
+        assert_eq!(normals.len(), vertices.len());
+        // The triangles that are kept, the hits give their index among them
+        let positions = vertices
+            .iter()
+            .map(|&p| glam::Vec3::from_array(p))
+            .collect::<Vec<_>>();
+        let mut valid_indices = indices.to_vec();
+        skip_degenerate_triangles(&positions, &mut valid_indices);
+
+        let geom_id = self.insert_mesh(material, vertices, &valid_indices);
+        self.normals.insert(
+            geom_id,
+            VertexNormals {
+                normals: normals.iter().map(|&n| glam::Vec3::from_array(n)).collect(),
+                triangles: valid_indices,
+            },
+        );
+        geom_id
+    }
+
     fn insert_sphere(
         &mut self,
         material: MaterialId,
@@ -261,7 +543,122 @@ impl SceneT for EmbreeScene<'_> {
         let geom =
             SphereGeometry::try_new(self.device, (center.0.x, center.0.y, center.0.z), radius)
                 .unwrap();
-        self.insert_geometry(material, &geom)
+        let geom_id = self.insert_geometry(material, &geom);
+        self.spheres.insert(geom_id);
+        geom_id
+    }
+
+    fn insert_sphere_light(&mut self, le: Rgb, center: Point, radius: f32) -> Self::GeometryHandle {
+        let material = self.insert_material(emissive_sphere_material(le));
+        let object = self.insert_sphere(material, center, radius);
+        self.lights.push(Box::new(SphereEmitter {
+            sphere: SphereLight { center, radius },
+            le,
+            object,
+        }));
+        object
+    }
+
+    fn insert_quad_light(
+        &mut self,
+        le: Rgb,
+        corner: Point,
+        edges: [glam::Vec3; 2],
+    ) -> Self::GeometryHandle {
+        let material = self.insert_material(emissive_quad_material(le));
+        let (vertices, indices) = quad_mesh(corner, edges);
+        let object = self.insert_mesh(material, &vertices, &indices);
+        self.lights.push(Box::new(QuadEmitter {
+            corner,
+            edges,
+            emission: TexturedEmit {
+                le: Box::new(Uniform(le)),
+                two_sided: false,
+            },
+            exponent: 0.0,
+            object: Some(object),
+        }));
+        object
+    }
+
+    fn insert_curve(
+        &mut self,
+        material: MaterialId,
+        control_points: &[[f32; 3]],
+        widths: &[f32],
+    ) -> Self::GeometryHandle {
+        assert_eq!(control_points.len(), widths.len());
+        assert!(control_points.len() >= 4, "a curve has at least 4 points");
+
+        let geometry = {
+            let geometry = unsafe {
+                embree4_sys::rtcNewGeometry(
+                    self.device.as_raw_handle(),
+                    embree4_sys::RTCGeometryType::ROUND_BSPLINE_CURVE,
+                )
+            };
+            if geometry.is_null() {
+                panic!("Failed to create geometry: {:?}", self.device.error());
+            }
+
+            // Embree wants the radius along with each point
+            let vertices = control_points
+                .iter()
+                .zip(widths)
+                .map(|(&[x, y, z], &width)| [x, y, z, width / 2.0])
+                .collect::<Vec<_>>();
+            let vertex_buf_ptr = unsafe {
+                embree4_sys::rtcSetNewGeometryBuffer(
+                    geometry,
+                    embree4_sys::RTCBufferType::VERTEX,
+                    0,
+                    embree4_sys::RTCFormat::FLOAT4,
+                    4 * size_of::<f32>(),
+                    vertices.len(),
+                )
+            };
+            if vertex_buf_ptr.is_null() {
+                panic!(
+                    "Failed to create curve vertex buffer: {:?}",
+                    self.device.error()
+                );
+            }
+            unsafe {
+                std::slice::from_raw_parts_mut(vertex_buf_ptr as *mut f32, 4 * vertices.len())
+            }
+            .copy_from_slice(bytemuck::cast_slice(&vertices));
+
+            // A segment starts at each of the points but the last 3
+            let indices = (0..control_points.len() as u32 - 3).collect::<Vec<_>>();
+            let index_buf_ptr = unsafe {
+                embree4_sys::rtcSetNewGeometryBuffer(
+                    geometry,
+                    embree4_sys::RTCBufferType::INDEX,
+                    0,
+                    embree4_sys::RTCFormat::UINT,
+                    size_of::<u32>(),
+                    indices.len(),
+                )
+            };
+            if index_buf_ptr.is_null() {
+                panic!(
+                    "Failed to create curve index buffer: {:?}",
+                    self.device.error()
+                );
+            }
+            unsafe { std::slice::from_raw_parts_mut(index_buf_ptr as *mut u32, indices.len()) }
+                .copy_from_slice(&indices);
+
+            unsafe {
+                embree4_sys::rtcCommitGeometry(geometry);
+            }
+            if let Some(err) = self.device.error() {
+                panic!("Failed to create curve {:?}", err);
+            }
+
+            CustomGeometry { handle: geometry }
+        };
+        self.insert_geometry(material, &geometry)
     }
 }
 
@@ -282,3 +679,151 @@ impl Geometry for CustomGeometry {
         self.handle
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    };
+
+    use embree4_rs::device::Device;
+    use glam::Vec3;
+
+    use crate::{
+        math::point::Point,
+        ray::Ray,
+        scene::{examples::SpheresScene, SceneT},
+        shape::{IntersectionResult, Shape},
+        utils::timer::timed_scope_log,
+    };
+
+    use super::{EmbreeScene, Geometry, ProgressForwarder, SphereGeometry};
+
+    #[test]
+    fn monotonic_progress() {
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let cancel = Arc::new(AtomicBool::new(false));
+        let forwarder = ProgressForwarder::new(sender, cancel.clone());
+
+        // Each build thread reports its own progress, the threads are not in step
+        std::thread::scope(|scope| {
+            for thread in 0..4 {
+                let forwarder = &forwarder;
+                scope.spawn(move || {
+                    for step in 0..=100 {
+                        let amount = (step as f64 / 100.0 + thread as f64 * 0.01).min(1.0);
+                        assert!(forwarder.report(amount));
+                    }
+                });
+            }
+        });
+        forwarder.close();
+        let received = receiver.iter().collect::<Vec<_>>();
+        assert!(!received.is_empty());
+        assert!(received.windows(2).all(|w| w[0] < w[1]), "{received:?}");
+        assert_eq!(received.last(), Some(&1.0));
+
+        cancel.store(true, Ordering::SeqCst);
+        assert!(!forwarder.report(0.5));
+    }
+
+    #[test]
+    fn stream_matches_scalar() {
+        let device = Device::try_new(None).unwrap();
+        let mut scene = EmbreeScene::new(&device);
+        SpheresScene::insert_into(&mut scene);
+        let material = scene.sky_material;
+        scene.insert_mesh(
+            material,
+            &[[-1.0, -0.2, -2.0], [1.0, -0.2, -2.0], [0.0, -0.2, 1.0]],
+            &[[0, 1, 2]],
+        );
+        // A geometry with a mask of its own, that the rays see as well
+        let masked = SphereGeometry::try_new(&device, (1.2, 0.9, -1.5), 0.2).unwrap();
+        unsafe {
+            embree4_sys::rtcSetGeometryMask(masked.geometry(), 0b10);
+            embree4_sys::rtcCommitGeometry(masked.geometry());
+        }
+        let masked_id = scene.insert_geometry(material, &masked);
+        let scene = scene.commit().unwrap();
+
+        let rays = (0..10_000)
+            .map(|i| {
+                let (x, y) = ((i % 100) as f32 / 50.0 - 1.0, (i / 100) as f32 / 50.0 - 1.0);
+                Ray::new(Point::ORIGIN, Vec3::new(x, y, -1.0).normalize())
+            })
+            .collect::<Vec<_>>();
+
+        let scalar = timed_scope_log("scalar intersection", || {
+            rays.iter()
+                .map(|&ray| scene.intersection_full(ray))
+                .collect::<Vec<_>>()
+        })
+        .res;
+        let stream =
+            timed_scope_log("stream intersection", || scene.intersection_stream(&rays)).res;
+
+        assert_eq!(scalar.len(), stream.len());
+        assert!(scalar.iter().any(|hit| matches!(
+            hit,
+            IntersectionResult::Intersection(hit) if hit.local_info.object == masked_id
+        )));
+        for (a, b) in scalar.into_iter().zip(stream) {
+            match (a, b) {
+                (IntersectionResult::Intersection(a), IntersectionResult::Intersection(b)) => {
+                    assert_eq!(a.local_info.object, b.local_info.object);
+                    assert_eq!(a.t, b.t);
+                    assert_eq!(a.local_info.pos, b.local_info.pos);
+                    assert_eq!(a.local_info.normal, b.local_info.normal);
+                    assert_eq!(a.local_info.material.0, b.local_info.material.0);
+                    assert_eq!(a.local_info.uv, b.local_info.uv);
+                }
+                (IntersectionResult::NoIntersection, IntersectionResult::NoIntersection) => (),
+                _ => panic!("stream and scalar intersections disagree"),
+            }
+        }
+    }
+
+    #[test]
+    fn occlusion() {
+        let device = Device::try_new(None).unwrap();
+        let mut scene = EmbreeScene::new(&device);
+        let material = scene.sky_material;
+        scene.insert_sphere(material, Point::new(0.0, 0.0, -2.0), 0.5);
+        let scene = scene.commit().unwrap();
+
+        let blocked = Ray::new_with_range(Point::ORIGIN, Vec3::NEG_Z, 0.0..4.0);
+        assert!(scene.intersect_bare(blocked).is_intersection());
+
+        let too_short = Ray::new_with_range(Point::ORIGIN, Vec3::NEG_Z, 0.0..1.0);
+        assert!(!scene.intersect_bare(too_short).is_intersection());
+
+        let unblocked = Ray::new_with_range(Point::ORIGIN, Vec3::Z, 0.0..4.0);
+        assert!(!scene.intersect_bare(unblocked).is_intersection());
+    }
+
+    #[test]
+    fn quad_light_is_an_object() {
+        let device = Device::try_new(None).unwrap();
+        let mut scene = EmbreeScene::new(&device);
+        // Above the origin, facing it
+        scene.insert_quad_light(
+            [1.0, 1.0, 1.0].into(),
+            Point::new(-1.0, 1.0, -1.0),
+            [Vec3::X * 2.0, Vec3::Z * 2.0],
+        );
+        let scene = scene.commit().unwrap();
+
+        // The light of the quad is found from its hits, to weight them with the light samples
+        let hit = scene
+            .intersection_full(Ray::new(Point::ORIGIN, Vec3::Y))
+            .unwrap();
+        assert_eq!(hit.local_info.normal, Vec3::NEG_Y);
+        let (light, _) = scene
+            .lights
+            .object_light(hit.local_info.object, Point::ORIGIN, Some(Vec3::Y))
+            .unwrap();
+        assert!(light.pdf_li(Point::ORIGIN, Vec3::Y) > 0.0);
+    }
+}
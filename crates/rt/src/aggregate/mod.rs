@@ -1 +1,6 @@
+pub mod bezier_patch;
+pub mod clipped;
+pub mod curve;
 pub mod embree;
+pub mod nested;
+pub mod triangle_mesh;
@@ -0,0 +1,278 @@
+//! Aggregates of shapes, that can be nested: a scene can be made of scenes, each one instanced
+//! with its own transform.
+use std::sync::Arc;
+
+use glam::Vec3;
+
+use crate::{
+    math::{
+        bounds::Bounds,
+        point::Point,
+        transform::{Transform, Transformer},
+    },
+    ray::Ray,
+    shape::{
+        local_info, FullIntersectionResult, IntersectionResult, MinIntersectionResult,
+        RayIntersection, Shape,
+    },
+};
+
+/// Shapes intersected together, the closest hit wins.
+///
+/// The children are culled by their bounds and the ray is shortened each time a child is hit, a
+/// hierarchy is built by nesting aggregates
+pub struct Aggregate {
+    children: Vec<(Box<dyn Shape>, Bounds)>,
+    bounds: Bounds,
+}
+
+impl Default for Aggregate {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Aggregate {
+    pub fn new() -> Self {
+        Self {
+            children: Vec::new(),
+            bounds: Bounds::EMPTY,
+        }
+    }
+
+    pub fn push(&mut self, child: Box<dyn Shape>) {
+        let bounds = child.bounding_box();
+        self.bounds = self.bounds.union(bounds);
+        self.children.push((child, bounds));
+    }
+
+    /// The closest hit among the children whose bounds are hit
+    fn closest<T>(
+        &self,
+        mut ray: Ray,
+        intersect: impl Fn(&dyn Shape, Ray) -> IntersectionResult<T>,
+    ) -> IntersectionResult<T> {
+        let inv_dir = ray.direction.recip();
+        if self.bounds.intersect_ray(&ray, inv_dir).is_none() {
+            return IntersectionResult::NoIntersection;
+        }
+
+        let mut closest = IntersectionResult::NoIntersection;
+        for (child, bounds) in &self.children {
+            if bounds.intersect_ray(&ray, inv_dir).is_none() {
+                continue;
+            }
+            if let hit @ IntersectionResult::Intersection(RayIntersection { t, .. }) =
+                intersect(child.as_ref(), ray)
+            {
+                ray.bounds.1 = t;
+                closest = hit;
+            }
+        }
+        closest
+    }
+}
+
+impl Shape for Aggregate {
+    fn intersection_full(&self, ray: Ray) -> FullIntersectionResult {
+        self.closest(ray, |child, ray| child.intersection_full(ray))
+    }
+
+    fn intersect_bare(&self, ray: Ray) -> MinIntersectionResult {
+        self.closest(ray, |child, ray| child.intersect_bare(ray))
+    }
+
+    fn bounding_box(&self) -> Bounds {
+        self.bounds
+    }
+}
+
+/// A shape placed in the world by a transform, the same shape can be shared by many instances.
+///
+/// The rays are brought into the space of the shape, their direction normalized there, and the
+/// hits are brought back into the world with the distances along the ray of the world
+pub struct Instance {
+    pub shape: Arc<dyn Shape>,
+    transform: Transform,
+    bounds: Bounds,
+}
+
+impl Instance {
+    pub fn new(shape: Arc<dyn Shape>, transform: Transform) -> Self {
+        let local = shape.bounding_box();
+        let corners = (0..8)
+            .map(|corner| {
+                let pick = |axis: usize, origin: f32, end: f32| {
+                    if corner >> axis & 1 == 0 {
+                        origin
+                    } else {
+                        end
+                    }
+                };
+                let (Point(origin), Point(end)) = (local.origin, local.end);
+                transform.apply(Point::new(
+                    pick(0, origin.x, end.x),
+                    pick(1, origin.y, end.y),
+                    pick(2, origin.z, end.z),
+                ))
+            })
+            .collect::<Vec<_>>();
+        Self {
+            shape,
+            transform,
+            bounds: Bounds::from_points(&corners),
+        }
+    }
+
+    /// The ray in the space of the shape, and the length there of a unit of distance of the world
+    fn to_local(&self, ray: Ray) -> (Ray, f32) {
+        let Transform {
+            translation,
+            scale,
+            rot,
+        } = &self.transform;
+        let origin = rot.inverse() * (ray.origin - *translation).vec() / *scale;
+        let direction = rot.inverse() * ray.direction / *scale;
+        let length = direction.length();
+
+        let mut local = Ray::new(Point(origin), direction / length);
+        local.bounds = (ray.bounds.0 * length, ray.bounds.1 * length);
+        (local, length)
+    }
+
+    /// A normal of the space of the shape in the world, through the inverse transpose of the
+    /// transform
+    fn normal_to_world(&self, normal: Vec3) -> Vec3 {
+        (self.transform.rot * (normal / self.transform.scale)).normalize()
+    }
+}
+
+impl Shape for Instance {
+    fn intersection_full(&self, ray: Ray) -> FullIntersectionResult {
+        let (local, length) = self.to_local(ray);
+        match self.shape.intersection_full(local) {
+            IntersectionResult::Intersection(RayIntersection { t, local_info }) => {
+                let t = t / length;
+                IntersectionResult::Intersection(RayIntersection {
+                    t,
+                    local_info: local_info::Full {
+                        pos: ray.at(t),
+                        normal: self.normal_to_world(local_info.normal),
+                        shading_normal: self.normal_to_world(local_info.shading_normal),
+                        ..local_info
+                    },
+                })
+            }
+            IntersectionResult::NoIntersection => IntersectionResult::NoIntersection,
+        }
+    }
+
+    fn intersect_bare(&self, ray: Ray) -> MinIntersectionResult {
+        let (local, length) = self.to_local(ray);
+        match self.shape.intersect_bare(local) {
+            IntersectionResult::Intersection(RayIntersection { t, .. }) => {
+                let t = t / length;
+                IntersectionResult::Intersection(RayIntersection {
+                    t,
+                    local_info: local_info::Minimum { pos: ray.at(t) },
+                })
+            }
+            IntersectionResult::NoIntersection => IntersectionResult::NoIntersection,
+        }
+    }
+
+    fn bounding_box(&self) -> Bounds {
+        self.bounds
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use glam::{Quat, Vec3};
+    use rand::{Rng, SeedableRng};
+
+    use crate::{
+        integrators::pathtracing::tests::Spheres,
+        material::MaterialId,
+        math::{
+            point::Point,
+            transform::{Transform, Transformer},
+        },
+        ray::Ray,
+        shape::{IntersectionResult, Shape},
+    };
+
+    use super::{Aggregate, Instance};
+
+    #[test]
+    fn nested_matches_flattened() {
+        let local = [
+            (Point::new(0.0, 0.0, 0.0), 0.5, MaterialId(0)),
+            (Point::new(1.0, 0.5, 0.0), 0.25, MaterialId(1)),
+        ];
+        let shared: Arc<dyn Shape> = Arc::new(Spheres(local.to_vec()));
+        let transforms = [
+            Transform {
+                translation: Vec3::new(0.0, 0.0, -4.0),
+                scale: Vec3::splat(2.0),
+                rot: Quat::from_rotation_y(0.7),
+            },
+            Transform {
+                translation: Vec3::new(-1.5, 1.0, -3.0),
+                scale: Vec3::splat(0.5),
+                rot: Quat::from_rotation_x(-1.2) * Quat::from_rotation_z(0.3),
+            },
+        ];
+        let other = (Point::new(1.5, -1.0, -3.0), 0.7, MaterialId(2));
+
+        // The same spheres, all in the world
+        let mut flattened = vec![other];
+        for transform in &transforms {
+            for &(center, radius, material) in &local {
+                // The scales are uniform
+                flattened.push((
+                    transform.apply(center),
+                    radius * transform.scale.x,
+                    material,
+                ));
+            }
+        }
+        let flattened = Spheres(flattened);
+
+        // Two levels: an instance next to an aggregate of the other instance and of a sphere
+        let [first, second] = transforms;
+        let mut inner = Aggregate::new();
+        inner.push(Box::new(Instance::new(shared.clone(), second)));
+        inner.push(Box::new(Spheres(vec![other])));
+        let mut nested = Aggregate::new();
+        nested.push(Box::new(Instance::new(shared, first)));
+        nested.push(Box::new(inner));
+
+        let mut rng = crate::Rng::seed_from_u64(4);
+        let mut hits = 0;
+        for _ in 0..2000 {
+            let direction = Vec3::new(rng.gen_range(-0.8..0.8), rng.gen_range(-0.8..0.8), -1.0);
+            let ray = Ray::new(Point::ORIGIN, direction.normalize());
+            match (
+                flattened.intersection_full(ray),
+                nested.intersection_full(ray),
+            ) {
+                (IntersectionResult::Intersection(f), IntersectionResult::Intersection(n)) => {
+                    hits += 1;
+                    assert!((f.t - n.t).abs() < 1e-4, "{} != {}", f.t, n.t);
+                    assert!((f.local_info.pos - n.local_info.pos).length() < 1e-4);
+                    assert!((f.local_info.normal - n.local_info.normal).length() < 1e-3);
+                    assert_eq!(f.local_info.material.0, n.local_info.material.0);
+                    assert!(nested.intersect_bare(ray).is_intersection());
+                }
+                (IntersectionResult::NoIntersection, IntersectionResult::NoIntersection) => {
+                    assert!(!nested.intersect_bare(ray).is_intersection());
+                }
+                (f, n) => panic!("{ray:?}: {f:?} != {n:?}"),
+            }
+        }
+        assert!(hits > 200, "{hits}");
+    }
+}
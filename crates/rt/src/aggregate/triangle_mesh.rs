@@ -0,0 +1,328 @@
+use glam::Vec3;
+
+use crate::{
+    material::MaterialId,
+    math::{bounds::Bounds, point::Point},
+    ray::Ray,
+    shape::{local_info, FullIntersectionResult, MinIntersectionResult, RayIntersection, Shape},
+    utils::counter::counter,
+};
+
+/// A mesh stored as flat arrays of vertices and indices, intersected by testing every triangle
+pub struct TriangleMesh {
+    pub material: MaterialId,
+    pub positions: Vec<Vec3>,
+    /// Normals of the vertices, interpolated over the triangles into the shading normals. The
+    /// geometric normals are used if there is none
+    pub normals: Option<Vec<Vec3>>,
+    pub indices: Vec<[u32; 3]>,
+    bounds: Bounds,
+}
+
+/// Whether the triangle can't be hit in a meaningful way: one of its vertices is missing or not
+/// finite, or its area is so small next to its edges that its normal is only rounding errors
+fn is_degenerate(positions: &[Vec3], triangle: [u32; 3]) -> bool {
+    if triangle.iter().any(|&i| i as usize >= positions.len()) {
+        return true;
+    }
+    let [a, b, c] = triangle.map(|i| positions[i as usize]);
+    if !(a.is_finite() && b.is_finite() && c.is_finite()) {
+        return true;
+    }
+    let (ab, ac) = (b - a, c - a);
+    ab.cross(ac).length() <= f32::EPSILON * ab.length() * ac.length()
+}
+
+/// Removes the degenerate triangles of a mesh, that would give garbage normals to the shading,
+/// and returns how many were removed. Their number is logged
+pub fn skip_degenerate_triangles(positions: &[Vec3], indices: &mut Vec<[u32; 3]>) -> usize {
+    let before = indices.len();
+    indices.retain(|&triangle| !is_degenerate(positions, triangle));
+
+    let skipped = before - indices.len();
+    if skipped > 0 {
+        log::warn!("skipped {skipped} degenerate triangles out of {before}");
+    }
+    skipped
+}
+
+/// A hit on a triangle of a mesh
+#[derive(Debug, Clone, Copy)]
+struct TriangleHit {
+    triangle: usize,
+    t: f32,
+    /// Barycentric coordinates of the hit along the 2nd and 3rd vertices
+    uv: [f32; 2],
+}
+
+impl TriangleMesh {
+    /// The degenerate triangles are skipped, see [skip_degenerate_triangles]
+    pub fn new(
+        material: MaterialId,
+        positions: Vec<Vec3>,
+        normals: Option<Vec<Vec3>>,
+        mut indices: Vec<[u32; 3]>,
+    ) -> Self {
+        skip_degenerate_triangles(&positions, &mut indices);
+        if let Some(ref normals) = normals {
+            assert_eq!(normals.len(), positions.len());
+        }
+        let bounds = positions
+            .iter()
+            .fold(Bounds::EMPTY, |b, &p| b.union_point(Point(p)));
+
+        Self {
+            material,
+            positions,
+            normals,
+            indices,
+            bounds,
+        }
+    }
+
+    fn vertices(&self, triangle: usize) -> [Vec3; 3] {
+        self.indices[triangle].map(|i| self.positions[i as usize])
+    }
+
+    /// Watertight intersection, see "Watertight Ray/Triangle Intersection", Woop et al. 2013.
+    ///
+    /// The triangle is projected in a space where the ray goes along +z from the origin, the edge
+    /// functions are then computed the same way for the two triangles sharing an edge so that a
+    /// ray can't go through the gap between them.
+    fn intersect_triangle(&self, ray: &Ray, triangle: usize) -> Option<TriangleHit> {
+        counter!("Triangle tests");
+        let d = ray.direction;
+        let kz = match d.abs() {
+            Vec3 { x, y, z } if x >= y && x >= z => 0,
+            Vec3 { y, z, .. } if y >= z => 1,
+            _ => 2,
+        };
+        let mut kx = (kz + 1) % 3;
+        let mut ky = (kx + 1) % 3;
+        // Keep the winding of the triangle
+        if d[kz] < 0.0 {
+            std::mem::swap(&mut kx, &mut ky);
+        }
+
+        let shear = Vec3::new(d[kx] / d[kz], d[ky] / d[kz], d[kz].recip());
+        let [a, b, c] = self
+            .vertices(triangle)
+            .map(|p| p - ray.origin.vec())
+            .map(|p| Vec3::new(p[kx] - shear.x * p[kz], p[ky] - shear.y * p[kz], p[kz]));
+
+        let mut u = c.x * b.y - c.y * b.x;
+        let mut v = a.x * c.y - a.y * c.x;
+        let mut w = b.x * a.y - b.y * a.x;
+        // On an edge, fall back to double precision to get the sign right
+        if u == 0.0 || v == 0.0 || w == 0.0 {
+            let edge =
+                |p: Vec3, q: Vec3| (p.x as f64 * q.y as f64 - p.y as f64 * q.x as f64) as f32;
+            u = edge(c, b);
+            v = edge(a, c);
+            w = edge(b, a);
+        }
+
+        if (u < 0.0 || v < 0.0 || w < 0.0) && (u > 0.0 || v > 0.0 || w > 0.0) {
+            return None;
+        }
+        let det = u + v + w;
+        if det == 0.0 {
+            return None;
+        }
+
+        let t = shear.z * (u * a.z + v * b.z + w * c.z) / det;
+        ray.range().contains(&t).then_some(TriangleHit {
+            triangle,
+            t,
+            uv: [v / det, w / det],
+        })
+    }
+
+    fn closest_hit(&self, ray: &Ray) -> Option<TriangleHit> {
+        let mut ray = *ray;
+        let mut closest = None;
+        for triangle in 0..self.indices.len() {
+            if let Some(hit) = self.intersect_triangle(&ray, triangle) {
+                ray.bounds.1 = hit.t;
+                closest = Some(hit);
+            }
+        }
+        closest
+    }
+
+    /// The geometric and the shading normals at a hit
+    fn normals(&self, hit: &TriangleHit) -> (Vec3, Vec3) {
+        let [p0, p1, p2] = self.vertices(hit.triangle);
+        let geometric = (p1 - p0).cross(p2 - p0).normalize_or_zero();
+        let shading = match self.normals {
+            Some(ref normals) => {
+                let [u, v] = hit.uv;
+                let [n0, n1, n2] = self.indices[hit.triangle].map(|i| normals[i as usize]);
+                ((1.0 - u - v) * n0 + u * n1 + v * n2).normalize_or_zero()
+            }
+            None => geometric,
+        };
+        (geometric, shading)
+    }
+}
+
+impl Shape for TriangleMesh {
+    fn intersection_full(&self, ray: Ray) -> FullIntersectionResult {
+        match self.closest_hit(&ray) {
+            Some(hit) => {
+                let (normal, shading_normal) = self.normals(&hit);
+                FullIntersectionResult::Intersection(RayIntersection {
+                    t: hit.t,
+                    local_info: local_info::Full {
+                        pos: ray.at(hit.t),
+                        normal,
+                        shading_normal,
+                        material: self.material,
+                        uv: hit.uv,
+                        object: 0,
+                    },
+                })
+            }
+            None => FullIntersectionResult::NoIntersection,
+        }
+    }
+
+    fn intersect_bare(&self, ray: Ray) -> MinIntersectionResult {
+        match self.closest_hit(&ray) {
+            Some(hit) => MinIntersectionResult::Intersection(RayIntersection {
+                t: hit.t,
+                local_info: local_info::Minimum { pos: ray.at(hit.t) },
+            }),
+            None => MinIntersectionResult::NoIntersection,
+        }
+    }
+
+    fn bounding_box(&self) -> Bounds {
+        self.bounds
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::Vec3;
+
+    use crate::{
+        material::MaterialId,
+        math::point::Point,
+        ray::Ray,
+        shape::{FullIntersectionResult, Shape},
+    };
+
+    use super::{skip_degenerate_triangles, TriangleMesh};
+
+    /// The square [-1, 1]² at z = -1, facing the origin
+    fn quad(normals: Option<Vec<Vec3>>) -> TriangleMesh {
+        TriangleMesh::new(
+            MaterialId(0),
+            vec![
+                Vec3::new(-1.0, -1.0, -1.0),
+                Vec3::new(1.0, -1.0, -1.0),
+                Vec3::new(1.0, 1.0, -1.0),
+                Vec3::new(-1.0, 1.0, -1.0),
+            ],
+            normals,
+            vec![[0, 1, 2], [0, 2, 3]],
+        )
+    }
+
+    #[test]
+    fn intersection() {
+        let mesh = quad(None);
+        let ray = Ray::new(Point::ORIGIN, Vec3::new(0.5, -0.25, -1.0).normalize());
+        let FullIntersectionResult::Intersection(hit) = mesh.intersection_full(ray) else {
+            panic!("the ray misses the quad");
+        };
+        assert!((hit.local_info.pos.vec() - Vec3::new(0.5, -0.25, -1.0)).length() < 1e-5);
+        assert_eq!(hit.local_info.normal, Vec3::Z);
+        // On the first triangle, 0.25 * (-1, -1) + 0.375 * (1, -1) + 0.375 * (1, 1)
+        let [u, v] = hit.local_info.uv;
+        assert!(
+            (u - 0.375).abs() < 1e-5 && (v - 0.375).abs() < 1e-5,
+            "{u} {v}"
+        );
+
+        let behind = Ray::new(Point::ORIGIN, Vec3::Z);
+        assert!(!mesh.intersection_full(behind).is_intersection());
+        let short = Ray::new_with_range(Point::ORIGIN, -Vec3::Z, 0.0..0.5);
+        assert!(!mesh.intersect_bare(short).is_intersection());
+
+        let bounds = mesh.bounding_box();
+        assert_eq!(bounds.origin, Point::new(-1.0, -1.0, -1.0));
+        assert_eq!(bounds.end, Point::new(1.0, 1.0, -1.0));
+    }
+
+    #[test]
+    fn interpolated_normals() {
+        let normals = [-Vec3::X, Vec3::X, Vec3::X, -Vec3::X]
+            .map(|n| (n + Vec3::Z).normalize())
+            .to_vec();
+        let mesh = quad(Some(normals));
+
+        let normal = |x: f32| {
+            let ray = Ray::new(Point::new(x, 0.0, 0.0), -Vec3::Z);
+            mesh.intersection_full(ray).unwrap().local_info
+        };
+        assert!((normal(0.0).shading_normal - Vec3::Z).length() < 1e-5);
+        assert!(normal(0.5).shading_normal.x > 0.0 && normal(-0.5).shading_normal.x < 0.0);
+        // The geometric normal stays the one of the triangles
+        assert_eq!(normal(0.5).normal, Vec3::Z);
+    }
+
+    #[test]
+    fn watertight() {
+        let mesh = quad(None);
+        for i in 0..1000 {
+            let x = i as f32 / 1000.0;
+            let on_edge = Vec3::new(2.0 * x - 1.0, 2.0 * x - 1.0, -1.0);
+            let origin = Point::new((i % 7) as f32 * 0.3, (i % 13) as f32 * -0.1, 1.0 + x);
+            let ray = Ray::new(origin, (on_edge - origin.vec()).normalize());
+
+            let hits = (0..2)
+                .filter(|&triangle| mesh.intersect_triangle(&ray, triangle).is_some())
+                .count();
+            assert!(hits > 0, "the ray to {on_edge} goes through the edge");
+            assert!(mesh.intersection_full(ray).is_intersection());
+        }
+    }
+
+    #[test]
+    fn degenerate_triangles() {
+        let positions = vec![
+            Vec3::new(-1.0, -1.0, -1.0),
+            Vec3::new(1.0, -1.0, -1.0),
+            Vec3::new(1.0, 1.0, -1.0),
+            Vec3::new(-1.0, 1.0, -1.0),
+            // On the diagonal of the quad
+            Vec3::new(0.0, 0.0, -1.0),
+            Vec3::new(f32::NAN, 0.0, -1.0),
+        ];
+        let indices = vec![
+            [0, 1, 2],
+            [0, 4, 2],
+            [0, 2, 3],
+            [1, 1, 3],
+            [5, 1, 2],
+            [0, 1, 9],
+        ];
+
+        let mut valid = indices.clone();
+        assert_eq!(skip_degenerate_triangles(&positions, &mut valid), 4);
+        assert_eq!(valid, vec![[0, 1, 2], [0, 2, 3]]);
+
+        // The rays on the diagonal and on the degenerate triangles hit the valid ones
+        let mesh = TriangleMesh::new(MaterialId(0), positions, None, indices);
+        assert_eq!(mesh.indices.len(), 2);
+        for x in [-0.5, 0.0, 0.25, 0.9] {
+            let ray = Ray::new(Point::new(x, x, 0.0), -Vec3::Z);
+            let FullIntersectionResult::Intersection(hit) = mesh.intersection_full(ray) else {
+                panic!("the ray at {x} misses the quad");
+            };
+            assert_eq!(hit.local_info.normal, Vec3::Z);
+        }
+    }
+}
@@ -0,0 +1,205 @@
+//! Baking: the lighting computed once on the surfaces of the meshes instead of for the pixels of
+//! a camera, to be reused by another renderer.
+use rand::Rng as _;
+use rayon::prelude::*;
+
+use crate::{
+    material::{LightDescriptor, MaterialDescriptor, MaterialId},
+    math::{
+        distributions::{CosineHemisphere3, Samplable, Samples},
+        point::Point,
+        transform::Frame,
+        vec::Vec3,
+    },
+    ray::Ray,
+    scene::SceneT,
+    shape::Shape,
+    Rng, Seed,
+};
+
+/// Ambient occlusion: the fraction of the hemisphere around the normal that is not occluded,
+/// weighted by the cosine, from 0 in a closed cavity to 1 on a fully exposed surface
+#[derive(Debug, Clone, Copy)]
+pub struct AmbientOcclusion {
+    pub samples: u32,
+    /// The occluders farther than it don't count, it can be infinite
+    pub max_distance: f32,
+}
+
+impl AmbientOcclusion {
+    /// The ambient occlusion at `p` on a surface of normal `normal`, normalized
+    pub fn at(&self, objects: &dyn Shape, p: Point, normal: Vec3, rng: &mut Rng) -> f32 {
+        let frame = Frame::new(normal);
+        let unoccluded = (0..self.samples)
+            .filter(|_| {
+                let local = CosineHemisphere3.sample_with(Samples([rng.gen(), rng.gen()]));
+                let mut ray = Ray::spawn(p, normal, frame.from_local(local));
+                ray.bounds.1 = self.max_distance;
+                !objects.intersect_bare(ray).is_intersection()
+            })
+            .count();
+        unoccluded as f32 / self.samples.max(1) as f32
+    }
+
+    /// The ambient occlusion of each vertex, computed in parallel. The rng of a vertex is seeded
+    /// from its index so that a bake is the same from a run to the next
+    pub fn bake_vertices(
+        &self,
+        objects: &dyn Shape,
+        positions: &[Vec3],
+        normals: &[Vec3],
+        seed: u64,
+    ) -> Vec<f32> {
+        positions
+            .par_iter()
+            .zip(normals)
+            .enumerate()
+            .map(|(index, (&p, &normal))| {
+                let mut rng = Seed {
+                    seed,
+                    x: index as u32,
+                    y: 0,
+                    sample_idx: 0,
+                }
+                .into_rng(0);
+                if normal == Vec3::ZERO {
+                    // Only on degenerate triangles, there is no hemisphere
+                    return 1.0;
+                }
+                self.at(objects, Point(p), normal, &mut rng)
+            })
+            .collect()
+    }
+}
+
+/// Normals of the vertices of a mesh, the sum of the normals of their triangles weighted by
+/// their areas. Zero for the vertices of no triangle
+pub fn vertex_normals(positions: &[Vec3], indices: &[[u32; 3]]) -> Vec<Vec3> {
+    let mut normals = vec![Vec3::ZERO; positions.len()];
+    for triangle in indices {
+        let [a, b, c] = triangle.map(|i| positions[i as usize]);
+        // Twice the area of the triangle
+        let normal = (b - a).cross(c - a);
+        for &i in triangle {
+            normals[i as usize] += normal;
+        }
+    }
+    normals.iter().map(|n| n.normalize_or_zero()).collect()
+}
+
+/// A mesh to bake
+#[derive(Debug, Clone, Default)]
+pub struct BakedMesh {
+    pub positions: Vec<Vec3>,
+    pub indices: Vec<[u32; 3]>,
+}
+
+/// Keeps the meshes of a scene, the rest of the scene is ignored
+#[derive(Debug, Default)]
+pub struct MeshCollector {
+    pub meshes: Vec<BakedMesh>,
+    materials: usize,
+}
+
+impl SceneT for MeshCollector {
+    type GeometryHandle = ();
+
+    fn insert_material(&mut self, _mat: MaterialDescriptor) -> MaterialId {
+        self.materials += 1;
+        MaterialId(self.materials - 1)
+    }
+
+    fn insert_light(&mut self, _light: LightDescriptor) {}
+
+    fn insert_mesh(&mut self, _material: MaterialId, vertices: &[[f32; 3]], indices: &[[u32; 3]]) {
+        self.meshes.push(BakedMesh {
+            positions: vertices.iter().map(|&p| Vec3::from_array(p)).collect(),
+            indices: indices.to_vec(),
+        });
+    }
+
+    fn insert_sphere(&mut self, _material: MaterialId, _origin: Point, _radius: f32) {}
+
+    fn insert_curve(&mut self, _material: MaterialId, _points: &[[f32; 3]], _widths: &[f32]) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+
+    use crate::{
+        aggregate::{nested::Aggregate, triangle_mesh::TriangleMesh},
+        material::MaterialId,
+        math::{point::Point, vec::Vec3},
+        scene::SceneT,
+    };
+
+    use super::{vertex_normals, AmbientOcclusion, MeshCollector};
+
+    /// The square of side `2 * half` at height `y`, facing up
+    fn square(half: f32, y: f32) -> (Vec<Vec3>, Vec<[u32; 3]>) {
+        (
+            vec![
+                Vec3::new(-half, y, -half),
+                Vec3::new(-half, y, half),
+                Vec3::new(half, y, half),
+                Vec3::new(half, y, -half),
+            ],
+            vec![[0, 1, 2], [0, 2, 3]],
+        )
+    }
+
+    #[test]
+    fn ambient_occlusion() {
+        let (positions, indices) = square(1.0, 0.0);
+        let mut collector = MeshCollector::default();
+        let flat = positions.iter().map(|p| p.to_array()).collect::<Vec<_>>();
+        collector.insert_mesh(MaterialId(0), &flat, &indices);
+        assert_eq!(collector.meshes[0].positions, positions);
+        let normals = vertex_normals(&positions, &indices);
+        assert!(normals.iter().all(|&n| (n - Vec3::Y).length() < 1e-6));
+
+        let plane = TriangleMesh::new(MaterialId(0), positions.clone(), None, indices.clone());
+        let ao = AmbientOcclusion {
+            samples: 256,
+            max_distance: f32::INFINITY,
+        };
+        // Nothing but the plane itself, every vertex is fully exposed
+        let exposed = ao.bake_vertices(&plane, &positions, &normals, 0);
+        assert!(exposed.iter().all(|&ao| ao > 0.99), "{exposed:?}");
+        assert_eq!(exposed, ao.bake_vertices(&plane, &positions, &normals, 0));
+
+        // Under a wide roof, a point is occluded, more than next to a small one
+        let occluded = |half: f32| {
+            let (roof, roof_indices) = square(half, 1.0);
+            let mut scene = Aggregate::new();
+            scene.push(Box::new(TriangleMesh::new(
+                MaterialId(0),
+                positions.clone(),
+                None,
+                indices.clone(),
+            )));
+            scene.push(Box::new(TriangleMesh::new(
+                MaterialId(0),
+                roof,
+                None,
+                roof_indices,
+            )));
+            let mut rng = crate::Rng::seed_from_u64(5);
+            ao.at(&scene, Point::ORIGIN, Vec3::Y, &mut rng)
+        };
+        let (wide, small) = (occluded(10.0), occluded(0.5));
+        assert!(wide < 0.1, "{wide}");
+        assert!(wide < small && small < 0.9, "{small}");
+
+        // Only the occluders within the distance count
+        let short = AmbientOcclusion {
+            max_distance: 0.5,
+            ..ao
+        };
+        let (roof, roof_indices) = square(10.0, 1.0);
+        let roof = TriangleMesh::new(MaterialId(0), roof, None, roof_indices);
+        let mut rng = crate::Rng::seed_from_u64(6);
+        assert_eq!(short.at(&roof, Point::ORIGIN, Vec3::Y, &mut rng), 1.0);
+    }
+}
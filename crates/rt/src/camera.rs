@@ -1,11 +1,22 @@
 use glam::Vec2;
 
 use crate::{
-    math::{point::Point, quaternion::Quat, vec::Vec3},
+    math::{
+        point::Point,
+        quaternion::{slerp, Quat},
+        vec::Vec3,
+    },
     ray::Ray,
+    sampler::{draw_2d, Dimension},
+    shape::{IntersectionResult, Shape},
     Ctx,
 };
 
+/// Beyond it, a camera ray is left to hit whatever back face comes next
+const MAX_SKIPPED_BACK_FACES: usize = 16;
+/// Number of rays per side of the grid probing the image in [Camera::inside_geometry]
+const INSIDE_PROBES: u32 = 8;
+
 pub struct Camera {
     /// Aperture is the diameter of the the opening of the camera.\
     /// Higher aperture means more light incomming but a more blurry image.
@@ -29,6 +40,12 @@ pub struct Camera {
 
     // should use scene transformation and assume camera is always facing the +Z direction
     pub rotation: Quat,
+
+    /// Lens shift, in viewport units: the sensor is moved in its plane so that the center of the
+    /// image sees what the point `shift` of the viewport would see without it. As the sensor
+    /// isn't tilted, the vertical lines stay parallel, eg `(0, -0.5)` frames a building higher
+    /// without looking up at it
+    pub shift: Vec2,
 }
 
 impl Camera {
@@ -53,6 +70,7 @@ impl Camera {
             center_of_lens,
             rotation,
             aperture,
+            shift: Vec2::ZERO,
         }
     }
 
@@ -60,16 +78,22 @@ impl Camera {
     ///
     /// Simulate aperture, focal length stochastically
     pub fn ray(&self, ctx: &mut Ctx, coords: Vec2) -> Ray {
+        let lens = draw_2d(ctx.sampler, &mut ctx.rng, Dimension::Lens);
+        self.ray_through_lens(coords, lens)
+    }
+
+    /// The ray from the given pixel coordinates through the point `[dx, dy]` of the lens, in
+    /// [-1, 1]
+    pub fn ray_through_lens(&self, coords: Vec2, [dx, dy]: [f32; 2]) -> Ray {
         let vcoords = ViewportCoord::from_pixel_coord(self, coords);
         let center_of_sensor = self.center_of_lens + self.focal_length * Vec3::Z;
 
         // from the sensor
         let ray_origin = center_of_sensor
-            + vcoords.vx * self.viewport_half_width * Vec3::X
-            + vcoords.vy * self.viewport_half_height * Vec3::Y;
+            + (vcoords.vx + self.shift.x) * self.viewport_half_width * Vec3::X
+            + (vcoords.vy + self.shift.y) * self.viewport_half_height * Vec3::Y;
 
         // to the lens
-        let Vec2 { x: dx, y: dy } = ctx.sampler.sample_2d();
         let offset = self.aperture / 2.0
             * Vec3 {
                 x: dx,
@@ -83,6 +107,113 @@ impl Camera {
             self.rotation.mul_vec3(ray_dst - ray_origin).normalize(),
         )
     }
+
+    /// Whether the camera seems to be inside a closed geometry: the lens is within the bounds of
+    /// the scene and the rays through a grid over the image that hit something hit back faces
+    /// only
+    pub fn inside_geometry(&self, objects: &dyn Shape) -> bool {
+        let bounds = objects.bounding_box();
+        let p = self.center_of_lens.vec();
+        if bounds.is_empty() || !(p.cmpge(bounds.origin.vec()) & p.cmple(bounds.end.vec())).all() {
+            return false;
+        }
+
+        let mut hits = 0;
+        for i in 0..INSIDE_PROBES * INSIDE_PROBES {
+            let coords = Vec2::new(
+                (i % INSIDE_PROBES) as f32 + 0.5,
+                (i / INSIDE_PROBES) as f32 + 0.5,
+            ) / INSIDE_PROBES as f32
+                * Vec2::new(self.width as f32, self.height as f32);
+            let ray = self.ray_through_lens(coords, [0.0; 2]);
+            if let IntersectionResult::Intersection(hit) = objects.intersection_full(ray) {
+                if hit.local_info.normal.dot(ray.direction) <= 0.0 {
+                    return false;
+                }
+                hits += 1;
+            }
+        }
+        hits > 0
+    }
+}
+
+/// Moves the start of a camera ray past the back faces it hits first, so that a camera inside a
+/// closed mesh sees through it to what is outside. The origin is kept, the distances along the
+/// ray stay the ones from the lens
+pub fn skip_back_faces(objects: &dyn Shape, mut ray: Ray) -> Ray {
+    for _ in 0..MAX_SKIPPED_BACK_FACES {
+        match objects.intersection_full(ray) {
+            IntersectionResult::Intersection(hit)
+                if hit.local_info.normal.dot(ray.direction) > 0.0 =>
+            {
+                ray.bounds.0 = hit.t + 1e-4 * (1.0 + hit.t);
+            }
+            _ => break,
+        }
+    }
+    ray
+}
+
+/// Where a camera is at a given time of an animation
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CameraKeyframe {
+    pub center_of_lens: Point,
+    pub rotation: Quat,
+    pub focal_length: f32,
+}
+
+impl CameraKeyframe {
+    /// The camera at `t` in [0, 1] along a path going through all the keyframes, spending the same
+    /// time between each pair of them. The orientation is slerped, the rest is lerped
+    pub fn interpolate(keyframes: &[Self], t: f32) -> Self {
+        let segments = keyframes.len() - 1;
+        if segments == 0 {
+            return keyframes[0];
+        }
+
+        let x = t.clamp(0.0, 1.0) * segments as f32;
+        let index = (x as usize).min(segments - 1);
+        let (a, b) = (keyframes[index], keyframes[index + 1]);
+        let t = x - index as f32;
+        Self {
+            center_of_lens: Point(a.center_of_lens.0.lerp(b.center_of_lens.0, t)),
+            rotation: slerp(a.rotation, b.rotation, t),
+            focal_length: a.focal_length + t * (b.focal_length - a.focal_length),
+        }
+    }
+}
+
+/// The settings of a photographic exposure. The radiance reaching the sensor is scaled by
+/// [Exposure::scale], the default settings keep it as it is
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Exposure {
+    pub shutter_seconds: f32,
+    /// Sensitivity of the sensor
+    pub iso: f32,
+    /// Ratio of the focal length to the diameter of the aperture
+    pub fstop: f32,
+}
+
+impl Default for Exposure {
+    fn default() -> Self {
+        Self {
+            shutter_seconds: 1.0,
+            iso: 100.0,
+            fstop: 1.0,
+        }
+    }
+}
+
+impl Exposure {
+    /// The exposure value relative to ISO 100, following APEX: `log2(N² / t) - log2(S / 100)`
+    pub fn ev100(&self) -> f32 {
+        f32::log2(self.fstop * self.fstop / self.shutter_seconds) - f32::log2(self.iso / 100.0)
+    }
+
+    /// `2^-EV100`: doubling the time or the sensitivity doubles the brightness
+    pub fn scale(&self) -> f32 {
+        self.shutter_seconds * self.iso / (100.0 * self.fstop * self.fstop)
+    }
 }
 
 /// Represent a coordinate in the viewport space.
@@ -105,3 +236,157 @@ impl ViewportCoord {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use glam::Vec2;
+
+    use crate::{
+        integrators::pathtracing::tests::Spheres,
+        material::MaterialId,
+        math::{point::Point, quaternion::Quat, vec::Vec3},
+        shape::Shape,
+    };
+
+    use super::{skip_back_faces, Camera, CameraKeyframe, Exposure};
+
+    #[test]
+    fn lens_shift() {
+        let mut camera = Camera::new(
+            200,
+            100,
+            f32::to_radians(60.0),
+            1.0,
+            Point::new(1.0, 2.0, 3.0),
+            Quat::IDENTITY,
+            0.0,
+        );
+        let direction = |camera: &Camera, x: f32, y: f32| {
+            let ray = camera.ray_through_lens(Vec2::new(x, y), [0.0; 2]);
+            assert_eq!(ray.origin, camera.center_of_lens);
+            assert!((ray.direction.length() - 1.0).abs() < 1e-6);
+            ray.direction
+        };
+        // Without shift, the lines along the optical axis vanish at the center of the image
+        assert_eq!(direction(&camera, 100.0, 50.0), Vec3::NEG_Z);
+        let unshifted = direction(&camera, 100.0, 25.0);
+
+        camera.shift = Vec2::new(0.0, -0.5);
+        // They now vanish lower in the image, and the center sees what was higher up
+        assert!(direction(&camera, 100.0, 75.0).abs_diff_eq(Vec3::NEG_Z, 1e-6));
+        assert!(direction(&camera, 100.0, 50.0).abs_diff_eq(unshifted, 1e-6));
+        // The rays of a column of pixels stay in a vertical plane: the vertical lines are
+        // parallel in the image
+        for y in [0.0, 30.0, 99.0] {
+            let d = direction(&camera, 150.0, y);
+            let d0 = direction(&camera, 150.0, 75.0);
+            assert!((d.x / d.z - d0.x / d0.z).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn camera_inside_geometry() {
+        // A room around the camera, and a wall far behind it
+        let scene = Spheres(vec![
+            (Point::ORIGIN, 1.0, MaterialId(0)),
+            (Point::new(0.0, 0.0, -1005.0), 1000.0, MaterialId(1)),
+        ]);
+        let camera = |center_of_lens| {
+            Camera::new(
+                64,
+                48,
+                f32::to_radians(60.0),
+                1.0,
+                center_of_lens,
+                Quat::IDENTITY,
+                0.0,
+            )
+        };
+        let inside = camera(Point::ORIGIN);
+        assert!(inside.inside_geometry(&scene));
+        assert!(!camera(Point::new(0.0, 0.0, 3.0)).inside_geometry(&scene));
+
+        let ray = inside.ray_through_lens(Vec2::new(32.0, 24.0), [0.0; 2]);
+        assert_eq!(
+            scene.intersection_full(ray).unwrap().local_info.material.0,
+            0
+        );
+        let hit = scene
+            .intersection_full(skip_back_faces(&scene, ray))
+            .unwrap();
+        assert_eq!(hit.local_info.material.0, 1);
+        assert!((hit.t - 5.0).abs() < 1e-4, "{}", hit.t);
+
+        // The front faces are kept
+        let outside = camera(Point::new(0.0, 0.0, 3.0));
+        let ray = outside.ray_through_lens(Vec2::new(32.0, 24.0), [0.0; 2]);
+        let hit = scene
+            .intersection_full(skip_back_faces(&scene, ray))
+            .unwrap();
+        assert_eq!(hit.local_info.material.0, 0);
+    }
+
+    #[test]
+    fn exposure() {
+        assert_eq!(Exposure::default().ev100(), 0.0);
+        assert_eq!(Exposure::default().scale(), 1.0);
+
+        // Sunny 16: f/16 at 1/125 s, about EV 15
+        let sunny = Exposure {
+            shutter_seconds: 1.0 / 125.0,
+            iso: 100.0,
+            fstop: 16.0,
+        };
+        assert!((sunny.ev100() - (256.0f32 * 125.0).log2()).abs() < 1e-5);
+        assert!((sunny.ev100() - 14.966).abs() < 1e-3);
+        assert!((sunny.scale() - 2f32.powf(-sunny.ev100())).abs() < 1e-9);
+
+        let iso_200 = Exposure {
+            iso: 200.0,
+            ..sunny
+        };
+        assert!((iso_200.ev100() - (sunny.ev100() - 1.0)).abs() < 1e-5);
+        assert_eq!(iso_200.scale(), 2.0 * sunny.scale());
+        let stopped_down = Exposure {
+            fstop: 16.0 * 2f32.sqrt(),
+            ..sunny
+        };
+        assert!((stopped_down.scale() - sunny.scale() / 2.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn keyframe_interpolation() {
+        let keyframes = [
+            CameraKeyframe {
+                center_of_lens: Point::new(0.0, 0.0, 0.0),
+                rotation: Quat::IDENTITY,
+                focal_length: 1.0,
+            },
+            CameraKeyframe {
+                center_of_lens: Point::new(2.0, 0.0, 0.0),
+                rotation: Quat::from_rotation_y(1.0),
+                focal_length: 3.0,
+            },
+            CameraKeyframe {
+                center_of_lens: Point::new(2.0, 2.0, 0.0),
+                rotation: Quat::from_rotation_y(2.0),
+                focal_length: 3.0,
+            },
+        ];
+        assert_eq!(CameraKeyframe::interpolate(&keyframes, 0.0), keyframes[0]);
+        assert_eq!(CameraKeyframe::interpolate(&keyframes, 1.0), keyframes[2]);
+        assert_eq!(
+            CameraKeyframe::interpolate(&keyframes[..1], 0.7),
+            keyframes[0]
+        );
+
+        let quarter = CameraKeyframe::interpolate(&keyframes, 0.25);
+        assert_eq!(quarter.center_of_lens, Point::new(1.0, 0.0, 0.0));
+        assert_eq!(quarter.focal_length, 2.0);
+        assert!(quarter
+            .rotation
+            .abs_diff_eq(Quat::from_rotation_y(0.5), 1e-6));
+        let middle = CameraKeyframe::interpolate(&keyframes, 0.5);
+        assert!(middle.rotation.abs_diff_eq(keyframes[1].rotation, 1e-6));
+    }
+}
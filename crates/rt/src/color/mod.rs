@@ -3,9 +3,10 @@ use std::marker::PhantomData;
 use bytemuck::{Pod, Zeroable};
 use colorspace::Colorspace;
 
-use crate::math::vec::{RgbAsVec3Ext, Vec3AsRgbExt};
+use crate::math::vec::{RgbAsVec3Ext, Vec3, Vec3AsRgbExt};
 
 pub mod colorspace;
+pub mod spectrum;
 
 #[repr(C)]
 #[derive(Clone, Copy, bytemuck::Zeroable)]
@@ -81,9 +82,84 @@ impl<S: colorspace::Colorspace> Color<S> {
         self.0
     }
 
+    /// The Y of CIE XYZ, relative to the display white
+    pub fn luminance(self) -> f32 {
+        S::to_cie_xyz(self.0)[1]
+    }
+
     pub fn to_byte_array(self) -> [u8; 3] {
         self.0.map(|c| (c * 255. + 0.5) as u8)
     }
+
+    /// The color of the pixel `(x, y)` in 8 bits, dithered so that the smooth gradients don't get
+    /// banded, see [dither_to_byte]
+    pub fn to_dithered_byte_array(self, x: u32, y: u32) -> [u8; 3] {
+        self.0.map(|c| dither_to_byte(c, x, y))
+    }
+}
+
+/// Size of the side of the dither matrix
+pub const DITHER_SIZE: u32 = 8;
+
+/// Quantizes to 8 bits the value `c` in [0, 1] of the pixel `(x, y)`, rounding up or down
+/// depending on the pixel so that the mean of each [DITHER_SIZE]² tile is the true value.
+///
+/// The thresholds are the base 2 radical inverse, the first dimension of the Halton sequence, of
+/// the interleaved bits of the coordinates: neighbour pixels get thresholds far apart, this is the
+/// Bayer ordered dithering.
+pub fn dither_to_byte(c: f32, x: u32, y: u32) -> u8 {
+    let bits = DITHER_SIZE.trailing_zeros();
+    let (x, y) = (x % DITHER_SIZE, y % DITHER_SIZE);
+    let mut index = 0;
+    for bit in 0..bits {
+        index |= (((x ^ y) >> bit) & 1) << (2 * bit + 1);
+        index |= ((x >> bit) & 1) << (2 * bit);
+    }
+    let radical_inverse = index.reverse_bits() as f32 / 2f32.powi(32);
+    let threshold = radical_inverse + 0.5 / (DITHER_SIZE * DITHER_SIZE) as f32;
+    (c * 255. + threshold) as u8
+}
+
+/// Components of the albedo below this are too dark to divide by, the color is kept as it is
+pub const DEMODULATION_EPSILON: f32 = 1e-3;
+
+/// The albedo that [demodulate] and [remodulate] divide and multiply by: the dark components are
+/// replaced by 1
+fn demodulation_albedo(albedo: Rgb) -> Vec3 {
+    Vec3::from_array(albedo.0.map(|a| {
+        if a.abs() > DEMODULATION_EPSILON {
+            a
+        } else {
+            1.0
+        }
+    }))
+}
+
+/// The lighting only: the color divided by the albedo of the first hit. It is smoother than the
+/// color, the textures are gone, so it is easier to denoise
+pub fn demodulate(color: Rgb, albedo: Rgb) -> Rgb {
+    (color.vec() / demodulation_albedo(albedo)).rgb()
+}
+
+/// The color back from the lighting given by [demodulate], with the same albedo
+pub fn remodulate(irradiance: Rgb, albedo: Rgb) -> Rgb {
+    (irradiance.vec() * demodulation_albedo(albedo)).rgb()
+}
+
+/// Highlight rolloff: the luminance above `knee` is compressed smoothly towards `2 * knee`, the
+/// color being scaled as a whole so that its hue is kept. Below the knee the color is unchanged.
+///
+/// The curve `knee + x / (1 + x / knee)` of the excess `x` over the knee has a slope of 1 at the
+/// knee. It tames the fireflies with less bias than a hard clamp, as the bright samples still
+/// stay brighter than the others
+pub fn soft_clamp(color: Rgb, knee: f32) -> Rgb {
+    let luminance = color.luminance();
+    if luminance <= knee || luminance.is_nan() {
+        return color;
+    }
+    let excess = luminance - knee;
+    let compressed = knee + excess / (1.0 + excess / knee);
+    (compressed / luminance) * color
 }
 
 impl<S: colorspace::Colorspace> From<[f32; 3]> for Color<S> {
@@ -165,3 +241,82 @@ pub mod linear {
     pub const GREEN: Rgb = Rgb::from_array([0.0, 1.0, 0.0]);
     pub const BLUE: Rgb = Rgb::from_array([0.0, 0.0, 1.0]);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{demodulate, dither_to_byte, remodulate, soft_clamp, Rgb, DITHER_SIZE};
+
+    #[test]
+    fn demodulation() {
+        let color = Rgb::from_array([0.3, 1.7, 0.02]);
+        for albedo in [
+            Rgb::from_array([0.8, 0.5, 0.2]),
+            Rgb::from_array([0.05, 1.0, 0.7]),
+            // The black component of the albedo is kept as it is
+            Rgb::from_array([0.4, 0.0, 0.9]),
+        ] {
+            let irradiance = demodulate(color, albedo);
+            for c in 0..3 {
+                if albedo.0[c] > 0.0 {
+                    assert!((irradiance.0[c] * albedo.0[c] - color.0[c]).abs() < 1e-6);
+                } else {
+                    assert_eq!(irradiance.0[c], color.0[c]);
+                }
+            }
+            let remodulated = remodulate(irradiance, albedo);
+            for (r, c) in remodulated.0.into_iter().zip(color.0) {
+                assert!((r - c).abs() <= 1e-6 * c, "{remodulated:?} != {color:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn highlight_rolloff() {
+        let knee = 2.0;
+        for color in [[0.5, 0.2, 0.1], [1.0, 2.0, 0.5], [1.9, 1.9, 1.9]] {
+            let color = Rgb::from_array(color);
+            assert!(color.luminance() <= knee);
+            assert_eq!(soft_clamp(color, knee).0, color.0);
+        }
+
+        let color = Rgb::from_array([0.2, 1.0, 0.5]);
+        let mut previous = knee;
+        for scale in [3.0, 5.0, 10.0, 100.0, 1e4] {
+            let clamped = soft_clamp(scale * color, knee);
+            let luminance = clamped.luminance();
+            assert!(
+                luminance > previous && luminance < 2.0 * knee,
+                "{luminance}"
+            );
+            previous = luminance;
+            // The ratios of the components are kept
+            for c in 0..3 {
+                assert!((clamped.0[c] / luminance - color.0[c] / color.luminance()).abs() < 1e-4);
+            }
+        }
+    }
+
+    #[test]
+    fn dithering() {
+        for k in 0..=100 {
+            let c = k as f32 / 100.0;
+            let mut sum = 0;
+            for y in 0..DITHER_SIZE {
+                for x in 0..DITHER_SIZE {
+                    let byte = dither_to_byte(c, x, y);
+                    // The same pixel of another tile
+                    assert_eq!(
+                        byte,
+                        dither_to_byte(c, x + 3 * DITHER_SIZE, y + DITHER_SIZE)
+                    );
+                    sum += byte as u32;
+                }
+            }
+            let mean = sum as f32 / (DITHER_SIZE * DITHER_SIZE) as f32;
+            assert!(
+                (mean - c * 255.0).abs() <= 0.5 / (DITHER_SIZE * DITHER_SIZE) as f32 + 1e-4,
+                "{c} averages to {mean}"
+            );
+        }
+    }
+}
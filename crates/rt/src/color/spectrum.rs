@@ -0,0 +1,295 @@
+//! Reflectance spectra upsampled from RGB albedos, see "A Low-Dimensional Function Space for
+//! Efficient Spectral Upsampling", Jakob & Hanika 2019.
+//!
+//! A reflectance is a sigmoid of a quadratic polynomial of the wavelength: it is smooth and
+//! always in [0, 1], so the colors stay physically plausible.
+use std::sync::OnceLock;
+
+use glam::{DMat3, DVec3};
+
+use super::{colorspace, colorspace::Colorspace, Rgb};
+
+/// Range of wavelengths of the spectra, in nm
+pub const LAMBDA_MIN: f32 = 360.0;
+pub const LAMBDA_MAX: f32 = 830.0;
+const LAMBDA_STEP: f64 = 5.0;
+/// Number of wavelengths the spectra are integrated over
+const LAMBDA_SAMPLES: usize = 95;
+
+/// CIE 1931 color matching functions at `lambda` in nm, from the multi-lobe fit of "Simple
+/// Analytic Approximations to the CIE XYZ Color Matching Functions", Wyman et al. 2013
+pub fn cie_xyz(lambda: f32) -> [f32; 3] {
+    let lobe = |mu: f32, sigma_low: f32, sigma_high: f32| {
+        let sigma = if lambda < mu { sigma_low } else { sigma_high };
+        f32::exp(-0.5 * ((lambda - mu) / sigma).powi(2))
+    };
+    [
+        1.056 * lobe(599.8, 37.9, 31.0) + 0.362 * lobe(442.0, 16.0, 26.7)
+            - 0.065 * lobe(501.1, 20.4, 26.2),
+        0.821 * lobe(568.8, 46.9, 40.5) + 0.286 * lobe(530.9, 16.3, 31.1),
+        1.217 * lobe(437.0, 11.8, 36.0) + 0.681 * lobe(459.0, 26.0, 13.8),
+    ]
+}
+
+fn sigmoid(x: f64) -> f64 {
+    if x.is_infinite() {
+        (x > 0.0) as u8 as f64
+    } else {
+        0.5 + x / (2.0 * x.hypot(1.0))
+    }
+}
+
+/// The wavelength mapped to [0, 1], the polynomials are fitted over it to keep them well
+/// conditioned
+fn normalized(lambda: f64) -> f64 {
+    (lambda - LAMBDA_MIN as f64) / (LAMBDA_MAX - LAMBDA_MIN) as f64
+}
+
+/// Weight of each wavelength in the linear RGB of a spectrum. The light is the equal energy
+/// illuminant, white balanced so that a reflectance of 1 is white
+fn rgb_weights() -> &'static [DVec3; LAMBDA_SAMPLES] {
+    static WEIGHTS: OnceLock<[DVec3; LAMBDA_SAMPLES]> = OnceLock::new();
+    WEIGHTS.get_or_init(|| {
+        let mut weights = [DVec3::ZERO; LAMBDA_SAMPLES];
+        for (k, weight) in weights.iter_mut().enumerate() {
+            let lambda = LAMBDA_MIN + k as f32 * LAMBDA_STEP as f32;
+            let rgb = colorspace::Linear_RGB::from_cie_xyz(cie_xyz(lambda));
+            *weight = DVec3::from_array(rgb.map(|c| c as f64));
+        }
+        let white = weights.iter().sum::<DVec3>();
+        weights.map(|weight| weight / white)
+    })
+}
+
+/// Linear RGB of the reflectance of coefficients `c`
+fn reflectance_rgb(c: DVec3) -> DVec3 {
+    rgb_weights()
+        .iter()
+        .enumerate()
+        .map(|(k, &weight)| {
+            let x = normalized(LAMBDA_MIN as f64 + k as f64 * LAMBDA_STEP);
+            sigmoid((c.x * x + c.y) * x + c.z) * weight
+        })
+        .sum()
+}
+
+/// Newton iterations on the coefficients so that the reflectance has the color `target`. Returns
+/// the remaining error
+fn fit(target: DVec3, c: &mut DVec3, iterations: usize) -> f64 {
+    const EPSILON: f64 = 1e-5;
+    let mut residual = reflectance_rgb(*c) - target;
+    for _ in 0..iterations {
+        if residual.length() < 1e-6 {
+            break;
+        }
+        let jacobian = DMat3::from_cols(
+            (reflectance_rgb(*c + EPSILON * DVec3::X) - target - residual) / EPSILON,
+            (reflectance_rgb(*c + EPSILON * DVec3::Y) - target - residual) / EPSILON,
+            (reflectance_rgb(*c + EPSILON * DVec3::Z) - target - residual) / EPSILON,
+        );
+        if jacobian.determinant().abs() < 1e-15 {
+            break;
+        }
+        // The step is shortened until it gets closer
+        let step = jacobian.inverse() * residual;
+        let Some((next, next_residual)) = (0..16)
+            .map(|i| {
+                let next = *c - 0.5f64.powi(i) * step;
+                (next, reflectance_rgb(next) - target)
+            })
+            .find(|(_, r)| r.is_finite() && r.length() < residual.length())
+        else {
+            break;
+        };
+        *c = next;
+        residual = next_residual;
+    }
+    residual.length()
+}
+
+/// Resolution of each axis of the table
+const TABLE_RES: usize = 16;
+
+/// Coefficients of the reflectances of a grid of colors. A color is located by its largest
+/// channel, the value of this channel and the ratios of the two others to it
+struct Table {
+    /// Values of the largest channel at each step of the grid, denser near 0 and 1
+    scale: [f64; TABLE_RES],
+    /// Indexed by channel, scale, and ratios of the next and previous channels
+    coefficients: Vec<DVec3>,
+}
+
+impl Table {
+    fn index(channel: usize, k: usize, j: usize, i: usize) -> usize {
+        ((channel * TABLE_RES + k) * TABLE_RES + j) * TABLE_RES + i
+    }
+
+    /// The color of an entry of the table
+    fn color(channel: usize, z: f64, x: f64, y: f64) -> DVec3 {
+        let mut rgb = DVec3::ZERO;
+        rgb[channel] = z;
+        rgb[(channel + 1) % 3] = x * z;
+        rgb[(channel + 2) % 3] = y * z;
+        rgb
+    }
+
+    /// Each fit starts from the coefficients of its neighbour along the scale, from a mid gray
+    /// where the coefficients are about 0
+    fn build() -> Self {
+        let smoothstep = |x: f64| x * x * (3.0 - 2.0 * x);
+        let scale =
+            std::array::from_fn(|k| smoothstep(smoothstep(k as f64 / (TABLE_RES - 1) as f64)));
+        let ratio = |i: usize| i as f64 / (TABLE_RES - 1) as f64;
+
+        let mut coefficients = vec![DVec3::ZERO; 3 * TABLE_RES.pow(3)];
+        let start = TABLE_RES / 5;
+        for channel in 0..3 {
+            for j in 0..TABLE_RES {
+                for i in 0..TABLE_RES {
+                    let mut c = DVec3::ZERO;
+                    for k in start..TABLE_RES {
+                        let target = Self::color(channel, scale[k], ratio(i), ratio(j));
+                        fit(target, &mut c, 32);
+                        coefficients[Self::index(channel, k, j, i)] = c;
+                    }
+                    let mut c = coefficients[Self::index(channel, start, j, i)];
+                    for k in (0..start).rev() {
+                        let target = Self::color(channel, scale[k], ratio(i), ratio(j));
+                        fit(target, &mut c, 32);
+                        coefficients[Self::index(channel, k, j, i)] = c;
+                    }
+                }
+            }
+        }
+        Self {
+            scale,
+            coefficients,
+        }
+    }
+
+    fn get() -> &'static Self {
+        static TABLE: OnceLock<Table> = OnceLock::new();
+        TABLE.get_or_init(Self::build)
+    }
+
+    /// Trilinear interpolation of the coefficients of `rgb`, which must not be black
+    fn lookup(&self, rgb: DVec3) -> DVec3 {
+        let channel = if rgb.x >= rgb.y && rgb.x >= rgb.z {
+            0
+        } else if rgb.y >= rgb.z {
+            1
+        } else {
+            2
+        };
+        let z = rgb[channel];
+        let x = rgb[(channel + 1) % 3] / z * (TABLE_RES - 1) as f64;
+        let y = rgb[(channel + 2) % 3] / z * (TABLE_RES - 1) as f64;
+
+        let k = self
+            .scale
+            .partition_point(|&s| s <= z)
+            .clamp(1, TABLE_RES - 1)
+            - 1;
+        let (i, j) = (
+            (x as usize).min(TABLE_RES - 2),
+            (y as usize).min(TABLE_RES - 2),
+        );
+        let (dx, dy, dz) = (
+            x - i as f64,
+            y - j as f64,
+            (z - self.scale[k]) / (self.scale[k + 1] - self.scale[k]),
+        );
+
+        let mut c = DVec3::ZERO;
+        for (dk, wz) in [(0, 1.0 - dz), (1, dz)] {
+            for (dj, wy) in [(0, 1.0 - dy), (1, dy)] {
+                for (di, wx) in [(0, 1.0 - dx), (1, dx)] {
+                    c += wx
+                        * wy
+                        * wz
+                        * self.coefficients[Self::index(channel, k + dk, j + dj, i + di)];
+                }
+            }
+        }
+        c
+    }
+}
+
+/// A smooth reflectance spectrum, always in [0, 1]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SigmoidPolynomial {
+    /// Coefficients of the polynomial over the normalized wavelength, highest degree first
+    coefficients: [f32; 3],
+}
+
+impl SigmoidPolynomial {
+    /// Reflectance at `lambda` in nm
+    pub fn eval(&self, lambda: f32) -> f32 {
+        let [c0, c1, c2] = self.coefficients.map(|c| c as f64);
+        let x = normalized(lambda as f64);
+        sigmoid((c0 * x + c1) * x + c2) as f32
+    }
+
+    /// Linear RGB of the light reflected from a white light
+    pub fn to_rgb(&self) -> Rgb {
+        let c = DVec3::from_array(self.coefficients.map(|c| c as f64));
+        Rgb::from_array(reflectance_rgb(c).to_array().map(|c| c as f32))
+    }
+}
+
+impl Rgb {
+    /// The smooth reflectance spectrum whose color is this albedo, which is clamped to [0, 1]
+    pub fn to_reflectance_spectrum(self) -> SigmoidPolynomial {
+        let rgb = DVec3::from_array(self.to_array().map(|c| c.clamp(0.0, 1.0) as f64));
+
+        // Grays are constant spectra, the black and white ones can't be fitted
+        if rgb.x == rgb.y && rgb.y == rgb.z {
+            let v = rgb.x;
+            let c2 = (v - 0.5) / (v * (1.0 - v)).sqrt();
+            let c2 = if c2.is_nan() { f64::INFINITY } else { c2 };
+            return SigmoidPolynomial {
+                coefficients: [0.0, 0.0, c2 as f32],
+            };
+        }
+
+        // The table gives a first guess, refined for this exact color
+        let mut c = Table::get().lookup(rgb);
+        fit(rgb, &mut c, 8);
+        SigmoidPolynomial {
+            coefficients: c.to_array().map(|c| c as f32),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::color::Rgb;
+
+    use super::{LAMBDA_MAX, LAMBDA_MIN};
+
+    #[test]
+    fn round_trip() {
+        let colors = [
+            [0.8, 0.3, 0.1],
+            [0.2, 0.5, 0.7],
+            [0.05, 0.6, 0.2],
+            [0.9, 0.9, 0.2],
+            [0.01, 0.02, 0.03],
+            [0.5, 0.5, 0.5],
+            [0.0, 0.0, 0.0],
+            [1.0, 1.0, 1.0],
+        ];
+        for color in colors {
+            let spectrum = Rgb::from_array(color).to_reflectance_spectrum();
+            let rgb = spectrum.to_rgb().to_array();
+            for (c, expected) in rgb.into_iter().zip(color) {
+                assert!((c - expected).abs() < 2e-3, "{color:?} gives {rgb:?}");
+            }
+
+            for lambda in (LAMBDA_MIN as u32..=LAMBDA_MAX as u32).step_by(10) {
+                let r = spectrum.eval(lambda as f32);
+                assert!((0.0..=1.0).contains(&r), "{color:?} at {lambda}: {r}");
+            }
+        }
+    }
+}
@@ -1,27 +1,63 @@
-use crate::{ray::Ray, renderer::RayResult, Ctx};
+use crate::{
+    material::BSDF,
+    math::distributions::sphere_uv_from_direction,
+    memory::Arena,
+    ray::Ray,
+    renderer::{RayResult, World},
+    Ctx,
+};
 
-mod pathtracing;
+pub(crate) mod pathtracing;
 mod randomwalk;
+mod toon;
+mod whitted;
 
 pub trait Integrator: Send + Sync {
     fn ray_cast(&self, ctx: &mut Ctx, ray: Ray, depth: u32) -> RayResult;
-    fn sky_ray(&self, _ctx: &mut Ctx, _ray: Ray) -> RayResult {
-        // let material = &ctx.world.materials[ctx.world.world_material.0].material;
-        // let record = local_info::Full {
-        //     pos: ray.origin,
-        //     normal: -ray.direction,
-        //     material: ctx.world.world_material,
-        //     uv: sphere_uv_from_direction(-ray.direction),
-        // };
+    fn sky_ray(&self, ctx: &mut Ctx, ray: Ray) -> RayResult {
+        sky_ray(ctx.world, &ctx.arena, ray)
+    }
+
+    /// Returns the wavefront flavour of the integrator, if it has one
+    fn as_wavefront(&self) -> Option<&dyn WavefrontIntegrator> {
+        None
+    }
+}
+
+/// A camera ray waiting to be traced by a [WavefrontIntegrator], along with the context used for
+/// its whole path
+pub struct WavefrontRay<'a> {
+    pub ray: Ray,
+    pub ctx: Ctx<'a>,
+}
+
+/// An integrator that traces a whole batch of rays bounce by bounce: every ray of the batch is
+/// intersected, then every hit is shaded and the surviving rays are compacted for the next bounce.
+///
+/// The results must be the same as what [Integrator::ray_cast] would give for each ray with the
+/// same rng.
+pub trait WavefrontIntegrator: Send + Sync {
+    /// The rays are traced in `world`, the BxDFs of their hits are built in the arena of their
+    /// context
+    fn ray_cast_wavefront(&self, world: &World, rays: Vec<WavefrontRay>) -> Vec<RayResult>;
+}
 
-        // let scattered = material.scatter(ray, &record, &mut ctx.rng);
-        RayResult {
-            color: [0.5, 0.3, 1.0].into(),
-            samples_accumulated: 1,
-            ..Default::default()
-        }
+/// The radiance coming from the world material along an escaped ray
+fn sky_ray(world: &World, arena: &Arena, ray: Ray) -> RayResult {
+    let material = world.materials[world.world_material.0]
+        .material
+        .bxdf(arena, sphere_uv_from_direction(ray.direction));
+    // The sky is seen from the inside, its normal faces the ray
+    let bsdf = BSDF::new(-ray.direction, material);
+    RayResult {
+        color: world.attenuate(bsdf.le(-ray.direction), f32::INFINITY),
+        samples_accumulated: 1,
+        escaped: true,
+        ..Default::default()
     }
 }
 
 pub use pathtracing::PathTracer;
 pub use randomwalk::RandomWalkIntegrator;
+pub use toon::ToonIntegrator;
+pub use whitted::WhittedIntegrator;
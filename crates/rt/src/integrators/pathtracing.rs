@@ -1,80 +1,2048 @@
 use glam::Vec3;
 use log::trace;
-use rand::prelude::Distribution;
+use rand::Rng as _;
 
 use crate::{
-    color::linear::BLACK,
-    material::{BxDFSample, BSDF},
-    math::{distributions::Samples, vec::RgbAsVec3Ext},
+    color::{
+        linear::{BLACK, WHITE},
+        Rgb,
+    },
+    light::power_heuristic,
+    material::{BxDF, BxDFFlags, BxDFSample, BSDF},
+    math::{distributions::Samples, point::Point, vec::RgbAsVec3Ext},
     ray::Ray,
-    renderer::RayResult,
-    shape::IntersectionResult,
+    renderer::{RayResult, World},
+    sampler::{draw_1d, draw_2d, Dimension, ONE_MINUS_EPSILON},
+    shape::{local_info, IntersectionResult, RayIntersection},
     Ctx,
 };
 
-use super::Integrator;
+use super::{Integrator, WavefrontIntegrator, WavefrontRay};
 
 pub struct PathTracer {
     pub max_depth: u32,
+    /// Maximum number of bounces on each kind of lobe
+    pub max_diffuse_depth: u32,
+    pub max_glossy_depth: u32,
+    pub max_specular_depth: u32,
+    /// Both the reflection and the transmission of the smooth dielectrics are traced for the
+    /// first `split_depth` specular bounces of a path, only one of them is sampled after
+    pub split_depth: u32,
+    /// Number of light samples, each with its shadow ray, averaged at each hit
+    pub shadow_samples: u32,
 }
 
-impl Integrator for PathTracer {
-    fn ray_cast(&self, ctx: &mut Ctx, ray: Ray, depth: u32) -> RayResult {
-        let uniform = rand::distributions::Uniform::new(0.0, 1.0);
-        if depth == self.max_depth {
-            return RayResult::default();
+/// Number of bounces of a path on each kind of lobe
+#[derive(Debug, Clone, Copy, Default)]
+struct LobeDepths {
+    diffuse: u32,
+    glossy: u32,
+    specular: u32,
+}
+
+/// Indices of refraction of the dielectrics a path is inside of, from the outermost to the
+/// innermost, each one along with its object.
+///
+/// The surface of a dielectric is between its index of refraction and the one of the medium
+/// around it, which is not always the void: a bubble in water
+#[derive(Debug, Clone, Copy, Default)]
+struct IorStack {
+    len: usize,
+    media: [(u32, f32); IorStack::CAPACITY],
+}
+
+impl IorStack {
+    /// Once this deep, the objects that are entered are not tracked anymore
+    const CAPACITY: usize = 8;
+
+    /// Index of refraction of the medium around `object`: the innermost one that is not it, 1 for
+    /// the void
+    fn exterior(&self, object: u32) -> f32 {
+        self.media[..self.len]
+            .iter()
+            .rev()
+            .find(|&&(o, _)| o != object)
+            .map_or(1.0, |&(_, ior)| ior)
+    }
+
+    /// The path went through the surface of `object`, entering it or leaving it
+    fn cross(&mut self, object: u32, ior: f32, entering: bool) {
+        if entering {
+            if self.len < Self::CAPACITY {
+                self.media[self.len] = (object, ior);
+                self.len += 1;
+            }
+        } else if let Some(index) = self.media[..self.len]
+            .iter()
+            .rposition(|&(o, _)| o == object)
+        {
+            self.media.copy_within(index + 1..self.len, index);
+            self.len -= 1;
         }
-        trace!("depth {depth:?}");
+    }
 
-        let ray = Ray::new_with_range(ray.origin, ray.direction, 0.00001..ray.bounds.1);
+    /// The media after a scattering on a surface of `object`, made of `material`
+    fn scattered(
+        mut self,
+        material: &dyn BxDF,
+        object: u32,
+        wo: Vec3,
+        normal: Vec3,
+        sampled: &BxDFSample,
+    ) -> Self {
+        if let Some(ior) = material.ior() {
+            if sampled.flags.contains(BxDFFlags::Transmission) {
+                self.cross(object, ior, wo.dot(normal) > 0.0);
+            }
+        }
+        self
+    }
+}
 
-        let isect = ctx.world.objects.intersection_full(ray);
-        let IntersectionResult::Intersection(record) = isect else {
-            return self.sky_ray(ctx, ray);
+impl PathTracer {
+    /// A path tracer where only the total depth is limited
+    pub fn new(max_depth: u32) -> Self {
+        Self {
+            max_depth,
+            max_diffuse_depth: max_depth,
+            max_glossy_depth: max_depth,
+            max_specular_depth: max_depth,
+            split_depth: 0,
+            shadow_samples: 1,
+        }
+    }
+
+    /// The reflection and the transmission of a smooth dielectric if the path is still split on
+    /// the specular bounces, along with the probability to choose them, which is their pdf
+    fn split(
+        &self,
+        bxdf: &dyn BxDF,
+        bsdf: &BSDF<'_, dyn BxDF + '_>,
+        wo: Vec3,
+        uv: [f32; 2],
+        lobes: LobeDepths,
+    ) -> Option<[(f32, BxDFSample); 2]> {
+        if lobes.specular >= self.split_depth || !bxdf.flags().contains(BxDFFlags::Specular) {
+            return None;
+        }
+
+        let reflected = bsdf.sample_f(wo, Samples(uv), Samples([0.0]))?;
+        let transmitted = bsdf.sample_f(wo, Samples(uv), Samples([ONE_MINUS_EPSILON]))?;
+        let specular = |sample: &BxDFSample| sample.flags.contains(BxDFFlags::Specular);
+        // Past the critical angle, both are the reflection
+        if !specular(&reflected) || !specular(&transmitted) || reflected.flags == transmitted.flags
+        {
+            return None;
+        }
+        Some([(reflected.pdf, reflected), (transmitted.pdf, transmitted)])
+    }
+
+    /// Count a bounce on the sampled lobe, returns None if the path is already too deep for it
+    fn bounce(&self, mut lobes: LobeDepths, flags: BxDFFlags) -> Option<LobeDepths> {
+        let (depth, max_depth) = if flags.contains(BxDFFlags::Specular) {
+            (&mut lobes.specular, self.max_specular_depth)
+        } else if flags.contains(BxDFFlags::Diffusion) {
+            (&mut lobes.diffuse, self.max_diffuse_depth)
+        } else {
+            (&mut lobes.glossy, self.max_glossy_depth)
         };
 
-        let material = &ctx.world.materials[record.local_info.material.0].material;
+        if *depth >= max_depth {
+            return None;
+        }
+        *depth += 1;
+        Some(lobes)
+    }
+
+    /// Transmittance of the segment of `ray` up to a hit of length `t` if it went through the
+    /// inside of an object, which is the case when it hits the back of a surface
+    fn interior_transmittance(material: &dyn BxDF, ray: &Ray, normal: Vec3, t: f32) -> Option<Rgb> {
+        if ray.direction.dot(normal) > 0.0 {
+            material.interior_transmittance(t)
+        } else {
+            None
+        }
+    }
+
+    /// Radiance `l` at the end of a segment of length `t` seen from its origin: it is attenuated
+    /// by the interior of the object it went through if any, by the fog otherwise
+    fn attenuate(world: &World, l: Rgb, t: f32, interior: Option<Rgb>) -> Rgb {
+        match interior {
+            Some(transmittance) => transmittance * l,
+            None => world.attenuate(l, t),
+        }
+    }
+
+    /// The radiance of the sky along a path that escaped the world
+    fn escape(&self, ctx: &mut Ctx, ray: Ray, depth: u32) -> RayResult {
+        let sky = self.sky_ray(ctx, ray);
+        if ctx.debug {
+            log::info!("debug depth {depth}: escaped {ray:?}, sky {:?}", sky.color);
+        }
+        sky
+    }
+
+    /// Whether the lights are sampled at a surface. The delta lobes of the specular ones are never
+    /// lit by a sampled direction
+    fn samples_lights(world: &World, flags: BxDFFlags) -> bool {
+        !world.lights.is_empty() && !flags.is_empty() && !flags.contains(BxDFFlags::Specular)
+    }
+
+    /// The side of the surface the light it reflects arrives on, the one of `wo`. None if it
+    /// also lets the light through
+    fn lit_side(flags: BxDFFlags, normal: Vec3, wo: Vec3) -> Option<Vec3> {
+        (!flags.contains(BxDFFlags::Transmission)).then(|| normal.dot(wo).signum() * normal)
+    }
+
+    /// Next event estimation: the light arriving at the hit straight from the lights of the
+    /// world, reflected toward `wo`. It is the mean of [PathTracer::shadow_samples] light samples,
+    /// the first one drawn from the dimensions of the sampler and the others from the rng
+    fn direct_lighting(
+        &self,
+        ctx: &mut Ctx,
+        bsdf: &BSDF<'_, dyn BxDF + '_>,
+        record: &RayIntersection<local_info::Full>,
+        wo: Vec3,
+        depth: u32,
+    ) -> Rgb {
+        if !Self::samples_lights(ctx.world, bsdf.flags()) {
+            return BLACK;
+        }
+
+        let mut direct = BLACK;
+        for i in 0..self.shadow_samples {
+            let (u, samples) = if i == 0 {
+                (
+                    draw_1d(ctx.sampler, &mut ctx.rng, Dimension::LightChoice(depth)),
+                    draw_2d(ctx.sampler, &mut ctx.rng, Dimension::Light(depth)),
+                )
+            } else {
+                (ctx.rng.gen(), [ctx.rng.gen(), ctx.rng.gen()])
+            };
+            direct = direct + Self::light_sample(ctx, bsdf, record, wo, depth, u, samples);
+        }
+        (1.0 / self.shadow_samples as f32) * direct
+    }
+
+    /// The light arriving at the hit from a light chosen by the light tree with `u`, at the point
+    /// of it given by `samples`, weighted against the BSDF samples that reach it
+    fn light_sample(
+        ctx: &mut Ctx,
+        bsdf: &BSDF<'_, dyn BxDF + '_>,
+        record: &RayIntersection<local_info::Full>,
+        wo: Vec3,
+        depth: u32,
+        u: f32,
+        samples: [f32; 2],
+    ) -> Rgb {
+        let (pos, normal) = (record.local_info.pos, record.local_info.normal);
+        let side = Self::lit_side(bsdf.flags(), normal, wo);
+        let Some((light, pmf)) = ctx.world.lights.sample(pos, side, u) else {
+            return BLACK;
+        };
+        let Some(sample) = light.sample_li(pos, Samples(samples)) else {
+            return BLACK;
+        };
+        let fcos = normal.dot(sample.wi).abs() * bsdf.f(wo, sample.wi);
+        if fcos.vec().max_element() <= 0.0 {
+            return BLACK;
+        }
+
+        let mut shadow_ray = Ray::spawn(pos, normal, sample.wi);
+        shadow_ray.bounds.1 = sample.distance * (1.0 - 1e-4);
+        if ctx
+            .world
+            .objects
+            .intersect_bare(shadow_ray)
+            .is_intersection()
+        {
+            return BLACK;
+        }
+
+        let weight = match sample.pdf {
+            None => 1.0 / pmf,
+            Some(pdf) => {
+                // The lights the rays can hit are also reached by the directions sampled from the
+                // BSDF, see [Self::emission_weight]
+                let pdf = pmf * pdf;
+                let bsdf_pdf = match light.object() {
+                    Some(_) => bsdf.pdf(wo, sample.wi),
+                    None => 0.0,
+                };
+                power_heuristic(pdf, bsdf_pdf) / pdf
+            }
+        };
+        let direct = weight * fcos * ctx.world.attenuate(sample.li, sample.distance);
+        if ctx.debug {
+            log::info!(
+                "debug depth {depth}: light sampled toward {} at {}, direct {direct:?}",
+                sample.wi,
+                sample.distance
+            );
+        }
+        direct
+    }
+
+    /// Weight of the emission of `object` reached along `wi` sampled from the BSDF at `origin`,
+    /// against the samples of the light it is drawn there. 1 if the object is not a light
+    fn emission_weight(world: &World, origin: Option<Origin>, object: u32, wi: Vec3) -> f32 {
+        let Some(origin) = origin else {
+            return 1.0;
+        };
+        let Some((light, pmf)) = world
+            .lights
+            .object_light(object, origin.position, origin.side)
+        else {
+            return 1.0;
+        };
+        power_heuristic(origin.pdf, pmf * light.pdf_li(origin.position, wi))
+    }
+
+    /// The step of a path at a hit, shared by the recursive and the wavefront loops: the light
+    /// arriving there straight from the lights, then the sampling of the directions the path goes
+    /// on along. Returns None if the surface is cut out there, the path then goes on straight
+    fn shade(
+        &self,
+        ctx: &mut Ctx,
+        ray: &Ray,
+        record: &RayIntersection<local_info::Full>,
+        depth: u32,
+        history: History,
+    ) -> Option<Scattering> {
+        if ctx.debug {
+            log::info!(
+                "debug depth {depth}: hit {:?} at t = {}, normal {}, {:?}",
+                record.local_info.pos,
+                record.t,
+                record.local_info.normal,
+                record.local_info.material
+            );
+        }
+
+        let descriptor = &ctx.world.materials[record.local_info.material.0];
+        if descriptor.alpha.is_some()
+            && descriptor.is_cut_out(
+                record.local_info.uv,
+                Samples([draw_1d(
+                    ctx.sampler,
+                    &mut ctx.rng,
+                    Dimension::AlphaCut(depth),
+                )]),
+            )
+        {
+            return None;
+        }
+
+        let object = record.local_info.object;
+        let material = descriptor.material.bxdf(&ctx.arena, record.local_info.uv);
+        let interior =
+            Self::interior_transmittance(material, ray, record.local_info.normal, record.t);
+        let relative = material
+            .in_medium(&ctx.arena, history.media.exterior(object))
+            .unwrap_or(material);
         // TODO: The material should do it
-        let bsdf = BSDF::new(record.local_info.normal, material.as_ref());
+        let bsdf = BSDF::new(record.local_info.shading_normal, relative);
 
         let wo = -ray.direction;
-        let sampled = bsdf
-            .sample_f(
-                wo,
-                Samples([uniform.sample(&mut ctx.rng), uniform.sample(&mut ctx.rng)]),
-                Samples([uniform.sample(&mut ctx.rng)]),
-            )
-            .unwrap_or(BxDFSample {
-                wi: Vec3::ZERO,
-                f: BLACK,
-                pdf: 1.0,
+        let uv = draw_2d(ctx.sampler, &mut ctx.rng, Dimension::BxDF(depth));
+        let w = draw_1d(ctx.sampler, &mut ctx.rng, Dimension::Lobe(depth));
+        let split = self.split(relative, &bsdf, wo, uv, history.lobes);
+        let emission =
+            Self::emission_weight(ctx.world, history.origin, object, ray.direction) * bsdf.le(wo);
+        let mut scattering = Scattering {
+            le: emission + self.direct_lighting(ctx, &bsdf, record, wo, depth),
+            interior,
+            albedo: BLACK,
+            specular: None,
+            split: split.is_some(),
+            branches: [None, None],
+        };
+        let single;
+        // Each branch along with the probability to take it
+        let sampled_branches: &[(f32, BxDFSample)] = match &split {
+            Some(both) => both,
+            None => {
+                let sampled = bsdf
+                    .sample_f(wo, Samples(uv), Samples([w]))
+                    .unwrap_or(BxDFSample {
+                        wi: Vec3::ZERO,
+                        f: BLACK,
+                        pdf: 1.0,
+                        flags: BxDFFlags::empty(),
+                    });
+                if sampled.flags.contains(BxDFFlags::Specular) {
+                    scattering.specular = Some(sampled.flags);
+                }
+                single = [(1.0, sampled)];
+                &single
+            }
+        };
+
+        for (branch, &(probability, ref sampled)) in
+            scattering.branches.iter_mut().zip(sampled_branches)
+        {
+            trace!("sampled {:?}", sampled);
+            if ctx.debug {
+                log::info!(
+                    "debug depth {depth}: sampled wi {} with pdf {}, f {:?}, {:?}, branch \
+                    probability {probability}",
+                    sampled.wi,
+                    sampled.pdf,
+                    sampled.f,
+                    sampled.flags
+                );
+            }
+            scattering.albedo = scattering.albedo + probability * sampled.f;
+
+            let fcos = record.local_info.normal.dot(sampled.wi).abs() * sampled.f;
+            trace!("fcos {fcos:?}");
+            let next_lobes = if fcos.vec().max_element().abs() != 0.0 {
+                self.bounce(history.lobes, sampled.flags)
+            } else {
+                None
+            };
+            let Some(lobes) = next_lobes else {
+                continue;
+            };
+
+            *branch = Some(Branch {
+                probability,
+                weight: probability / sampled.pdf * fcos,
+                ray: Ray::spawn(record.local_info.pos, record.local_info.normal, sampled.wi),
+                history: History {
+                    lobes,
+                    media: history.media.scattered(
+                        material,
+                        object,
+                        wo,
+                        record.local_info.normal,
+                        sampled,
+                    ),
+                    origin: Self::samples_lights(ctx.world, bsdf.flags()).then_some(Origin {
+                        position: record.local_info.pos,
+                        side: Self::lit_side(bsdf.flags(), record.local_info.normal, wo),
+                        pdf: sampled.pdf,
+                    }),
+                },
             });
-        trace!("sampled {:?}", sampled);
-
-        let fcos = record.local_info.normal.dot(sampled.wi).abs() * sampled.f;
-        trace!("fcos {fcos:?}");
-        let (li, ray_depth) = if fcos.vec().max_element().abs() != 0.0 {
-            let ray_result =
-                self.ray_cast(ctx, Ray::new(record.local_info.pos, sampled.wi), depth + 1);
-            (
-                material.le() + 1.0 / sampled.pdf * fcos * ray_result.color,
-                ray_result.ray_depth,
-            )
-        } else {
-            (material.le(), 0.0)
+        }
+
+        trace!("le {:?}", scattering.le);
+        if ctx.debug {
+            log::info!("debug depth {depth}: le {:?}", scattering.le);
+        }
+        Some(scattering)
+    }
+
+    fn trace(&self, ctx: &mut Ctx, ray: Ray, depth: u32, history: History) -> RayResult {
+        if depth == self.max_depth {
+            return RayResult::default();
+        }
+        trace!("depth {depth:?}");
+
+        let isect = ctx.world.objects.intersection_full(ray);
+        let IntersectionResult::Intersection(record) = isect else {
+            return self.escape(ctx, ray, depth);
+        };
+
+        let Some(scattering) = self.shade(ctx, &ray, &record, depth, history) else {
+            let ray_result = self.trace(
+                ctx,
+                Ray::spawn(
+                    record.local_info.pos,
+                    record.local_info.normal,
+                    ray.direction,
+                ),
+                depth + 1,
+                history,
+            );
+            return RayResult {
+                color: ctx.world.attenuate(ray_result.color, record.t),
+                z: ray_result.z + record.t,
+                ray_depth: ray_result.ray_depth + record.t,
+                ..ray_result
+            };
         };
 
+        let (mut li, mut ray_depth) = (scattering.le, 0.0);
+        let mut first_specular = scattering.specular;
+        for branch in scattering.branches.into_iter().flatten() {
+            let ray_result = self.trace(ctx, branch.ray, depth + 1, branch.history);
+            li = li + branch.weight * ray_result.color;
+            ray_depth += branch.probability * ray_result.ray_depth;
+            if !scattering.split {
+                first_specular = first_specular.or(ray_result.first_specular);
+            }
+        }
+
         trace!("li {:?}", li);
-        trace!("le {:?}", material.le());
+        if ctx.debug {
+            log::info!("debug depth {depth}: li {li:?}");
+        }
 
         RayResult {
             normal: record.local_info.normal,
             position: record.local_info.pos,
-            albedo: sampled.f,
-            color: li,
+            albedo: scattering.albedo,
+            color: Self::attenuate(ctx.world, li, record.t, scattering.interior),
             z: record.t,
             ray_depth: ray_depth + record.t,
             samples_accumulated: 1,
+            escaped: false,
+            object: Some(record.local_info.object),
+            first_specular,
         }
     }
 }
+
+/// A path scattered by a surface
+struct Scattering {
+    /// Radiance emitted by the surface toward the path, along with the light of the lights it
+    /// reflects directly
+    le: Rgb,
+    /// Transmittance of the segment leading to the hit if it went through the inside of an object
+    interior: Option<Rgb>,
+    albedo: Rgb,
+    /// The lobe sampled there if it is specular and the path is not split on it
+    specular: Option<BxDFFlags>,
+    /// Both the reflection and the transmission are taken
+    split: bool,
+    /// The branches the path goes on along, there is only the first one if it is not split. A
+    /// branch is None when the path ends there
+    branches: [Option<Branch>; 2],
+}
+
+/// The path going on after a scattering
+struct Branch {
+    /// Probability to take the branch
+    probability: f32,
+    /// Weight of the radiance coming back along the branch
+    weight: Rgb,
+    ray: Ray,
+    history: History,
+}
+
+/// What the next vertices of a path depend on from the ones before
+#[derive(Debug, Clone, Copy, Default)]
+struct History {
+    lobes: LobeDepths,
+    media: IorStack,
+    /// The last vertex, if the lights were also sampled there
+    origin: Option<Origin>,
+}
+
+/// The vertex a path comes from, to weight the emission of a light it hits against the samples of
+/// the light drawn there
+#[derive(Debug, Clone, Copy)]
+struct Origin {
+    position: Point,
+    /// The side of the surface the lights were chosen for, see [PathTracer::lit_side]
+    side: Option<Vec3>,
+    /// Pdf of the direction sampled from the BSDF
+    pdf: f32,
+}
+
+impl Integrator for PathTracer {
+    fn ray_cast(&self, ctx: &mut Ctx, ray: Ray, depth: u32) -> RayResult {
+        self.trace(ctx, ray, depth, History::default())
+    }
+
+    /// The paths of the wavefront loop don't branch, there is none when splitting
+    fn as_wavefront(&self) -> Option<&dyn WavefrontIntegrator> {
+        (self.split_depth == 0).then_some(self as _)
+    }
+}
+
+/// A path being traced by the wavefront loop
+struct PathState<'a> {
+    ray: Ray,
+    ctx: Ctx<'a>,
+    /// The first vertex of the path. Its color and ray depth are only known at the end
+    first_hit: Option<RayResult>,
+    /// `(le, weight, t, interior)` of each vertex after which the path continued, `interior` is
+    /// the transmittance of the segment leading to it if it was inside an object
+    vertices: Vec<(Rgb, Rgb, f32, Option<Rgb>)>,
+    /// Color and ray depth of the last vertex of the path
+    terminal: (Rgb, f32),
+    /// `t` of each cut out surface crossed before the first vertex
+    cutouts: Vec<f32>,
+    /// The lobe sampled at the first specular vertex of the path
+    first_specular: Option<BxDFFlags>,
+    history: History,
+}
+
+impl WavefrontIntegrator for PathTracer {
+    fn ray_cast_wavefront(&self, world: &World, rays: Vec<WavefrontRay>) -> Vec<RayResult> {
+        let mut paths = rays
+            .into_iter()
+            .map(|WavefrontRay { ray, ctx }| PathState {
+                ray,
+                ctx,
+                first_hit: None,
+                vertices: Vec::new(),
+                terminal: (BLACK, 0.0),
+                cutouts: Vec::new(),
+                first_specular: None,
+                history: History::default(),
+            })
+            .collect::<Vec<_>>();
+
+        let mut active = (0..paths.len()).collect::<Vec<_>>();
+        let mut depth = 0;
+        while !active.is_empty() && depth != self.max_depth {
+            trace!("depth {depth:?}, {} active paths", active.len());
+
+            // Intersection pass
+            let rays = active
+                .iter()
+                .map(|&index| paths[index].ray)
+                .collect::<Vec<_>>();
+            let isects = world.objects.intersection_stream(&rays);
+
+            // Shading pass, the surviving paths are compacted into the next wavefront
+            let mut next_active = Vec::with_capacity(active.len());
+            for ((index, ray), isect) in active.into_iter().zip(rays).zip(isects) {
+                let path = &mut paths[index];
+                let IntersectionResult::Intersection(record) = isect else {
+                    let sky = self.escape(&mut path.ctx, ray, depth);
+                    path.terminal = (sky.color, sky.ray_depth);
+                    path.first_hit.get_or_insert(sky);
+                    continue;
+                };
+
+                let Some(scattering) =
+                    self.shade(&mut path.ctx, &ray, &record, depth, path.history)
+                else {
+                    // The ray goes on unchanged
+                    if path.first_hit.is_none() {
+                        path.cutouts.push(record.t);
+                    }
+                    path.vertices.push((BLACK, WHITE, record.t, None));
+                    path.ray = Ray::spawn(
+                        record.local_info.pos,
+                        record.local_info.normal,
+                        ray.direction,
+                    );
+                    next_active.push(index);
+                    continue;
+                };
+
+                path.first_hit.get_or_insert(RayResult {
+                    normal: record.local_info.normal,
+                    object: Some(record.local_info.object),
+                    position: record.local_info.pos,
+                    albedo: scattering.albedo,
+                    z: record.t,
+                    samples_accumulated: 1,
+                    ..Default::default()
+                });
+                if let Some(flags) = scattering.specular {
+                    path.first_specular.get_or_insert(flags);
+                }
+
+                // The paths are never split, see [PathTracer::as_wavefront]
+                let [branch, _] = scattering.branches;
+                if let Some(branch) = branch {
+                    path.history = branch.history;
+                    path.vertices.push((
+                        scattering.le,
+                        branch.weight,
+                        record.t,
+                        scattering.interior,
+                    ));
+                    path.ray = branch.ray;
+                    next_active.push(index);
+                } else {
+                    path.terminal = (
+                        Self::attenuate(world, scattering.le, record.t, scattering.interior),
+                        record.t,
+                    );
+                }
+            }
+
+            active = next_active;
+            depth += 1;
+        }
+
+        paths
+            .into_iter()
+            .map(|path| {
+                let Some(first_hit) = path.first_hit else {
+                    return RayResult::default();
+                };
+
+                // The estimator is evaluated back to front, in the same order as the recursion
+                // does, to get the exact same results
+                let (color, ray_depth) = path.vertices.iter().rev().fold(
+                    path.terminal,
+                    |(li, ray_depth), &(le, weight, t, interior)| {
+                        (
+                            Self::attenuate(world, le + weight * li, t, interior),
+                            ray_depth + t,
+                        )
+                    },
+                );
+
+                let z = path.cutouts.iter().rev().fold(first_hit.z, |z, t| z + t);
+
+                RayResult {
+                    color,
+                    ray_depth,
+                    z,
+                    first_specular: path.first_specular,
+                    ..first_hit
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use glam::Vec3;
+
+    use crate::{
+        aggregate::triangle_mesh::TriangleMesh,
+        color::{
+            linear::{BLACK, WHITE},
+            Rgb,
+        },
+        integrators::{Integrator, WavefrontIntegrator, WavefrontRay},
+        light::{Light, Lights, PointLight, QuadEmitter, SphereEmitter, SphereLight, SpotLight},
+        material::{
+            texture::Uniform, DielectricBxDF, DiffuseBxDF, EmitBxDF, MaterialDescriptor,
+            MaterialId, TexturedEmit,
+        },
+        math::{bounds::Bounds, point::Point, vec::Vec3Ext},
+        memory::{Arena, ArenaInner},
+        ray::Ray,
+        renderer::{
+            Channel, GlobalFog, LumaChannel, RayResult, RaySeries, RgbChannel, World,
+            BACKGROUND_OBJECT_ID,
+        },
+        sampler::{DummyPixelSampler, Sampler},
+        scene::{emissive_quad_material, quad_mesh},
+        shape::{
+            local_info, FullIntersectionResult, IntersectionResult, MinIntersectionResult,
+            RayIntersection, Shape,
+        },
+        Ctx, Seed,
+    };
+
+    use super::PathTracer;
+
+    /// The context of the sample `sample_idx` of the pixel (0, 0)
+    pub(crate) fn test_ctx<'a>(
+        world: &'a World,
+        arena: &'a ArenaInner,
+        sampler: &'a mut dyn Sampler,
+        sample_idx: u32,
+    ) -> Ctx<'a> {
+        let seed = Seed {
+            seed: 0,
+            x: 0,
+            y: 0,
+            sample_idx,
+        };
+        Ctx {
+            rng: seed.into_rng(0),
+            world,
+            arena: Arena::new(arena),
+            seed,
+            sampler,
+            debug: false,
+        }
+    }
+
+    /// Spheres of `(center, radius, material)`
+    pub(crate) struct Spheres(pub Vec<(Point, f32, MaterialId)>);
+
+    impl Shape for Spheres {
+        fn intersection_full(&self, ray: Ray) -> FullIntersectionResult {
+            self.0
+                .iter()
+                .enumerate()
+                .map(|(object, &(center, radius, material))| {
+                    let oc = ray.origin - center;
+                    let b = oc.dot(ray.direction);
+                    let delta = b * b - oc.length_squared() + radius * radius;
+                    let t = [-b - delta.sqrt(), -b + delta.sqrt()]
+                        .into_iter()
+                        .find(|t| delta >= 0.0 && ray.range().contains(t));
+                    match t {
+                        Some(t) => IntersectionResult::Intersection(RayIntersection {
+                            t,
+                            local_info: local_info::Full {
+                                pos: ray.at(t),
+                                normal: (ray.at(t) - center) / radius,
+                                shading_normal: (ray.at(t) - center) / radius,
+                                material,
+                                uv: [0.0, 0.0],
+                                object: object as u32,
+                            },
+                        }),
+                        None => IntersectionResult::NoIntersection,
+                    }
+                })
+                .fold(IntersectionResult::NoIntersection, IntersectionResult::min)
+        }
+
+        fn intersect_bare(&self, ray: Ray) -> MinIntersectionResult {
+            match self.intersection_full(ray) {
+                IntersectionResult::Intersection(RayIntersection { t, local_info }) => {
+                    IntersectionResult::Intersection(RayIntersection {
+                        t,
+                        local_info: local_info::Minimum {
+                            pos: local_info.pos,
+                        },
+                    })
+                }
+                IntersectionResult::NoIntersection => IntersectionResult::NoIntersection,
+            }
+        }
+
+        fn bounding_box(&self) -> Bounds {
+            self.0
+                .iter()
+                .map(|&(center, radius, _)| {
+                    Bounds::new(center - Vec3::splat(radius), center + Vec3::splat(radius))
+                })
+                .fold(Bounds::EMPTY, Bounds::union)
+        }
+    }
+
+    #[test]
+    fn spot_light_next_event() {
+        let materials = [MaterialDescriptor {
+            label: None,
+            material: Box::new(DiffuseBxDF {
+                albedo: WHITE,
+                ..Default::default()
+            }),
+            alpha: None,
+        }];
+        // The floor is the top of a huge sphere, the light is pointing down at it from above the
+        // camera
+        let spheres = Spheres(vec![(Point::new(0.0, -101.0, 0.0), 100.0, MaterialId(0))]);
+        let lights: [Box<dyn Light>; 1] = [Box::new(SpotLight {
+            position: Point::new(0.0, 1.0, 0.0),
+            direction: Vec3::NEG_Y,
+            cos_total_width: f32::to_radians(30.0).cos(),
+            cos_falloff_start: f32::to_radians(20.0).cos(),
+            intensity: Rgb::from_array([4.0 * std::f32::consts::PI; 3]),
+        })];
+        let world = World {
+            objects: &spheres,
+            lights: &Lights::new(&lights),
+            materials: &materials,
+            world_material: MaterialId(0),
+            fog: None,
+        };
+        // Only the light arriving straight from the light
+        let integrator = PathTracer::new(1);
+        let arena = ArenaInner::new(1024);
+        let mut sampler = DummyPixelSampler;
+        let mut floor = |x: f32| {
+            let mut ctx = test_ctx(&world, &arena, &mut sampler, 0);
+            let ray = Ray::new(Point::ORIGIN, Vec3::new(x, -1.0, 0.0).normalize());
+            integrator.ray_cast(&mut ctx, ray, 0).color.to_array()
+        };
+
+        // Right below the light, at a distance of 2: an irradiance of pi
+        for c in floor(0.0) {
+            assert!((c - 1.0).abs() < 1e-3, "{c}");
+        }
+        // Outside of the cone
+        assert_eq!(floor(2.0), [0.0; 3]);
+    }
+
+    #[test]
+    fn sphere_light_mis() {
+        let materials = [
+            MaterialDescriptor {
+                label: None,
+                material: Box::new(DiffuseBxDF {
+                    albedo: WHITE,
+                    ..Default::default()
+                }),
+                alpha: None,
+            },
+            MaterialDescriptor {
+                label: None,
+                material: Box::new(EmitBxDF {
+                    le: [4.0, 4.0, 4.0].into(),
+                    two_sided: true,
+                }),
+                alpha: None,
+            },
+        ];
+        let sphere = SphereLight {
+            center: Point::new(0.0, 1.0, 0.0),
+            radius: 0.5,
+        };
+        let spheres = Spheres(vec![
+            (Point::new(0.0, -2.0, 0.0), 1.0, MaterialId(0)),
+            (sphere.center, sphere.radius, MaterialId(1)),
+        ]);
+        let emitter: [Box<dyn Light>; 1] = [Box::new(SphereEmitter {
+            sphere,
+            le: [4.0, 4.0, 4.0].into(),
+            object: 1,
+        })];
+        // The top of a diffuse sphere lit by the light, reached by the BSDF samples only or by
+        // the light samples as well
+        let integrator = PathTracer::new(2);
+        let arena = ArenaInner::new(1024);
+        let mut sampler = DummyPixelSampler;
+        let samples = 100_000;
+        let mut floor = |lights: &[Box<dyn Light>]| {
+            let world = World {
+                objects: &spheres,
+                lights: &Lights::new(lights),
+                materials: &materials,
+                world_material: MaterialId(0),
+                fog: None,
+            };
+            let ray = Ray::new(Point::ORIGIN, Vec3::NEG_Y);
+            (0..samples)
+                .map(|i| {
+                    let mut ctx = test_ctx(&world, &arena, &mut sampler, i);
+                    integrator.ray_cast(&mut ctx, ray, 0).color.to_array()[0] as f64
+                })
+                .sum::<f64>()
+                / samples as f64
+        };
+
+        // Facing a sphere, the irradiance is pi le sin^2, sin being the one of the half angle of
+        // its cone
+        let expected = 4.0 * 0.25f64.powi(2);
+        for mean in [floor(&[]), floor(&emitter)] {
+            assert!(
+                (mean - expected).abs() < 0.04 * expected,
+                "{mean} {expected}"
+            );
+        }
+    }
+
+    #[test]
+    fn quad_light_next_event() {
+        let materials = [MaterialDescriptor {
+            label: None,
+            material: Box::new(DiffuseBxDF {
+                albedo: WHITE,
+                ..Default::default()
+            }),
+            alpha: None,
+        }];
+        // The top of a diffuse sphere, a square light of side 2 facing it from a height of 1
+        let spheres = Spheres(vec![(Point::new(0.0, -2.0, 0.0), 1.0, MaterialId(0))]);
+        let lights: [Box<dyn Light>; 1] = [Box::new(QuadEmitter {
+            corner: Point::new(-1.0, 0.0, -1.0),
+            edges: [Vec3::new(2.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 2.0)],
+            emission: TexturedEmit {
+                le: Box::new(Uniform([2.0, 2.0, 2.0].into())),
+                two_sided: false,
+            },
+            exponent: 0.0,
+            object: None,
+        })];
+        let world = World {
+            objects: &spheres,
+            lights: &Lights::new(&lights),
+            materials: &materials,
+            world_material: MaterialId(0),
+            fog: None,
+        };
+        let integrator = PathTracer::new(1);
+        let arena = ArenaInner::new(1024);
+        let mut sampler = DummyPixelSampler;
+        let samples = 20_000;
+        let mean = (0..samples)
+            .map(|i| {
+                let mut ctx = test_ctx(&world, &arena, &mut sampler, i);
+                let ray = Ray::new(Point::ORIGIN, Vec3::NEG_Y);
+                integrator.ray_cast(&mut ctx, ray, 0).color.to_array()[0] as f64
+            })
+            .sum::<f64>()
+            / samples as f64;
+
+        // The form factor of the four unit squares around the normal
+        let a = 0.5f64.sqrt();
+        let expected = 2.0 * 4.0 / std::f64::consts::PI * a * a.atan();
+        assert!(
+            (mean - expected).abs() < 0.03 * expected,
+            "{mean} {expected}"
+        );
+    }
+
+    /// Spheres and a mesh, the mesh being the object after the spheres
+    struct SpheresAndMesh(Spheres, TriangleMesh);
+
+    impl Shape for SpheresAndMesh {
+        fn intersection_full(&self, ray: Ray) -> FullIntersectionResult {
+            let mesh = match self.1.intersection_full(ray) {
+                IntersectionResult::Intersection(RayIntersection { t, local_info }) => {
+                    IntersectionResult::Intersection(RayIntersection {
+                        t,
+                        local_info: local_info::Full {
+                            object: self.0 .0.len() as u32,
+                            ..local_info
+                        },
+                    })
+                }
+                IntersectionResult::NoIntersection => IntersectionResult::NoIntersection,
+            };
+            self.0.intersection_full(ray).min(mesh)
+        }
+
+        fn intersect_bare(&self, ray: Ray) -> MinIntersectionResult {
+            self.0.intersect_bare(ray).min(self.1.intersect_bare(ray))
+        }
+
+        fn bounding_box(&self) -> Bounds {
+            self.0.bounding_box().union(self.1.bounding_box())
+        }
+    }
+
+    #[test]
+    fn quad_light_mis() {
+        let le = Rgb::from_array([2.0; 3]);
+        let materials = [
+            MaterialDescriptor {
+                label: None,
+                material: Box::new(DiffuseBxDF {
+                    albedo: WHITE,
+                    ..Default::default()
+                }),
+                alpha: None,
+            },
+            emissive_quad_material(le),
+        ];
+        // The top of a diffuse sphere, a square light of side 2 facing it from a height of 1 that
+        // is an object of the world as well
+        let (corner, edges) = (
+            Point::new(-1.0, 0.0, -1.0),
+            [Vec3::new(2.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 2.0)],
+        );
+        let (vertices, indices) = quad_mesh(corner, edges);
+        let objects = SpheresAndMesh(
+            Spheres(vec![(Point::new(0.0, -2.0, 0.0), 1.0, MaterialId(0))]),
+            TriangleMesh::new(
+                MaterialId(1),
+                vertices.map(Vec3::from_array).to_vec(),
+                None,
+                indices.to_vec(),
+            ),
+        );
+        let emitter: [Box<dyn Light>; 1] = [Box::new(QuadEmitter {
+            corner,
+            edges,
+            emission: TexturedEmit {
+                le: Box::new(Uniform(le)),
+                two_sided: false,
+            },
+            exponent: 0.0,
+            object: Some(1),
+        })];
+        // Reached by the BSDF samples only or by the light samples as well
+        let integrator = PathTracer::new(2);
+        let arena = ArenaInner::new(1024);
+        let mut sampler = DummyPixelSampler;
+        let samples = 100_000;
+        let mut floor = |lights: &[Box<dyn Light>]| {
+            let world = World {
+                objects: &objects,
+                lights: &Lights::new(lights),
+                materials: &materials,
+                world_material: MaterialId(0),
+                fog: None,
+            };
+            let ray = Ray::new(Point::new(0.0, -0.5, 0.0), Vec3::NEG_Y);
+            (0..samples)
+                .map(|i| {
+                    let mut ctx = test_ctx(&world, &arena, &mut sampler, i);
+                    integrator.ray_cast(&mut ctx, ray, 0).color.to_array()[0] as f64
+                })
+                .sum::<f64>()
+                / samples as f64
+        };
+
+        // The form factor of the four unit squares around the normal
+        let a = 0.5f64.sqrt();
+        let expected = 2.0 * 4.0 / std::f64::consts::PI * a * a.atan();
+        for mean in [floor(&[]), floor(&emitter)] {
+            assert!(
+                (mean - expected).abs() < 0.03 * expected,
+                "{mean} {expected}"
+            );
+        }
+    }
+
+    #[test]
+    fn shadow_samples() {
+        let materials = [MaterialDescriptor {
+            label: None,
+            material: Box::new(DiffuseBxDF {
+                albedo: WHITE,
+                ..Default::default()
+            }),
+            alpha: None,
+        }];
+        // The top of a diffuse sphere lit by a large square light
+        let spheres = Spheres(vec![(Point::new(0.0, -2.0, 0.0), 1.0, MaterialId(0))]);
+        let lights: [Box<dyn Light>; 1] = [Box::new(QuadEmitter {
+            corner: Point::new(-2.0, 0.0, -2.0),
+            edges: [Vec3::new(4.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 4.0)],
+            emission: TexturedEmit {
+                le: Box::new(Uniform([2.0, 2.0, 2.0].into())),
+                two_sided: false,
+            },
+            exponent: 0.0,
+            object: None,
+        })];
+        let world = World {
+            objects: &spheres,
+            lights: &Lights::new(&lights),
+            materials: &materials,
+            world_material: MaterialId(0),
+            fog: None,
+        };
+        let arena = ArenaInner::new(1024);
+        let mut sampler = DummyPixelSampler;
+        let samples = 4096;
+        let mut radiance = |integrator: PathTracer| {
+            (0..samples)
+                .map(|i| {
+                    let mut ctx = test_ctx(&world, &arena, &mut sampler, i);
+                    let ray = Ray::new(Point::ORIGIN, Vec3::NEG_Y);
+                    integrator.ray_cast(&mut ctx, ray, 0).color.to_array()[0]
+                })
+                .collect::<Vec<_>>()
+        };
+        let stats = |values: &[f32]| {
+            let mean = values.iter().sum::<f32>() / samples as f32;
+            let variance =
+                values.iter().map(|v| (v - mean).powi(2)).sum::<f32>() / (samples - 1) as f32;
+            (mean, variance)
+        };
+
+        // A single sample is the default
+        let one = radiance(PathTracer::new(1));
+        assert_eq!(
+            one,
+            radiance(PathTracer {
+                shadow_samples: 1,
+                ..PathTracer::new(1)
+            })
+        );
+
+        // The same radiance, with the variance of the mean of the samples
+        let (mean, variance) = stats(&one);
+        let (many_mean, many_variance) = stats(&radiance(PathTracer {
+            shadow_samples: 8,
+            ..PathTracer::new(1)
+        }));
+        assert!(variance > 0.0);
+        assert!(
+            (many_mean - mean).abs() < 3.0 * (variance / samples as f32).sqrt(),
+            "{many_mean} != {mean}"
+        );
+        assert!(
+            4.0 * many_variance < variance,
+            "{many_variance} vs {variance}"
+        );
+    }
+
+    #[test]
+    fn wavefront_matches_recursive() {
+        let materials = vec![
+            MaterialDescriptor {
+                label: None,
+                material: Box::new(DiffuseBxDF {
+                    albedo: [0.8, 0.5, 0.2].into(),
+                    ..Default::default()
+                }),
+                alpha: None,
+            },
+            MaterialDescriptor {
+                label: None,
+                material: Box::new(DielectricBxDF {
+                    ior: 1.5,
+                    roughness: 0.0,
+                    transmittance_color: [0.5, 0.9, 0.7].into(),
+                }),
+                alpha: None,
+            },
+            MaterialDescriptor {
+                label: None,
+                material: Box::new(EmitBxDF {
+                    le: [4.0, 4.0, 4.0].into(),
+                    two_sided: true,
+                }),
+                alpha: None,
+            },
+            MaterialDescriptor {
+                label: None,
+                material: Box::new(DiffuseBxDF {
+                    albedo: [0.2, 0.8, 0.2].into(),
+                    ..Default::default()
+                }),
+                alpha: Some(Box::new(Uniform([0.5, 0.5, 0.5].into()))),
+            },
+        ];
+        let spheres = Spheres(vec![
+            (Point::new(0.0, 0.0, -1.0), 0.5, MaterialId(0)),
+            (Point::new(0.6, 0.0, -0.8), 0.3, MaterialId(1)),
+            (Point::new(0.0, 2.0, -1.0), 0.8, MaterialId(2)),
+            (Point::new(-0.5, 0.1, -0.7), 0.3, MaterialId(3)),
+        ]);
+        let lights: [Box<dyn Light>; 1] = [Box::new(PointLight {
+            position: Point::new(1.0, 1.0, 0.0),
+            intensity: [2.0, 2.0, 2.0].into(),
+        })];
+        let world = World {
+            objects: &spheres,
+            lights: &Lights::new(&lights),
+            materials: &materials,
+            world_material: MaterialId(0),
+            fog: Some(GlobalFog {
+                sigma_t: 0.2,
+                color: [0.1, 0.2, 0.3].into(),
+            }),
+        };
+        let integrator = PathTracer {
+            max_depth: 8,
+            max_diffuse_depth: 3,
+            max_glossy_depth: 8,
+            max_specular_depth: 6,
+            split_depth: 0,
+            shadow_samples: 1,
+        };
+
+        let ray = |i: u32| {
+            let u = i as f32 / 256.0;
+            Ray::new(Point::ORIGIN, Vec3::new(u - 0.5, 0.3 * u, -1.0).normalize())
+        };
+
+        let arena = ArenaInner::new(1024);
+        let mut sampler = DummyPixelSampler;
+        let recursive = (0..256)
+            .map(|i| {
+                let mut ctx = test_ctx(&world, &arena, &mut sampler, i);
+                integrator.ray_cast(&mut ctx, ray(i), 0)
+            })
+            .collect::<Vec<_>>();
+
+        let mut samplers = vec![DummyPixelSampler; 256];
+        let wavefront = integrator.ray_cast_wavefront(
+            &world,
+            (0..256)
+                .zip(&mut samplers)
+                .map(|(i, sampler)| WavefrontRay {
+                    ray: ray(i),
+                    ctx: test_ctx(&world, &arena, sampler, i),
+                })
+                .collect(),
+        );
+
+        assert_eq!(recursive.len(), wavefront.len());
+        for (r, w) in recursive.iter().zip(&wavefront) {
+            assert_eq!(r.color.to_array(), w.color.to_array());
+            assert_eq!(r.albedo.to_array(), w.albedo.to_array());
+            assert_eq!(r.normal, w.normal);
+            assert_eq!(r.position, w.position);
+            assert_eq!(r.z, w.z);
+            assert_eq!(r.ray_depth, w.ray_depth);
+            assert_eq!(r.samples_accumulated, w.samples_accumulated);
+            assert_eq!(r.escaped, w.escaped);
+        }
+    }
+
+    #[test]
+    fn spawned_rays_do_not_self_intersect() {
+        // Far away from the origin, where the hit points are the least precise
+        let center = Point::new(1e5, -1e5, 1e5);
+        let sphere = Spheres(vec![(center, 10.0, MaterialId(0))]);
+
+        for i in 0..1000 {
+            let u = i as f32 / 1000.0;
+            let direction = Vec3::new(u - 0.5, 0.5 - u * u, -1.0).normalize();
+            let isect = sphere
+                .intersection_full(Ray::new(center - 20.0 * direction, direction))
+                .unwrap();
+            let (pos, normal) = (isect.local_info.pos, isect.local_info.normal);
+
+            // Leaving the surface, nothing else can be hit
+            let reflected = (-direction).reflect(normal);
+            let outgoing = sphere.intersection_full(Ray::spawn(pos, normal, reflected));
+            assert!(!outgoing.is_intersection(), "self-intersection at {pos:?}");
+
+            // Going through, the far side of the sphere is hit, not the near one
+            let through = sphere
+                .intersection_full(Ray::spawn(pos, normal, direction))
+                .unwrap();
+            assert!(through.t > 1.0, "self-intersection at {pos:?}");
+        }
+
+        // A thin shell, the inner sphere must be hit when going through the outer one
+        let shell = Spheres(vec![
+            (Point::ORIGIN, 1.0, MaterialId(0)),
+            (Point::ORIGIN, 0.999, MaterialId(1)),
+        ]);
+        for i in 0..1000 {
+            let u = i as f32 / 1000.0;
+            let direction = Vec3::new(u - 0.5, 0.1, -1.0).normalize();
+            let isect = shell
+                .intersection_full(Ray::new(Point::ORIGIN - 2.0 * direction, direction))
+                .unwrap();
+            assert_eq!(isect.local_info.material.0, 0);
+
+            let inner = shell
+                .intersection_full(Ray::spawn(
+                    isect.local_info.pos,
+                    isect.local_info.normal,
+                    direction,
+                ))
+                .unwrap();
+            assert_eq!(
+                inner.local_info.material.0, 1,
+                "light leaked through the shell"
+            );
+        }
+    }
+
+    /// The floor y = 0, facing +y, shaded as if it was facing `shading_normal`
+    struct TiltedFloor {
+        shading_normal: Vec3,
+    }
+
+    impl Shape for TiltedFloor {
+        fn intersection_full(&self, ray: Ray) -> FullIntersectionResult {
+            let t = -ray.origin.0.y / ray.direction.y;
+            if !ray.range().contains(&t) {
+                return FullIntersectionResult::NoIntersection;
+            }
+            FullIntersectionResult::Intersection(RayIntersection {
+                t,
+                local_info: local_info::Full {
+                    pos: ray.at(t),
+                    normal: Vec3::Y,
+                    shading_normal: self.shading_normal,
+                    material: MaterialId(0),
+                    uv: [0.0, 0.0],
+                    object: 0,
+                },
+            })
+        }
+
+        fn intersect_bare(&self, ray: Ray) -> MinIntersectionResult {
+            match self.intersection_full(ray) {
+                IntersectionResult::Intersection(RayIntersection { t, local_info }) => {
+                    IntersectionResult::Intersection(RayIntersection {
+                        t,
+                        local_info: local_info::Minimum {
+                            pos: local_info.pos,
+                        },
+                    })
+                }
+                IntersectionResult::NoIntersection => IntersectionResult::NoIntersection,
+            }
+        }
+
+        fn bounding_box(&self) -> Bounds {
+            Bounds::new(Point::new(-1e3, 0.0, -1e3), Point::new(1e3, 0.0, 1e3))
+        }
+    }
+
+    #[test]
+    fn shading_normal_is_only_for_the_bxdf() {
+        let materials = [MaterialDescriptor {
+            label: None,
+            material: Box::new(DiffuseBxDF {
+                albedo: WHITE,
+                ..Default::default()
+            }),
+            alpha: None,
+        }];
+        // Seen from above, lit from above, but the shading normal points away from both: the
+        // shadow ray must still leave on the geometric side of the floor, the one of the light
+        let floor = TiltedFloor {
+            shading_normal: Vec3::new(-1.0, 0.2, 0.0).normalize(),
+        };
+        let lights: [Box<dyn Light>; 1] = [Box::new(PointLight {
+            position: Point::new(2.0, 1.0, 0.0),
+            intensity: Rgb::from_array([5.0 * std::f32::consts::PI; 3]),
+        })];
+        let world = World {
+            objects: &floor,
+            lights: &Lights::new(&lights),
+            materials: &materials,
+            world_material: MaterialId(0),
+            fog: None,
+        };
+        let integrator = PathTracer::new(1);
+        let arena = ArenaInner::new(1024);
+        let mut sampler = DummyPixelSampler;
+        let mut ctx = test_ctx(&world, &arena, &mut sampler, 0);
+        let ray = Ray::new(
+            Point::new(2.0, 2.0, 0.0),
+            Vec3::new(-1.0, -1.0, 0.0).normalize(),
+        );
+        let color = integrator.ray_cast(&mut ctx, ray, 0).color.to_array();
+
+        // An irradiance of pi / sqrt(5), the cosine is the one of the geometric normal
+        for c in color {
+            assert!((c - 5f32.sqrt().recip()).abs() < 1e-3, "{c}");
+        }
+    }
+
+    #[test]
+    fn alpha_cutout() {
+        let spheres = Spheres(vec![
+            (Point::new(0.0, 0.0, -2.0), 0.5, MaterialId(1)),
+            (Point::new(0.0, 0.0, -4.0), 1.0, MaterialId(0)),
+        ]);
+        let integrator = PathTracer::new(8);
+        let arena = ArenaInner::new(1024);
+
+        let trace = |alpha: Rgb| {
+            let materials = [
+                MaterialDescriptor {
+                    label: None,
+                    material: Box::new(EmitBxDF {
+                        le: [4.0, 2.0, 1.0].into(),
+                        two_sided: true,
+                    }),
+                    alpha: None,
+                },
+                MaterialDescriptor {
+                    label: None,
+                    material: Box::new(DiffuseBxDF {
+                        albedo: BLACK,
+                        ..Default::default()
+                    }),
+                    alpha: Some(Box::new(Uniform(alpha))),
+                },
+            ];
+            let world = World {
+                objects: &spheres,
+                lights: &Lights::default(),
+                materials: &materials,
+                world_material: MaterialId(0),
+                fog: None,
+            };
+            let mut sampler = DummyPixelSampler;
+
+            (0..64)
+                .map(|x| {
+                    let mut ctx = test_ctx(&world, &arena, &mut sampler, x);
+                    integrator.ray_cast(&mut ctx, Ray::new(Point::ORIGIN, Vec3::NEG_Z), 0)
+                })
+                .collect::<Vec<_>>()
+        };
+
+        for opaque in trace(WHITE) {
+            assert_eq!(opaque.color.to_array(), BLACK.to_array());
+            assert_eq!(opaque.position, Point::new(0.0, 0.0, -1.5));
+        }
+        for transparent in trace(BLACK) {
+            assert_eq!(transparent.color.to_array(), [4.0, 2.0, 1.0]);
+            assert!((transparent.position - Point::new(0.0, 0.0, -3.0)).length() < 1e-4);
+            assert!((transparent.z - 3.0).abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn one_sided_emitter() {
+        let materials = [MaterialDescriptor {
+            label: None,
+            material: Box::new(EmitBxDF {
+                le: [4.0, 2.0, 1.0].into(),
+                two_sided: false,
+            }),
+            alpha: None,
+        }];
+        let spheres = Spheres(vec![(Point::new(0.0, 0.0, -2.0), 1.0, MaterialId(0))]);
+        let world = World {
+            objects: &spheres,
+            lights: &Lights::default(),
+            materials: &materials,
+            world_material: MaterialId(0),
+            fog: None,
+        };
+        let integrator = PathTracer::new(8);
+        let arena = ArenaInner::new(1024);
+        let mut sampler = DummyPixelSampler;
+        let mut ctx = test_ctx(&world, &arena, &mut sampler, 0);
+
+        // The normals of the sphere point outward, so it only emits outward
+        let outside = integrator.ray_cast(&mut ctx, Ray::new(Point::ORIGIN, Vec3::NEG_Z), 0);
+        assert_eq!(outside.color.to_array(), [4.0, 2.0, 1.0]);
+
+        let inside = Ray::new(
+            Point::new(0.0, 0.0, -2.0),
+            Vec3::new(0.3, 0.5, 1.0).normalize(),
+        );
+        let inside = integrator.ray_cast(&mut ctx, inside, 0);
+        assert_eq!(inside.color.to_array(), BLACK.to_array());
+    }
+
+    #[test]
+    fn colored_glass_absorbs() {
+        let materials = [
+            MaterialDescriptor {
+                label: None,
+                material: Box::new(EmitBxDF {
+                    le: [100.0, 100.0, 100.0].into(),
+                    two_sided: true,
+                }),
+                alpha: None,
+            },
+            MaterialDescriptor {
+                label: None,
+                material: Box::new(DielectricBxDF {
+                    ior: 1.5,
+                    roughness: 0.0,
+                    transmittance_color: [0.5, 1.0, 1.0].into(),
+                }),
+                alpha: None,
+            },
+        ];
+        let integrator = PathTracer::new(8);
+        let arena = ArenaInner::new(1024);
+        let mut sampler = DummyPixelSampler;
+
+        // Ratio of red to green of the paths going straight through a glass sphere to the light
+        let mut red_ratio = |radius: f32| {
+            let spheres = Spheres(vec![
+                (Point::new(0.0, 0.0, -2.0), radius, MaterialId(1)),
+                (Point::new(0.0, 0.0, -6.0), 2.0, MaterialId(0)),
+            ]);
+            let world = World {
+                objects: &spheres,
+                lights: &Lights::default(),
+                materials: &materials,
+                world_material: MaterialId(1),
+                fog: None,
+            };
+            (0..64)
+                .filter_map(|x| {
+                    let mut ctx = test_ctx(&world, &arena, &mut sampler, x);
+                    let ray = Ray::new(Point::ORIGIN, Vec3::NEG_Z);
+                    let [r, g, _] = integrator.ray_cast(&mut ctx, ray, 0).color.to_array();
+                    (g >= 50.0).then_some(r / g)
+                })
+                .next()
+                .expect("no path reaches the light")
+        };
+
+        // The light goes through a whole diameter of glass
+        for radius in [0.25, 0.5] {
+            let expected = 0.5f32.powf(2.0 * radius);
+            let ratio = red_ratio(radius);
+            assert!((ratio - expected).abs() < 1e-3, "{ratio} != {expected}");
+        }
+    }
+
+    #[test]
+    fn specular_depth_is_separate() {
+        let materials = [
+            MaterialDescriptor {
+                label: None,
+                material: Box::new(EmitBxDF {
+                    le: [100.0, 100.0, 100.0].into(),
+                    two_sided: true,
+                }),
+                alpha: None,
+            },
+            MaterialDescriptor {
+                label: None,
+                material: Box::new(DielectricBxDF {
+                    ior: 1.5,
+                    roughness: 0.0,
+                    transmittance_color: WHITE,
+                }),
+                alpha: None,
+            },
+        ];
+        // Going through the glass sphere takes two specular bounces
+        let spheres = Spheres(vec![
+            (Point::new(0.0, 0.0, -2.0), 0.5, MaterialId(1)),
+            (Point::new(0.0, 0.0, -6.0), 2.0, MaterialId(0)),
+        ]);
+        let world = World {
+            objects: &spheres,
+            lights: &Lights::default(),
+            materials: &materials,
+            world_material: MaterialId(1),
+            fog: None,
+        };
+        let arena = ArenaInner::new(1024);
+        let mut sampler = DummyPixelSampler;
+
+        let mut reaches_light = |integrator: PathTracer| {
+            (0..64).any(|x| {
+                let mut ctx = test_ctx(&world, &arena, &mut sampler, x);
+                let res = integrator.ray_cast(&mut ctx, Ray::new(Point::ORIGIN, Vec3::NEG_Z), 0);
+                res.color.to_array()[0] >= 50.0
+            })
+        };
+
+        assert!(reaches_light(PathTracer {
+            max_depth: 8,
+            max_diffuse_depth: 1,
+            max_glossy_depth: 1,
+            max_specular_depth: 8,
+            split_depth: 0,
+            shadow_samples: 1,
+        }));
+        assert!(!reaches_light(PathTracer {
+            max_depth: 8,
+            max_diffuse_depth: 8,
+            max_glossy_depth: 8,
+            max_specular_depth: 1,
+            split_depth: 0,
+            shadow_samples: 1,
+        }));
+    }
+
+    #[test]
+    fn sky_is_the_world_material() {
+        let materials = [
+            MaterialDescriptor {
+                label: None,
+                material: Box::new(DiffuseBxDF {
+                    albedo: WHITE,
+                    ..Default::default()
+                }),
+                alpha: None,
+            },
+            MaterialDescriptor {
+                label: None,
+                material: Box::new(EmitBxDF {
+                    le: [0.5, 0.3, 1.0].into(),
+                    two_sided: false,
+                }),
+                alpha: None,
+            },
+        ];
+        let nothing = Spheres(vec![]);
+        let integrator = PathTracer::new(8);
+        let arena = ArenaInner::new(1024);
+        let mut sampler = DummyPixelSampler;
+
+        let mut background = |world_material| {
+            let world = World {
+                objects: &nothing,
+                lights: &Lights::default(),
+                materials: &materials,
+                world_material,
+                fog: None,
+            };
+            let mut ctx = test_ctx(&world, &arena, &mut sampler, 0);
+            let ray = Ray::new(Point::ORIGIN, Vec3::new(0.2, 0.5, -1.0).normalize());
+            let res = integrator.ray_cast(&mut ctx, ray, 0);
+            assert!(res.escaped);
+            res.color.to_array()
+        };
+
+        assert_eq!(background(MaterialId(0)), BLACK.to_array());
+        // Even a one sided emitter faces the inside of the sky
+        assert_eq!(background(MaterialId(1)), [0.5, 0.3, 1.0]);
+    }
+
+    #[test]
+    fn object_ids() {
+        let materials = [MaterialDescriptor {
+            label: None,
+            material: Box::new(DiffuseBxDF {
+                albedo: WHITE,
+                ..Default::default()
+            }),
+            alpha: None,
+        }];
+        let spheres = Spheres(vec![
+            (Point::new(-1.0, 0.0, -3.0), 0.5, MaterialId(0)),
+            (Point::new(1.0, 0.0, -3.0), 0.5, MaterialId(0)),
+        ]);
+        let world = World {
+            objects: &spheres,
+            lights: &Lights::default(),
+            materials: &materials,
+            world_material: MaterialId(0),
+            fog: None,
+        };
+        let integrator = PathTracer::new(8);
+        let arena = ArenaInner::new(1024);
+        let mut sampler = DummyPixelSampler;
+
+        let mut object_id = |x: f32| {
+            let mut series = RaySeries::default();
+            for sample_idx in 0..4 {
+                let mut ctx = test_ctx(&world, &arena, &mut sampler, sample_idx);
+                let ray = Ray::new(Point::new(x, 0.0, 0.0), Vec3::NEG_Z);
+                series.add_sample(integrator.ray_cast(&mut ctx, ray, 0), 1.0);
+            }
+            series
+                .as_pixelresult(false)
+                .channels
+                .into_iter()
+                .find_map(|channel| match channel {
+                    Channel::LumaChannel(LumaChannel::ObjectId, id) => Some(id.0),
+                    _ => None,
+                })
+                .unwrap()
+        };
+
+        let (left, right) = (object_id(-1.0), object_id(1.0));
+        assert_ne!(left, right);
+        assert_eq!(object_id(-1.2), left);
+        assert_eq!(object_id(0.0), BACKGROUND_OBJECT_ID);
+    }
+
+    #[test]
+    fn position_aov() {
+        let materials = [MaterialDescriptor {
+            label: None,
+            material: Box::new(DiffuseBxDF {
+                albedo: WHITE,
+                ..Default::default()
+            }),
+            alpha: None,
+        }];
+        let spheres = Spheres(vec![(Point::new(1.0, 0.0, -3.0), 1.0, MaterialId(0))]);
+        let world = World {
+            objects: &spheres,
+            lights: &Lights::default(),
+            materials: &materials,
+            world_material: MaterialId(0),
+            fog: None,
+        };
+        let integrator = PathTracer::new(8);
+        let arena = ArenaInner::new(1024);
+        let mut sampler = DummyPixelSampler;
+
+        // Half of the samples of the pixel miss the sphere
+        let mut series = RaySeries::default();
+        for sample_idx in 0..4 {
+            let mut ctx = test_ctx(&world, &arena, &mut sampler, sample_idx);
+            let x = if sample_idx % 2 == 0 { 1.0 } else { -1.0 };
+            let ray = Ray::new(Point::new(x, 0.0, 0.0), Vec3::NEG_Z);
+            series.add_sample(integrator.ray_cast(&mut ctx, ray, 0), 1.0);
+        }
+        let position = |series: &RaySeries| {
+            series
+                .as_pixelresult(false)
+                .channels
+                .into_iter()
+                .find_map(|channel| match channel {
+                    Channel::RgbChannel(RgbChannel::Position, p) => Some(p.to_array()),
+                    _ => None,
+                })
+                .unwrap()
+        };
+        assert_eq!(position(&series), [1.0, 0.0, -2.0]);
+
+        let mut escaped = RaySeries::default();
+        escaped.add_sample(
+            RayResult {
+                samples_accumulated: 1,
+                escaped: true,
+                ..Default::default()
+            },
+            1.0,
+        );
+        assert!(position(&escaped).iter().all(|c| c.is_nan()));
+    }
+
+    #[test]
+    fn fog() {
+        let materials = [MaterialDescriptor {
+            label: None,
+            material: Box::new(EmitBxDF {
+                le: [4.0, 2.0, 1.0].into(),
+                two_sided: true,
+            }),
+            alpha: None,
+        }];
+        let near = (Point::new(-1.0, 0.0, -3.0), 0.5, MaterialId(0));
+        let far = (Point::new(1.0, 0.0, -10.0), 0.5, MaterialId(0));
+        let spheres = Spheres(vec![near, far]);
+        let integrator = PathTracer::new(8);
+        let arena = ArenaInner::new(1024);
+        let mut sampler = DummyPixelSampler;
+
+        let mut trace = |fog, target: Point| {
+            let world = World {
+                objects: &spheres,
+                lights: &Lights::default(),
+                materials: &materials,
+                world_material: MaterialId(0),
+                fog,
+            };
+            let mut ctx = test_ctx(&world, &arena, &mut sampler, 0);
+            let ray = Ray::new(Point::ORIGIN, target.vec().normalize());
+            integrator.ray_cast(&mut ctx, ray, 0).color.to_array()
+        };
+
+        let no_density = Some(GlobalFog {
+            sigma_t: 0.0,
+            color: [1.0, 1.0, 1.0].into(),
+        });
+        assert_eq!(trace(None, near.0), trace(no_density, near.0));
+        assert_eq!(trace(None, far.0), trace(no_density, far.0));
+
+        let fog = Some(GlobalFog {
+            sigma_t: 0.1,
+            color: BLACK,
+        });
+        let (near, far) = (trace(fog, near.0), trace(fog, far.0));
+        for c in 0..3 {
+            assert!(far[c] < near[c]);
+            assert!(near[c] < [4.0, 2.0, 1.0][c]);
+        }
+    }
+
+    #[test]
+    fn nested_dielectrics() {
+        let glass = |ior| MaterialDescriptor {
+            label: None,
+            material: Box::new(DielectricBxDF {
+                ior,
+                roughness: 0.0,
+                transmittance_color: WHITE,
+            }),
+            alpha: None,
+        };
+        let materials = [
+            MaterialDescriptor {
+                label: None,
+                material: Box::new(DiffuseBxDF {
+                    albedo: BLACK,
+                    ..Default::default()
+                }),
+                alpha: None,
+            },
+            MaterialDescriptor {
+                label: None,
+                material: Box::new(EmitBxDF {
+                    le: [10.0, 10.0, 10.0].into(),
+                    two_sided: true,
+                }),
+                alpha: None,
+            },
+            glass(1.5),
+            glass(2.0),
+        ];
+        let integrator = PathTracer::new(16);
+        let arena = ArenaInner::new(1024);
+        let mut sampler = DummyPixelSampler;
+
+        // Mean radiance along a row of rays through a glass sphere, in front of a light
+        let mut row = |inner: Option<MaterialId>| {
+            let center = Point::new(0.0, 0.0, -3.0);
+            let mut spheres = vec![
+                (Point::new(0.8, 0.0, -7.0), 0.8, MaterialId(1)),
+                (center, 1.0, MaterialId(2)),
+            ];
+            spheres.extend(inner.map(|material| (center, 0.5, material)));
+            let spheres = Spheres(spheres);
+            let world = World {
+                objects: &spheres,
+                lights: &Lights::default(),
+                materials: &materials,
+                world_material: MaterialId(0),
+                fog: None,
+            };
+            (0..16)
+                .map(|x| {
+                    let direction = Vec3::new((x as f32 / 15.0 - 0.5) * 0.6, 0.0, -1.0);
+                    let samples = 1024;
+                    (0..samples)
+                        .map(|sample_idx| {
+                            let mut ctx =
+                                test_ctx(&world, &arena, &mut sampler, x * samples + sample_idx);
+                            let ray = Ray::new(Point::ORIGIN, direction.normalize());
+                            integrator.ray_cast(&mut ctx, ray, 0).color.to_array()[0]
+                        })
+                        .sum::<f32>()
+                        / samples as f32
+                })
+                .collect::<Vec<_>>()
+        };
+
+        // Inside a glass of the same IOR, the inner surface doesn't refract anything
+        let single = row(None);
+        let nested = row(Some(MaterialId(2)));
+        for (x, (a, b)) in single.iter().zip(&nested).enumerate() {
+            assert!((a - b).abs() < 1.0, "ray {x}: {a} != {b}");
+        }
+
+        // It does if the IORs are different
+        let denser = row(Some(MaterialId(3)));
+        assert!(single.iter().zip(&denser).any(|(a, b)| (a - b).abs() > 2.0));
+    }
+
+    #[test]
+    fn first_specular_split() {
+        let materials = [
+            MaterialDescriptor {
+                label: None,
+                material: Box::new(DiffuseBxDF::default()),
+                alpha: None,
+            },
+            MaterialDescriptor {
+                label: None,
+                material: Box::new(DielectricBxDF {
+                    ior: 1.5,
+                    roughness: 0.0,
+                    transmittance_color: WHITE,
+                }),
+                alpha: None,
+            },
+        ];
+        let spheres = Spheres(vec![(Point::new(0.0, 0.0, -3.0), 1.0, MaterialId(1))]);
+        let world = World {
+            objects: &spheres,
+            lights: &Lights::default(),
+            materials: &materials,
+            world_material: MaterialId(0),
+            fog: None,
+        };
+        let arena = ArenaInner::new(1024);
+        let mut sampler = DummyPixelSampler;
+        let integrator = PathTracer::new(8);
+
+        // The fractions of the samples of a pixel reflected and transmitted by the glass
+        let mut split = |direction: Vec3| {
+            let mut series = RaySeries::default();
+            for sample_idx in 0..2000 {
+                let mut ctx = test_ctx(&world, &arena, &mut sampler, sample_idx);
+                let ray = Ray::new(Point::ORIGIN, direction.normalize());
+                series.add_sample(integrator.ray_cast(&mut ctx, ray, 0), 1.0);
+            }
+            series
+                .as_pixelresult(false)
+                .channels
+                .into_iter()
+                .find_map(|channel| match channel {
+                    Channel::RgbChannel(RgbChannel::SpecularSplit, c) => Some(c.to_array()),
+                    _ => None,
+                })
+                .unwrap()
+        };
+
+        // Hit head on, most of the light goes through the glass
+        let [reflected, transmitted, _] = split(Vec3::NEG_Z);
+        assert!((reflected + transmitted - 1.0).abs() < 1e-6);
+        assert!(reflected < 0.1, "{reflected}");
+        // At grazing angles, most of it is reflected
+        let grazing = f32::asin(0.995 / 3.0);
+        let [reflected, transmitted, _] = split(Vec3::new(grazing.tan(), 0.0, -1.0));
+        assert!((reflected + transmitted - 1.0).abs() < 1e-6);
+        assert!(reflected > 0.5, "{reflected}");
+        // Missing the sphere, there is no specular event
+        assert_eq!(split(Vec3::new(1.0, 0.0, -1.0)), [0.0; 3]);
+
+        // The wavefront loop records the same lobes
+        let ray = Ray::new(
+            Point::ORIGIN,
+            Vec3::new(grazing.tan(), 0.0, -1.0).normalize(),
+        );
+        let mut samplers = vec![DummyPixelSampler; 200];
+        let wavefront = integrator.ray_cast_wavefront(
+            &world,
+            (0..200)
+                .zip(&mut samplers)
+                .map(|(sample_idx, sampler)| WavefrontRay {
+                    ray,
+                    ctx: test_ctx(&world, &arena, sampler, sample_idx),
+                })
+                .collect(),
+        );
+        for (sample_idx, wavefront) in (0..200).zip(wavefront) {
+            let mut ctx = test_ctx(&world, &arena, &mut sampler, sample_idx);
+            let scalar = integrator.ray_cast(&mut ctx, ray, 0);
+            assert!(wavefront.first_specular.is_some());
+            assert_eq!(scalar.first_specular, wavefront.first_specular);
+        }
+    }
+
+    #[test]
+    fn splitting_reduces_variance() {
+        let materials = [
+            MaterialDescriptor {
+                label: None,
+                material: Box::new(DiffuseBxDF {
+                    albedo: BLACK,
+                    ..Default::default()
+                }),
+                alpha: None,
+            },
+            MaterialDescriptor {
+                label: None,
+                material: Box::new(EmitBxDF {
+                    le: [10.0, 10.0, 10.0].into(),
+                    two_sided: true,
+                }),
+                alpha: None,
+            },
+            MaterialDescriptor {
+                label: None,
+                material: Box::new(DielectricBxDF {
+                    ior: 1.5,
+                    roughness: 0.0,
+                    transmittance_color: WHITE,
+                }),
+                alpha: None,
+            },
+        ];
+        // A glass sphere in front of a light, the reflections go to the black sky
+        let spheres = Spheres(vec![
+            (Point::new(0.0, 0.0, -2.0), 0.5, MaterialId(2)),
+            (Point::new(0.0, 0.0, -6.0), 2.0, MaterialId(1)),
+        ]);
+        let world = World {
+            objects: &spheres,
+            lights: &Lights::default(),
+            materials: &materials,
+            world_material: MaterialId(0),
+            fog: None,
+        };
+        let arena = ArenaInner::new(1024);
+        let mut sampler = DummyPixelSampler;
+
+        // Mean and variance of the radiance of a ray hitting the sphere off center
+        let mut stats = |split_depth| {
+            let integrator = PathTracer {
+                split_depth,
+                ..PathTracer::new(16)
+            };
+            let samples = 1024;
+            let values = (0..samples)
+                .map(|sample_idx| {
+                    let mut ctx = test_ctx(&world, &arena, &mut sampler, sample_idx);
+                    let ray = Ray::new(Point::ORIGIN, Vec3::new(0.3, 0.0, -2.0).normalize());
+                    integrator.ray_cast(&mut ctx, ray, 0).color.to_array()[0]
+                })
+                .collect::<Vec<_>>();
+            let mean = values.iter().sum::<f32>() / samples as f32;
+            let variance =
+                values.iter().map(|v| (v - mean).powi(2)).sum::<f32>() / (samples - 1) as f32;
+            (mean, variance)
+        };
+
+        let (mean, variance) = stats(0);
+        let (split_mean, split_variance) = stats(2);
+        assert!(mean > 1.0, "{mean}");
+        // The same radiance, split paths cost at most 4 times more
+        assert!(
+            (split_mean - mean).abs() < 3.0 * (variance / 1024.0).sqrt(),
+            "{split_mean} != {mean}"
+        );
+        assert!(
+            4.0 * split_variance < variance,
+            "{split_variance} vs {variance}"
+        );
+    }
+}
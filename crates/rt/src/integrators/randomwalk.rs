@@ -1,7 +1,6 @@
 use std::f32::consts::FRAC_1_PI;
 
 use log::trace;
-use rand::prelude::Distribution;
 
 use crate::{
     material::BSDF,
@@ -11,6 +10,7 @@ use crate::{
     },
     ray::Ray,
     renderer::RayResult,
+    sampler::{draw_2d, Dimension},
     shape::IntersectionResult,
     Ctx,
 };
@@ -27,43 +27,50 @@ impl Integrator for RandomWalkIntegrator {
             return RayResult::default();
         }
 
-        let ray = Ray::new_with_range(ray.origin, ray.direction, 0.00001..ray.bounds.1);
-
         let isect = ctx.world.objects.intersection_full(ray);
         let IntersectionResult::Intersection(record) = isect else {
             return self.sky_ray(ctx, ray);
         };
 
-        let material = &ctx.world.materials[record.local_info.material.0].material;
+        let material = ctx.world.materials[record.local_info.material.0]
+            .material
+            .bxdf(&ctx.arena, record.local_info.uv);
         // TODO: The material should do it
-        let bsdf = BSDF::new(record.local_info.normal, material.as_ref());
+        let bsdf = BSDF::new(record.local_info.shading_normal, material);
 
-        let uniform = rand::distributions::Uniform::new(0.0, 1.0);
         let wo = -ray.direction;
-        let wi = UniformUnitSphere3.sample_with(Samples([
-            uniform.sample(&mut ctx.rng),
-            uniform.sample(&mut ctx.rng),
-        ]));
+        let wi = UniformUnitSphere3.sample_with(Samples(draw_2d(
+            ctx.sampler,
+            &mut ctx.rng,
+            Dimension::BxDF(depth),
+        )));
 
         let f = bsdf.f(wo, wi);
 
         let fcos = record.local_info.normal.dot(wi).abs() * f;
         trace!("{fcos:?}");
         let li = if fcos.vec().max_element().abs() != 0.0 {
-            let ray_result = self.ray_cast(ctx, Ray::new(record.local_info.pos, wi), depth + 1);
-            material.le() + FRAC_1_PI / 4.0 * fcos * ray_result.color
+            let ray_result = self.ray_cast(
+                ctx,
+                Ray::spawn(record.local_info.pos, record.local_info.normal, wi),
+                depth + 1,
+            );
+            bsdf.le(wo) + FRAC_1_PI / 4.0 * fcos * ray_result.color
         } else {
-            material.le()
+            bsdf.le(wo)
         };
 
         RayResult {
             normal: record.local_info.normal,
             position: record.local_info.pos,
             albedo: f,
-            color: li,
+            color: ctx.world.attenuate(li, record.t),
             z: record.t,
             ray_depth: record.t,
             samples_accumulated: 1,
+            escaped: false,
+            object: Some(record.local_info.object),
+            first_specular: None,
         }
     }
 }
@@ -0,0 +1,197 @@
+use std::f32::consts::PI;
+
+use glam::Vec3;
+
+use crate::{
+    color::{linear::BLACK, Rgb},
+    material::BSDF,
+    math::{
+        distributions::Samples,
+        vec::{RgbAsVec3Ext, Vec3AsRgbExt},
+    },
+    ray::Ray,
+    renderer::RayResult,
+    shape::IntersectionResult,
+    Ctx,
+};
+
+use super::Integrator;
+
+/// Non photorealistic shading of the first hit: the Gooch cool to warm ramp over `N·L`, quantized
+/// in bands as in cel shading, with dark silhouettes where the surface is seen edge on.
+///
+/// Global illumination and shadows are ignored, see "A Non-Photorealistic Lighting Model For
+/// Automatic Technical Illustration", Gooch et al. 1998
+pub struct ToonIntegrator {
+    /// Color of the surfaces facing away from the lights
+    pub cool: Rgb,
+    /// Color of the surfaces facing the lights
+    pub warm: Rgb,
+    /// Number of bands of the ramp, it is continuous if there is less than 2
+    pub bands: u32,
+    /// The surface is part of the silhouette where `|N·V|` is below this
+    pub silhouette: f32,
+}
+
+impl ToonIntegrator {
+    /// How much of the albedo is added to the cool and warm colors
+    const COOL_ALBEDO: f32 = 0.25;
+    const WARM_ALBEDO: f32 = 0.5;
+
+    /// Position of the ramp between the cool and warm colors
+    fn ramp(&self, n_dot_l: f32) -> f32 {
+        let t = (1.0 + n_dot_l) / 2.0;
+        if self.bands < 2 {
+            return t;
+        }
+        let bands = self.bands as f32;
+        ((t * bands).floor() / (bands - 1.0)).min(1.0)
+    }
+}
+
+impl Default for ToonIntegrator {
+    fn default() -> Self {
+        Self {
+            cool: [0.0, 0.0, 0.55].into(),
+            warm: [0.3, 0.3, 0.0].into(),
+            bands: 4,
+            silhouette: 0.2,
+        }
+    }
+}
+
+impl Integrator for ToonIntegrator {
+    fn ray_cast(&self, ctx: &mut Ctx, ray: Ray, _depth: u32) -> RayResult {
+        let isect = ctx.world.objects.intersection_full(ray);
+        let IntersectionResult::Intersection(record) = isect else {
+            return self.sky_ray(ctx, ray);
+        };
+
+        let normal = record.local_info.normal;
+        let material = ctx.world.materials[record.local_info.material.0]
+            .material
+            .bxdf(&ctx.arena, record.local_info.uv);
+        let bsdf = BSDF::new(record.local_info.shading_normal, material);
+        let wo = -ray.direction;
+        // The surface is shaded on the side it is seen from
+        let normal = normal * normal.dot(wo).signum();
+        let albedo = PI * bsdf.f(wo, normal);
+
+        let color = if normal.dot(wo) < self.silhouette {
+            BLACK
+        } else {
+            let pos = record.local_info.pos;
+            // Without lights, the scene is lit from the camera
+            let ramp = if ctx.world.lights.is_empty() {
+                self.ramp(normal.dot(wo))
+            } else {
+                ctx.world
+                    .lights
+                    .iter()
+                    .map(|light| {
+                        // Where a light doesn't reach, the surface is as if facing away from it
+                        self.ramp(
+                            light
+                                .sample_li(pos, Samples([0.5, 0.5]))
+                                .map_or(-1.0, |sample| normal.dot(sample.wi)),
+                        )
+                    })
+                    .sum::<f32>()
+                    / ctx.world.lights.len() as f32
+            };
+            let cool = self.cool.vec() + Self::COOL_ALBEDO * albedo.vec();
+            let warm = self.warm.vec() + Self::WARM_ALBEDO * albedo.vec();
+            Vec3::lerp(cool, warm, ramp).rgb() + bsdf.le(wo)
+        };
+
+        RayResult {
+            normal,
+            position: record.local_info.pos,
+            albedo,
+            color,
+            z: record.t,
+            ray_depth: record.t,
+            samples_accumulated: 1,
+            object: Some(record.local_info.object),
+            ..Default::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::Vec3;
+
+    use crate::{
+        color::linear::{BLACK, WHITE},
+        integrators::{
+            pathtracing::tests::{test_ctx, Spheres},
+            Integrator,
+        },
+        light::{Light, Lights, PointLight},
+        material::{DiffuseBxDF, MaterialDescriptor, MaterialId},
+        math::point::Point,
+        memory::ArenaInner,
+        ray::Ray,
+        renderer::World,
+        sampler::DummyPixelSampler,
+    };
+
+    use super::ToonIntegrator;
+
+    #[test]
+    fn bands_and_silhouette() {
+        let materials = [MaterialDescriptor {
+            label: None,
+            material: Box::new(DiffuseBxDF {
+                albedo: [0.8, 0.2, 0.2].into(),
+                ..Default::default()
+            }),
+            alpha: None,
+        }];
+        let spheres = Spheres(vec![(Point::new(0.0, 0.0, -3.0), 1.0, MaterialId(0))]);
+        let lights: [Box<dyn Light>; 1] = [Box::new(PointLight {
+            position: Point::new(0.0, 10.0, -3.0),
+            intensity: WHITE,
+        })];
+        let world = World {
+            objects: &spheres,
+            lights: &Lights::new(&lights),
+            materials: &materials,
+            world_material: MaterialId(0),
+            fog: None,
+        };
+        let integrator = ToonIntegrator::default();
+        let arena = ArenaInner::new(1024);
+        let mut sampler = DummyPixelSampler;
+
+        // From the bottom to the top of the sphere
+        let colors = (0..=64)
+            .map(|i| {
+                let y = -0.99 + 1.98 * i as f32 / 64.0;
+                let mut ctx = test_ctx(&world, &arena, &mut sampler, 0);
+                let ray = Ray::new(Point::new(0.0, y, 0.0), -Vec3::Z);
+                integrator.ray_cast(&mut ctx, ray, 0).color.to_array()
+            })
+            .collect::<Vec<_>>();
+
+        // The edges are seen edge on
+        assert_eq!(colors[0], BLACK.to_array());
+        assert_eq!(colors[64], BLACK.to_array());
+
+        let mut bands = colors
+            .iter()
+            .filter(|&&c| c != BLACK.to_array())
+            .map(|c| c.map(f32::to_bits))
+            .collect::<Vec<_>>();
+        bands.dedup();
+        assert!(bands.len() <= integrator.bands as usize, "{bands:?}");
+
+        // Warmer toward the light, cooler away from it
+        let (bottom, top) = (colors[16], colors[48]);
+        assert!(
+            top[0] > bottom[0] && top[2] < bottom[2],
+            "{bottom:?} {top:?}"
+        );
+    }
+}
@@ -0,0 +1,265 @@
+use glam::Vec3;
+
+use crate::{
+    color::{linear::BLACK, Rgb},
+    material::{BxDF, BxDFFlags, BxDFSample, BSDF},
+    math::{distributions::Samples, point::Point, vec::RgbAsVec3Ext},
+    ray::Ray,
+    renderer::RayResult,
+    shape::IntersectionResult,
+    Ctx,
+};
+
+use super::Integrator;
+
+/// Whitted style ray tracing: the lights of the world light the surfaces directly, with hard
+/// shadows, and the perfect reflections and refractions of the specular surfaces are followed
+/// recursively.
+///
+/// It is deterministic, so without noise, but there is no indirect lighting at all: it is a fast
+/// preview of the scene and a baseline to compare the path tracer to. See "An Improved
+/// Illumination Model for Shaded Display", Whitted 1980
+pub struct WhittedIntegrator {
+    pub max_depth: u32,
+}
+
+impl WhittedIntegrator {
+    pub fn new(max_depth: u32) -> Self {
+        Self { max_depth }
+    }
+
+    /// The perfect reflection and refraction of a specular surface, each one with the weight of
+    /// its radiance. None if the surface is not specular
+    fn specular_branches(
+        bxdf: &dyn BxDF,
+        bsdf: &BSDF<'_, dyn BxDF + '_>,
+        wo: Vec3,
+    ) -> Option<Vec<(f32, BxDFSample)>> {
+        if !bxdf.flags().contains(BxDFFlags::Specular) {
+            return None;
+        }
+
+        let specular = |sample: &BxDFSample| sample.flags.contains(BxDFFlags::Specular);
+        let sample = |w| {
+            bsdf.sample_f(wo, Samples([0.5, 0.5]), Samples([w]))
+                .filter(specular)
+        };
+        match (sample(0.0), sample(1.0)) {
+            // Both lobes are traced, instead of choosing one with the probability that is its pdf
+            (Some(reflected), Some(transmitted)) if reflected.flags != transmitted.flags => {
+                Some(vec![(1.0, reflected), (1.0, transmitted)])
+            }
+            // A mirror, or past the critical angle
+            (Some(sampled), _) | (None, Some(sampled)) => Some(vec![(1.0 / sampled.pdf, sampled)]),
+            // A rough surface, lit directly
+            (None, None) => None,
+        }
+    }
+
+    /// The light of the unoccluded lights reflected toward `wo` at `pos`
+    fn direct_lighting(
+        &self,
+        ctx: &Ctx,
+        bsdf: &BSDF<'_, dyn BxDF + '_>,
+        pos: Point,
+        normal: Vec3,
+        wo: Vec3,
+    ) -> Rgb {
+        let mut l = BLACK;
+        for light in ctx.world.lights.iter() {
+            // The lights with an area are lit from their center
+            let Some(sample) = light.sample_li(pos, Samples([0.5, 0.5])) else {
+                continue;
+            };
+            let fcos = normal.dot(sample.wi).abs() * bsdf.f(wo, sample.wi);
+            if fcos.vec().max_element() <= 0.0 {
+                continue;
+            }
+
+            let mut shadow_ray = Ray::spawn(pos, normal, sample.wi);
+            shadow_ray.bounds.1 = sample.distance * (1.0 - 1e-4);
+            if ctx
+                .world
+                .objects
+                .intersect_bare(shadow_ray)
+                .is_intersection()
+            {
+                continue;
+            }
+            let li = match sample.pdf {
+                Some(pdf) => (1.0 / pdf) * sample.li,
+                None => sample.li,
+            };
+            l = l + fcos * ctx.world.attenuate(li, sample.distance);
+        }
+        l
+    }
+}
+
+impl Integrator for WhittedIntegrator {
+    fn ray_cast(&self, ctx: &mut Ctx, ray: Ray, depth: u32) -> RayResult {
+        if depth == self.max_depth {
+            return RayResult::default();
+        }
+
+        let isect = ctx.world.objects.intersection_full(ray);
+        let IntersectionResult::Intersection(record) = isect else {
+            return self.sky_ray(ctx, ray);
+        };
+
+        let normal = record.local_info.normal;
+        let pos = record.local_info.pos;
+        let material = ctx.world.materials[record.local_info.material.0]
+            .material
+            .bxdf(&ctx.arena, record.local_info.uv);
+        let bsdf = BSDF::new(record.local_info.shading_normal, material);
+        let wo = -ray.direction;
+
+        let (mut li, mut ray_depth, mut albedo) = (bsdf.le(wo), 0.0, BLACK);
+        match Self::specular_branches(material, &bsdf, wo) {
+            Some(branches) => {
+                for (weight, sampled) in branches {
+                    albedo = albedo + weight * sampled.pdf * sampled.f;
+                    let fcos = normal.dot(sampled.wi).abs() * sampled.f;
+                    let ray_result =
+                        self.ray_cast(ctx, Ray::spawn(pos, normal, sampled.wi), depth + 1);
+                    li = li + weight * fcos * ray_result.color;
+                    ray_depth += weight * sampled.pdf * ray_result.ray_depth;
+                }
+            }
+            None => {
+                albedo = std::f32::consts::PI * bsdf.f(wo, normal * normal.dot(wo).signum());
+                li = li + self.direct_lighting(ctx, &bsdf, pos, normal, wo);
+            }
+        }
+
+        RayResult {
+            normal,
+            position: pos,
+            albedo,
+            color: ctx.world.attenuate(li, record.t),
+            z: record.t,
+            ray_depth: ray_depth + record.t,
+            samples_accumulated: 1,
+            escaped: false,
+            object: Some(record.local_info.object),
+            first_specular: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::Vec3;
+
+    use crate::{
+        color::{linear::WHITE, Rgb},
+        integrators::{
+            pathtracing::tests::{test_ctx, Spheres},
+            Integrator,
+        },
+        light::{Light, Lights, PointLight},
+        material::{BxDF, BxDFFlags, BxDFSample, DiffuseBxDF, MaterialDescriptor, MaterialId},
+        math::{distributions::Samples, point::Point},
+        memory::ArenaInner,
+        ray::Ray,
+        renderer::World,
+        sampler::DummyPixelSampler,
+        shape::Shape,
+    };
+
+    use super::WhittedIntegrator;
+
+    /// A perfect mirror
+    struct Mirror;
+
+    impl BxDF for Mirror {
+        fn flags(&self) -> BxDFFlags {
+            BxDFFlags::Reflection | BxDFFlags::Specular
+        }
+        fn f(&self, _wo: Vec3, _wi: Vec3) -> Rgb {
+            Rgb::from_array([0.0; 3])
+        }
+        fn pdf(&self, _wo: Vec3, _wi: Vec3) -> f32 {
+            0.0
+        }
+        fn sample_f(&self, wo: Vec3, _uv: Samples<2>, _w: Samples<1>) -> Option<BxDFSample> {
+            Some(BxDFSample {
+                wi: Vec3::new(-wo.x, -wo.y, wo.z),
+                f: (1.0 / wo.z.abs()) * WHITE,
+                pdf: 1.0,
+                flags: self.flags(),
+            })
+        }
+    }
+
+    #[test]
+    fn mirror_reflections() {
+        let materials = [
+            MaterialDescriptor {
+                label: None,
+                material: Box::new(Mirror),
+                alpha: None,
+            },
+            MaterialDescriptor {
+                label: None,
+                material: Box::new(DiffuseBxDF {
+                    albedo: [1.0, 0.0, 0.0].into(),
+                    ..Default::default()
+                }),
+                alpha: None,
+            },
+        ];
+        // The red sphere is behind the camera, it is only seen in the mirror
+        let spheres = Spheres(vec![
+            (Point::new(0.0, 0.0, -3.0), 1.0, MaterialId(0)),
+            (Point::new(0.0, 0.0, 3.0), 1.0, MaterialId(1)),
+        ]);
+        let lights: [Box<dyn Light>; 1] = [Box::new(PointLight {
+            position: Point::new(0.0, 3.0, 0.0),
+            intensity: WHITE,
+        })];
+        let world = World {
+            objects: &spheres,
+            lights: &Lights::new(&lights),
+            materials: &materials,
+            world_material: MaterialId(1),
+            fog: None,
+        };
+        let integrator = WhittedIntegrator::new(4);
+        let arena = ArenaInner::new(1024);
+        let mut sampler = DummyPixelSampler;
+        let mut cast = |ray: Ray, depth: u32, sample_idx: u32| {
+            let mut ctx = test_ctx(&world, &arena, &mut sampler, sample_idx);
+            integrator.ray_cast(&mut ctx, ray, depth).color.to_array()
+        };
+
+        for x in [0.0, 0.01, -0.015, 0.02] {
+            let ray = Ray::new(Point::ORIGIN, Vec3::new(x, 0.0, -1.0).normalize());
+            let seen = cast(ray, 0, 0);
+            // Without noise
+            assert_eq!(seen, cast(ray, 0, 7));
+
+            // The mirror shows exactly what the reflected ray sees, it is sharp
+            let t = spheres.intersection_full(ray).unwrap();
+            let normal = t.local_info.normal;
+            let reflected = Ray::spawn(
+                t.local_info.pos,
+                normal,
+                ray.direction - 2.0 * ray.direction.dot(normal) * normal,
+            );
+            let expected = cast(reflected, 1, 0);
+            assert!(expected[0] > 0.0 && expected[1] == 0.0, "{x} {expected:?}");
+            for (s, e) in seen.into_iter().zip(expected) {
+                assert!(
+                    (s - e).abs() <= 1e-4 * e.max(1e-3),
+                    "{seen:?} != {expected:?}"
+                );
+            }
+        }
+
+        // Past the edge of the reflection of the red sphere, the black sky
+        let ray = Ray::new(Point::ORIGIN, Vec3::new(0.06, 0.0, -1.0).normalize());
+        assert_eq!(cast(ray, 0, 0), [0.0; 3]);
+    }
+}
@@ -4,10 +4,12 @@
 #![feature(negative_impls)]
 
 pub mod aggregate;
+pub mod bake;
 pub mod camera;
 pub mod color;
 pub mod filter;
 pub mod integrators;
+pub mod light;
 pub mod loader;
 pub mod material;
 pub mod math;
@@ -27,6 +29,8 @@ pub struct Ctx<'a> {
     pub arena: memory::Arena<'a>,
     pub seed: Seed,
     pub sampler: &'a mut dyn sampler::Sampler,
+    /// Log every step of the path, to debug a single sample of a pixel
+    pub debug: bool,
 }
 
 #[derive(Debug, Copy, Clone, Hash)]
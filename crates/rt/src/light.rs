@@ -0,0 +1,772 @@
+use std::collections::BTreeMap;
+
+use crate::{
+    color::Rgb,
+    material::{texture::Uv, BxDF, TexturedEmit},
+    math::{bounds::Bounds, distributions::Sample2D, point::Point, transform::Frame, vec::Vec3},
+    ray::Ray,
+    scene::light_tree::{LightInfo, LightTree},
+};
+
+/// The light arriving at a point from a light
+#[derive(Debug, Clone, Copy)]
+pub struct LightSample {
+    /// Direction toward the light, normalized
+    pub wi: Vec3,
+    /// Radiance arriving from the light, it has to be checked that the light is not occluded
+    pub li: Rgb,
+    /// Distance to the light, the shadow ray stops before it
+    pub distance: f32,
+    /// Pdf of `wi` in solid angle. None for the infinitely small lights, sampled with a
+    /// probability of 1: `li` is then the irradiance of a surface facing the light
+    pub pdf: Option<f32>,
+}
+
+/// A light that can be sampled directly.
+///
+/// Most of the lights can't be hit by the rays, they are only seen through their samples. The
+/// ones that are the surface of an object are also seen by the rays hitting it: both ways of
+/// reaching them are weighted with multiple importance sampling
+pub trait Light: Send + Sync {
+    /// The light arriving at `p` from a point of the light drawn with `samples`, None if there
+    /// is none
+    fn sample_li(&self, p: Point, samples: Sample2D) -> Option<LightSample>;
+
+    /// The object of the world whose surface is the light, None if the rays can't hit it
+    fn object(&self) -> Option<u32> {
+        None
+    }
+
+    /// The pdf in solid angle of [Light::sample_li] drawing `wi` from `p`, for the lights that
+    /// are the surface of an object
+    fn pdf_li(&self, _p: Point, _wi: Vec3) -> f32 {
+        0.0
+    }
+
+    /// Where the light is and how bright it is, to choose it among the others
+    fn info(&self) -> LightInfo;
+}
+
+/// The lights of a world, along with the tree choosing the one to sample at a point
+pub struct Lights<'a> {
+    lights: &'a [Box<dyn Light>],
+    tree: LightTree,
+    /// The light that each object is the surface of, see [Light::object]
+    objects: BTreeMap<u32, usize>,
+}
+
+impl<'a> Lights<'a> {
+    pub fn new(lights: &'a [Box<dyn Light>]) -> Self {
+        let infos = lights.iter().map(|light| light.info()).collect::<Vec<_>>();
+        Self {
+            lights,
+            tree: LightTree::new(&infos),
+            objects: lights
+                .iter()
+                .enumerate()
+                .filter_map(|(index, light)| Some((light.object()?, index)))
+                .collect(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.lights.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lights.is_empty()
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'a, Box<dyn Light>> {
+        self.lights.iter()
+    }
+
+    /// Choose a light to light `p` with `u` in [0, 1), along with the probability to choose it.
+    /// `normal` is the side of the surface the light must arrive on, None if it lets the light
+    /// through
+    pub fn sample(&self, p: Point, normal: Option<Vec3>, u: f32) -> Option<(&'a dyn Light, f32)> {
+        let (light, pmf) = self.tree.sample(p, normal, u)?;
+        Some((self.lights[light].as_ref(), pmf))
+    }
+
+    /// The light whose surface is `object` if there is one, along with the probability that
+    /// [Self::sample] chooses it for `p`
+    pub fn object_light(
+        &self,
+        object: u32,
+        p: Point,
+        normal: Option<Vec3>,
+    ) -> Option<(&'a dyn Light, f32)> {
+        let &light = self.objects.get(&object)?;
+        Some((self.lights[light].as_ref(), self.tree.pmf(p, normal, light)))
+    }
+}
+
+impl Default for Lights<'_> {
+    fn default() -> Self {
+        Self::new(&[])
+    }
+}
+
+/// Weight of a sample drawn with `pdf` among the ones drawn with `other_pdf` as well, see
+/// "Optimally Combining Sampling Techniques for Monte Carlo Rendering", Veach and Guibas 1995
+pub fn power_heuristic(pdf: f32, other_pdf: f32) -> f32 {
+    if pdf.is_infinite() {
+        return 1.0;
+    }
+    let (pdf2, other_pdf2) = (pdf * pdf, other_pdf * other_pdf);
+    if pdf2 + other_pdf2 == 0.0 {
+        return 0.0;
+    }
+    pdf2 / (pdf2 + other_pdf2)
+}
+
+/// A point light emitting the same intensity in every direction
+#[derive(Debug, Clone, Copy)]
+pub struct PointLight {
+    pub position: Point,
+    /// Radiant intensity
+    pub intensity: Rgb,
+}
+
+impl Light for PointLight {
+    fn sample_li(&self, p: Point, _samples: Sample2D) -> Option<LightSample> {
+        let to_light = self.position - p;
+        let distance = to_light.length();
+        let wi = to_light / distance;
+        if !wi.is_finite() {
+            return None;
+        }
+
+        Some(LightSample {
+            wi,
+            li: (1.0 / (distance * distance)) * self.intensity,
+            distance,
+            pdf: None,
+        })
+    }
+
+    fn info(&self) -> LightInfo {
+        LightInfo {
+            bounds: Bounds::from_points(&[self.position]),
+            power: 4.0 * std::f32::consts::PI * self.intensity.luminance(),
+        }
+    }
+}
+
+/// A point light only lighting inside a cone, with a smooth falloff at its edge
+#[derive(Debug, Clone, Copy)]
+pub struct SpotLight {
+    pub position: Point,
+    /// Axis of the cone, normalized
+    pub direction: Vec3,
+    /// Cosine of the angle between the axis and the edge of the cone, nothing is lit beyond it
+    pub cos_total_width: f32,
+    /// Cosine of the angle between the axis and where the falloff starts, everything is fully
+    /// lit inside it
+    pub cos_falloff_start: f32,
+    /// Radiant intensity along the axis
+    pub intensity: Rgb,
+}
+
+impl SpotLight {
+    /// Fraction of the intensity emitted in a direction of cosine `cos_theta` with the axis
+    pub fn falloff(&self, cos_theta: f32) -> f32 {
+        if cos_theta >= self.cos_falloff_start {
+            return 1.0;
+        }
+        if cos_theta <= self.cos_total_width {
+            return 0.0;
+        }
+        let x =
+            (cos_theta - self.cos_total_width) / (self.cos_falloff_start - self.cos_total_width);
+        x * x * (3.0 - 2.0 * x)
+    }
+}
+
+impl Light for SpotLight {
+    fn sample_li(&self, p: Point, _samples: Sample2D) -> Option<LightSample> {
+        let to_light = self.position - p;
+        let distance = to_light.length();
+        let wi = to_light / distance;
+        let falloff = self.falloff(self.direction.dot(-wi));
+        if falloff == 0.0 || !wi.is_finite() {
+            return None;
+        }
+
+        Some(LightSample {
+            wi,
+            li: (falloff / (distance * distance)) * self.intensity,
+            distance,
+            pdf: None,
+        })
+    }
+
+    /// The falloff is counted as if it were linear
+    fn info(&self) -> LightInfo {
+        let solid_angle = 2.0
+            * std::f32::consts::PI
+            * (1.0 - (self.cos_falloff_start + self.cos_total_width) / 2.0);
+        LightInfo {
+            bounds: Bounds::from_points(&[self.position]),
+            power: solid_angle * self.intensity.luminance(),
+        }
+    }
+}
+
+/// A spherical emitter, as seen from the points it lights
+#[derive(Debug, Clone, Copy)]
+pub struct SphereLight {
+    pub center: Point,
+    pub radius: f32,
+}
+
+impl SphereLight {
+    /// Cosine of the half angle of the cone the sphere subtends from `p`, and one minus it
+    /// computed without cancellation for the small cones. None when `p` is inside of the sphere
+    fn cone(&self, p: Point) -> Option<(f32, f32)> {
+        let distance2 = (self.center - p).length_squared();
+        let sin2_max = self.radius * self.radius / distance2;
+        if sin2_max >= 1.0 || sin2_max.is_nan() {
+            return None;
+        }
+        let cos_max = (1.0 - sin2_max).sqrt();
+        Some((cos_max, sin2_max / (1.0 + cos_max)))
+    }
+}
+
+/// A direction from `p` toward the sphere, drawn uniformly in the cone the sphere subtends rather
+/// than over its whole surface: the half of the sphere facing away is never sampled, and nor is
+/// the solid angle around it. Returns the direction, normalized, and its pdf in solid angle.
+///
+/// From inside of the sphere, every direction hits it and nothing is returned, the sphere has to
+/// be sampled another way
+pub fn sample_sphere_light(
+    p: Point,
+    sphere: &SphereLight,
+    samples: Sample2D,
+) -> Option<(Vec3, f32)> {
+    let (cos_max, one_minus_cos_max) = sphere.cone(p)?;
+    let cos_theta = 1.0 - samples[0] * one_minus_cos_max;
+    let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+    let (sin_phi, cos_phi) = (std::f32::consts::TAU * samples[1]).sin_cos();
+    debug_assert!(cos_theta >= cos_max - 1e-6);
+
+    let frame = Frame::new((sphere.center - p).normalize());
+    let wi = frame.from_local(Vec3::new(
+        sin_theta * cos_phi,
+        sin_theta * sin_phi,
+        cos_theta,
+    ));
+    Some((
+        wi.normalize(),
+        1.0 / (std::f32::consts::TAU * one_minus_cos_max),
+    ))
+}
+
+/// The pdf of [sample_sphere_light] drawing the direction `wi` from `p`, `wi` being normalized
+pub fn sphere_light_pdf(p: Point, sphere: &SphereLight, wi: Vec3) -> f32 {
+    let Some((cos_max, one_minus_cos_max)) = sphere.cone(p) else {
+        return 0.0;
+    };
+    if wi.dot((sphere.center - p).normalize()) < cos_max {
+        return 0.0;
+    }
+    1.0 / (std::f32::consts::TAU * one_minus_cos_max)
+}
+
+/// A sphere of the world emitting the same radiance everywhere, sampled in the cone it subtends
+/// from the points it lights
+#[derive(Debug, Clone, Copy)]
+pub struct SphereEmitter {
+    pub sphere: SphereLight,
+    pub le: Rgb,
+    /// The object of the world that is the sphere
+    pub object: u32,
+}
+
+impl Light for SphereEmitter {
+    fn sample_li(&self, p: Point, samples: Sample2D) -> Option<LightSample> {
+        let (wi, pdf) = sample_sphere_light(p, &self.sphere, samples)?;
+        // The near side of the sphere along `wi`, the direction can graze it
+        let to_center = self.sphere.center - p;
+        let b = wi.dot(to_center);
+        let discriminant = b * b - (to_center.length_squared() - self.sphere.radius.powi(2));
+        Some(LightSample {
+            wi,
+            li: self.le,
+            distance: b - discriminant.max(0.0).sqrt(),
+            pdf: Some(pdf),
+        })
+    }
+
+    fn object(&self) -> Option<u32> {
+        Some(self.object)
+    }
+
+    fn pdf_li(&self, p: Point, wi: Vec3) -> f32 {
+        sphere_light_pdf(p, &self.sphere, wi)
+    }
+
+    fn info(&self) -> LightInfo {
+        let SphereLight { center, radius } = self.sphere;
+        let area = 4.0 * std::f32::consts::PI * radius * radius;
+        LightInfo {
+            bounds: Bounds::from_points(&[
+                center - Vec3::splat(radius),
+                center + Vec3::splat(radius),
+            ]),
+            power: std::f32::consts::PI * area * self.le.luminance(),
+        }
+    }
+}
+
+/// A point drawn on the surface of an [Emitter]
+#[derive(Debug, Clone, Copy)]
+pub struct EmitterSample {
+    pub p: Point,
+    /// Normal of the surface, normalized, on the side that emits when only one does
+    pub normal: Vec3,
+    pub uv: Uv,
+}
+
+/// A light with an area, it can be hit by the rays as well as sampled directly.
+///
+/// The points are drawn from the geometry only, whatever the radiance of the texture: the same
+/// pdf then weights the light samples and the BSDF samples that hit the emitter
+pub trait Emitter: Send + Sync {
+    fn sample_point(&self, samples: Sample2D) -> EmitterSample;
+    /// The pdf in area measure of [Emitter::sample_point] drawing the point at `uv`
+    fn pdf_area(&self, uv: Uv) -> f32;
+    /// The radiance emitted at `point` toward `wo`, normalized
+    fn le(&self, point: &EmitterSample, wo: Vec3) -> Rgb;
+}
+
+/// The light arriving at `p` from a point drawn on the emitter, its pdf being the one of its
+/// direction in solid angle. None if the point can't light `p`
+pub fn sample_emitter_li(
+    p: Point,
+    emitter: &dyn Emitter,
+    samples: Sample2D,
+) -> Option<LightSample> {
+    let point = emitter.sample_point(samples);
+    let to_light = point.p - p;
+    let distance = to_light.length();
+    let wi = to_light / distance;
+    let pdf = emitter_pdf_li(p, emitter, &point);
+    if pdf == 0.0 || !pdf.is_finite() || !wi.is_finite() {
+        return None;
+    }
+
+    Some(LightSample {
+        wi,
+        li: emitter.le(&point, -wi),
+        distance,
+        pdf: Some(pdf),
+    })
+}
+
+/// The pdf in solid angle of [sample_emitter_li] drawing `point` of the emitter from `p`, to
+/// weight the BSDF samples that hit it
+pub fn emitter_pdf_li(p: Point, emitter: &dyn Emitter, point: &EmitterSample) -> f32 {
+    let to_light = point.p - p;
+    let distance2 = to_light.length_squared();
+    let cos_light = point.normal.dot(to_light).abs() / distance2.sqrt();
+    if cos_light == 0.0 || cos_light.is_nan() {
+        return 0.0;
+    }
+    emitter.pdf_area(point.uv) * distance2 / cos_light
+}
+
+/// A parallelogram emitting the radiance of a texture, the uv going from `corner` along each edge.
+/// Its normal is the cross product of the edges.
+///
+/// As a [Light], it is only seen through its samples unless it is also an object of the world,
+/// see [crate::scene::SceneT::insert_quad_light]
+pub struct QuadEmitter {
+    pub corner: Point,
+    pub edges: [Vec3; 2],
+    pub emission: TexturedEmit,
+    /// Emission profile: the radiance is the one of the texture times `cos^exponent` of the angle
+    /// to the normal. At 0 the emitter is Lambertian, its radiance is the same in every direction,
+    /// the higher the more it is focused along the normal as a spotlight
+    pub exponent: f32,
+    /// The object of the world that is the quad, it has to emit the same radiance
+    pub object: Option<u32>,
+}
+
+impl QuadEmitter {
+    fn cross(&self) -> Vec3 {
+        self.edges[0].cross(self.edges[1])
+    }
+
+    pub fn area(&self) -> f32 {
+        self.cross().length()
+    }
+
+    /// The distance along the ray to the quad, and the point hit
+    pub fn intersect(&self, ray: Ray) -> Option<(f32, EmitterSample)> {
+        let n = self.cross();
+        let t = n.dot(self.corner - ray.origin) / n.dot(ray.direction);
+        if !ray.range().contains(&t) {
+            return None;
+        }
+
+        let d = ray.at(t) - self.corner;
+        let uv = [
+            d.cross(self.edges[1]).dot(n) / n.length_squared(),
+            self.edges[0].cross(d).dot(n) / n.length_squared(),
+        ];
+        if !uv.iter().all(|x| (0.0..=1.0).contains(x)) {
+            return None;
+        }
+        let point = EmitterSample {
+            p: ray.at(t),
+            normal: n.normalize(),
+            uv,
+        };
+        Some((t, point))
+    }
+}
+
+impl Emitter for QuadEmitter {
+    fn sample_point(&self, samples: Sample2D) -> EmitterSample {
+        EmitterSample {
+            p: self.corner + samples[0] * self.edges[0] + samples[1] * self.edges[1],
+            normal: self.cross().normalize(),
+            uv: [samples[0], samples[1]],
+        }
+    }
+
+    fn pdf_area(&self, _uv: Uv) -> f32 {
+        1.0 / self.area()
+    }
+
+    fn le(&self, point: &EmitterSample, wo: Vec3) -> Rgb {
+        // The emission is expressed in the frame of the surface
+        let wo = Vec3::new(0.0, 0.0, point.normal.dot(wo));
+        let le = self.emission.at(point.uv).le(wo);
+        if self.exponent == 0.0 {
+            return le;
+        }
+        wo.z.abs().powf(self.exponent) * le
+    }
+}
+
+impl Light for QuadEmitter {
+    fn sample_li(&self, p: Point, samples: Sample2D) -> Option<LightSample> {
+        sample_emitter_li(p, self, samples)
+    }
+
+    fn object(&self) -> Option<u32> {
+        self.object
+    }
+
+    fn pdf_li(&self, p: Point, wi: Vec3) -> f32 {
+        match self.intersect(Ray::new(p, wi)) {
+            Some((_, point)) => emitter_pdf_li(p, self, &point),
+            None => 0.0,
+        }
+    }
+
+    /// The radiance is averaged on a grid of the texture, the profile of emission concentrates
+    /// the power of a Lambertian emitter by `2 / (exponent + 2)`
+    fn info(&self) -> LightInfo {
+        const GRID: usize = 8;
+        let le = (0..GRID * GRID)
+            .map(|i| {
+                let uv = [i % GRID, i / GRID].map(|x| (x as f32 + 0.5) / GRID as f32);
+                self.emission.le.color(uv).luminance()
+            })
+            .sum::<f32>()
+            / (GRID * GRID) as f32;
+        let sides = if self.emission.two_sided { 2.0 } else { 1.0 };
+        let [u, v] = self.edges;
+        LightInfo {
+            bounds: Bounds::from_points(&[
+                self.corner,
+                self.corner + u,
+                self.corner + v,
+                self.corner + u + v,
+            ]),
+            power: sides * std::f32::consts::PI * self.area() * le * 2.0 / (self.exponent + 2.0),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{Rng, SeedableRng};
+
+    use crate::{
+        color::Rgb,
+        material::{
+            texture::{Texture, Uniform, Uv},
+            TexturedEmit,
+        },
+        math::{
+            distributions::{CosineHemisphere3, DirectionalPDF, Samplable, Samples},
+            point::Point,
+            vec::Vec3,
+        },
+        ray::Ray,
+    };
+
+    use super::{
+        emitter_pdf_li, power_heuristic, sample_emitter_li, sample_sphere_light, sphere_light_pdf,
+        Emitter, Light, QuadEmitter, SphereEmitter, SphereLight, SpotLight,
+    };
+
+    /// A radiance growing along the first edge
+    struct Gradient;
+
+    impl Texture for Gradient {
+        fn color(&self, uv: Uv) -> Rgb {
+            Rgb::from_array([4.0 * uv[0] + 0.1; 3])
+        }
+    }
+
+    #[test]
+    fn textured_emitter_mis() {
+        // Above the origin and facing it, the uv going along y then x
+        let quad = QuadEmitter {
+            corner: Point::new(-1.0, -1.0, 1.0),
+            edges: [Vec3::new(0.0, 2.0, 0.0), Vec3::new(2.0, 0.0, 0.0)],
+            emission: TexturedEmit {
+                le: Box::new(Gradient),
+                two_sided: false,
+            },
+            exponent: 0.0,
+            object: None,
+        };
+        let p = Point::ORIGIN;
+        let point = quad.sample_point(Samples([0.25, 0.5]));
+        assert_eq!(point.normal, Vec3::NEG_Z);
+        assert_eq!(point.p.vec(), Vec3::new(0.0, -0.5, 1.0));
+        // Seen from behind, it is black
+        assert_eq!(quad.le(&point, Vec3::Z).to_array(), [0.0; 3]);
+
+        // The light reflected by a white diffuse surface at the origin, facing up
+        let f = std::f32::consts::FRAC_1_PI;
+        let samples = 200_000;
+        let mut rng = crate::Rng::seed_from_u64(2);
+        let (mut bsdf_only, mut light_only, mut mis) = (0.0, 0.0, 0.0);
+        for _ in 0..samples {
+            let wi = CosineHemisphere3.sample_with(Samples([rng.gen(), rng.gen()]));
+            let pdf_bsdf = CosineHemisphere3.pdf(wi.z);
+            if let Some((_, point)) = quad.intersect(Ray::new(p, wi)) {
+                let le = quad.le(&point, -wi).to_array()[0] as f64;
+                let pdf_light = emitter_pdf_li(p, &quad, &point);
+                bsdf_only += le;
+                mis += le * power_heuristic(pdf_bsdf, pdf_light) as f64;
+            }
+
+            let sample = sample_emitter_li(p, &quad, Samples([rng.gen(), rng.gen()])).unwrap();
+            let pdf_light = sample.pdf.unwrap();
+            let cos = sample.wi.z;
+            assert!(cos > 0.0);
+            let li = (f * sample.li.to_array()[0] * cos / pdf_light) as f64;
+            light_only += li;
+            mis += li * power_heuristic(pdf_light, CosineHemisphere3.pdf(cos)) as f64;
+        }
+        let [bsdf_only, light_only, mis] = [bsdf_only, light_only, mis].map(|l| l / samples as f64);
+        assert!(
+            (mis - bsdf_only).abs() < 0.02 * bsdf_only,
+            "{mis} {bsdf_only}"
+        );
+        assert!(
+            (mis - light_only).abs() < 0.02 * light_only,
+            "{mis} {light_only}"
+        );
+    }
+
+    #[test]
+    fn emitter_cosine_law() {
+        // A small square emitter at a height of 1, facing down on a parallel receiver
+        let (side, height) = (0.02, 1.0);
+        let emitter = |exponent| QuadEmitter {
+            corner: Point::new(-side / 2.0, -side / 2.0, height),
+            edges: [Vec3::new(0.0, side, 0.0), Vec3::new(side, 0.0, 0.0)],
+            emission: TexturedEmit {
+                le: Box::new(Uniform(Rgb::from_array([1.0; 3]))),
+                two_sided: false,
+            },
+            exponent,
+            object: None,
+        };
+        // The illuminance of the receiver at `x` from the center, from the light samples
+        let illuminance = |quad: &QuadEmitter, x: f32| {
+            let mut rng = crate::Rng::seed_from_u64(3);
+            let samples = 4000;
+            (0..samples)
+                .map(|_| {
+                    let sample = quad
+                        .sample_li(Point::new(x, 0.0, 0.0), Samples([rng.gen(), rng.gen()]))
+                        .unwrap();
+                    (sample.li.to_array()[0] * sample.wi.z / sample.pdf.unwrap()) as f64
+                })
+                .sum::<f64>()
+                / samples as f64
+        };
+
+        for exponent in [0.0, 3.0] {
+            let quad = emitter(exponent);
+            // The radiance and the area of the emitter over the squared height
+            let e0 = (quad.area() / (height * height)) as f64;
+            for x in [0.0, 0.5, 1.0, 2.0] {
+                // Both the cosine at the receiver and the one at the emitter, over the squared
+                // distance: cos^4 for a Lambertian emitter, times the emission profile
+                let cos = height / (height * height + x * x).sqrt();
+                let expected = e0 * (cos as f64).powf(4.0 + exponent as f64);
+                let e = illuminance(&quad, x);
+                assert!(
+                    (e - expected).abs() < 1e-3 * e0,
+                    "exponent {exponent} at {x}: {e} != {expected}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn sphere_light_sampling() {
+        for (p, sphere) in [
+            (
+                Point::ORIGIN,
+                SphereLight {
+                    center: Point::new(0.0, 0.0, 2.0),
+                    radius: 1.0,
+                },
+            ),
+            (
+                Point::new(0.3, -0.2, 0.1),
+                SphereLight {
+                    center: Point::new(-1.0, 0.5, -0.5),
+                    radius: 1.2,
+                },
+            ),
+            // Farther away, the cone is thin
+            (
+                Point::new(1.0, 2.0, 3.0),
+                SphereLight {
+                    center: Point::new(-5.0, 6.0, 8.0),
+                    radius: 2.0,
+                },
+            ),
+        ] {
+            let mut rng = crate::Rng::seed_from_u64(1);
+            let to_center = sphere.center - p;
+            let distance = to_center.length();
+            let cos_max = (1.0 - (sphere.radius / distance).powi(2)).sqrt();
+            for _ in 0..10000 {
+                let (wi, pdf) =
+                    sample_sphere_light(p, &sphere, Samples([rng.gen(), rng.gen()])).unwrap();
+                assert!((wi.length() - 1.0).abs() < 1e-5);
+                assert!(wi.dot(to_center / distance) >= cos_max - 1e-5, "{wi}");
+                assert_eq!(pdf, sphere_light_pdf(p, &sphere, wi));
+
+                // The ray hits the sphere
+                let b = wi.dot(to_center);
+                let discriminant = b * b - (to_center.length_squared() - sphere.radius.powi(2));
+                assert!(
+                    discriminant >= -1e-3 * distance * distance,
+                    "{discriminant}"
+                );
+            }
+
+            // The pdf integrates to 1 over the sphere of directions
+            let samples = 400_000;
+            let integral = (0..samples)
+                .map(|_| {
+                    let z: f32 = rng.gen_range(-1.0..1.0);
+                    let phi = std::f32::consts::TAU * rng.gen::<f32>();
+                    let r = (1.0 - z * z).sqrt();
+                    let wi = Vec3::new(r * phi.cos(), r * phi.sin(), z);
+                    sphere_light_pdf(p, &sphere, wi) as f64
+                })
+                .sum::<f64>()
+                * 4.0
+                * std::f64::consts::PI
+                / samples as f64;
+            assert!((integral - 1.0).abs() < 0.05, "{integral}");
+
+            // Uniform over the cone: the cosines to the axis are uniform and so are the angles
+            // around it
+            let frame = crate::math::transform::Frame::new(to_center / distance);
+            let mut bins = [[0; 8]; 2];
+            for _ in 0..80000 {
+                let (wi, _) =
+                    sample_sphere_light(p, &sphere, Samples([rng.gen(), rng.gen()])).unwrap();
+                let local = frame.to_local(wi);
+                let u = ((1.0 - local.z) / (1.0 - cos_max)).clamp(0.0, 0.999);
+                let phi = (local.y.atan2(local.x) / std::f32::consts::TAU)
+                    .rem_euclid(1.0)
+                    .min(0.999);
+                bins[0][(u * 8.0) as usize] += 1;
+                bins[1][(phi * 8.0) as usize] += 1;
+            }
+            for count in bins.into_iter().flatten() {
+                assert!((count as f32 - 10000.0).abs() < 500.0, "{bins:?}");
+            }
+        }
+
+        // Inside of the sphere
+        let sphere = SphereLight {
+            center: Point::new(0.5, 0.0, 1.0),
+            radius: 0.6,
+        };
+        let inside = Point::new(0.5, 0.1, 1.0);
+        assert!(sample_sphere_light(inside, &sphere, Samples([0.5; 2])).is_none());
+        assert_eq!(sphere_light_pdf(inside, &sphere, Vec3::Z), 0.0);
+
+        // The light samples of a sphere of the world stop on its near side
+        let emitter = SphereEmitter {
+            sphere: SphereLight {
+                center: Point::new(0.0, 0.0, 3.0),
+                radius: 1.0,
+            },
+            le: Rgb::from_array([2.0; 3]),
+            object: 4,
+        };
+        let sample = emitter
+            .sample_li(Point::ORIGIN, Samples([0.0, 0.0]))
+            .unwrap();
+        assert_eq!(sample.wi, Vec3::Z);
+        assert!((sample.distance - 2.0).abs() < 1e-5, "{}", sample.distance);
+        assert_eq!(sample.pdf, Some(emitter.pdf_li(Point::ORIGIN, Vec3::Z)));
+        assert_eq!(emitter.object(), Some(4));
+    }
+
+    #[test]
+    fn spot_light() {
+        let light = SpotLight {
+            position: Point::new(0.0, 2.0, 0.0),
+            direction: Vec3::NEG_Y,
+            cos_total_width: f32::to_radians(30.0).cos(),
+            cos_falloff_start: f32::to_radians(20.0).cos(),
+            intensity: Rgb::from_array([4.0, 4.0, 4.0]),
+        };
+
+        let on_axis = light.sample_li(Point::ORIGIN, Samples([0.5; 2])).unwrap();
+        assert_eq!(on_axis.wi, Vec3::Y);
+        assert_eq!(on_axis.distance, 2.0);
+        assert_eq!(on_axis.li.to_array(), [1.0; 3]);
+
+        // At 25°, in the falloff
+        let falloff = light
+            .sample_li(
+                Point::new(2.0 * f32::to_radians(25.0).tan(), 0.0, 0.0),
+                Samples([0.5; 2]),
+            )
+            .unwrap();
+        let li = falloff.li.to_array()[0] * falloff.distance.powi(2) / 4.0;
+        assert!(0.0 < li && li < 1.0, "{li}");
+
+        // At 40°, outside of the cone
+        let outside = Point::new(2.0 * f32::to_radians(40.0).tan(), 0.0, 0.0);
+        assert!(light.sample_li(outside, Samples([0.5; 2])).is_none());
+        // Behind the light
+        assert!(light
+            .sample_li(Point::new(0.0, 3.0, 0.0), Samples([0.5; 2]))
+            .is_none());
+    }
+}
@@ -0,0 +1,100 @@
+use std::{io::Read, path::Path};
+
+use anyhow::{bail, Result};
+
+use crate::material::measured::{
+    MeasuredBxDF, MERL_TABLE_SIZE, PHI_DIFF_RES, THETA_DIFF_RES, THETA_HALF_RES,
+};
+
+/// Load a BRDF of the MERL database
+pub fn load_merl<P: AsRef<Path>>(path: P) -> Result<MeasuredBxDF> {
+    let file = std::fs::File::open(path)?;
+    read_merl(std::io::BufReader::new(file))
+}
+
+/// The format is three i32 giving the dimensions of the table followed by the table itself in f64,
+/// all in little endian
+pub fn read_merl<R: Read>(mut reader: R) -> Result<MeasuredBxDF> {
+    let mut header = [0u8; 12];
+    reader.read_exact(&mut header)?;
+    let dims: [i32; 3] =
+        std::array::from_fn(|i| i32::from_le_bytes(header[4 * i..4 * i + 4].try_into().unwrap()));
+
+    if dims != [THETA_HALF_RES, THETA_DIFF_RES, PHI_DIFF_RES].map(|x| x as i32) {
+        bail!("unexpected MERL table dimensions {dims:?}");
+    }
+
+    let mut data = vec![0u8; 3 * MERL_TABLE_SIZE * std::mem::size_of::<f64>()];
+    reader.read_exact(&mut data)?;
+    let table = data
+        .chunks_exact(8)
+        .map(|x| f64::from_le_bytes(x.try_into().unwrap()) as f32)
+        .collect();
+
+    Ok(MeasuredBxDF::new(table))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f64::consts::PI;
+
+    use glam::Vec3;
+
+    use crate::material::{
+        measured::{MERL_TABLE_SIZE, PHI_DIFF_RES, THETA_DIFF_RES, THETA_HALF_RES},
+        BxDF,
+    };
+
+    use super::read_merl;
+
+    /// A MERL file of a smooth made up BRDF
+    fn merl_file(dims: [i32; 3]) -> Vec<u8> {
+        let mut file = dims
+            .iter()
+            .flat_map(|x| x.to_le_bytes())
+            .collect::<Vec<_>>();
+        for c in 0..3 {
+            for i in 0..MERL_TABLE_SIZE {
+                let theta_half = (i / (THETA_DIFF_RES * PHI_DIFF_RES)) as f64;
+                let theta_diff = (i / PHI_DIFF_RES % THETA_DIFF_RES) as f64;
+                let phi_diff = (i % PHI_DIFF_RES) as f64 / PHI_DIFF_RES as f64 * PI;
+                let x = 1000.0 * (1.0 + c as f64) / (1.0 + theta_half)
+                    * (1.0 + theta_diff / THETA_DIFF_RES as f64)
+                    * (1.0 + 0.5 * phi_diff.sin());
+                file.extend(x.to_le_bytes());
+            }
+        }
+        file
+    }
+
+    #[test]
+    fn header() {
+        let dims = [THETA_HALF_RES, THETA_DIFF_RES, PHI_DIFF_RES].map(|x| x as i32);
+        let brdf = read_merl(merl_file(dims).as_slice()).unwrap();
+        assert_eq!(brdf.table.len(), 3 * MERL_TABLE_SIZE);
+        assert_eq!(brdf.table[0], 1000.0);
+        assert_eq!(brdf.table[MERL_TABLE_SIZE], 2000.0);
+
+        assert!(read_merl(merl_file([90, 90, 90]).as_slice()).is_err());
+        assert!(read_merl(&merl_file(dims)[..1000]).is_err());
+    }
+
+    #[test]
+    fn reciprocity() {
+        let dims = [THETA_HALF_RES, THETA_DIFF_RES, PHI_DIFF_RES].map(|x| x as i32);
+        let brdf = read_merl(merl_file(dims).as_slice()).unwrap();
+
+        for i in 0..100 {
+            let u = i as f32 / 100.0;
+            let wo = Vec3::new(u - 0.5, 0.3 * u, 0.2 + u).normalize();
+            let wi = Vec3::new(0.4 - u * u, u - 0.7, 1.0 - 0.8 * u).normalize();
+
+            let a = brdf.f(wo, wi).to_array();
+            let b = brdf.f(wi, wo).to_array();
+            for c in 0..3 {
+                assert!(a[c] > 0.0);
+                assert!((a[c] - b[c]).abs() <= 0.05 * a[c], "{a:?} != {b:?}");
+            }
+        }
+    }
+}
@@ -0,0 +1,147 @@
+//! Clean up of the imported meshes, which often have their vertices duplicated and no normals.
+use std::collections::HashMap;
+
+use glam::Vec3;
+
+/// A mesh whose coincident vertices are merged, with a normal for each vertex that
+/// [TriangleMesh](crate::aggregate::triangle_mesh::TriangleMesh) interpolates
+#[derive(Debug, Clone, PartialEq)]
+pub struct WeldedMesh {
+    pub positions: Vec<[f32; 3]>,
+    pub normals: Vec<[f32; 3]>,
+    pub indices: Vec<[u32; 3]>,
+}
+
+/// Merges the vertices at the same position and gives them smooth normals, the sum of the normals
+/// of the triangles around them weighted by their area.
+///
+/// Only the triangles whose normals are less than `angle_threshold` radians apart are smoothed
+/// together, a vertex on a sharper edge is split into one per side so that the edge stays sharp
+pub fn weld_and_smooth(
+    vertices: &[[f32; 3]],
+    indices: &[[u32; 3]],
+    angle_threshold: f32,
+) -> WeldedMesh {
+    // -0.0 and 0.0 are the same position
+    let key = |p: [f32; 3]| p.map(|c| (c + 0.0).to_bits());
+    let mut welded = HashMap::new();
+    let mut positions = Vec::new();
+    let remap = vertices
+        .iter()
+        .map(|&p| {
+            *welded.entry(key(p)).or_insert_with(|| {
+                positions.push(p);
+                positions.len() as u32 - 1
+            })
+        })
+        .collect::<Vec<_>>();
+    let triangles = indices
+        .iter()
+        .map(|triangle| triangle.map(|i| remap[i as usize]))
+        .collect::<Vec<_>>();
+
+    // Twice the area along the normal
+    let face_normals = triangles
+        .iter()
+        .map(|triangle| {
+            let [a, b, c] = triangle.map(|i| Vec3::from_array(positions[i as usize]));
+            (b - a).cross(c - a)
+        })
+        .collect::<Vec<_>>();
+    let mut faces_around = vec![Vec::new(); positions.len()];
+    for (face, triangle) in triangles.iter().enumerate() {
+        for &vertex in triangle {
+            faces_around[vertex as usize].push(face);
+        }
+    }
+
+    let cos_threshold = angle_threshold.cos();
+    let mut split = HashMap::new();
+    let mut mesh = WeldedMesh {
+        positions: Vec::new(),
+        normals: Vec::new(),
+        indices: Vec::with_capacity(triangles.len()),
+    };
+    for (face, triangle) in triangles.iter().enumerate() {
+        let normal = face_normals[face].normalize_or_zero();
+        let corners = triangle.map(|vertex| {
+            let smooth = faces_around[vertex as usize]
+                .iter()
+                .map(|&other| face_normals[other])
+                .filter(|other| normal.dot(other.normalize_or_zero()) >= cos_threshold)
+                .sum::<Vec3>()
+                .normalize_or_zero()
+                .to_array();
+            *split.entry((vertex, key(smooth))).or_insert_with(|| {
+                mesh.positions.push(positions[vertex as usize]);
+                mesh.normals.push(smooth);
+                mesh.positions.len() as u32 - 1
+            })
+        });
+        mesh.indices.push(corners);
+    }
+    mesh
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use glam::Vec3;
+
+    use super::weld_and_smooth;
+
+    /// The cube [-1, 1]³ with 4 vertices of its own for each face, facing outside
+    fn cube() -> (Vec<[f32; 3]>, Vec<[u32; 3]>) {
+        let (mut vertices, mut indices) = (Vec::new(), Vec::new());
+        for axis in 0..3 {
+            for side in [-1.0, 1.0] {
+                let normal = side * Vec3::AXES[axis];
+                let (mut u, mut v) = (Vec3::AXES[(axis + 1) % 3], Vec3::AXES[(axis + 2) % 3]);
+                if side < 0.0 {
+                    (u, v) = (v, u);
+                }
+                let start = vertices.len() as u32;
+                for (a, b) in [(-1.0, -1.0), (1.0, -1.0), (1.0, 1.0), (-1.0, 1.0)] {
+                    vertices.push((normal + a * u + b * v).to_array());
+                }
+                indices.extend([[start, start + 1, start + 2], [start, start + 2, start + 3]]);
+            }
+        }
+        (vertices, indices)
+    }
+
+    #[test]
+    fn welded_cube() {
+        let (vertices, indices) = cube();
+        assert_eq!(vertices.len(), 24);
+
+        // The corners are smoothed over the 3 faces around them, a face counts twice when both
+        // of its triangles touch the corner
+        let smooth = weld_and_smooth(&vertices, &indices, f32::to_radians(100.0));
+        assert_eq!(smooth.positions.len(), 8);
+        assert_eq!(smooth.indices.len(), 12);
+        for (p, n) in smooth.positions.iter().zip(&smooth.normals) {
+            let (p, n) = (Vec3::from_array(*p), Vec3::from_array(*n));
+            assert_eq!(n.signum(), p.signum());
+            assert!(n.dot(p.normalize()) > 0.9, "{n} at {p}");
+        }
+
+        // The 90° edges are kept sharp, each face has the normal of its own
+        let sharp = weld_and_smooth(&vertices, &indices, f32::to_radians(30.0));
+        let unique = sharp
+            .positions
+            .iter()
+            .map(|p| p.map(f32::to_bits))
+            .collect::<HashSet<_>>();
+        assert_eq!(unique.len(), 8);
+        assert_eq!(sharp.positions.len(), 24);
+        for triangle in &sharp.indices {
+            let [a, b, c] = triangle.map(|i| Vec3::from_array(sharp.positions[i as usize]));
+            let face_normal = (b - a).cross(c - a).normalize();
+            for &i in triangle {
+                assert_eq!(Vec3::from_array(sharp.normals[i as usize]), face_normal);
+            }
+        }
+    }
+}
@@ -1,3 +1,8 @@
+pub mod merl;
+pub mod mesh;
 pub mod obj;
+mod obj_cache;
 
+pub use merl::load_merl;
 pub use obj::ObjLoaderExt;
+pub use obj_cache::hash_obj;
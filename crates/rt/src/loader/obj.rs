@@ -1,4 +1,4 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use glam::Vec3;
 
@@ -12,13 +12,37 @@ use crate::{
     scene::SceneT,
 };
 
+use super::{
+    mesh::{weld_and_smooth, WeldedMesh},
+    obj_cache,
+};
+
+/// The edges of the meshes without normals that are sharper than this stay sharp when they are
+/// smoothed
+const CREASE_ANGLE: f32 = std::f32::consts::FRAC_PI_3;
+
 pub trait ObjLoaderExt {
+    /// Loads the meshes of an OBJ file, along with the diffuse colors of its materials.
+    ///
+    /// The transformed meshes are cached in a `.cache` directory next to the file, a cache file
+    /// is used as long as the OBJ file, its MTL files and the transform are the same
     fn load_obj<T: Into<PathBuf>>(
         &mut self,
         mesh_path: T,
         transform: Transform,
         default_material: MaterialId,
     );
+
+    /// Same as [ObjLoaderExt::load_obj], but the meshes, the objects and groups of the file, are
+    /// given the material their name is mapped to in `overrides` instead of their own one. The
+    /// meshes whose name isn't in the map keep their imported material
+    fn load_obj_with_overrides<T: Into<PathBuf>>(
+        &mut self,
+        mesh_path: T,
+        transform: Transform,
+        default_material: MaterialId,
+        overrides: &[(String, MaterialId)],
+    );
 }
 
 impl<S: SceneT> ObjLoaderExt for S {
@@ -28,14 +52,143 @@ impl<S: SceneT> ObjLoaderExt for S {
         transform: Transform,
         default_material: MaterialId,
     ) {
+        self.load_obj_with_overrides(mesh_path, transform, default_material, &[]);
+    }
+
+    fn load_obj_with_overrides<P: Into<PathBuf>>(
+        &mut self,
+        mesh_path: P,
+        transform: Transform,
+        default_material: MaterialId,
+        overrides: &[(String, MaterialId)],
+    ) {
+        let mesh_path = mesh_path.into();
+        let key = obj_cache::key(&mesh_path, &transform).expect("Failed to read OBJ file");
+        let cache_path = obj_cache::path(&mesh_path, &transform, key);
+        let obj = match obj_cache::read(&cache_path) {
+            Ok(obj) => {
+                log::info!("Loading cached meshes from {}", cache_path.display());
+                obj
+            }
+            Err(_) => {
+                let obj = LoadedObj::parse(&mesh_path, &transform);
+                if let Err(err) = obj_cache::write(&cache_path, &obj) {
+                    log::warn!("Can't cache the meshes in {}: {err}", cache_path.display());
+                }
+                obj
+            }
+        };
+        for (name, _) in overrides {
+            if !obj.meshes.iter().any(|mesh| mesh.name == *name) {
+                log::warn!("No mesh named {name} to override the material of");
+            }
+        }
+        obj.insert_into(self, default_material, overrides);
+    }
+}
+
+/// A material of an OBJ file
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct LoadedMaterial {
+    pub name: String,
+    pub diffuse: [f32; 3],
+}
+
+/// A transformed mesh of an OBJ file
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct LoadedMesh {
+    pub name: String,
+    /// Index in the materials of the OBJ file
+    pub material: Option<usize>,
+    pub positions: Vec<[f32; 3]>,
+    /// Those of the file if it has one for each vertex, the smoothed ones otherwise
+    pub normals: Vec<[f32; 3]>,
+    pub indices: Vec<[u32; 3]>,
+}
+
+/// The content of an OBJ file, ready to be inserted in a scene
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct LoadedObj {
+    /// None when the materials couldn't be loaded
+    pub materials: Option<Vec<LoadedMaterial>>,
+    pub meshes: Vec<LoadedMesh>,
+}
+
+impl LoadedObj {
+    pub fn parse(mesh_path: &Path, transform: &Transform) -> Self {
         let mut options = tobj::GPU_LOAD_OPTIONS;
         options.single_index = true;
-        let (mut models, materials) =
-            tobj::load_obj(mesh_path.into(), &options).expect("Failed to load OBJ file");
+        let (models, materials) =
+            tobj::load_obj(mesh_path, &options).expect("Failed to load OBJ file");
+
+        let materials = materials.ok().map(|materials| {
+            materials
+                .into_iter()
+                .map(|material| LoadedMaterial {
+                    name: material.name,
+                    diffuse: material.diffuse,
+                })
+                .collect()
+        });
+
+        let meshes = models
+            .into_iter()
+            .map(|model| {
+                let mut mesh = model.mesh;
+                assert!(mesh.positions.len() % 3 == 0);
+                let vertices: &mut [Vec3] = bytemuck::cast_slice_mut(&mut mesh.positions);
+
+                // Apply transform in place
+                for point in vertices {
+                    *point = transform.apply(Point(*point)).vec()
+                }
+                let positions = bytemuck::cast_slice(&mesh.positions).to_vec();
+                let indices = bytemuck::cast_slice(&mesh.indices).to_vec();
+
+                let WeldedMesh {
+                    positions,
+                    normals,
+                    indices,
+                } = if mesh.normals.len() == mesh.positions.len() {
+                    // By the inverse transpose of the rotation then scale
+                    let normals = bytemuck::cast_slice::<_, Vec3>(&mesh.normals)
+                        .iter()
+                        .map(|&n| {
+                            let n = transform.rot.mul_vec3(n) / transform.scale;
+                            n.normalize_or_zero().to_array()
+                        })
+                        .collect();
+                    WeldedMesh {
+                        positions,
+                        normals,
+                        indices,
+                    }
+                } else {
+                    weld_and_smooth(&positions, &indices, CREASE_ANGLE)
+                };
 
+                LoadedMesh {
+                    name: model.name,
+                    material: mesh.material_id,
+                    positions,
+                    normals,
+                    indices,
+                }
+            })
+            .collect();
+
+        Self { materials, meshes }
+    }
+
+    fn insert_into<S: SceneT>(
+        &self,
+        scene: &mut S,
+        default_material: MaterialId,
+        overrides: &[(String, MaterialId)],
+    ) {
         let mut material_ids = vec![];
 
-        let has_non_default_materials = if let Ok(materials) = materials {
+        let has_non_default_materials = if let Some(materials) = &self.materials {
             for material in materials {
                 // let ke: Option<_> = material.unknown_param.get("Ke").and_then(|x| {
                 //     x.split(' ')
@@ -53,7 +206,7 @@ impl<S: SceneT> ObjLoaderExt for S {
                 // });
 
                 // let mat_id = if let Some(ke) = ke {
-                //     self.insert_material(crate::material::MaterialDescriptor {
+                //     scene.insert_material(crate::material::MaterialDescriptor {
                 //         label: None,
                 //         material: Box::new(MixMaterial {
                 //             p: 0.5,
@@ -68,11 +221,13 @@ impl<S: SceneT> ObjLoaderExt for S {
                 //         }),
                 //     })
                 // } else {
-                let mat_id = self.insert_material(crate::material::MaterialDescriptor {
+                let mat_id = scene.insert_material(crate::material::MaterialDescriptor {
                     label: None,
                     material: Box::new(DiffuseBxDF {
                         albedo: Rgb::from_array(material.diffuse),
+                        ..Default::default()
                     }),
+                    alpha: None,
                 });
                 // };
 
@@ -90,15 +245,16 @@ impl<S: SceneT> ObjLoaderExt for S {
             false
         };
 
-        for model in &mut models {
-            let mesh = &mut model.mesh;
-            log::debug!("Loading model {}", model.name);
+        for mesh in &self.meshes {
+            log::debug!("Loading model {}", mesh.name);
 
-            // TODO: Grab normals if any
             // TODO: vertices are duplicated for each sub mesh... meh
 
-            let material = if has_non_default_materials {
-                match mesh.material_id {
+            let overridden = overrides.iter().find(|(name, _)| *name == mesh.name);
+            let material = if let Some(&(_, material)) = overridden {
+                material
+            } else if has_non_default_materials {
+                match mesh.material {
                     Some(mat_id) => *material_ids.get(mat_id).unwrap_or(&default_material),
                     None => default_material,
                 }
@@ -106,18 +262,133 @@ impl<S: SceneT> ObjLoaderExt for S {
                 default_material
             };
 
-            assert!(mesh.positions.len() % 3 == 0);
-            let vertices: &mut [Vec3] = bytemuck::cast_slice_mut(&mut mesh.positions);
+            scene.insert_mesh_with_normals(material, &mesh.positions, &mesh.normals, &mesh.indices);
+        }
+    }
+}
 
-            // Apply transform in place
-            for point in vertices {
-                *point = transform.apply(Point(*point)).vec()
-            }
+#[cfg(test)]
+mod tests {
+    use glam::{Quat, Vec3};
+
+    use crate::{
+        material::{
+            DielectricBxDF, LightDescriptor, MaterialDescriptor, MaterialId, ThinDielectricBxDF,
+        },
+        math::{point::Point, transform::Transform},
+        scene::SceneT,
+    };
+
+    use super::{LoadedObj, ObjLoaderExt};
+
+    /// The labels of the materials, and the material and the triangles of each mesh
+    #[derive(Default)]
+    struct Recorder {
+        materials: Vec<Option<String>>,
+        meshes: Vec<(MaterialId, Vec<[u32; 3]>)>,
+    }
+
+    impl SceneT for Recorder {
+        type GeometryHandle = ();
 
-            self.insert_mesh(
-                material,
-                bytemuck::cast_slice(&mesh.positions),
-                bytemuck::cast_slice(&mesh.indices),
+        fn insert_material(&mut self, mat: MaterialDescriptor) -> MaterialId {
+            self.materials.push(mat.label);
+            MaterialId(self.materials.len() - 1)
+        }
+
+        fn insert_light(&mut self, _light: LightDescriptor) {}
+
+        fn insert_mesh(&mut self, material: MaterialId, _: &[[f32; 3]], indices: &[[u32; 3]]) {
+            self.meshes.push((material, indices.to_vec()));
+        }
+
+        fn insert_sphere(&mut self, _material: MaterialId, _origin: Point, _radius: f32) {}
+
+        fn insert_curve(&mut self, _material: MaterialId, _: &[[f32; 3]], _widths: &[f32]) {}
+    }
+
+    #[test]
+    fn material_overrides() {
+        let dir = std::env::temp_dir().join(format!("rt-obj-overrides-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mesh_path = dir.join("house.obj");
+        let mut obj = String::from("mtllib house.mtl\n");
+        for (x, y) in [(0.0, 0.0), (1.0, 0.0), (1.0, 1.0), (0.0, 1.0), (2.0, 0.0)] {
+            obj += &format!("v {x} {y} 0\n");
+        }
+        obj += "g walls\nusemtl brick\nf 1 2 3\nf 1 3 4\n";
+        obj += "g windows\nusemtl brick\nf 2 5 3\n";
+        std::fs::write(&mesh_path, &obj).unwrap();
+        std::fs::write(dir.join("house.mtl"), "newmtl brick\nKd 0.6 0.2 0.1\n").unwrap();
+
+        let mut scene = Recorder::default();
+        let label = |label: &str| Some(label.to_string());
+        let default_material = scene.insert_material(MaterialDescriptor {
+            label: label("default"),
+            material: Box::new(ThinDielectricBxDF { ior: 1.0 }),
+            alpha: None,
+        });
+        let glass = scene.insert_material(MaterialDescriptor {
+            label: label("glass"),
+            material: Box::new(DielectricBxDF {
+                ior: 1.5,
+                roughness: 0.0,
+                transmittance_color: [1.0; 3].into(),
+            }),
+            alpha: None,
+        });
+        let overrides = [
+            ("windows".to_string(), glass),
+            ("nothing".to_string(), glass),
+        ];
+        scene.load_obj_with_overrides(
+            &mesh_path,
+            Transform::default(),
+            default_material,
+            &overrides,
+        );
+        std::fs::remove_dir_all(dir).unwrap();
+
+        // The walls keep the material of the MTL file, inserted after the two others
+        let materials = scene
+            .meshes
+            .iter()
+            .map(|(material, indices)| (scene.materials[material.0].clone(), indices.len()))
+            .collect::<Vec<_>>();
+        assert_eq!(materials, [(None, 2), (label("glass"), 1)]);
+        assert_eq!(scene.meshes[0].0 .0, 2);
+    }
+
+    #[test]
+    fn normals() {
+        let dir = std::env::temp_dir().join(format!("rt-obj-normals-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut obj = String::new();
+        for (x, y) in [(0, 0), (1, 0), (1, 1), (0, 0), (1, 1), (0, 1)] {
+            obj += &format!("v {x} {y} 0\n");
+        }
+
+        // Without normals, the two triangles of the quad share their edge and are smoothed
+        let mesh_path = dir.join("flat.obj");
+        std::fs::write(&mesh_path, obj.clone() + "f 1 2 3\nf 4 5 6\n").unwrap();
+        let flat = LoadedObj::parse(&mesh_path, &Transform::default());
+        assert_eq!(flat.meshes[0].positions.len(), 4);
+        assert_eq!(flat.meshes[0].normals, [[0.0, 0.0, 1.0]; 4]);
+
+        // Those of the file are turned along with the mesh
+        let mesh_path = dir.join("shaded.obj");
+        obj += "vn 1 0 0\nf 1//1 2//1 3//1\nf 4//1 5//1 6//1\n";
+        std::fs::write(&mesh_path, obj).unwrap();
+        let transform = Transform {
+            rot: Quat::from_rotation_z(std::f32::consts::FRAC_PI_2),
+            ..Default::default()
+        };
+        let shaded = LoadedObj::parse(&mesh_path, &transform);
+        std::fs::remove_dir_all(dir).unwrap();
+        for &normal in &shaded.meshes[0].normals {
+            assert!(
+                Vec3::from_array(normal).abs_diff_eq(Vec3::Y, 1e-6),
+                "{normal:?}"
             );
         }
     }
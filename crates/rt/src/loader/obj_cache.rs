@@ -0,0 +1,317 @@
+//! Cache of the transformed meshes of the OBJ files, parsing the big ones takes a while.
+//!
+//! A cache file is named by a hash of what the meshes are built from, so that it is not used
+//! anymore as soon as one of them changes. The hash of the transform alone comes first in the
+//! name: the instances of an OBJ file under different transforms each keep their own cache file.
+use std::{
+    fs::File,
+    hash::{Hash, Hasher},
+    io::{self, BufReader, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::{math::transform::Transform, utils::stable_hash::StableHasher};
+
+use super::obj::{LoadedMaterial, LoadedMesh, LoadedObj};
+
+const MAGIC: &[u8; 8] = b"RTOBJ\0\0\0";
+/// To be bumped whenever the format or the loading changes
+const VERSION: u32 = 2;
+
+/// Hash of the OBJ file, of its MTL files and of the transform applied to the meshes
+pub(super) fn key(mesh_path: &Path, transform: &Transform) -> io::Result<u64> {
+    let mut hasher = StableHasher::default();
+    VERSION.hash(&mut hasher);
+    hash_obj(mesh_path, transform, &mut hasher)?;
+    Ok(hasher.finish())
+}
+
+/// Feeds the OBJ file, its MTL files and the transform of the meshes to `hasher`
+pub fn hash_obj(
+    mesh_path: &Path,
+    transform: &Transform,
+    hasher: &mut impl Hasher,
+) -> io::Result<()> {
+    let obj = std::fs::read(mesh_path)?;
+    obj.hash(hasher);
+
+    let dir = mesh_path.parent().unwrap_or(Path::new(""));
+    for line in obj.split(|&c| c == b'\n') {
+        if let Some(name) = line.strip_prefix(b"mtllib ") {
+            let name = String::from_utf8_lossy(name);
+            // A missing MTL file is hashed as empty, the materials don't load anyway
+            std::fs::read(dir.join(name.trim()))
+                .unwrap_or_default()
+                .hash(hasher);
+        }
+    }
+    hash_transform(transform, hasher);
+    Ok(())
+}
+
+fn hash_transform(transform: &Transform, hasher: &mut impl Hasher) {
+    let Transform {
+        translation,
+        scale,
+        rot,
+    } = transform;
+    for c in translation
+        .to_array()
+        .into_iter()
+        .chain(scale.to_array())
+        .chain(rot.to_array())
+    {
+        c.to_bits().hash(hasher);
+    }
+}
+
+/// The cache file of the OBJ file `mesh_path` under `transform`, in a `.cache` directory next to
+/// it
+pub(super) fn path(mesh_path: &Path, transform: &Transform, key: u64) -> PathBuf {
+    let mut hasher = StableHasher::default();
+    hash_transform(transform, &mut hasher);
+    let dir = mesh_path.parent().unwrap_or(Path::new("")).join(".cache");
+    dir.join(format!(
+        "{}-{:016x}-{key:016x}.bin",
+        stem(mesh_path),
+        hasher.finish()
+    ))
+}
+
+fn stem(mesh_path: &Path) -> String {
+    mesh_path
+        .file_stem()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .into_owned()
+}
+
+pub(super) fn read(cache_path: &Path) -> io::Result<LoadedObj> {
+    let mut r = BufReader::new(File::open(cache_path)?);
+    let mut magic = [0; 8];
+    r.read_exact(&mut magic)?;
+    if &magic != MAGIC || read_u64(&mut r)? != VERSION as u64 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "not a cache file",
+        ));
+    }
+
+    let materials = match read_u64(&mut r)? {
+        0 => None,
+        _ => Some(
+            (0..read_u64(&mut r)?)
+                .map(|_| {
+                    Ok(LoadedMaterial {
+                        name: read_string(&mut r)?,
+                        diffuse: read_array(&mut r, 1)?[0],
+                    })
+                })
+                .collect::<io::Result<_>>()?,
+        ),
+    };
+    let meshes = (0..read_u64(&mut r)?)
+        .map(|_| {
+            let name = read_string(&mut r)?;
+            let material = match read_u64(&mut r)? {
+                u64::MAX => None,
+                index => Some(index as usize),
+            };
+            let len = read_u64(&mut r)? as usize;
+            let positions = read_array(&mut r, len)?;
+            let normals = read_array(&mut r, len)?;
+            let len = read_u64(&mut r)? as usize;
+            let indices = read_array(&mut r, len)?;
+            Ok(LoadedMesh {
+                name,
+                material,
+                positions,
+                normals,
+                indices,
+            })
+        })
+        .collect::<io::Result<_>>()?;
+    Ok(LoadedObj { materials, meshes })
+}
+
+/// Writes the cache file, removing the ones of the previous versions of the OBJ file under the
+/// same transform
+pub(super) fn write(cache_path: &Path, obj: &LoadedObj) -> io::Result<()> {
+    let dir = cache_path.parent().unwrap_or(Path::new(""));
+    std::fs::create_dir_all(dir)?;
+    // Up to the hash of the transform, `<stem>-<transform>-`
+    let stale_prefix = format!(
+        "{}-",
+        stem(cache_path).rsplit_once('-').unwrap_or_default().0
+    );
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        let stale = name
+            .strip_prefix(&stale_prefix)
+            .is_some_and(|key| key.len() == "0123456789abcdef.bin".len() && key.ends_with(".bin"));
+        if stale {
+            std::fs::remove_file(entry.path())?;
+        }
+    }
+
+    // Written aside first, a render interrupted while writing must not leave half a cache file
+    let tmp_path = cache_path.with_extension("tmp");
+    let mut w = BufWriter::new(File::create(&tmp_path)?);
+    w.write_all(MAGIC)?;
+    w.write_all(&(VERSION as u64).to_le_bytes())?;
+
+    match &obj.materials {
+        None => w.write_all(&0u64.to_le_bytes())?,
+        Some(materials) => {
+            w.write_all(&1u64.to_le_bytes())?;
+            w.write_all(&(materials.len() as u64).to_le_bytes())?;
+            for material in materials {
+                write_string(&mut w, &material.name)?;
+                w.write_all(bytemuck::cast_slice(&[material.diffuse]))?;
+            }
+        }
+    }
+    w.write_all(&(obj.meshes.len() as u64).to_le_bytes())?;
+    for mesh in &obj.meshes {
+        write_string(&mut w, &mesh.name)?;
+        let material = mesh.material.map_or(u64::MAX, |index| index as u64);
+        w.write_all(&material.to_le_bytes())?;
+        w.write_all(&(mesh.positions.len() as u64).to_le_bytes())?;
+        w.write_all(bytemuck::cast_slice(&mesh.positions))?;
+        // As many as the positions
+        w.write_all(bytemuck::cast_slice(&mesh.normals))?;
+        w.write_all(&(mesh.indices.len() as u64).to_le_bytes())?;
+        w.write_all(bytemuck::cast_slice(&mesh.indices))?;
+    }
+    w.into_inner()?.sync_all()?;
+    std::fs::rename(tmp_path, cache_path)
+}
+
+fn read_u64(r: &mut impl Read) -> io::Result<u64> {
+    let mut bytes = [0; 8];
+    r.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+fn read_string(r: &mut impl Read) -> io::Result<String> {
+    let mut bytes = vec![0; read_u64(r)? as usize];
+    r.read_exact(&mut bytes)?;
+    String::from_utf8(bytes).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+}
+
+fn write_string(w: &mut impl Write, s: &str) -> io::Result<()> {
+    w.write_all(&(s.len() as u64).to_le_bytes())?;
+    w.write_all(s.as_bytes())
+}
+
+/// Reads `len` arrays of 3 values stored as their bytes
+fn read_array<T: bytemuck::Pod>(r: &mut impl Read, len: usize) -> io::Result<Vec<[T; 3]>> {
+    let mut values = vec![[T::zeroed(); 3]; len];
+    r.read_exact(bytemuck::cast_slice_mut(&mut values))?;
+    Ok(values)
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::{Quat, Vec3};
+
+    use crate::{
+        aggregate::triangle_mesh::TriangleMesh,
+        loader::obj::LoadedObj,
+        material::MaterialId,
+        math::{point::Point, transform::Transform},
+        ray::Ray,
+        shape::{FullIntersectionResult, Shape},
+    };
+
+    fn intersections(obj: &LoadedObj) -> Vec<Option<(f32, [f32; 2])>> {
+        let meshes = obj
+            .meshes
+            .iter()
+            .map(|mesh| {
+                TriangleMesh::new(
+                    MaterialId(0),
+                    mesh.positions
+                        .iter()
+                        .map(|&p| Vec3::from_array(p))
+                        .collect(),
+                    None,
+                    mesh.indices.clone(),
+                )
+            })
+            .collect::<Vec<_>>();
+        let mut hits = Vec::new();
+        for y in 0..16 {
+            for x in 0..16 {
+                let target = Vec3::new(x as f32 / 8.0 - 1.0, y as f32 / 8.0 - 1.0, -1.0);
+                let ray = Ray::new(Point::new(0.0, 0.0, 3.0), target - Vec3::new(0.0, 0.0, 3.0));
+                for mesh in &meshes {
+                    hits.push(match mesh.intersection_full(ray) {
+                        FullIntersectionResult::Intersection(hit) => {
+                            Some((hit.t, hit.local_info.uv))
+                        }
+                        FullIntersectionResult::NoIntersection => None,
+                    });
+                }
+            }
+        }
+        hits
+    }
+
+    #[test]
+    fn cached_obj_is_the_same() {
+        let dir = std::env::temp_dir().join(format!("rt-obj-cache-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mesh_path = dir.join("quad.obj");
+        let mut obj = String::from("mtllib quad.mtl\no quad\nusemtl red\n");
+        for (x, y) in [(-1.0, -1.0), (1.0, -1.0), (1.0, 1.0), (-1.0, 1.0)] {
+            obj += &format!("v {x} {y} 0.2\n");
+        }
+        obj += "f 1 2 3\nf 1 3 4\n";
+        std::fs::write(&mesh_path, &obj).unwrap();
+        std::fs::write(dir.join("quad.mtl"), "newmtl red\nKd 0.8 0.1 0.1\n").unwrap();
+
+        let transform = Transform {
+            translation: Vec3::new(0.1, 0.0, -1.0),
+            scale: Vec3::splat(0.8),
+            rot: Quat::from_rotation_y(0.3),
+        };
+        let key = super::key(&mesh_path, &transform).unwrap();
+        let cache_path = super::path(&mesh_path, &transform, key);
+        let fresh = LoadedObj::parse(&mesh_path, &transform);
+        super::write(&cache_path, &fresh).unwrap();
+        let cached = super::read(&cache_path).unwrap();
+        assert_eq!(fresh, cached);
+        assert_eq!(
+            cached.materials.as_ref().unwrap()[0].diffuse,
+            [0.8, 0.1, 0.1]
+        );
+        let hits = intersections(&fresh);
+        assert!(hits.iter().any(Option::is_some));
+        assert_eq!(hits, intersections(&cached));
+
+        // The same OBJ file under another transform has its own cache file
+        let moved = Transform {
+            translation: Vec3::ZERO,
+            ..transform
+        };
+        let moved_key = super::key(&mesh_path, &moved).unwrap();
+        assert_ne!(key, moved_key);
+        let moved_cache_path = super::path(&mesh_path, &moved, moved_key);
+        super::write(&moved_cache_path, &LoadedObj::parse(&mesh_path, &moved)).unwrap();
+        assert!(cache_path.exists());
+
+        // Any change gets its own cache file, the old one of the same transform is removed
+        std::fs::write(dir.join("quad.mtl"), "newmtl red\nKd 0.1 0.8 0.1\n").unwrap();
+        let new_key = super::key(&mesh_path, &transform).unwrap();
+        assert_ne!(key, new_key);
+        let new_cache_path = super::path(&mesh_path, &transform, new_key);
+        super::write(&new_cache_path, &LoadedObj::parse(&mesh_path, &transform)).unwrap();
+        assert!(!cache_path.exists());
+        assert!(new_cache_path.exists());
+        assert!(moved_cache_path.exists());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
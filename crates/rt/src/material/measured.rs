@@ -0,0 +1,107 @@
+use std::f32::consts::{FRAC_PI_2, PI};
+
+use glam::Vec3;
+
+use crate::{
+    color::Rgb,
+    math::{
+        distributions::{CosineHemisphere3, DirectionalPDF, Samplable, Sample1D, Sample2D},
+        vec::Vec3Ext,
+    },
+};
+
+use super::{BxDF, BxDFFlags, BxDFSample};
+
+pub const THETA_HALF_RES: usize = 90;
+pub const THETA_DIFF_RES: usize = 90;
+/// Only half of the range is tabulated, thanks to reciprocity
+pub const PHI_DIFF_RES: usize = 180;
+/// Number of entries of each color channel
+pub const MERL_TABLE_SIZE: usize = THETA_HALF_RES * THETA_DIFF_RES * PHI_DIFF_RES;
+
+const MERL_SCALE: [f32; 3] = [1.0 / 1500.0, 1.15 / 1500.0, 1.66 / 1500.0];
+
+/// A BRDF tabulated in the half/difference angles parametrization of the MERL database
+///
+/// See "A Data-Driven Reflectance Model", Matusik et al. 2003
+pub struct MeasuredBxDF {
+    /// The red, then green, then blue values, unscaled
+    pub table: Vec<f32>,
+}
+
+impl MeasuredBxDF {
+    pub fn new(table: Vec<f32>) -> Self {
+        assert_eq!(table.len(), 3 * MERL_TABLE_SIZE);
+        Self { table }
+    }
+
+    fn index(wo: Vec3, wi: Vec3) -> usize {
+        let wh = (wo + wi).normalize();
+        let theta_half = wh.z.clamp(-1.0, 1.0).acos();
+        let phi_half = wh.y.atan2(wh.x);
+
+        // The difference vector is wi in the frame where wh is the north pole
+        let wd = Vec3::new(
+            theta_half.cos() * (phi_half.cos() * wi.x + phi_half.sin() * wi.y)
+                - theta_half.sin() * wi.z,
+            -phi_half.sin() * wi.x + phi_half.cos() * wi.y,
+            theta_half.sin() * (phi_half.cos() * wi.x + phi_half.sin() * wi.y)
+                + theta_half.cos() * wi.z,
+        );
+        let theta_diff = wd.z.clamp(-1.0, 1.0).acos();
+        let mut phi_diff = wd.y.atan2(wd.x);
+        if phi_diff < 0.0 {
+            phi_diff += PI;
+        }
+
+        // The theta half axis is not uniform, there is more resolution near the specular peak
+        let theta_half_index = (theta_half / FRAC_PI_2 * THETA_HALF_RES as f32).max(0.0);
+        let theta_half_index = (theta_half_index * THETA_HALF_RES as f32).sqrt() as usize;
+        let theta_diff_index = (theta_diff / FRAC_PI_2 * THETA_DIFF_RES as f32) as usize;
+        let phi_diff_index = (phi_diff / PI * PHI_DIFF_RES as f32) as usize;
+
+        phi_diff_index.min(PHI_DIFF_RES - 1)
+            + PHI_DIFF_RES * theta_diff_index.min(THETA_DIFF_RES - 1)
+            + PHI_DIFF_RES * THETA_DIFF_RES * theta_half_index.min(THETA_HALF_RES - 1)
+    }
+}
+
+impl BxDF for MeasuredBxDF {
+    fn flags(&self) -> BxDFFlags {
+        BxDFFlags::Reflection
+    }
+
+    /// The table only covers the upper hemisphere, the lower one is its mirror
+    fn f(&self, wo: Vec3, wi: Vec3) -> Rgb {
+        if !wo.same_hemishpere(wi) || wo.z == 0.0 || wi.z == 0.0 {
+            return Rgb::default();
+        }
+
+        let mirror = Vec3::new(1.0, 1.0, wo.z.signum());
+        let index = Self::index(wo * mirror, wi * mirror);
+        let rgb: [f32; 3] = std::array::from_fn(|c| {
+            (self.table[index + c * MERL_TABLE_SIZE] * MERL_SCALE[c]).max(0.0)
+        });
+        Rgb::from_array(rgb)
+    }
+
+    fn pdf(&self, wo: Vec3, wi: Vec3) -> f32 {
+        if !wo.same_hemishpere(wi) {
+            return 0.0;
+        }
+        CosineHemisphere3.pdf(wi.z.abs())
+    }
+
+    /// There is no good way to importance sample a table, the cosine is used instead
+    fn sample_f(&self, wo: Vec3, uv: Sample2D, _w: Sample1D) -> Option<BxDFSample> {
+        let mut wi = CosineHemisphere3.sample_with(uv);
+        wi.z = wi.z.copysign(wo.z);
+
+        Some(BxDFSample {
+            wi,
+            f: self.f(wo, wi),
+            pdf: CosineHemisphere3.pdf(wi.z.abs()),
+            flags: BxDFFlags::Reflection,
+        })
+    }
+}
@@ -1,3 +1,4 @@
+pub mod measured;
 pub mod texture;
 
 use std::ops::Deref;
@@ -11,13 +12,19 @@ use crate::{
         linear::{BLACK, WHITE},
         Rgb,
     },
+    light::Light,
+    material::texture::{Texture, Uv},
     math::{
-        distributions::{self, CosineHemisphere3, DirectionalPDF, Samplable, Sample1D, Sample2D},
-        point::Point,
+        distributions::{
+            self, CosineHemisphere3, DirectionalPDF, Samplable, Sample1D, Sample2D,
+            UniformHemisphere3,
+        },
         transform::Frame,
         vec::Vec3Ext,
     },
+    memory::Arena,
     ray::Ray,
+    utils::counter::counter,
 };
 
 bitflags! {
@@ -35,6 +42,8 @@ pub struct BxDFSample {
     pub wi: Vec3,
     pub f: Rgb,
     pub pdf: f32,
+    /// The kind of lobe that was sampled
+    pub flags: BxDFFlags,
 }
 
 pub trait BxDF {
@@ -48,9 +57,41 @@ pub trait BxDF {
     fn sample_f(&self, wo: Vec3, uv: Sample2D, w: Sample1D) -> Option<BxDFSample>;
 
     // NOTE: This should not be here!
-    fn le(&self) -> Rgb {
+    /// Emitted radiance towards `wo`
+    fn le(&self, _wo: Vec3) -> Rgb {
         BLACK
     }
+
+    // NOTE: Neither should this, it belongs to a medium
+    /// Transmittance of a segment of length `distance` inside the object, None if light goes
+    /// through it as it does outside
+    fn interior_transmittance(&self, _distance: f32) -> Option<Rgb> {
+        None
+    }
+
+    /// Index of refraction of the inside of the object, None if its surface doesn't refract
+    fn ior(&self) -> Option<f32> {
+        None
+    }
+
+    /// The BxDF of the surface when the object is surrounded by a medium of index of refraction
+    /// `exterior_ior` rather than by the void. None if it is the same
+    fn in_medium<'a>(&'a self, _arena: &Arena<'a>, _exterior_ior: f32) -> Option<&'a dyn BxDF> {
+        None
+    }
+}
+
+/// What a surface is made of, it gives the BxDF of each hit on it
+pub trait Material: Send + Sync {
+    /// The BxDF of a hit at `uv`. When it depends on the hit, it is built in the arena
+    fn bxdf<'a>(&'a self, arena: &Arena<'a>, uv: Uv) -> &'a dyn BxDF;
+}
+
+/// A BxDF is a material that is the same everywhere
+impl<B: BxDF + Send + Sync> Material for B {
+    fn bxdf<'a>(&'a self, _arena: &Arena<'a>, _uv: Uv) -> &'a dyn BxDF {
+        self
+    }
 }
 
 pub struct BSDF<'a, I: BxDF + ?Sized> {
@@ -74,6 +115,7 @@ impl<I: BxDF + ?Sized> BSDF<'_, I> {
     }
 
     pub fn sample_f(&self, wo: Vec3, uv: Sample2D, w: Sample1D) -> Option<BxDFSample> {
+        counter!("BxDF samples");
         let wo_local = self.frame.to_local(wo);
         if wo_local.z == 0.0 {
             return None;
@@ -86,6 +128,7 @@ impl<I: BxDF + ?Sized> BSDF<'_, I> {
     }
 
     pub fn f(&self, wo: Vec3, wi: Vec3) -> Rgb {
+        counter!("BxDF evaluations");
         self.inner
             .f(self.frame.to_local(wo), self.frame.to_local(wi))
     }
@@ -93,11 +136,41 @@ impl<I: BxDF + ?Sized> BSDF<'_, I> {
         self.inner
             .pdf(self.frame.to_local(wo), self.frame.to_local(wi))
     }
+    pub fn le(&self, wo: Vec3) -> Rgb {
+        self.inner.le(self.frame.to_local(wo))
+    }
+}
+
+/// How the directions of a [DiffuseBxDF] are drawn
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum HemisphereSampling {
+    /// Proportionally to the cosine, the variance comes only from the incident light
+    #[default]
+    Cosine,
+    /// Uniformly, to show what the importance sampling of the cosine gains
+    Uniform,
+}
+
+impl HemisphereSampling {
+    fn pdf(self, costheta: f32) -> f32 {
+        match self {
+            HemisphereSampling::Cosine => CosineHemisphere3.pdf(costheta),
+            HemisphereSampling::Uniform => UniformHemisphere3.pdf(costheta),
+        }
+    }
+
+    fn sample(self, uv: Sample2D) -> Vec3 {
+        match self {
+            HemisphereSampling::Cosine => CosineHemisphere3.sample_with(uv),
+            HemisphereSampling::Uniform => UniformHemisphere3.sample_with(uv),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Copy, Default)]
 pub struct DiffuseBxDF {
     pub albedo: Rgb,
+    pub sampling: HemisphereSampling,
 }
 
 impl BxDF for DiffuseBxDF {
@@ -112,28 +185,160 @@ impl BxDF for DiffuseBxDF {
         core::f32::consts::FRAC_1_PI * self.albedo
     }
 
-    fn pdf(&self, _wo: Vec3, wi: Vec3) -> f32 {
-        CosineHemisphere3.pdf(wi.z.abs())
+    fn pdf(&self, wo: Vec3, wi: Vec3) -> f32 {
+        if !wo.same_hemishpere(wi) {
+            return 0.0;
+        }
+        self.sampling.pdf(wi.z.abs())
     }
 
     fn sample_f(&self, wo: Vec3, uv: Sample2D, _w: Sample1D) -> Option<BxDFSample> {
-        let mut wi = CosineHemisphere3.sample_with(uv);
+        let mut wi = self.sampling.sample(uv);
         wi.z = wi.z.copysign(wo.z);
 
-        let pdf = CosineHemisphere3.pdf(wi.z.abs());
+        let pdf = self.sampling.pdf(wi.z.abs());
 
         Some(BxDFSample {
             wi,
             f: core::f32::consts::FRAC_1_PI * self.albedo,
             pdf,
+            flags: BxDFFlags::Reflection | BxDFFlags::Diffusion,
         })
     }
 }
 
-#[derive(Debug, Clone, Copy, Default)]
+/// A diffuse material whose albedo is read from a texture
+pub struct TexturedDiffuse {
+    pub albedo: Box<dyn Texture>,
+}
+
+impl Material for TexturedDiffuse {
+    fn bxdf<'a>(&'a self, arena: &Arena<'a>, uv: Uv) -> &'a dyn BxDF {
+        arena.alloc(DiffuseBxDF {
+            albedo: self.albedo.color(uv),
+            sampling: HemisphereSampling::Cosine,
+        })
+    }
+}
+
+/// Modified Phong model: a diffuse lobe and a cosine power lobe around the mirror direction, see
+/// "Using the Modified Phong Reflectance Model for Physically Based Rendering", Lafortune &
+/// Willems 1994. It is energy conserving as long as `diffuse + specular` is at most 1
+#[derive(Debug, Clone, Copy)]
+pub struct PhongBxDF {
+    pub diffuse: Rgb,
+    pub specular: Rgb,
+    /// The higher, the sharper the highlights
+    pub exponent: f32,
+}
+
+impl PhongBxDF {
+    /// Probability to sample the diffuse lobe rather than the specular one
+    fn diffuse_probability(&self) -> f32 {
+        let weight = |c: Rgb| c.to_array().iter().sum::<f32>();
+        let (diffuse, specular) = (weight(self.diffuse), weight(self.specular));
+        if diffuse + specular == 0.0 {
+            1.0
+        } else {
+            diffuse / (diffuse + specular)
+        }
+    }
+
+    /// Cosine of the angle between `wi` and the mirror direction of `wo`
+    fn cos_alpha(wo: Vec3, wi: Vec3) -> f32 {
+        Vec3::new(-wo.x, -wo.y, wo.z).dot(wi).max(0.0)
+    }
+}
+
+impl BxDF for PhongBxDF {
+    fn flags(&self) -> BxDFFlags {
+        BxDFFlags::Reflection | BxDFFlags::Diffusion
+    }
+
+    fn f(&self, wo: Vec3, wi: Vec3) -> Rgb {
+        if !wo.same_hemishpere(wi) {
+            return BLACK;
+        }
+        let n = self.exponent;
+        let specular =
+            (n + 2.0) * core::f32::consts::FRAC_1_PI / 2.0 * Self::cos_alpha(wo, wi).powf(n);
+        core::f32::consts::FRAC_1_PI * self.diffuse + specular * self.specular
+    }
+
+    fn pdf(&self, wo: Vec3, wi: Vec3) -> f32 {
+        if !wo.same_hemishpere(wi) {
+            return 0.0;
+        }
+        let n = self.exponent;
+        let pd = self.diffuse_probability();
+        let specular =
+            (n + 1.0) * core::f32::consts::FRAC_1_PI / 2.0 * Self::cos_alpha(wo, wi).powf(n);
+        pd * CosineHemisphere3.pdf(wi.z.abs()) + (1.0 - pd) * specular
+    }
+
+    fn sample_f(&self, wo: Vec3, uv: Sample2D, w: Sample1D) -> Option<BxDFSample> {
+        let pd = self.diffuse_probability();
+        let (wi, flags) = if w[0] < pd {
+            let mut wi = CosineHemisphere3.sample_with(uv);
+            wi.z = wi.z.copysign(wo.z);
+            (wi, BxDFFlags::Reflection | BxDFFlags::Diffusion)
+        } else {
+            // The lobe is sampled around the mirror direction
+            let cos_alpha = uv[0].powf(1.0 / (self.exponent + 1.0));
+            let sin_alpha = (1.0 - cos_alpha * cos_alpha).max(0.0).sqrt();
+            let phi = core::f32::consts::TAU * uv[1];
+            let local = Vec3::new(sin_alpha * phi.cos(), sin_alpha * phi.sin(), cos_alpha);
+            let wi = Frame::new(Vec3::new(-wo.x, -wo.y, wo.z)).from_local(local);
+            // Part of the lobe is below the surface
+            if !wo.same_hemishpere(wi) {
+                return None;
+            }
+            (wi, BxDFFlags::Reflection)
+        };
+
+        Some(BxDFSample {
+            wi,
+            f: self.f(wo, wi),
+            pdf: self.pdf(wo, wi),
+            flags,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
 pub struct DielectricBxDF {
     pub ior: f32,
     pub roughness: f32,
+    /// Fraction of the light going through a unit length of the glass, following Beer-Lambert law.
+    /// White is a clear glass
+    pub transmittance_color: Rgb,
+}
+
+impl DielectricBxDF {
+    /// The microfacet normal that scatters `wo` into `wi`, facing up, along with the relative ior
+    /// of the scattering (1 for a reflection).
+    ///
+    /// None if it is degenerate or if one of the directions is behind it
+    fn half_vector(&self, wo: Vec3, wi: Vec3) -> Option<(Vec3, f32)> {
+        let cosi = wi.z;
+        let coso = wo.z;
+        let ior = match (cosi * coso > 0.0, coso > 0.0) {
+            (true, _) => 1.0,
+            (false, true) => self.ior,
+            (false, false) => 1.0 / self.ior,
+        };
+
+        let wm = wi * ior + wo;
+        if cosi == 0.0 || coso == 0.0 || wm.length_squared() == 0.0 {
+            return None;
+        };
+        let wm = wm.z.signum() * wm.normalize();
+
+        if wi.dot(wm) * cosi < 0.0 || wo.dot(wm) * coso < 0.0 {
+            return None;
+        }
+        Some((wm, ior))
+    }
 }
 
 fn fresnel_dielectric(cosi: f32, ior: f32) -> f32 {
@@ -174,27 +379,13 @@ impl BxDF for DielectricBxDF {
 
         let cosi = wi.z;
         let coso = wo.z;
-        let reflect = cosi * coso > 0.0;
-        let ior = if reflect || coso > 0.0 {
-            self.ior
-        } else {
-            1.0 / self.ior
-        };
-        let wm = {
-            let wm = wi * ior + wo;
-            if cosi == 0.0 || coso == 0.0 || wm.length_squared() == 0.0 {
-                return BLACK;
-            };
-            wm.z.signum() * wm.normalize()
-        };
-
-        if wi.dot(wm) * cosi < 0.0 || wo.dot(wm) * coso < 0.0 {
+        let Some((wm, ior)) = self.half_vector(wo, wi) else {
             return BLACK;
-        }
+        };
 
-        let r = fresnel_dielectric(wo.dot(wm), ior);
+        let r = fresnel_dielectric(wo.dot(wm), self.ior);
         let t = 1.0 - r;
-        if reflect {
+        if cosi * coso > 0.0 {
             distrib.d(wm) * distrib.g(wo, wi) * r / f32::abs(4. * cosi * coso) * WHITE
         } else {
             let denom = (wi.dot(wm) + wo.dot(wm) / ior).powi(2) * cosi * coso;
@@ -214,29 +405,17 @@ impl BxDF for DielectricBxDF {
             return 0.0;
         }
 
-        let cosi = wi.z;
-        let coso = wo.z;
-        let reflect = cosi * cosi > 0.0;
-        let ior = if reflect { self.ior } else { 1.0 / self.ior };
-        let wm = {
-            let wm = wi * ior;
-            if cosi == 0.0 || coso == 0.0 || wm.length_squared() == 0.0 {
-                return 0.0;
-            };
-            wm.signum() * wm.normalize()
-        };
-
-        if wi.dot(wm) * cosi < 0.0 || wo.dot(wm) * coso < 0.0 {
+        let Some((wm, ior)) = self.half_vector(wo, wi) else {
             return 0.0;
-        }
+        };
 
         let r = fresnel_dielectric(wo.dot(wm), self.ior);
         let t = 1.0 - r;
 
-        if reflect {
+        if wi.z * wo.z > 0.0 {
             distrib.pdf(wo, wm) / (4.0 * f32::abs(wo.dot(wm))) * r / (r + t)
         } else {
-            let dwm_dwi = f32::abs(wi.dot(wm)) / (wi.dot(wm) + wi.dot(wm) / ior).powi(2);
+            let dwm_dwi = f32::abs(wi.dot(wm)) / (wi.dot(wm) + wo.dot(wm) / ior).powi(2);
             distrib.pdf(wo, wm) * dwm_dwi * t / (r + t)
         }
     }
@@ -248,49 +427,50 @@ impl BxDF for DielectricBxDF {
 
         if self.ior == 1.0 || distrib.is_smooth() {
             // perfect specular
-            let r = fresnel_dielectric(wo.z, self.ior);
+            let refracted = wo.refract(Vec3::Z, self.ior);
+            // Past the critical angle, all the light is reflected
+            let r = match refracted {
+                Some(_) => fresnel_dielectric(wo.z, self.ior),
+                None => 1.0,
+            };
             let t = 1.0 - r;
 
-            if w[0] <= r / (r + t) {
-                // perfect reflection
-                let wi = Vec3::new(-wo.x, -wo.y, wo.z);
-                Some(BxDFSample {
-                    wi,
-                    f: (r / wi.z.abs()) * WHITE,
-                    pdf: r / (r + t),
-                })
-            } else {
-                // perfect transmission (with refraction)
-                let (wi, _) = wo.refract(Vec3::Z, self.ior)?;
-                debug_assert!(!wi.is_nan());
-                Some(BxDFSample {
-                    wi,
-                    f: (t / wi.z.abs()) * WHITE,
-                    pdf: t / (r + t),
-                })
+            match refracted {
+                Some((wi, _)) if w[0] > r / (r + t) => {
+                    // perfect transmission (with refraction)
+                    debug_assert!(!wi.is_nan());
+                    Some(BxDFSample {
+                        wi,
+                        f: (t / wi.z.abs()) * WHITE,
+                        pdf: t / (r + t),
+                        flags: BxDFFlags::Transmission | BxDFFlags::Specular,
+                    })
+                }
+                _ => {
+                    // perfect reflection
+                    let wi = Vec3::new(-wo.x, -wo.y, wo.z);
+                    Some(BxDFSample {
+                        wi,
+                        f: (r / wi.z.abs()) * WHITE,
+                        pdf: r / (r + t),
+                        flags: BxDFFlags::Reflection | BxDFFlags::Specular,
+                    })
+                }
             }
         } else {
             // rough
             let wm = distrib.sample_wm(wo, uv);
             trace!("wm {wm:?} wo {wo:?}");
-            let r = fresnel_dielectric(wo.dot(wm), self.ior);
+            let refracted = wo.refract(wm, self.ior);
+            // Past the critical angle of the microfacet, all the light is reflected
+            let r = match refracted {
+                Some(_) => fresnel_dielectric(wo.dot(wm), self.ior),
+                None => 1.0,
+            };
             let t = 1.0 - r;
-            if w[0] < r / (r + t) {
-                // reflection
-                let wi = wo.reflect(wm);
-                trace!("{:?}  {:?} {:?}", wo, wm, wi);
-                if !wo.same_hemishpere(wi) {
-                    return None;
-                };
-
-                let f = distrib.d(wm) * distrib.g(wo, wi) * r / (4.0 * wi.z * wo.z) * WHITE;
-
-                let pdf = distrib.pdf(wo, wm) / (4.0 * f32::abs(wo.dot(wm))) * r / (r + t);
-                debug_assert!(!pdf.is_nan());
-                Some(BxDFSample { wi, f, pdf })
-            } else {
+            let transmitted = refracted.filter(|_| w[0] >= r / (r + t));
+            if let Some((wi, ior)) = transmitted {
                 // transmission
-                let (wi, ior) = wo.refract(wm, self.ior)?;
                 if wi.same_hemishpere(wo) || wi.z == 0.0 {
                     return None;
                 }
@@ -306,10 +486,55 @@ impl BxDF for DielectricBxDF {
 
                 let pdf = distrib.pdf(wo, wm) * dwm_dwi * t / (r + t);
                 debug_assert!(!pdf.is_nan());
-                Some(BxDFSample { wi, f, pdf })
+                Some(BxDFSample {
+                    wi,
+                    f,
+                    pdf,
+                    flags: BxDFFlags::Transmission,
+                })
+            } else {
+                // reflection
+                let wi = wo.reflect(wm);
+                trace!("{:?}  {:?} {:?}", wo, wm, wi);
+                if !wo.same_hemishpere(wi) {
+                    return None;
+                };
+
+                let f = distrib.d(wm) * distrib.g(wo, wi) * r / (4.0 * wi.z * wo.z) * WHITE;
+
+                let pdf = distrib.pdf(wo, wm) / (4.0 * f32::abs(wo.dot(wm))) * r / (r + t);
+                debug_assert!(!pdf.is_nan());
+                Some(BxDFSample {
+                    wi,
+                    f,
+                    pdf,
+                    flags: BxDFFlags::Reflection,
+                })
             }
         }
     }
+
+    fn interior_transmittance(&self, distance: f32) -> Option<Rgb> {
+        Some(Rgb::from_array(
+            self.transmittance_color
+                .to_array()
+                .map(|c| c.powf(distance)),
+        ))
+    }
+
+    fn ior(&self) -> Option<f32> {
+        Some(self.ior)
+    }
+
+    fn in_medium<'a>(&'a self, arena: &Arena<'a>, exterior_ior: f32) -> Option<&'a dyn BxDF> {
+        if exterior_ior == 1.0 {
+            return None;
+        }
+        Some(arena.alloc(DielectricBxDF {
+            ior: self.ior / exterior_ior,
+            ..*self
+        }))
+    }
 }
 
 #[derive(Debug, Clone, Copy, Default)]
@@ -353,6 +578,7 @@ impl BxDF for ThinDielectricBxDF {
                 wi,
                 f: (r / wi.z.abs()) * WHITE,
                 pdf: r / (r + t),
+                flags: BxDFFlags::Reflection | BxDFFlags::Specular,
             })
         } else {
             // perfect transmission (with refraction)
@@ -361,6 +587,7 @@ impl BxDF for ThinDielectricBxDF {
                 wi,
                 f: (t / wi.z.abs()) * WHITE,
                 pdf: t / (r + t),
+                flags: BxDFFlags::Transmission | BxDFFlags::Specular,
             })
         }
     }
@@ -369,6 +596,8 @@ impl BxDF for ThinDielectricBxDF {
 #[derive(Debug, Clone, Copy, Default)]
 pub struct EmitBxDF {
     pub le: Rgb,
+    /// When false, only the side the normal points to emits light
+    pub two_sided: bool,
 }
 
 impl BxDF for EmitBxDF {
@@ -388,8 +617,35 @@ impl BxDF for EmitBxDF {
         None
     }
 
-    fn le(&self) -> Rgb {
-        self.le
+    fn le(&self, wo: Vec3) -> Rgb {
+        if self.two_sided || wo.z > 0.0 {
+            self.le
+        } else {
+            BLACK
+        }
+    }
+}
+
+/// An emitter whose radiance is read from a texture
+pub struct TexturedEmit {
+    pub le: Box<dyn Texture>,
+    /// When false, only the side the normal points to emits light
+    pub two_sided: bool,
+}
+
+impl TexturedEmit {
+    /// The emission of the surface at `uv`
+    pub fn at(&self, uv: Uv) -> EmitBxDF {
+        EmitBxDF {
+            le: self.le.color(uv),
+            two_sided: self.two_sided,
+        }
+    }
+}
+
+impl Material for TexturedEmit {
+    fn bxdf<'a>(&'a self, arena: &Arena<'a>, uv: Uv) -> &'a dyn BxDF {
+        arena.alloc(self.at(uv))
     }
 }
 
@@ -400,7 +656,21 @@ pub struct Scattered {
 
 pub struct MaterialDescriptor {
     pub label: Option<String>,
-    pub material: Box<dyn BxDF + Send + Sync>,
+    pub material: Box<dyn Material>,
+    /// Opacity of the material, read from the red channel of the texture.
+    ///
+    /// Where it is less than 1 the surface is stochastically cut out: rays go straight through it
+    /// without being shaded
+    pub alpha: Option<Box<dyn Texture>>,
+}
+
+impl MaterialDescriptor {
+    /// Whether a ray hitting the surface at `uv` passes through it, `u` should be sampled in [0;1)
+    pub fn is_cut_out(&self, uv: Uv, u: Sample1D) -> bool {
+        self.alpha
+            .as_ref()
+            .is_some_and(|alpha| u[0] >= alpha.color(uv).0[0])
+    }
 }
 
 impl std::fmt::Debug for MaterialDescriptor {
@@ -408,14 +678,23 @@ impl std::fmt::Debug for MaterialDescriptor {
         f.debug_struct("MaterialDescriptor")
             .field("label", &self.label)
             .field("material", &"<material>")
+            .field("alpha", &self.alpha.as_ref().map(|_| "<texture>"))
             .finish()
     }
 }
 
-#[derive(Debug, Clone)]
 pub struct LightDescriptor {
     pub label: Option<String>,
-    pub light_pos: Point,
+    pub light: Box<dyn Light>,
+}
+
+impl std::fmt::Debug for LightDescriptor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("LightDescriptor")
+            .field("label", &self.label)
+            .field("light", &"<light>")
+            .finish()
+    }
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -428,3 +707,278 @@ impl Deref for MaterialId {
         &self.0
     }
 }
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use glam::Vec3;
+    use rand::{Rng as _, SeedableRng};
+
+    use crate::{
+        color::{linear::WHITE, Rgb},
+        material::texture::Uniform,
+        math::{
+            distributions::{
+                tests::check_density, CosineHemisphere3, DirectionalPDF, Samplable, Samples,
+            },
+            vec::Vec3Ext,
+        },
+        memory::{Arena, ArenaInner},
+        Rng,
+    };
+
+    use super::{
+        BxDF, BxDFFlags, BxDFSample, DielectricBxDF, DiffuseBxDF, HemisphereSampling, Material,
+        PhongBxDF, TexturedDiffuse,
+    };
+
+    /// Check with a chi-squared test that the directions drawn by `sample_f` follow `pdf`.
+    ///
+    /// The specular lobes are ignored as they have no density
+    pub(crate) fn check_sampling(bxdf: &dyn BxDF, wo: Vec3) {
+        check_density(
+            &format!("wo = {wo}"),
+            |rng| {
+                let uv = Samples([rng.gen(), rng.gen()]);
+                let w = Samples([rng.gen()]);
+                bxdf.sample_f(wo, uv, w)
+                    .filter(|sample| !sample.flags.contains(BxDFFlags::Specular))
+                    .map(|sample| sample.wi)
+            },
+            |wi| bxdf.pdf(wo, wi),
+        );
+    }
+
+    /// Cosine weighted over the hemisphere of `wo`, or over the other one if `flipped` so that
+    /// the sampling disagrees with the pdf
+    struct CosineHemisphere {
+        flipped: bool,
+    }
+
+    impl BxDF for CosineHemisphere {
+        fn flags(&self) -> BxDFFlags {
+            BxDFFlags::Reflection | BxDFFlags::Diffusion
+        }
+        fn f(&self, _wo: Vec3, _wi: Vec3) -> Rgb {
+            WHITE
+        }
+        fn pdf(&self, wo: Vec3, wi: Vec3) -> f32 {
+            if wo.same_hemishpere(wi) {
+                CosineHemisphere3.pdf(wi.z.abs())
+            } else {
+                0.0
+            }
+        }
+        fn sample_f(&self, wo: Vec3, uv: Samples<2>, _w: Samples<1>) -> Option<BxDFSample> {
+            let mut wi = CosineHemisphere3.sample_with(uv);
+            wi.z = wi.z.copysign(if self.flipped { -wo.z } else { wo.z });
+            Some(BxDFSample {
+                wi,
+                f: WHITE,
+                pdf: CosineHemisphere3.pdf(wi.z.abs()),
+                flags: self.flags(),
+            })
+        }
+    }
+
+    #[test]
+    fn sampling_check() {
+        let wo = Vec3::new(0.3, 0.2, 0.9).normalize();
+        check_sampling(&CosineHemisphere { flipped: false }, wo);
+    }
+
+    #[test]
+    #[should_panic]
+    fn sampling_check_mismatch() {
+        let wo = Vec3::new(0.3, 0.2, 0.9).normalize();
+        check_sampling(&CosineHemisphere { flipped: true }, wo);
+    }
+
+    #[test]
+    fn diffuse_sampling() {
+        for sampling in [HemisphereSampling::Cosine, HemisphereSampling::Uniform] {
+            let bxdf = DiffuseBxDF {
+                albedo: [0.5, 0.5, 0.5].into(),
+                sampling,
+            };
+            check_sampling(&bxdf, Vec3::new(0.3, 0.2, 0.9).normalize());
+            check_sampling(&bxdf, Vec3::new(0.3, 0.2, -0.5).normalize());
+        }
+    }
+
+    #[test]
+    fn uniform_diffuse_sampling() {
+        // The light reflected toward wo under a sky brighter at the zenith, the exact value is
+        // albedo * (1 + 2/3)
+        let wo = Vec3::new(0.3, 0.2, 0.9).normalize();
+        let reflected = |sampling| {
+            let bxdf = DiffuseBxDF {
+                albedo: WHITE,
+                sampling,
+            };
+            let mut rng = Rng::seed_from_u64(4);
+            let samples = 100_000;
+            let (mut sum, mut sqsum) = (0.0, 0.0);
+            for _ in 0..samples {
+                let uv = Samples([rng.gen(), rng.gen()]);
+                let sample = bxdf.sample_f(wo, uv, Samples([rng.gen()])).unwrap();
+                assert_eq!(sample.pdf, bxdf.pdf(wo, sample.wi));
+                assert_eq!(sample.f.to_array(), bxdf.f(wo, sample.wi).to_array());
+                let estimate =
+                    sample.f.to_array()[0] * sample.wi.z * (1.0 + sample.wi.z) / sample.pdf;
+                sum += estimate;
+                sqsum += estimate * estimate;
+            }
+            let mean = sum / samples as f32;
+            (mean, sqsum / samples as f32 - mean * mean)
+        };
+
+        let (cosine, cosine_variance) = reflected(HemisphereSampling::Cosine);
+        let (uniform, uniform_variance) = reflected(HemisphereSampling::Uniform);
+        for mean in [cosine, uniform] {
+            assert!((mean - 5.0 / 3.0).abs() < 0.01, "{mean}");
+        }
+        assert!(
+            uniform_variance > 2.0 * cosine_variance,
+            "{uniform_variance} <= 2 * {cosine_variance}"
+        );
+    }
+
+    #[test]
+    fn phong_sampling() {
+        let bxdf = PhongBxDF {
+            diffuse: [0.3, 0.2, 0.1].into(),
+            specular: [0.5, 0.5, 0.5].into(),
+            exponent: 20.0,
+        };
+        let mut rng = Rng::seed_from_u64(2);
+        for wo in [
+            Vec3::new(0.3, 0.2, 0.9),
+            Vec3::new(0.8, -0.1, 0.2),
+            Vec3::new(0.3, 0.2, -0.5),
+        ] {
+            let wo = wo.normalize();
+            check_sampling(&bxdf, wo);
+
+            for _ in 0..1000 {
+                let uv = Samples([rng.gen(), rng.gen()]);
+                let Some(sample) = bxdf.sample_f(wo, uv, Samples([rng.gen()])) else {
+                    continue;
+                };
+                assert!(sample.wi.same_hemishpere(wo));
+                assert_eq!(sample.pdf, bxdf.pdf(wo, sample.wi));
+            }
+        }
+    }
+
+    #[test]
+    fn bxdf_in_arena() {
+        let albedo = [0.2, 0.5, 0.7].into();
+        let stack = DiffuseBxDF {
+            albedo,
+            sampling: HemisphereSampling::Cosine,
+        };
+        let material = TexturedDiffuse {
+            albedo: Box::new(Uniform(albedo)),
+        };
+        let inner = ArenaInner::new(16);
+        let arena = Arena::new(&inner);
+        // More BxDFs than the first block of the arena can hold
+        let bxdfs = (0..8)
+            .map(|_| material.bxdf(&arena, [0.3, 0.6]))
+            .collect::<Vec<_>>();
+
+        let wo = Vec3::new(0.3, 0.2, 0.9).normalize();
+        let mut rng = Rng::seed_from_u64(0);
+        for bxdf in bxdfs {
+            let u: [f32; 3] = rng.gen();
+            let expected = stack
+                .sample_f(wo, Samples([u[0], u[1]]), Samples([u[2]]))
+                .unwrap();
+            let sample = bxdf
+                .sample_f(wo, Samples([u[0], u[1]]), Samples([u[2]]))
+                .unwrap();
+            assert_eq!(sample.wi, expected.wi);
+            assert_eq!(sample.f.to_array(), expected.f.to_array());
+            assert_eq!(sample.pdf, expected.pdf);
+            assert_eq!(
+                bxdf.f(wo, sample.wi).to_array(),
+                stack.f(wo, sample.wi).to_array()
+            );
+        }
+    }
+
+    #[test]
+    fn rough_dielectric_sampling() {
+        let bxdf = DielectricBxDF {
+            ior: 1.5,
+            roughness: 0.5,
+            transmittance_color: WHITE,
+        };
+        check_sampling(&bxdf, Vec3::new(0.3, 0.2, 0.9).normalize());
+        check_sampling(&bxdf, Vec3::new(0.3, 0.2, -0.9).normalize());
+    }
+
+    /// Past the critical angle, the light inside the glass is reflected instead of being lost
+    #[test]
+    fn total_internal_reflection() {
+        // 60° from the normal, the critical angle is about 42°
+        let wo = Vec3::new(0.75f32.sqrt(), 0.0, -0.5);
+        let mut rng = Rng::seed_from_u64(3);
+        for roughness in [0.0, 0.1] {
+            let bxdf = DielectricBxDF {
+                ior: 1.5,
+                roughness,
+                transmittance_color: WHITE,
+            };
+            let mut reflected = 0;
+            for _ in 0..1000 {
+                let uv = Samples([rng.gen(), rng.gen()]);
+                let Some(sample) = bxdf.sample_f(wo, uv, Samples([rng.gen()])) else {
+                    // Only the rough reflection may go under a microfacet
+                    assert!(roughness != 0.0, "the ray vanished");
+                    continue;
+                };
+                if sample.flags.contains(BxDFFlags::Reflection) {
+                    assert!(sample.wi.z < 0.0);
+                    reflected += 1;
+                }
+                if roughness == 0.0 {
+                    assert_eq!(sample.f.to_array()[0] * sample.wi.z.abs() / sample.pdf, 1.0);
+                }
+            }
+            // Some microfacets are tilted enough for the rough glass to let light through
+            let expected = if roughness == 0.0 { 1000 } else { 900 };
+            assert!(reflected >= expected, "{reflected} reflections");
+        }
+    }
+
+    /// Rough glass used to get a wrong half vector in `pdf` and `f`
+    #[test]
+    fn rough_dielectric_consistency() {
+        let bxdf = DielectricBxDF {
+            ior: 1.5,
+            roughness: 0.3,
+            transmittance_color: WHITE,
+        };
+        let mut rng = Rng::seed_from_u64(1);
+        for wo in [Vec3::new(0.5, -0.1, 0.6), Vec3::new(-0.2, 0.4, -0.7)] {
+            let wo = wo.normalize();
+            check_sampling(&bxdf, wo);
+
+            for _ in 0..1000 {
+                let uv = Samples([rng.gen(), rng.gen()]);
+                let Some(sample) = bxdf.sample_f(wo, uv, Samples([rng.gen()])) else {
+                    continue;
+                };
+                let pdf = bxdf.pdf(wo, sample.wi);
+                // Glass is white
+                let f = bxdf.f(wo, sample.wi).to_array()[0];
+                assert!((sample.pdf - pdf).abs() <= 1e-3 * pdf, "{sample:?} {pdf}");
+                assert!(
+                    (sample.f.to_array()[0] - f).abs() <= 1e-3 * f,
+                    "{sample:?} {f}"
+                );
+            }
+        }
+    }
+}
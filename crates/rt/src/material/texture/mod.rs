@@ -1,4 +1,9 @@
-use crate::color::Rgb;
+use image::Rgb32FImage;
+
+use crate::{
+    color::Rgb,
+    math::{distributions::Sample2D, vec::Vec2},
+};
 
 pub type Uv = [f32; 2];
 pub trait Texture: Sync + Send {
@@ -34,3 +39,175 @@ impl Texture for Checker {
         }
     }
 }
+
+/// Remaps the uv before looking up the inner texture, to tile or rotate it. The uv are scaled,
+/// then rotated by `rotation` radians around the origin, then offset
+pub struct TransformedTexture {
+    pub inner: Box<dyn Texture>,
+    pub scale: Vec2,
+    pub offset: Vec2,
+    pub rotation: f32,
+}
+
+impl TransformedTexture {
+    pub fn transform(&self, [u, v]: Uv) -> Uv {
+        let uv = Vec2::from_angle(self.rotation).rotate(self.scale * Vec2::new(u, v)) + self.offset;
+        uv.to_array()
+    }
+}
+
+impl Texture for TransformedTexture {
+    fn color(&self, uv: Uv) -> Rgb {
+        self.inner.color(self.transform(uv))
+    }
+}
+
+/// How an [ImageTexture] is filtered over the footprint of a lookup
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Filtering {
+    /// The nearest texel, whatever the footprint
+    #[default]
+    Point,
+    /// The nearest texel to a point picked at random in the footprint: the texture is box
+    /// filtered by the samples of the pixel, the aliasing turns into noise without a mip pyramid
+    Stochastic,
+}
+
+/// A texture read from an image in linear RGB, repeated outside of [0, 1]². The v axis goes up
+/// the image
+pub struct ImageTexture {
+    pub image: Rgb32FImage,
+    pub filtering: Filtering,
+}
+
+impl ImageTexture {
+    fn texel(&self, [u, v]: Uv) -> Rgb {
+        let (width, height) = self.image.dimensions();
+        let x = (u.rem_euclid(1.0) * width as f32) as u32;
+        let y = ((1.0 - v.rem_euclid(1.0)) * height as f32) as u32;
+        Rgb::from_array(self.image.get_pixel(x.min(width - 1), y.min(height - 1)).0)
+    }
+
+    /// The color of the texture over the footprint of the lookup, the extent in uv around `uv`
+    /// that the ray covers, with `sample` to jitter the lookup
+    pub fn filtered_color(&self, uv: Uv, footprint: Vec2, sample: Sample2D) -> Rgb {
+        match self.filtering {
+            Filtering::Point => self.texel(uv),
+            Filtering::Stochastic => {
+                let jitter = (Vec2::from_array(sample.0) - 0.5) * footprint;
+                self.texel([uv[0] + jitter.x, uv[1] + jitter.y])
+            }
+        }
+    }
+}
+
+impl Texture for ImageTexture {
+    /// Without a footprint, this is point sampling
+    fn color(&self, uv: Uv) -> Rgb {
+        self.texel(uv)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::Rgb32FImage;
+    use rand::{Rng, SeedableRng};
+
+    use crate::{
+        color::linear::{BLACK, WHITE},
+        math::{distributions::Samples, vec::Vec2},
+    };
+
+    use super::{Checker, Filtering, ImageTexture, Texture, TransformedTexture, Uniform};
+
+    fn checker() -> Box<dyn Texture> {
+        Box::new(Checker {
+            odd: Box::new(Uniform(BLACK)),
+            even: Box::new(Uniform(WHITE)),
+        })
+    }
+
+    /// Number of changes of color along the diagonal of [0, 1]²
+    fn transitions(texture: &dyn Texture) -> usize {
+        let colors = (0..1000)
+            .map(|i| {
+                let x = (i as f32 + 0.5) / 1000.0;
+                texture.color([x, 0.3 * x + 0.01]).to_array()
+            })
+            .collect::<Vec<_>>();
+        colors.windows(2).filter(|w| w[0] != w[1]).count()
+    }
+
+    #[test]
+    fn transformed_texture() {
+        let identity = TransformedTexture {
+            inner: checker(),
+            scale: Vec2::ONE,
+            offset: Vec2::ZERO,
+            rotation: 0.0,
+        };
+        let reference = checker();
+        for i in 0..100 {
+            let uv = [i as f32 / 100.0, (i * 37 % 100) as f32 / 100.0];
+            assert_eq!(identity.transform(uv), uv);
+            assert_eq!(
+                identity.color(uv).to_array(),
+                reference.color(uv).to_array()
+            );
+        }
+
+        let scaled = TransformedTexture {
+            scale: Vec2::splat(2.0),
+            ..identity
+        };
+        let (base, doubled) = (transitions(reference.as_ref()), transitions(&scaled));
+        assert!(base > 0);
+        assert!(doubled.abs_diff(2 * base) <= 1, "{doubled} != 2 * {base}");
+
+        let rotated = TransformedTexture {
+            inner: checker(),
+            scale: Vec2::ONE,
+            offset: Vec2::new(0.5, 0.0),
+            rotation: std::f32::consts::FRAC_PI_2,
+        };
+        let [u, v] = rotated.transform([1.0, 0.0]);
+        assert!((u - 0.5).abs() < 1e-6 && (v - 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn stochastic_filtering() {
+        // A checkerboard of one texel squares
+        let image = Rgb32FImage::from_fn(16, 16, |x, y| image::Rgb([((x + y) % 2) as f32; 3]));
+        let point = ImageTexture {
+            image: image.clone(),
+            filtering: Filtering::Point,
+        };
+        let stochastic = ImageTexture {
+            image,
+            filtering: Filtering::Stochastic,
+        };
+
+        let mut rng = crate::Rng::seed_from_u64(0);
+        for _ in 0..1000 {
+            let uv = [rng.gen_range(-1.0..2.0), rng.gen_range(-1.0..2.0)];
+            let sample = Samples([rng.gen(), rng.gen()]);
+            assert_eq!(
+                stochastic.filtered_color(uv, Vec2::ZERO, sample).to_array(),
+                point.color(uv).to_array()
+            );
+        }
+
+        // Over many texels, the samples average to the mean of the texture
+        let samples = 10000;
+        let mean = (0..samples)
+            .map(|_| {
+                let sample = Samples([rng.gen(), rng.gen()]);
+                stochastic
+                    .filtered_color([0.5, 0.5], Vec2::splat(0.5), sample)
+                    .to_array()[0]
+            })
+            .sum::<f32>()
+            / samples as f32;
+        assert!((mean - 0.5).abs() < 0.02, "{mean}");
+    }
+}
@@ -14,6 +14,12 @@ pub struct Bounds {
 }
 
 impl Bounds {
+    /// The bounds containing nothing, the identity of [Bounds::union]
+    pub const EMPTY: Bounds = Bounds {
+        origin: Point(Vec3::INFINITY),
+        end: Point(Vec3::NEG_INFINITY),
+    };
+
     pub fn new(origin: Point, end: Point) -> Self {
         assert!(AABBPointOrder(origin) <= AABBPointOrder(end));
 
@@ -90,15 +96,80 @@ impl Bounds {
         }
     }
 
+    /// The range of t, clipped to the bounds of the ray, for which the ray is inside the bounds.
+    ///
+    /// `inv_dir` is the inverse of the direction of the ray, that can be computed once for many
+    /// boxes. A ray parallel to a slab gets infinite ts, or NaN if it lies exactly on the plane of
+    /// the slab, in which case it is considered inside.
+    pub fn intersect_ray(&self, ray: &Ray, inv_dir: Vec3) -> Option<(f32, f32)> {
+        let t0 = (self.origin - ray.origin) * inv_dir;
+        let t1 = (self.end - ray.origin) * inv_dir;
+
+        let on_slab = t0.is_nan_mask() | t1.is_nan_mask();
+        let near = Vec3::select(on_slab, Vec3::NEG_INFINITY, t0.min(t1));
+        let far = Vec3::select(on_slab, Vec3::INFINITY, t0.max(t1));
+
+        let t_min = near.max_element().max(ray.bounds.0);
+        let t_max = far.min_element().min(ray.bounds.1);
+        (t_min <= t_max).then_some((t_min, t_max))
+    }
+
     pub fn from_bounds(x: Bounds, y: Bounds) -> Bounds {
-        Self {
-            origin: Point(x.origin.vec().min(y.origin.vec())),
-            end: Point(x.end.vec().max(y.end.vec())),
-        }
+        x.union(y)
     }
     pub fn diag(&self) -> Vec3 {
         self.end - self.origin
     }
+
+    pub fn is_empty(&self) -> bool {
+        self.origin.vec().cmpgt(self.end.vec()).any()
+    }
+
+    /// The smallest bounds containing both bounds
+    pub fn union(self, other: Bounds) -> Bounds {
+        Self {
+            origin: Point(self.origin.vec().min(other.origin.vec())),
+            end: Point(self.end.vec().max(other.end.vec())),
+        }
+    }
+
+    /// The smallest bounds containing the bounds and the point
+    pub fn union_point(self, point: Point) -> Bounds {
+        Self {
+            origin: Point(self.origin.vec().min(point.vec())),
+            end: Point(self.end.vec().max(point.vec())),
+        }
+    }
+
+    pub fn centroid(&self) -> Point {
+        Point((self.origin.vec() + self.end.vec()) / 2.0)
+    }
+
+    pub fn surface_area(&self) -> f32 {
+        if self.is_empty() {
+            return 0.0;
+        }
+        let d = self.diag();
+        2.0 * (d.x * d.y + d.y * d.z + d.z * d.x)
+    }
+
+    /// The index of the axis along which the bounds are the widest
+    pub fn longest_axis(&self) -> usize {
+        let d = self.diag();
+        if d.x >= d.y && d.x >= d.z {
+            0
+        } else if d.y >= d.z {
+            1
+        } else {
+            2
+        }
+    }
+
+    /// Whether the two bounds share at least a point, touching counts
+    pub fn overlaps(&self, other: &Bounds) -> bool {
+        self.origin.vec().cmple(other.end.vec()).all()
+            && other.origin.vec().cmple(self.end.vec()).all()
+    }
 }
 
 /// A private type that allows for custom order on points
@@ -137,4 +208,63 @@ mod tests {
         assert!(b.ray_intersect(&ray).unwrap().start.abs() < 0.01);
         assert!((b.ray_intersect(&ray).unwrap().end - 1.0).abs() < 0.01);
     }
+
+    #[test]
+    fn union() {
+        let a = Bounds::new(Point::new(0.0, 0.0, 0.0), Point::new(1.0, 1.0, 1.0));
+        let b = Bounds::new(Point::new(2.0, -1.0, 0.5), Point::new(3.0, 0.0, 2.0));
+        let u = a.union(b);
+
+        assert!(!a.overlaps(&b));
+        assert!(u.overlaps(&a) && u.overlaps(&b));
+        assert_eq!(u.origin, Point::new(0.0, -1.0, 0.0));
+        assert_eq!(u.end, Point::new(3.0, 1.0, 2.0));
+        assert_eq!(u.longest_axis(), 0);
+        assert_eq!(u.centroid(), Point::new(1.5, 0.0, 1.0));
+
+        let empty = Bounds::EMPTY;
+        assert!(empty.is_empty());
+        assert_eq!(empty.surface_area(), 0.0);
+        assert_eq!(empty.union(a).origin, a.origin);
+        assert_eq!(empty.union(a).end, a.end);
+        let p = empty.union_point(Point::new(1.0, 2.0, 3.0));
+        assert_eq!(
+            (p.origin, p.end),
+            (Point::new(1.0, 2.0, 3.0), Point::new(1.0, 2.0, 3.0))
+        );
+    }
+
+    #[test]
+    fn surface_area() {
+        let cube = Bounds::new(Point::ORIGIN, Point::new(1.0, 1.0, 1.0));
+        assert_eq!(cube.surface_area(), 6.0);
+    }
+
+    #[test]
+    fn intersect_ray() {
+        let b = Bounds::new(Point::new(-1.0, -1.0, -3.0), Point::new(1.0, 1.0, -1.0));
+        let intersect = |origin, direction: Vec3| {
+            let ray = Ray::new(origin, direction.normalize());
+            b.intersect_ray(&ray, ray.direction.recip())
+        };
+
+        assert_eq!(intersect(Point::ORIGIN, -Vec3::Z), Some((1.0, 3.0)));
+        assert_eq!(intersect(Point::ORIGIN, Vec3::Z), None);
+        assert_eq!(intersect(Point::ORIGIN, Vec3::new(1.0, 0.0, -0.1)), None);
+        assert_eq!(
+            intersect(Point::new(0.0, 0.0, -2.0), Vec3::X),
+            Some((0.0, 1.0))
+        );
+
+        // Parallel to the x and y slabs, inside them, outside them and on their boundary
+        assert_eq!(
+            intersect(Point::new(0.5, 0.5, 0.0), -Vec3::Z),
+            Some((1.0, 3.0))
+        );
+        assert_eq!(intersect(Point::new(2.0, 0.5, 0.0), -Vec3::Z), None);
+        assert_eq!(
+            intersect(Point::new(1.0, -1.0, 0.0), -Vec3::Z),
+            Some((1.0, 3.0))
+        );
+    }
 }
@@ -136,10 +136,14 @@ impl Distribution<Vec3> for UniformUnitSphere3 {
     }
 }
 
+/// The spherical mapping of the textures of the spheres and of the world material, `direction`
+/// being normalized. `v` goes from 0 at +Y to 1 at -Y and `u` turns around Y from 0 at -Z, through
+/// -X at 0.25, +Z at 0.5 and +X at 0.75; the seam is at -Z, where `u` is always 0.
+/// Exactly at the poles, `u` is 0.5.
 pub fn sphere_uv_from_direction(direction: Vec3) -> Uv {
-    let h = direction.dot(Vec3::Y);
-    let a = (direction - (h * Vec3::Y)).normalize();
-    let u = 0.5 + f32::atan2(a.x, a.z) / std::f32::consts::TAU;
+    let h = direction.y.clamp(-1.0, 1.0);
+    // atan2 is still defined at the poles, where x = z = 0
+    let u = (0.5 + f32::atan2(direction.x, direction.z) / std::f32::consts::TAU).rem_euclid(1.0);
     let v = f32::acos(h) / std::f32::consts::PI;
 
     [u, v]
@@ -162,7 +166,7 @@ impl Samplable<Vec3, 2> for UniformHemisphere3 {
 }
 impl DirectionalPDF for UniformHemisphere3 {
     fn pdf(&self, _costheta: f32) -> f32 {
-        f32::consts::FRAC_1_PI
+        0.5 * f32::consts::FRAC_1_PI
     }
 }
 
@@ -215,8 +219,13 @@ impl IsotropicTrowbridgeReitzDistribution {
         let cos2theta = wm.z * wm.z;
         let sin2theta = 1.0 - cos2theta;
         let tan2theta = sin2theta / cos2theta;
+        if tan2theta.is_infinite() {
+            return 0.0;
+        }
 
-        1.0 / (f32::consts::PI * cos2theta.powi(2) * (self.alpha + tan2theta).powi(2))
+        let alpha2 = self.alpha.powi(2);
+        let e = tan2theta / alpha2;
+        1.0 / (f32::consts::PI * alpha2 * cos2theta.powi(2) * (1.0 + e).powi(2))
     }
     pub fn g(&self, w0: Vec3, w1: Vec3) -> f32 {
         1.0 / (1.0 + self.lambda(w0) + self.lambda(w1))
@@ -228,8 +237,11 @@ impl IsotropicTrowbridgeReitzDistribution {
         let cos2theta = w.z * w.z;
         let sin2theta = 1.0 - cos2theta;
         let tan2theta = sin2theta / cos2theta;
+        if tan2theta.is_infinite() {
+            return 0.0;
+        }
 
-        (f32::sqrt(1.0 + self.alpha.powi(2) * tan2theta)) / 2.0
+        (f32::sqrt(1.0 + self.alpha.powi(2) * tan2theta) - 1.0) / 2.0
     }
 
     pub fn pdf(&self, w: Vec3, wm: Vec3) -> f32 {
@@ -263,3 +275,144 @@ impl IsotropicTrowbridgeReitzDistribution {
         Vec3::new(self.alpha * nh.x, self.alpha * nh.y, f32::max(1e-6, nh.z)).normalize()
     }
 }
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::f64::consts::{PI, TAU};
+
+    use glam::Vec3;
+    use rand::{Rng as _, SeedableRng};
+
+    use crate::Rng;
+
+    use super::{sphere_uv_from_direction, IsotropicTrowbridgeReitzDistribution, Samples};
+
+    const THETA_BINS: usize = 16;
+    const PHI_BINS: usize = 32;
+    /// Each bin is subdivided to integrate the pdf over it
+    const SUBDIVISIONS: usize = 16;
+    const SAMPLES: usize = 200_000;
+
+    /// The bins are uniform in cos theta and phi, so that they all have the same solid angle
+    fn bin(w: Vec3) -> usize {
+        let theta = ((w.z + 1.0) / 2.0 * THETA_BINS as f32) as usize;
+        let phi = (w.y.atan2(w.x) / std::f32::consts::TAU).rem_euclid(1.0) * PHI_BINS as f32;
+        theta.min(THETA_BINS - 1) * PHI_BINS + (phi as usize).min(PHI_BINS - 1)
+    }
+
+    /// Check with a chi-squared test that the directions drawn by `sample` follow the density
+    /// `pdf` over the sphere. `sample` may draw nothing, by a probability of one minus the
+    /// integral of `pdf`
+    pub(crate) fn check_density(
+        label: &str,
+        mut sample: impl FnMut(&mut Rng) -> Option<Vec3>,
+        pdf: impl Fn(Vec3) -> f32,
+    ) {
+        let mut rng = Rng::seed_from_u64(0);
+        let mut observed = vec![0.0f64; THETA_BINS * PHI_BINS];
+        for _ in 0..SAMPLES {
+            if let Some(w) = sample(&mut rng) {
+                observed[bin(w)] += 1.0
+            }
+        }
+
+        let cell_solid_angle = 4.0 * PI / (THETA_BINS * PHI_BINS * SUBDIVISIONS.pow(2)) as f64;
+        let expected = (0..THETA_BINS * PHI_BINS).map(|bin| {
+            let (theta, phi) = ((bin / PHI_BINS) as f64, (bin % PHI_BINS) as f64);
+            let mut integral = 0.0;
+            for i in 0..SUBDIVISIONS {
+                for j in 0..SUBDIVISIONS {
+                    let sub = |x: usize| (x as f64 + 0.5) / SUBDIVISIONS as f64;
+                    let z = -1.0 + 2.0 * (theta + sub(i)) / THETA_BINS as f64;
+                    let phi = TAU * (phi + sub(j)) / PHI_BINS as f64;
+                    let r = (1.0 - z * z).sqrt();
+                    let wi = Vec3::new((r * phi.cos()) as f32, (r * phi.sin()) as f32, z as f32);
+                    integral += pdf(wi) as f64 * cell_solid_angle;
+                }
+            }
+            integral * SAMPLES as f64
+        });
+
+        // The bins expecting few samples are pooled together
+        let (mut chi2, mut dof) = (0.0, 0);
+        let (mut pooled_observed, mut pooled_expected) = (0.0, 0.0);
+        for (observed, expected) in observed.iter().zip(expected) {
+            if expected < 5.0 {
+                pooled_observed += observed;
+                pooled_expected += expected;
+            } else {
+                chi2 += (observed - expected).powi(2) / expected;
+                dof += 1;
+            }
+        }
+        if pooled_expected > 0.0 {
+            chi2 += (pooled_observed - pooled_expected).powi(2) / pooled_expected;
+            dof += 1;
+        }
+        assert!(
+            pooled_expected > 0.0 || pooled_observed < 5.0,
+            "{pooled_observed} samples where the pdf is 0, {label}"
+        );
+
+        // Wilson–Hilferty approximation of the chi-squared distribution
+        let k = dof as f64;
+        let z = ((chi2 / k).cbrt() - (1.0 - 2.0 / (9.0 * k))) / (2.0 / (9.0 * k)).sqrt();
+        assert!(
+            z < 4.0,
+            "chi2 = {chi2} for {dof} degrees of freedom, {label}"
+        );
+    }
+
+    /// The visible normals are drawn with the density `g1(w) / cos theta * d(wm) * max(0, w . wm)`
+    #[test]
+    fn visible_normals_sampling() {
+        for alpha in [0.2, 0.5, 0.9] {
+            let distrib = IsotropicTrowbridgeReitzDistribution { alpha };
+            for w in [
+                Vec3::Z,
+                Vec3::new(0.5, 0.0, 0.8),
+                Vec3::new(-0.6, 0.7, 0.3),
+                // Closer to the horizon, the pdf is not integrated finely enough over the bins
+                // at the pole
+                Vec3::new(0.9, 0.1, 0.25),
+            ] {
+                let w = w.normalize();
+                check_density(
+                    &format!("alpha = {alpha}, w = {w}"),
+                    |rng| Some(distrib.sample_wm(w, Samples([rng.gen(), rng.gen()]))),
+                    |wm| {
+                        if wm.z <= 0.0 || w.dot(wm) <= 0.0 {
+                            0.0
+                        } else {
+                            distrib.pdf(w, wm)
+                        }
+                    },
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn sphere_uv() {
+        let cases = [
+            (Vec3::Y, [0.5, 0.0]),
+            (Vec3::NEG_Y, [0.5, 1.0]),
+            (Vec3::NEG_Z, [0.0, 0.5]),
+            (Vec3::NEG_X, [0.25, 0.5]),
+            (Vec3::Z, [0.5, 0.5]),
+            (Vec3::X, [0.75, 0.5]),
+        ];
+        for (direction, expected) in cases {
+            let uv = sphere_uv_from_direction(direction);
+            for (c, e) in uv.into_iter().zip(expected) {
+                assert!((c - e).abs() < 1e-6, "{direction} gives {uv:?}");
+            }
+        }
+        // Both sides of the seam
+        for x in [1e-7, -1e-7, 0.0, -0.0] {
+            let [u, _] = sphere_uv_from_direction(Vec3::new(x, 0.0, -1.0));
+            assert!(!(1e-6..=1.0 - 1e-6).contains(&u), "{x} gives {u}");
+            assert!((0.0..1.0).contains(&u));
+        }
+    }
+}
@@ -25,3 +25,54 @@ impl From<LookAt> for Quat {
         }
     }
 }
+
+/// Normalized linear interpolation along the shortest path from `a` to `b`. Cheaper than
+/// [slerp] but its angular speed is not constant
+pub fn nlerp(a: Quat, b: Quat, t: f32) -> Quat {
+    let b = if a.dot(b) < 0.0 { -b } else { b };
+    (a * (1.0 - t) + b * t).normalize()
+}
+
+/// Spherical linear interpolation, at constant angular speed along the shortest path from `a` to
+/// `b`
+pub fn slerp(a: Quat, b: Quat, t: f32) -> Quat {
+    // q and -q are the same rotation, interpolating toward the other one is the long way around
+    let (b, cos) = match a.dot(b) {
+        cos if cos < 0.0 => (-b, -cos),
+        cos => (b, cos),
+    };
+    // The rotations are too close for the sine of the angle between them to be precise
+    if cos > 0.9995 {
+        return nlerp(a, b, t);
+    }
+
+    let angle = cos.acos();
+    (a * ((1.0 - t) * angle).sin() + b * (t * angle).sin()) / angle.sin()
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::{Quat, Vec3};
+
+    use super::{nlerp, slerp};
+
+    #[test]
+    fn slerp_halfway() {
+        let a = Quat::from_rotation_y(0.2);
+        let b = Quat::from_rotation_y(1.4);
+        assert!(slerp(a, b, 0.0).abs_diff_eq(a, 1e-6));
+        assert!(slerp(a, b, 1.0).abs_diff_eq(b, 1e-6));
+        assert!(slerp(a, b, 0.5).abs_diff_eq(Quat::from_rotation_y(0.8), 1e-6));
+
+        // -b is the same rotation as b, still the short way
+        assert!(slerp(a, -b, 0.5).abs_diff_eq(Quat::from_rotation_y(0.8), 1e-6));
+        let (axis, angle) = slerp(Quat::IDENTITY, Quat::from_rotation_x(3.0), 0.5).to_axis_angle();
+        assert!(axis.abs_diff_eq(Vec3::X, 1e-6));
+        assert!((angle - 1.5).abs() < 1e-5, "{angle}");
+
+        // Close rotations are interpolated linearly
+        let c = Quat::from_rotation_y(0.21);
+        assert!(slerp(a, c, 0.5).abs_diff_eq(Quat::from_rotation_y(0.205), 1e-6));
+        assert!(slerp(a, c, 0.5).abs_diff_eq(nlerp(a, c, 0.5), 1e-6));
+    }
+}
@@ -1,6 +1,6 @@
 use core::f32;
 
-use bytemuck::Zeroable;
+use bytemuck::{Pod, Zeroable};
 
 use crate::color::{Luma, Rgb};
 
@@ -48,28 +48,47 @@ impl VarianceSeries {
         }
 
         // This estimator is unbiased thx to the n - 1
-        (self.sqsum - self.sum * self.sum / self.count as f32) / (self.count as f32 - 1.0)
+        // Rounding errors can make it slightly negative when all the samples are the same
+        let v = (self.sqsum - self.sum * self.sum / self.count as f32) / (self.count as f32 - 1.0);
+        v.max(0.0)
     }
 
-    /// Returns the error $\varepsilon$ such that the real value is between $\left[\varepsilon-m, \varepsilon+m\right]$ with 95% confidence
+    /// Standard deviation of the mean of the samples
+    pub fn standard_error(&self) -> f32 {
+        (self.variance() / self.count as f32).sqrt()
+    }
+
+    /// Returns the error $\varepsilon$ such that the real value is between $\left[m-\varepsilon, m+\varepsilon\right]$ with 95% confidence
     ///
-    /// This assume the distibution follows a normal law. This is clearly false.
+    /// This assume the mean follows a normal law, which gets true as the number of samples grows.
     pub fn error_with_95_confidence(&self) -> Option<f32> {
-        let df = self.count - 1;
-        if df < 15 {
-            return None;
-        }
-
-        Some(STUDENT_5[df.min(30) - 15] * self.variance().sqrt())
+        let df = self.count.checked_sub(1).filter(|&df| df >= 1)?;
+        Some(student_95(df) * self.standard_error())
     }
 
     pub fn value(&self) -> f32 {
         self.mean()
     }
 
+    pub fn to_record(&self) -> SeriesRecord {
+        SeriesRecord {
+            count: self.count as u32,
+            sum: self.sum,
+            sqsum: self.sqsum,
+        }
+    }
+
+    pub fn from_record(record: SeriesRecord) -> Self {
+        Self {
+            count: record.count as usize,
+            sum: record.sum,
+            sqsum: record.sqsum,
+        }
+    }
+
     pub fn is_precise_enough(&self, abs_err: f32) -> Option<f32> {
         self.error_with_95_confidence().and_then(|err| {
-            if err < abs_err {
+            if err <= abs_err {
                 Some(self.mean())
             } else {
                 None
@@ -78,22 +97,53 @@ impl VarianceSeries {
     }
 }
 
+/// The plain data of a [VarianceSeries], to store it or send it away
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Zeroable, Pod)]
+pub struct SeriesRecord {
+    pub count: u32,
+    pub sum: f32,
+    pub sqsum: f32,
+}
+
 /// Some values of the student distribution
 ///
-/// Start with degree of freedom = 15
+/// Start with degree of freedom = 1
 ///
 /// Left and right values are the same as the student distribution is symetric,
 ///
 /// Generated with
 /// ```python
 /// from scipy import stats
-/// [stats.t(df=i).interval(0.95)[0] for i in range(15, 31)]
+/// [stats.t(df=i).interval(0.95)[1] for i in range(1, 31)]
 /// ```
-const STUDENT_5: [f32; 16] = [
-    2.131, 2.120, 2.110, 2.101, 2.093, 2.086, 2.080, 2.074, 2.069, 2.064, 2.060, 2.056, 2.052,
-    2.048, 2.045, 2.042,
+const STUDENT_5: [f32; 30] = [
+    12.706, 4.303, 3.182, 2.776, 2.571, 2.447, 2.365, 2.306, 2.262, 2.228, 2.201, 2.179, 2.160,
+    2.145, 2.131, 2.120, 2.110, 2.101, 2.093, 2.086, 2.080, 2.074, 2.069, 2.064, 2.060, 2.056,
+    2.052, 2.048, 2.045, 2.042,
 ];
 
+/// Half width of the 95% interval of the student distribution with `df` degrees of freedom
+///
+/// Past the table, the first term of the Cornish-Fisher expansion around the normal law is used
+fn student_95(df: usize) -> f32 {
+    const Z: f32 = 1.960;
+    match STUDENT_5.get(df - 1) {
+        Some(&t) => t,
+        None => Z + (Z * Z * Z + Z) / (4.0 * df as f32),
+    }
+}
+
+/// When a series of samples is considered to have converged
+#[derive(Debug, Clone, Copy)]
+pub struct Convergence {
+    /// Half width of the 95% confidence interval of the mean allowed, relative to the brightness
+    /// of the series
+    pub allowed_error: f32,
+    /// A series never converges with fewer samples, the variance can't be trusted before
+    pub min_samples: usize,
+}
+
 #[derive(Default, Clone)]
 pub struct RgbSeries {
     r: VarianceSeries,
@@ -112,7 +162,14 @@ impl RgbSeries {
         self.b.add_sample(rgb.0[2]);
     }
 
-    pub fn is_precise_enough(&self, abs_err: f32) -> Option<Rgb> {
+    /// The mean if each channel is known within the allowed error relative to the brightest one
+    pub fn is_precise_enough(&self, convergence: &Convergence) -> Option<Rgb> {
+        if self.r.count < convergence.min_samples {
+            return None;
+        }
+        let brightness = self.mean().to_array().into_iter().fold(0.0, f32::max);
+        let abs_err = convergence.allowed_error * brightness;
+
         let r = self.r.is_precise_enough(abs_err)?;
         let g = self.g.is_precise_enough(abs_err)?;
         let b = self.b.is_precise_enough(abs_err)?;
@@ -134,6 +191,25 @@ impl RgbSeries {
         // Hum... can we do better than that?
         Luma((r * r + g * g + b * b).sqrt())
     }
+    /// Variance of the mean of the series relative to its squared brightness. `epsilon` is added
+    /// to the squared brightness so that the dark series don't need to be infinitely precise
+    pub fn relative_variance_of_mean(&self, epsilon: f32) -> f32 {
+        let brightness = self.mean().to_array().into_iter().fold(0.0, f32::max);
+        self.variance().0 / self.r.count as f32 / (brightness * brightness + epsilon)
+    }
+
+    pub fn to_record(&self) -> [SeriesRecord; 3] {
+        [&self.r, &self.g, &self.b].map(VarianceSeries::to_record)
+    }
+
+    pub fn from_record([r, g, b]: [SeriesRecord; 3]) -> Self {
+        Self {
+            r: VarianceSeries::from_record(r),
+            g: VarianceSeries::from_record(g),
+            b: VarianceSeries::from_record(b),
+        }
+    }
+
     pub fn merge(lhs: Self, rhs: Self) -> Self {
         Self {
             r: VarianceSeries::merge(lhs.r, rhs.r),
@@ -174,6 +250,19 @@ impl FilteredRgb {
         self.rgb / self.sum_of_weigth
     }
 
+    /// The weighted sum of the colors, then the sum of the weights
+    pub fn to_record(&self) -> [f32; 4] {
+        let [r, g, b] = self.rgb.to_array();
+        [r, g, b, self.sum_of_weigth]
+    }
+
+    pub fn from_record([r, g, b, sum_of_weigth]: [f32; 4]) -> Self {
+        Self {
+            rgb: Rgb::from_array([r, g, b]),
+            sum_of_weigth,
+        }
+    }
+
     pub fn merge(self, rhs: Self) -> Self {
         Self {
             rgb: self.rgb + rhs.rgb,
@@ -181,3 +270,47 @@ impl FilteredRgb {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::color::Rgb;
+
+    use super::{Convergence, RgbSeries};
+
+    /// Number of samples of the stream taken before it is precise enough
+    fn samples_to_converge(
+        convergence: &Convergence,
+        mut stream: impl Iterator<Item = Rgb>,
+    ) -> usize {
+        let mut series = RgbSeries::new();
+        for n in 1..100_000 {
+            series.add_sample(stream.next().unwrap());
+            if series.is_precise_enough(convergence).is_some() {
+                return n;
+            }
+        }
+        panic!("the series never converged");
+    }
+
+    #[test]
+    fn convergence() {
+        let convergence = Convergence {
+            allowed_error: 0.05,
+            min_samples: 16,
+        };
+
+        // Mean 1 and standard deviation 0.5, the standard error of the mean is 0.5 / sqrt(n) so
+        // the interval is 1.96 * 0.5 / sqrt(n) <= 0.05 after about 384 samples
+        let alternating = (0..).map(|i| Rgb::from_array([1.0 + 0.5 * (-1.0f32).powi(i); 3]));
+        let n = samples_to_converge(&convergence, alternating);
+        assert!((380..395).contains(&n), "{n}");
+
+        // The error is relative to the brightness
+        let dim = (0..).map(|i| Rgb::from_array([0.1 + 0.05 * (-1.0f32).powi(i); 3]));
+        assert_eq!(samples_to_converge(&convergence, dim), n);
+
+        // Without any variance, only the minimum number of samples is needed
+        let constant = std::iter::repeat(Rgb::from_array([0.5, 0.2, 0.0]));
+        assert_eq!(samples_to_converge(&convergence, constant), 16);
+    }
+}
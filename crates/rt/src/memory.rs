@@ -1,12 +1,42 @@
 use std::{
     alloc::{Allocator, Layout},
-    cell::Cell,
+    cell::{Cell, RefCell},
     ops::Deref,
 };
 
-pub struct ArenaInner {
-    inner: *mut u8,
+/// A chunk of memory of the arena
+struct Block {
+    ptr: *mut u8,
     capacity: usize,
+}
+
+impl Block {
+    fn new(capacity: usize) -> Self {
+        Self {
+            ptr: unsafe { std::alloc::alloc(Layout::from_size_align(capacity, 1).unwrap()) },
+            capacity,
+        }
+    }
+
+    /// Offset of an allocation of `layout` starting at `cur` if it fits in the block
+    fn fit(&self, cur: usize, layout: Layout) -> Option<usize> {
+        let start = cur + unsafe { self.ptr.add(cur) }.align_offset(layout.align());
+        (start + layout.size() <= self.capacity).then_some(start)
+    }
+}
+
+impl Drop for Block {
+    fn drop(&mut self) {
+        unsafe { std::alloc::dealloc(self.ptr, Layout::from_size_align(self.capacity, 1).unwrap()) }
+    }
+}
+
+/// A bump allocator. When a block is full, the allocations go on in the next block of the chain,
+/// a new one twice as big is added once they are all full
+pub struct ArenaInner {
+    blocks: RefCell<Vec<Block>>,
+    /// The block being allocated from, and the offset of its free memory
+    current: Cell<usize>,
     cur: Cell<usize>,
 }
 
@@ -16,27 +46,23 @@ impl ArenaInner {
     pub fn new(capacity: usize) -> Self {
         assert!(capacity > 0);
         Self {
-            inner: unsafe { std::alloc::alloc(Layout::from_size_align(capacity, 1).unwrap()) },
-            capacity,
+            blocks: RefCell::new(vec![Block::new(capacity)]),
+            current: Cell::new(0),
             cur: Cell::new(0),
         }
     }
 
-    /// All allocations are invalidated when using this function
+    /// All allocations are invalidated when using this function. The blocks are kept to be
+    /// reused
     pub fn reuse(&mut self) -> &mut Self {
+        self.current.replace(0);
         self.cur.replace(0);
         self
     }
-}
 
-impl Drop for ArenaInner {
-    fn drop(&mut self) {
-        unsafe {
-            std::alloc::dealloc(
-                self.inner,
-                Layout::from_size_align(self.capacity, 1).unwrap(),
-            )
-        }
+    /// Total size of the blocks of the chain
+    pub fn capacity(&self) -> usize {
+        self.blocks.borrow().iter().map(|b| b.capacity).sum()
     }
 }
 
@@ -46,17 +72,26 @@ unsafe impl Allocator for ArenaInner {
             return Ok(std::ptr::NonNull::<[u8; 0]>::dangling());
         }
 
-        let padding = self.inner.align_offset(layout.align());
-        assert!(
-            self.cur.get() + padding + layout.size() <= self.capacity,
-            "OOM in arena"
-        );
-        self.cur.replace(self.cur.get() + padding);
+        let mut blocks = self.blocks.borrow_mut();
+        let mut start = blocks[self.current.get()].fit(self.cur.get(), layout);
+        while start.is_none() {
+            let next = self.current.get() + 1;
+            if next == blocks.len() {
+                let capacity = usize::max(
+                    2 * blocks[next - 1].capacity,
+                    layout.size() + layout.align(),
+                );
+                blocks.push(Block::new(capacity));
+            }
+            self.current.replace(next);
+            start = blocks[next].fit(0, layout);
+        }
+        let start = start.unwrap();
+        self.cur.replace(start + layout.size());
 
         let s = unsafe {
-            std::slice::from_raw_parts_mut(self.inner.add(self.cur.get()), layout.size())
+            std::slice::from_raw_parts_mut(blocks[self.current.get()].ptr.add(start), layout.size())
         };
-        self.cur.replace(self.cur.get() + layout.size());
 
         Ok(std::ptr::NonNull::new(s).unwrap())
     }
@@ -71,6 +106,11 @@ impl<'a> Arena<'a> {
     pub fn new(inner: &'a ArenaInner) -> Self {
         Self(inner)
     }
+
+    /// Move `value` in the arena, it lives until the arena is reused and is never dropped
+    pub fn alloc<T>(&self, value: T) -> &'a mut T {
+        Box::leak(Box::new_in(value, self.0))
+    }
 }
 
 impl Deref for Arena<'_> {
@@ -80,3 +120,39 @@ impl Deref for Arena<'_> {
         self.0
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{Arena, ArenaInner};
+
+    #[test]
+    fn growable() {
+        let mut inner = ArenaInner::new(64);
+        {
+            let arena = Arena::new(&inner);
+            // Way past the first block
+            let values = (0..100u64)
+                .map(|i| Box::new_in([i; 4], &*arena))
+                .collect::<Vec<_>>();
+            let big = Box::new_in([7u8; 1000], &*arena);
+            for (i, v) in values.iter().enumerate() {
+                assert_eq!(**v, [i as u64; 4]);
+                assert_eq!(v.as_ptr() as usize % std::mem::align_of::<u64>(), 0);
+            }
+            assert!(big.iter().all(|&x| x == 7));
+        }
+        let capacity = inner.capacity();
+        assert!(capacity >= 100 * 32 + 1000);
+
+        // The whole chain is reused, it does not grow anymore
+        inner.reuse();
+        {
+            let arena = Arena::new(&inner);
+            let _values = (0..100u64)
+                .map(|i| Box::new_in([i; 4], &*arena))
+                .collect::<Vec<_>>();
+            let _big = Box::new_in([7u8; 1000], &*arena);
+        }
+        assert_eq!(inner.capacity(), capacity);
+    }
+}
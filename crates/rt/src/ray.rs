@@ -4,6 +4,9 @@ use crate::math::point::Point;
 
 use super::math::vec::Vec3;
 
+/// Relative error allowed on a hit point, see [Ray::spawn]
+const SPAWN_EPSILON: f32 = 32.0 * f32::EPSILON;
+
 #[derive(Debug, Clone, Copy)]
 pub struct Ray {
     pub origin: Point,
@@ -30,6 +33,22 @@ impl Ray {
         }
     }
 
+    /// Spawn a ray leaving a surface at `pos`, where `normal` is the geometric normal.
+    ///
+    /// The origin is pushed along the normal, on the side the ray is leaving to, by an epsilon
+    /// that grows with the magnitude of `pos` so that the ray does not hit the surface it starts
+    /// from because of the rounding errors on the hit point.
+    pub fn spawn(pos: Point, normal: Vec3, direction: Vec3) -> Self {
+        let eps = SPAWN_EPSILON * (1.0 + pos.vec().abs().max_element());
+        let offset = if normal.dot(direction) >= 0.0 {
+            eps
+        } else {
+            -eps
+        } * normal;
+
+        Self::new(pos + offset, direction)
+    }
+
     pub fn range(&self) -> RangeInclusive<f32> {
         self.bounds.0..=self.bounds.1
     }
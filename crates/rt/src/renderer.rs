@@ -1,16 +1,25 @@
+use bytemuck::{Pod, Zeroable};
 use derive_more::derive::Display;
 
 use crate::{
     color::{self, Luma, Rgb},
-    material::{MaterialDescriptor, MaterialId},
+    light::Lights,
+    material::{BxDFFlags, MaterialDescriptor, MaterialId},
     math::{
         point::Point,
-        stat::{FilteredRgb, RgbSeries},
+        stat::{FilteredRgb, RgbSeries, SeriesRecord},
         vec::{RgbAsVec3Ext, Vec3, Vec3AsRgbExt},
     },
     shape::Shape,
+    utils::counter::counter,
 };
 
+/// World space position of the pixels where only the background is seen
+pub const ESCAPED_POSITION: f32 = f32::NAN;
+
+/// Object id of the pixels where only the background is seen
+pub const BACKGROUND_OBJECT_ID: f32 = -1.0;
+
 pub struct RayResult {
     pub normal: Vec3,
     pub position: Point,
@@ -19,6 +28,14 @@ pub struct RayResult {
     pub z: f32,
     pub ray_depth: f32,
     pub samples_accumulated: u32,
+    /// The camera ray left the scene without hitting anything
+    pub escaped: bool,
+    /// Id of the object hit by the camera ray
+    pub object: Option<u32>,
+    /// The lobe sampled at the first specular event of the path, as the integrator records it.
+    /// It tells whether the camera ray was reflected or transmitted by the glass it hit first. It
+    /// is None when there is none or when the path was split on it
+    pub first_specular: Option<BxDFFlags>,
 }
 
 #[derive(Clone, Default)]
@@ -26,15 +43,59 @@ pub struct RaySeries {
     pub samples_accumulated: u32,
     pub color: RgbSeries,
     pub filtered_color: FilteredRgb,
+    /// Sum of the positions of the first hits of the samples
     pub position: Point,
     pub normal: Vec3,
     pub albedo: Rgb,
     pub ray_depth: f32,
     pub z: f32,
+    /// Number of samples that escaped the scene
+    pub escaped: u32,
+    /// Id of the object hit by the first sample that hit something, ids can't be averaged
+    pub object: Option<u32>,
+    /// Number of samples accumulated in the AOVs: the position, normal, albedo, z and object
+    pub aov_samples: u32,
+    /// Number of the samples of the AOVs that escaped the scene
+    pub aov_escaped: u32,
+    /// Number of the samples of the AOVs reflected at their first specular event
+    pub reflected: u32,
+    /// Number of the samples of the AOVs transmitted at their first specular event
+    pub transmitted: u32,
+    /// Number of samples dropped because their color was not finite
+    pub rejected: u32,
+}
+
+/// The plain data of a [RaySeries], in a fixed layout of 4 bytes words to checkpoint a render or
+/// merge the renders of several machines
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Zeroable, Pod)]
+pub struct RaySeriesRecord {
+    pub samples_accumulated: u32,
+    pub color: [SeriesRecord; 3],
+    pub filtered_color: [f32; 4],
+    pub position: [f32; 3],
+    pub normal: [f32; 3],
+    pub albedo: [f32; 3],
+    pub ray_depth: f32,
+    pub z: f32,
+    pub escaped: u32,
+    /// [RaySeriesRecord::NO_OBJECT] when no object was hit
+    pub object: u32,
+    pub aov_samples: u32,
+    pub aov_escaped: u32,
+    pub reflected: u32,
+    pub transmitted: u32,
+    pub rejected: u32,
+}
+
+impl RaySeriesRecord {
+    pub const NO_OBJECT: u32 = u32::MAX;
 }
 
 impl RaySeries {
-    pub fn as_pixelresult(&self) -> PixelRenderResult {
+    /// With a transparent background, the alpha is the proportion of the samples that hit
+    /// something. Otherwise, it is 1 as soon as the pixel has been rendered.
+    pub fn as_pixelresult(&self, transparent_background: bool) -> PixelRenderResult {
         let RaySeries {
             position,
             normal,
@@ -44,43 +105,187 @@ impl RaySeries {
             z,
             ray_depth,
             samples_accumulated,
+            escaped,
+            object,
+            aov_samples,
+            aov_escaped,
+            reflected,
+            transmitted,
+            rejected: _,
         } = self;
 
-        let inv_samples = 1.0 / *samples_accumulated as f32;
+        // Pixels that were not rendered at all are left black and transparent
+        let (inv_samples, alpha) = if *samples_accumulated == 0 {
+            (0.0, 0.0)
+        } else if transparent_background {
+            let inv_samples = 1.0 / *samples_accumulated as f32;
+            (inv_samples, 1.0 - inv_samples * *escaped as f32)
+        } else {
+            (1.0 / *samples_accumulated as f32, 1.0)
+        };
+        let inv_aov_samples = if *aov_samples == 0 {
+            0.0
+        } else {
+            1.0 / *aov_samples as f32
+        };
+        // The position is only averaged over the samples that hit something
+        let hits = aov_samples - aov_escaped;
+        let position = if *aov_samples == 0 {
+            Vec3::ZERO
+        } else if hits == 0 {
+            Vec3::splat(ESCAPED_POSITION)
+        } else {
+            position.vec() / hits as f32
+        };
         PixelRenderResult {
             channels: vec![
-                RgbChannel::Normal.channel((inv_samples * *normal).rgb()),
-                RgbChannel::Position.channel((inv_samples * position.vec()).rgb()),
-                RgbChannel::Albedo.channel((inv_samples * albedo.vec()).rgb()),
+                RgbChannel::Normal.channel((inv_aov_samples * *normal).rgb()),
+                RgbChannel::Position.channel(position.rgb()),
+                RgbChannel::Albedo.channel((inv_aov_samples * albedo.vec()).rgb()),
                 RgbChannel::Color.channel(filtered_color.value()),
+                RgbChannel::Irradiance.channel(color::demodulate(
+                    filtered_color.value(),
+                    (inv_aov_samples * albedo.vec()).rgb(),
+                )),
+                RgbChannel::SpecularSplit.channel(Rgb::from_array([
+                    inv_aov_samples * *reflected as f32,
+                    inv_aov_samples * *transmitted as f32,
+                    0.0,
+                ])),
                 LumaChannel::Variance.channel(color.variance()),
-                LumaChannel::Z.channel(color::Luma(inv_samples * z)),
+                LumaChannel::Z.channel(color::Luma(inv_aov_samples * z)),
                 LumaChannel::RayDepth.channel(color::Luma(inv_samples * ray_depth)),
+                LumaChannel::Alpha.channel(color::Luma(alpha)),
+                LumaChannel::ObjectId.channel(color::Luma(
+                    object.map_or(BACKGROUND_OBJECT_ID, |id| id as f32),
+                )),
             ],
         }
     }
 
+    /// A NaN or an infinite color would poison the mean of the pixel forever, such samples are
+    /// dropped and counted
+    fn reject(&mut self, rhs: &RayResult) -> bool {
+        if rhs.color.vec().is_finite() {
+            return false;
+        }
+        counter!("Rejected non-finite samples");
+        self.rejected += 1;
+        true
+    }
+
     pub fn add_sample(&mut self, rhs: RayResult, weight: f32) {
+        if self.reject(&rhs) {
+            return;
+        }
         let RayResult {
             normal,
             position,
             albedo,
-            color,
             z,
-            ray_depth,
             samples_accumulated,
+            escaped,
+            object,
+            first_specular,
+            ..
         } = rhs;
 
-        self.color.add_sample(color);
-        self.filtered_color.add_sample(color, weight);
+        self.add_color_sample(rhs, weight);
         self.normal += normal;
         self.position = Point(self.position.vec() + position.vec());
         self.albedo = (self.albedo.vec() + albedo.vec()).rgb();
         self.z += z;
+        self.object = self.object.or(object);
+        self.aov_samples += samples_accumulated;
+        self.aov_escaped += escaped as u32;
+        if let Some(flags) = first_specular {
+            self.reflected += flags.contains(BxDFFlags::Reflection) as u32;
+            self.transmitted += flags.contains(BxDFFlags::Transmission) as u32;
+        }
+    }
+
+    /// Add the sample to the color only, the AOVs converge much faster than the color
+    pub fn add_color_sample(&mut self, rhs: RayResult, weight: f32) {
+        if self.reject(&rhs) {
+            return;
+        }
+        let RayResult {
+            color,
+            ray_depth,
+            samples_accumulated,
+            escaped,
+            ..
+        } = rhs;
+
+        self.color.add_sample(color);
+        self.filtered_color.add_sample(color, weight);
         self.ray_depth += ray_depth;
         self.samples_accumulated += samples_accumulated;
+        self.escaped += escaped as u32;
+    }
+
+    pub fn to_record(&self) -> RaySeriesRecord {
+        RaySeriesRecord {
+            samples_accumulated: self.samples_accumulated,
+            color: self.color.to_record(),
+            filtered_color: self.filtered_color.to_record(),
+            position: self.position.vec().to_array(),
+            normal: self.normal.to_array(),
+            albedo: self.albedo.to_array(),
+            ray_depth: self.ray_depth,
+            z: self.z,
+            escaped: self.escaped,
+            object: self.object.unwrap_or(RaySeriesRecord::NO_OBJECT),
+            aov_samples: self.aov_samples,
+            aov_escaped: self.aov_escaped,
+            reflected: self.reflected,
+            transmitted: self.transmitted,
+            rejected: self.rejected,
+        }
     }
 
+    pub fn from_record(record: &RaySeriesRecord) -> Self {
+        Self {
+            samples_accumulated: record.samples_accumulated,
+            color: RgbSeries::from_record(record.color),
+            filtered_color: FilteredRgb::from_record(record.filtered_color),
+            position: Point(Vec3::from_array(record.position)),
+            normal: Vec3::from_array(record.normal),
+            albedo: Rgb::from_array(record.albedo),
+            ray_depth: record.ray_depth,
+            z: record.z,
+            escaped: record.escaped,
+            object: (record.object != RaySeriesRecord::NO_OBJECT).then_some(record.object),
+            aov_samples: record.aov_samples,
+            aov_escaped: record.aov_escaped,
+            reflected: record.reflected,
+            transmitted: record.transmitted,
+            rejected: record.rejected,
+        }
+    }
+
+    /// The series as bytes, in native endianness
+    pub fn to_bytes(series: &[RaySeries]) -> Vec<u8> {
+        let records = series.iter().map(Self::to_record).collect::<Vec<_>>();
+        bytemuck::cast_slice(&records).to_vec()
+    }
+
+    /// The series given by [RaySeries::to_bytes], `None` if the length of `bytes` is not a
+    /// multiple of the size of a record
+    pub fn from_bytes(bytes: &[u8]) -> Option<Vec<RaySeries>> {
+        let size = std::mem::size_of::<RaySeriesRecord>();
+        if bytes.len() % size != 0 {
+            return None;
+        }
+        let series = bytes
+            .chunks_exact(size)
+            .map(|chunk| Self::from_record(&bytemuck::pod_read_unaligned(chunk)))
+            .collect();
+        Some(series)
+    }
+
+    /// Combines the series of two sets of samples of the same pixel, the variance included as it
+    /// comes from the sums and the sums of the squares
     pub fn merge(lhs: Self, rhs: Self) -> Self {
         Self {
             normal: lhs.normal + rhs.normal,
@@ -91,6 +296,13 @@ impl RaySeries {
             z: lhs.z + rhs.z,
             ray_depth: lhs.ray_depth + rhs.ray_depth,
             samples_accumulated: lhs.samples_accumulated + rhs.samples_accumulated,
+            escaped: lhs.escaped + rhs.escaped,
+            object: lhs.object.or(rhs.object),
+            aov_samples: lhs.aov_samples + rhs.aov_samples,
+            aov_escaped: lhs.aov_escaped + rhs.aov_escaped,
+            reflected: lhs.reflected + rhs.reflected,
+            transmitted: lhs.transmitted + rhs.transmitted,
+            rejected: lhs.rejected + rhs.rejected,
         }
     }
 }
@@ -105,6 +317,9 @@ impl Default for RayResult {
             z: 0.0,
             ray_depth: 0.0,
             samples_accumulated: 0,
+            escaped: false,
+            object: None,
+            first_specular: None,
         }
     }
 }
@@ -112,9 +327,14 @@ impl Default for RayResult {
 #[derive(Debug, Clone, Copy, Hash, Display, PartialEq, Eq)]
 pub enum RgbChannel {
     Color,
+    /// The color divided by the albedo, see [color::demodulate]
+    Irradiance,
     Position,
     Albedo,
     Normal,
+    /// Fraction of the samples reflected at their first specular event in red, and of the ones
+    /// transmitted in green
+    SpecularSplit,
 }
 
 impl RgbChannel {
@@ -131,6 +351,13 @@ pub enum LumaChannel {
     Variance,
     Z,
     RayDepth,
+    Alpha,
+    /// Id of the object seen in the pixel, [BACKGROUND_OBJECT_ID] if there is none
+    ObjectId,
+    /// Seconds spent intersecting the rays of the pixel, only given by a profiler
+    IntersectionTime,
+    /// Seconds spent on the pixel besides the intersections, only given by a profiler
+    ShadingTime,
 }
 impl LumaChannel {
     pub fn channel<RgbStorage, LumaStorage>(
@@ -153,7 +380,136 @@ pub type PixelRenderResult = GenericRenderResult<Rgb, Luma>;
 
 pub struct World<'a> {
     pub objects: &'a dyn Shape,
-    pub lights: &'a [Point],
+    pub lights: &'a Lights<'a>,
     pub materials: &'a [MaterialDescriptor],
     pub world_material: MaterialId,
+    pub fog: Option<GlobalFog>,
+}
+
+impl World<'_> {
+    /// Radiance at the start of a segment of length `t` given the radiance `l` at its end
+    pub fn attenuate(&self, l: Rgb, t: f32) -> Rgb {
+        match self.fog {
+            Some(fog) => fog.attenuate(l, t),
+            None => l,
+        }
+    }
+}
+
+/// A homogeneous fog filling the whole scene
+#[derive(Debug, Clone, Copy)]
+pub struct GlobalFog {
+    /// Extinction coefficient
+    pub sigma_t: f32,
+    /// Ambient radiance scattered by the fog toward the rays
+    pub color: Rgb,
+}
+
+impl GlobalFog {
+    /// The transmittance follows the Beer-Lambert law, what is not transmitted is replaced by the
+    /// color of the fog
+    pub fn attenuate(&self, l: Rgb, t: f32) -> Rgb {
+        if self.sigma_t == 0.0 {
+            return l;
+        }
+
+        let tr = f32::exp(-self.sigma_t * t);
+        tr * l + (1.0 - tr) * self.color
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{Rng, SeedableRng};
+
+    use crate::{
+        color::Rgb,
+        math::{point::Point, vec::Vec3},
+    };
+
+    use super::{RayResult, RaySeries, RaySeriesRecord};
+
+    #[test]
+    fn non_finite_samples_are_rejected() {
+        let sample = |color: [f32; 3]| RayResult {
+            color: Rgb::from_array(color),
+            samples_accumulated: 1,
+            ..Default::default()
+        };
+        let mut series = RaySeries::default();
+        series.add_sample(sample([1.0, 2.0, 3.0]), 1.0);
+        series.add_sample(sample([0.5, 1.0, 1.5]), 1.0);
+        let mean = series.color.mean().to_array();
+        let filtered = series.filtered_color.value().to_array();
+
+        series.add_sample(sample([f32::NAN, 0.0, 0.0]), 1.0);
+        series.add_color_sample(sample([0.0, f32::INFINITY, 0.0]), 1.0);
+        assert_eq!(series.color.mean().to_array(), mean);
+        assert_eq!(series.filtered_color.value().to_array(), filtered);
+        assert_eq!(series.samples_accumulated, 2);
+        assert_eq!(series.aov_samples, 2);
+        assert_eq!(series.rejected, 2);
+
+        let merged = RaySeries::merge(series.clone(), series);
+        assert_eq!(merged.rejected, 4);
+    }
+
+    #[test]
+    fn split_and_merged_series() {
+        let mut rng = crate::Rng::seed_from_u64(1);
+        let samples = (0..1000)
+            .map(|i| RayResult {
+                color: Rgb::from_array([rng.gen(), rng.gen::<f32>() * 4.0, 0.5]),
+                albedo: Rgb::from_array([0.2, 0.4, rng.gen()]),
+                normal: Vec3::Y,
+                position: Point::new(1.0, rng.gen(), 3.0),
+                z: rng.gen(),
+                ray_depth: 2.0,
+                samples_accumulated: 1,
+                escaped: i % 7 == 0,
+                object: (i % 7 != 0).then_some(3),
+                first_specular: None,
+            })
+            .collect::<Vec<_>>();
+        let accumulate = |samples: &[RayResult]| {
+            let mut series = RaySeries::default();
+            for sample in samples {
+                series.add_sample(RayResult { ..*sample }, 0.5);
+            }
+            series
+        };
+
+        let together = accumulate(&samples);
+        let (first, second) = samples.split_at(389);
+        // The partial series go through their serialization, as if rendered by other machines
+        let bytes = RaySeries::to_bytes(&[accumulate(first), accumulate(second)]);
+        assert_eq!(bytes.len(), 2 * std::mem::size_of::<RaySeriesRecord>());
+        let [first, second] = <[RaySeries; 2]>::try_from(RaySeries::from_bytes(&bytes).unwrap())
+            .ok()
+            .unwrap();
+        let merged = RaySeries::merge(first, second);
+
+        let (a, b) = (together.to_record(), merged.to_record());
+        assert_eq!(a.samples_accumulated, b.samples_accumulated);
+        assert_eq!(
+            (a.escaped, a.aov_escaped, a.object),
+            (b.escaped, b.aov_escaped, 3)
+        );
+        let close = |x: &[f32], y: &[f32]| {
+            x.iter()
+                .zip(y)
+                .all(|(x, y)| (x - y).abs() <= 1e-4 * x.abs().max(1.0))
+        };
+        assert!(close(&a.filtered_color, &b.filtered_color));
+        assert!(close(&a.position, &b.position));
+        assert!(close(&a.albedo, &b.albedo));
+        for (x, y) in a.color.iter().zip(&b.color) {
+            assert_eq!(x.count, y.count);
+            assert!(close(&[x.sum, x.sqsum], &[y.sum, y.sqsum]));
+        }
+        let variance = |series: &RaySeries| series.color.variance().0;
+        assert!((variance(&together) - variance(&merged)).abs() < 1e-4 * variance(&together));
+
+        assert!(RaySeries::from_bytes(&bytes[1..]).is_none());
+    }
 }
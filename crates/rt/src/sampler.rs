@@ -13,6 +13,86 @@ pub trait Sampler {
         u32::MAX
     }
     fn with_sample(&mut self, _sample: u32) {}
+
+    /// The antithetic sample of `u`, mirrored in the stratum it belongs to
+    fn mirror(&self, u: Vec2) -> Vec2 {
+        Vec2::ONE - u
+    }
+
+    /// Sample the dimensions allocated to `dimension`. A sampler that doesn't budget its
+    /// dimensions only samples the camera, the rest of the path is left to the rng
+    fn sample_dimension(&mut self, dimension: Dimension) -> Option<Vec2> {
+        match dimension {
+            Dimension::PixelOffset | Dimension::Lens => Some(self.sample_2d()),
+            _ => None,
+        }
+    }
+}
+
+/// What a sample of the path is used for. Each of them is drawn from its own 1d dimensions of
+/// the sample vector so that they are not correlated
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Dimension {
+    /// Position of the sample in the pixel
+    PixelOffset,
+    /// Position on the lens
+    Lens,
+    /// Direction sampled from the BxDF at a bounce
+    BxDF(u32),
+    /// Choice of the lobe of the BxDF at a bounce. Only uses its first dimension
+    Lobe(u32),
+    /// Choice of crossing a cut out surface at a bounce. Only uses its first dimension
+    AlphaCut(u32),
+    /// Point sampled on a light at a bounce
+    Light(u32),
+    /// Choice of the light sampled at a bounce. Only uses its first dimension
+    LightChoice(u32),
+}
+
+impl Dimension {
+    /// Number of 1d dimensions used by each bounce
+    const PER_BOUNCE: u32 = 7;
+
+    /// Index of the first of the two 1d dimensions of the sample
+    pub fn index(self) -> u32 {
+        let bounce = |depth: u32| 4 + Self::PER_BOUNCE * depth;
+        match self {
+            Dimension::PixelOffset => 0,
+            Dimension::Lens => 2,
+            Dimension::BxDF(depth) => bounce(depth),
+            Dimension::Lobe(depth) => bounce(depth) + 2,
+            Dimension::Light(depth) => bounce(depth) + 3,
+            Dimension::AlphaCut(depth) => bounce(depth) + 5,
+            Dimension::LightChoice(depth) => bounce(depth) + 6,
+        }
+    }
+
+    /// Number of 1d dimensions used by the sample
+    pub fn size(self) -> u32 {
+        match self {
+            Dimension::Lobe(_) | Dimension::AlphaCut(_) | Dimension::LightChoice(_) => 1,
+            _ => 2,
+        }
+    }
+}
+
+/// The 2d sample of `dimension`, drawn from `rng` if `sampler` doesn't budget it
+pub fn draw_2d(sampler: &mut dyn Sampler, rng: &mut crate::Rng, dimension: Dimension) -> [f32; 2] {
+    match sampler.sample_dimension(dimension) {
+        Some(u) => u.to_array(),
+        None => {
+            let uniform = Uniform::new(0.0, 1.0);
+            [uniform.sample(rng), uniform.sample(rng)]
+        }
+    }
+}
+
+/// The 1d sample of `dimension`, drawn from `rng` if `sampler` doesn't budget it
+pub fn draw_1d(sampler: &mut dyn Sampler, rng: &mut crate::Rng, dimension: Dimension) -> f32 {
+    match sampler.sample_dimension(dimension) {
+        Some(u) => u.x,
+        None => Uniform::new(0.0, 1.0).sample(rng),
+    }
 }
 
 /// Given a pixel coordinate (x, y), the sample is (x + 0.5, y + 0.5)
@@ -63,6 +143,49 @@ impl Sampler for UniformSampler {
     }
 }
 
+/// The position of `index` in a random permutation of `0..len` picked by `seed`, see
+/// "Correlated Multi-Jittered Sampling", Kensler 2013
+fn permute(index: u32, len: u32, seed: u32) -> u32 {
+    let mut w = len - 1;
+    w |= w >> 1;
+    w |= w >> 2;
+    w |= w >> 4;
+    w |= w >> 8;
+    w |= w >> 16;
+
+    // Cycle walking: the hash permutes the next power of two, until it lands in the range
+    let mut i = index;
+    loop {
+        i ^= seed;
+        i = i.wrapping_mul(0xe170893d);
+        i ^= seed >> 16;
+        i ^= (i & w) >> 4;
+        i ^= seed >> 8;
+        i = i.wrapping_mul(0x0929eb3f);
+        i ^= seed >> 23;
+        i ^= (i & w) >> 1;
+        i = i.wrapping_mul(1 | seed >> 27);
+        i = i.wrapping_mul(0x6935fa69);
+        i ^= (i & w) >> 11;
+        i = i.wrapping_mul(0x74dcb303);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0x9e501cc3);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0xc860a3df);
+        i &= w;
+        i ^= i >> 5;
+        if i < len {
+            break;
+        }
+    }
+    (i + seed) % len
+}
+
+/// Jittered samples, one in each stratum of a grid over the samples of a pixel.
+///
+/// Every [Dimension] of the path is stratified on its own: the strata are visited in a random
+/// order per pixel and per dimension, so that the dimensions are not correlated. The 1d ones are
+/// stratified in as many strata as there are samples
 #[derive(Clone)]
 pub struct StratifiedSampler {
     rng: crate::Rng,
@@ -109,4 +232,409 @@ impl Sampler for StratifiedSampler {
         self.sample = sample;
         self.rng = seed_rng(self.x, self.y, sample);
     }
+
+    fn mirror(&self, u: Vec2) -> Vec2 {
+        let strata = Vec2::new(self.samples_x as f32, self.samples_y as f32);
+        let stratum = (u * strata).floor().min(strata - 1.0);
+        ((2.0 * stratum + 1.0) / strata - u).clamp(Vec2::ZERO, Vec2::splat(ONE_MINUS_EPSILON))
+    }
+
+    fn sample_dimension(&mut self, dimension: Dimension) -> Option<Vec2> {
+        if dimension == Dimension::PixelOffset {
+            return Some(self.sample_2d());
+        }
+
+        let mut hasher = DefaultHasher::new();
+        (self.x, self.y, dimension.index()).hash(&mut hasher);
+        let count = self.sample_count();
+        let index = permute(self.sample % count, count, hasher.finish() as u32);
+        let (jitter_x, jitter_y) = (
+            self.uniform.sample(&mut self.rng),
+            self.uniform.sample(&mut self.rng),
+        );
+        let u = if dimension.size() == 1 {
+            Vec2::new((index as f32 + jitter_x) / count as f32, jitter_y)
+        } else {
+            Vec2::new(
+                ((index % self.samples_x) as f32 + jitter_x) / self.samples_x as f32,
+                ((index / self.samples_x) as f32 + jitter_y) / self.samples_y as f32,
+            )
+        };
+        Some(u.min(Vec2::splat(ONE_MINUS_EPSILON)))
+    }
+}
+
+/// Takes the samples of the inner sampler by pairs: the second sample of a pair draws the mirror
+/// of each point of the first one. For smooth integrands the errors of a pair cancel out
+#[derive(Clone)]
+pub struct AntitheticSampler<S> {
+    inner: S,
+    mirrored: bool,
+}
+
+impl<S: Sampler> AntitheticSampler<S> {
+    /// The inner sampler only sees half of the samples
+    pub fn new(inner: S) -> Self {
+        Self {
+            inner,
+            mirrored: false,
+        }
+    }
+}
+
+impl<S: Sampler> Sampler for AntitheticSampler<S> {
+    fn sample_2d(&mut self) -> Vec2 {
+        let u = self.inner.sample_2d();
+        if self.mirrored {
+            self.inner.mirror(u)
+        } else {
+            u
+        }
+    }
+
+    fn sample_count(&self) -> u32 {
+        self.inner.sample_count().saturating_mul(2)
+    }
+
+    fn with_sample(&mut self, sample: u32) {
+        self.inner.with_sample(sample / 2);
+        self.mirrored = sample % 2 == 1;
+    }
+
+    fn mirror(&self, u: Vec2) -> Vec2 {
+        self.inner.mirror(u)
+    }
+
+    fn sample_dimension(&mut self, dimension: Dimension) -> Option<Vec2> {
+        let u = self.inner.sample_dimension(dimension)?;
+        Some(if self.mirrored {
+            self.inner.mirror(u)
+        } else {
+            u
+        })
+    }
+}
+
+/// Number of dimensions of the Sobol sequence, from the direction numbers of Joe and Kuo
+const SOBOL_DIMENSIONS: usize = 16;
+
+/// `(s, a, m)` of the primitive polynomials of the dimensions after the first one, see
+/// "Constructing Sobol sequences with better two-dimensional projections", Joe & Kuo 2008
+const SOBOL_POLYNOMIALS: [(u32, u32, [u32; 6]); SOBOL_DIMENSIONS - 1] = [
+    (1, 0, [1, 0, 0, 0, 0, 0]),
+    (2, 1, [1, 3, 0, 0, 0, 0]),
+    (3, 1, [1, 3, 1, 0, 0, 0]),
+    (3, 2, [1, 1, 1, 0, 0, 0]),
+    (4, 1, [1, 1, 3, 3, 0, 0]),
+    (4, 4, [1, 3, 5, 13, 0, 0]),
+    (5, 2, [1, 1, 5, 5, 17, 0]),
+    (5, 4, [1, 1, 5, 5, 5, 0]),
+    (5, 7, [1, 1, 7, 11, 19, 0]),
+    (5, 11, [1, 1, 5, 1, 1, 0]),
+    (5, 13, [1, 1, 1, 3, 11, 0]),
+    (5, 14, [1, 3, 5, 5, 31, 0]),
+    (6, 1, [1, 3, 3, 9, 7, 49]),
+    (6, 13, [1, 1, 1, 15, 21, 21]),
+    (6, 16, [1, 3, 1, 13, 27, 49]),
+];
+
+const fn sobol_directions() -> [[u32; 32]; SOBOL_DIMENSIONS] {
+    let mut directions = [[0; 32]; SOBOL_DIMENSIONS];
+    let mut k = 0;
+    while k < 32 {
+        directions[0][k] = 1 << (31 - k);
+        k += 1;
+    }
+
+    let mut dim = 1;
+    while dim < SOBOL_DIMENSIONS {
+        let (s, a, m) = SOBOL_POLYNOMIALS[dim - 1];
+        let s = s as usize;
+        let v = &mut directions[dim];
+        let mut k = 0;
+        while k < 32 {
+            v[k] = if k < s {
+                m[k] << (31 - k)
+            } else {
+                let mut v_k = v[k - s] ^ (v[k - s] >> s);
+                let mut j = 1;
+                while j < s {
+                    v_k ^= ((a >> (s - 1 - j)) & 1) * v[k - j];
+                    j += 1;
+                }
+                v_k
+            };
+            k += 1;
+        }
+        dim += 1;
+    }
+    directions
+}
+
+const SOBOL_DIRECTIONS: [[u32; 32]; SOBOL_DIMENSIONS] = sobol_directions();
+
+/// The `index`-th point of the Sobol sequence in dimension `dim`, as a fixed point number
+fn sobol(index: u32, dim: usize) -> u32 {
+    let mut v = 0;
+    let mut index = index;
+    let mut k = 0;
+    while index != 0 {
+        if index & 1 == 1 {
+            v ^= SOBOL_DIRECTIONS[dim][k];
+        }
+        index >>= 1;
+        k += 1;
+    }
+    v
+}
+
+/// Hash based Owen scrambling, see "Practical Hash-based Owen Scrambling", Burley 2020.
+/// Each bit is flipped depending on the bits before it, which keeps the stratification of the
+/// sequence
+fn owen_scramble(v: u32, seed: u32) -> u32 {
+    let mut v = v.reverse_bits();
+    v ^= v.wrapping_mul(0x3d20adea);
+    v = v.wrapping_add(seed);
+    v = v.wrapping_mul((seed >> 16) | 1);
+    v ^= v.wrapping_mul(0x05526c56);
+    v ^= v.wrapping_mul(0x53a22864);
+    v.reverse_bits()
+}
+
+fn to_unit(v: u32) -> f32 {
+    (v as f32 * 2f32.powi(-32)).min(ONE_MINUS_EPSILON)
+}
+
+/// Owen scrambled Sobol sequence, giving each [Dimension] of the path its own dimensions of the
+/// sequence. The scrambling is seeded by the pixel so that the pixels are not correlated.
+///
+/// Once the dimensions of the sequence run out, deep in the path, the samples are padded with
+/// random ones
+#[derive(Clone)]
+pub struct SobolSampler {
+    x: u32,
+    y: u32,
+    sample: u32,
+}
+
+impl SobolSampler {
+    pub fn new(x: u32, y: u32) -> Self {
+        Self { x, y, sample: 0 }
+    }
+
+    /// The sample of the 1d dimension `dim`
+    fn sample_1d(&self, dim: u32) -> f32 {
+        let mut hasher = DefaultHasher::new();
+        if (dim as usize) < SOBOL_DIMENSIONS {
+            (self.x, self.y, dim).hash(&mut hasher);
+            to_unit(owen_scramble(
+                sobol(self.sample, dim as usize),
+                hasher.finish() as u32,
+            ))
+        } else {
+            (self.x, self.y, self.sample, dim).hash(&mut hasher);
+            let mut rng = crate::Rng::seed_from_u64(hasher.finish());
+            Uniform::new(0.0, 1.0).sample(&mut rng)
+        }
+    }
+}
+
+impl Sampler for SobolSampler {
+    fn sample_2d(&mut self) -> Vec2 {
+        self.sample_dimension(Dimension::PixelOffset).unwrap()
+    }
+
+    fn with_sample(&mut self, sample: u32) {
+        self.sample = sample;
+    }
+
+    fn sample_dimension(&mut self, dimension: Dimension) -> Option<Vec2> {
+        let dim = dimension.index();
+        Some(Vec2::new(self.sample_1d(dim), self.sample_1d(dim + 1)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::math::vec::Vec2;
+
+    use super::{
+        AntitheticSampler, Dimension, Sampler, SobolSampler, StratifiedSampler, SOBOL_DIMENSIONS,
+    };
+
+    /// Mean and variance over many pixels of the estimate of the integral of a smooth function
+    /// over the unit square, with `spp` samples each
+    fn estimate(spp: u32, mut sampler: impl FnMut(u32) -> Box<dyn Sampler>) -> (f64, f64) {
+        let f = |u: Vec2| (u.x * u.x + (3.0 * u.y).sin()) as f64;
+        let estimates = (0..4096)
+            .map(|x| {
+                let mut sampler = sampler(x);
+                (0..spp)
+                    .map(|sample| {
+                        sampler.with_sample(sample);
+                        f(sampler.sample_2d())
+                    })
+                    .sum::<f64>()
+                    / spp as f64
+            })
+            .collect::<Vec<_>>();
+        let mean = estimates.iter().sum::<f64>() / estimates.len() as f64;
+        let variance = estimates.iter().map(|e| (e - mean).powi(2)).sum::<f64>()
+            / (estimates.len() - 1) as f64;
+        (mean, variance)
+    }
+
+    #[test]
+    fn antithetic() {
+        let expected = 1.0 / 3.0 + (1.0 - 3f64.cos()) / 3.0;
+
+        let (mean, variance) = estimate(16, |x| Box::new(StratifiedSampler::new(x, 0, 4, 4)));
+        let (antithetic_mean, antithetic_variance) = estimate(16, |x| {
+            Box::new(AntitheticSampler::new(StratifiedSampler::new(x, 0, 2, 4)))
+        });
+
+        for mean in [mean, antithetic_mean] {
+            assert!((mean - expected).abs() < 1e-3, "{mean} != {expected}");
+        }
+        assert!(
+            antithetic_variance < 0.5 * variance,
+            "{antithetic_variance} >= {variance} / 2"
+        );
+
+        // The mirror of a point stays in its stratum
+        let sampler = StratifiedSampler::new(0, 0, 4, 2);
+        let mirrored = sampler.mirror(Vec2::new(0.3, 0.1));
+        assert!((mirrored - Vec2::new(0.45, 0.4)).length() < 1e-6);
+    }
+
+    #[test]
+    fn dimensions_dont_collide() {
+        let depth = 10;
+        let path = [Dimension::PixelOffset, Dimension::Lens]
+            .into_iter()
+            .chain((0..depth).flat_map(|depth| {
+                [
+                    Dimension::BxDF(depth),
+                    Dimension::Lobe(depth),
+                    Dimension::Light(depth),
+                    Dimension::AlphaCut(depth),
+                    Dimension::LightChoice(depth),
+                ]
+            }))
+            .collect::<Vec<_>>();
+
+        let mut used = std::collections::HashSet::new();
+        for &dimension in &path {
+            for dim in dimension.index()..dimension.index() + dimension.size() {
+                assert!(used.insert(dim), "{dimension:?} reuses the dimension {dim}");
+            }
+        }
+
+        // Past the Sobol dimensions, the padding still gives each dimension its own samples
+        let mut sampler = SobolSampler::new(3, 5);
+        let samples = path
+            .iter()
+            .map(|&dimension| {
+                (0..16)
+                    .map(|sample| {
+                        sampler.with_sample(sample);
+                        sampler.sample_dimension(dimension).unwrap().x.to_bits()
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect::<std::collections::HashSet<_>>();
+        assert_eq!(samples.len(), path.len());
+    }
+
+    #[test]
+    fn stratified_dimensions() {
+        let (samples_x, samples_y) = (4, 3);
+        let count = samples_x * samples_y;
+        let mut sampler = StratifiedSampler::new(5, 8, samples_x, samples_y);
+        let strata = |sampler: &mut StratifiedSampler, dimension| {
+            (0..count)
+                .map(|sample| {
+                    sampler.with_sample(sample);
+                    let u = sampler.sample_dimension(dimension).unwrap();
+                    if dimension.size() == 1 {
+                        (u.x * count as f32) as u32
+                    } else {
+                        (u.y * samples_y as f32) as u32 * samples_x
+                            + (u.x * samples_x as f32) as u32
+                    }
+                })
+                .collect::<Vec<_>>()
+        };
+
+        // One sample in each stratum of each dimension, in different orders
+        let mut orders = Vec::new();
+        for dimension in [
+            Dimension::PixelOffset,
+            Dimension::Lens,
+            Dimension::BxDF(0),
+            Dimension::Lobe(0),
+            Dimension::Light(0),
+            Dimension::AlphaCut(0),
+            Dimension::LightChoice(0),
+        ] {
+            let order = strata(&mut sampler, dimension);
+            let mut sorted = order.clone();
+            sorted.sort();
+            assert_eq!(sorted, (0..count).collect::<Vec<_>>(), "{dimension:?}");
+            orders.push(order);
+        }
+        orders.sort();
+        orders.dedup();
+        assert_eq!(orders.len(), 7);
+
+        // The order depends on the pixel
+        let mut other = StratifiedSampler::new(6, 8, samples_x, samples_y);
+        assert_ne!(
+            strata(&mut sampler, Dimension::BxDF(0)),
+            strata(&mut other, Dimension::BxDF(0))
+        );
+    }
+
+    #[test]
+    fn sobol_stratification() {
+        let mut sampler = SobolSampler::new(7, 2);
+        let points = |sampler: &SobolSampler, dim: u32| {
+            (0..16)
+                .map(|sample| {
+                    SobolSampler {
+                        sample,
+                        ..sampler.clone()
+                    }
+                    .sample_1d(dim)
+                })
+                .collect::<Vec<_>>()
+        };
+
+        // The scrambling keeps one point in each sixteenth of every dimension
+        for dim in 0..SOBOL_DIMENSIONS as u32 {
+            let mut strata = points(&sampler, dim)
+                .iter()
+                .map(|u| (u * 16.0) as u32)
+                .collect::<Vec<_>>();
+            strata.sort();
+            assert_eq!(strata, (0..16).collect::<Vec<_>>(), "dimension {dim}");
+        }
+
+        // The pixel offset is a (0, 4, 2)-net: one point in each elementary interval
+        let (xs, ys) = (points(&sampler, 0), points(&sampler, 1));
+        for log_x in 0..=4 {
+            let (nx, ny) = (1 << log_x, 1 << (4 - log_x));
+            let mut cells = xs
+                .iter()
+                .zip(&ys)
+                .map(|(x, y)| (x * nx as f32) as u32 * ny + (y * ny as f32) as u32)
+                .collect::<Vec<_>>();
+            cells.sort();
+            assert_eq!(cells, (0..16).collect::<Vec<_>>(), "{nx}x{ny}");
+        }
+
+        sampler.with_sample(3);
+        let u = sampler.sample_2d();
+        assert_eq!(u, Vec2::new(xs[3], ys[3]));
+    }
 }
@@ -1,7 +1,8 @@
-use crate::material::{DiffuseBxDF, EmitBxDF};
+use crate::material::DiffuseBxDF;
 use crate::scene::SceneT;
 use crate::{
-    color::Rgb,
+    color::{linear::WHITE, Rgb},
+    light::PointLight,
     loader::ObjLoaderExt,
     material::{LightDescriptor, MaterialDescriptor},
     math::{point::Point, transform::Transform},
@@ -15,7 +16,9 @@ impl CornellBoxScene {
             label: Some("Goosh - Default 2".to_string()),
             material: Box::new(DiffuseBxDF {
                 albedo: Rgb::from_array([5.5, 0.8, 0.9]),
+                ..Default::default()
             }),
+            alpha: None,
         });
 
         scene.load_obj(
@@ -30,14 +33,11 @@ impl CornellBoxScene {
 
         scene.insert_light(LightDescriptor {
             label: None,
-            light_pos: Point::new(0.0, 0.4, -0.4),
-        });
-        let l = scene.insert_material(MaterialDescriptor {
-            label: Some("light!".into()),
-            material: Box::new(EmitBxDF {
-                le: [5.0, 5.0, 5.0].into(),
+            light: Box::new(PointLight {
+                position: Point::new(0.0, 0.4, -0.4),
+                intensity: WHITE,
             }),
         });
-        scene.insert_sphere(l, Point::new(0.0, 0.0, 5.0), 3.0);
+        scene.insert_sphere_light([5.0, 5.0, 5.0].into(), Point::new(0.0, 0.0, 5.0), 3.0);
     }
 }
@@ -8,7 +8,9 @@ impl DebugScene {
             label: Some("Goosh - Default".to_string()),
             material: Box::new(DiffuseBxDF {
                 albedo: [1.0, 1.0, 0.0].into(),
+                ..Default::default()
             }),
+            alpha: None,
         });
 
         scene.insert_sphere(default_material, Point::new(0.0, 0.0, -1.0), 0.3);
@@ -3,7 +3,8 @@ use std::f32::consts::PI;
 use glam::{Quat, Vec3};
 
 use crate::{
-    color::Rgb,
+    color::{linear::WHITE, Rgb},
+    light::PointLight,
     loader::ObjLoaderExt,
     material::{DielectricBxDF, DiffuseBxDF},
     math::{point::Point, transform::Transform},
@@ -19,7 +20,9 @@ impl DragonScene {
             material: Box::new(DielectricBxDF {
                 ior: 1.5,
                 roughness: 0.2,
+                transmittance_color: Rgb::from_array([0.3, 0.8, 0.35]),
             }),
+            alpha: None,
         });
 
         scene.load_obj(
@@ -34,14 +37,19 @@ impl DragonScene {
 
         scene.insert_light(crate::material::LightDescriptor {
             label: None,
-            light_pos: Point::new(10.2, 80.0, 75.0),
+            light: Box::new(PointLight {
+                position: Point::new(10.2, 80.0, 75.0),
+                intensity: WHITE,
+            }),
         });
 
         let ball = scene.insert_material(crate::material::MaterialDescriptor {
             label: None,
             material: Box::new(DiffuseBxDF {
                 albedo: Rgb::from_array([0.2, 0.1, 0.5]),
+                ..Default::default()
             }),
+            alpha: None,
         });
         scene.insert_sphere(ball, Point::new(-0.7, -0.2, -1.9), 0.8);
     }
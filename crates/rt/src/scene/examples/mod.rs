@@ -1,11 +1,13 @@
 mod cornell;
 mod debug;
 mod dragon;
+mod prism;
 mod spheres;
 mod standford_bunny;
 
 pub use cornell::CornellBoxScene;
 pub use debug::DebugScene;
 pub use dragon::DragonScene;
+pub use prism::PrismScene;
 pub use spheres::SpheresScene;
 pub use standford_bunny::StandfordBunnyScene;
@@ -0,0 +1,139 @@
+use crate::{
+    color::linear::WHITE,
+    material::{DielectricBxDF, DiffuseBxDF, MaterialDescriptor},
+    math::point::Point,
+    scene::SceneT,
+};
+
+/// A glass prism lit from the side by a small and far away light, so that its rays are almost
+/// parallel, the refracted beam landing on a white screen.
+///
+/// The glass has a single index of refraction: the beam is only bent, it would be split in a
+/// spectrum once the index depends on the wavelength
+pub struct PrismScene;
+
+impl PrismScene {
+    pub fn insert_into<S: SceneT>(scene: &mut S) {
+        let glass = scene.insert_material(MaterialDescriptor {
+            label: Some("Prism".into()),
+            material: Box::new(DielectricBxDF {
+                ior: 1.5,
+                roughness: 0.0,
+                transmittance_color: WHITE,
+            }),
+            alpha: None,
+        });
+        let screen = scene.insert_material(MaterialDescriptor {
+            label: Some("Screen".into()),
+            material: Box::new(DiffuseBxDF {
+                albedo: [0.8, 0.8, 0.8].into(),
+                ..Default::default()
+            }),
+            alpha: None,
+        });
+
+        // Along Z, its section is an equilateral triangle pointing up
+        let (half_side, z_near, z_far) = (0.25, -0.9, -1.5);
+        let (bottom, top) = (-0.2, -0.2 + half_side * 3f32.sqrt());
+        let section = [[-half_side, bottom], [half_side, bottom], [0.0, top]];
+        let vertices = [z_near, z_far]
+            .into_iter()
+            .flat_map(|z| section.map(|[x, y]| [x, y, z]))
+            .collect::<Vec<_>>();
+        let mut indices = vec![[0, 2, 1], [3, 4, 5]];
+        for i in 0..3 {
+            let j = (i + 1) % 3;
+            indices.extend([[i, j, j + 3], [i, j + 3, i + 3]]);
+        }
+        scene.insert_mesh(glass, &vertices, &indices);
+
+        scene.insert_mesh(
+            screen,
+            &[
+                [-2.0, -0.2, -2.0],
+                [2.0, -0.2, -2.0],
+                [2.0, 1.5, -2.0],
+                [-2.0, 1.5, -2.0],
+            ],
+            &[[0, 1, 2], [0, 2, 3]],
+        );
+        scene.insert_mesh(
+            screen,
+            &[
+                [-2.0, -0.2, 0.0],
+                [2.0, -0.2, 0.0],
+                [2.0, -0.2, -2.0],
+                [-2.0, -0.2, -2.0],
+            ],
+            &[[0, 1, 2], [0, 2, 3]],
+        );
+
+        scene.insert_sphere_light(
+            [400.0, 400.0, 400.0].into(),
+            Point::new(-6.0, 1.0, -1.2),
+            0.1,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use embree4_rs::device::Device;
+    use glam::Vec3;
+
+    use crate::{
+        aggregate::embree::EmbreeScene,
+        integrators::{Integrator, PathTracer},
+        math::point::Point,
+        memory::{Arena, ArenaInner},
+        ray::Ray,
+        sampler::UniformSampler,
+        Ctx, Seed,
+    };
+
+    use super::PrismScene;
+
+    #[test]
+    fn prism_renders() {
+        let device = Device::try_new(None).unwrap();
+        let mut scene = EmbreeScene::new(&device);
+        PrismScene::insert_into(&mut scene);
+        let scene = scene.commit().unwrap();
+        let world = scene.into_world().unwrap();
+
+        let integrator = PathTracer::new(8);
+        let arena = ArenaInner::new(1 << 16);
+        let (width, height) = (16, 8);
+        let mut prism_hit = false;
+        for y in 0..height {
+            for x in 0..width {
+                let mut sampler = UniformSampler::new(x, y);
+                let seed = Seed {
+                    seed: 0,
+                    x,
+                    y,
+                    sample_idx: 0,
+                };
+                let mut ctx = Ctx {
+                    rng: seed.into_rng(0),
+                    world: &world,
+                    arena: Arena::new(&arena),
+                    seed,
+                    sampler: &mut sampler,
+                    debug: false,
+                };
+                let target = Vec3::new(
+                    (x as f32 + 0.5) / width as f32 - 0.5,
+                    0.5 - (y as f32 + 0.5) / height as f32,
+                    -1.0,
+                );
+                let ray = Ray::new(Point::ORIGIN, target.normalize());
+                let result = integrator.ray_cast(&mut ctx, ray, 0);
+                let color = result.color.to_array();
+                assert!(color.iter().all(|c| c.is_finite()), "{x}, {y}: {color:?}");
+                prism_hit |= result.object == Some(0);
+            }
+        }
+        assert!(prism_hit);
+    }
+}
@@ -1,4 +1,6 @@
 use crate::{
+    color::linear::WHITE,
+    light::PointLight,
     material::{DielectricBxDF, DiffuseBxDF, LightDescriptor, MaterialDescriptor},
     math::point::Point,
     scene::SceneT,
@@ -12,20 +14,26 @@ impl SpheresScene {
             label: None,
             material: Box::new(DiffuseBxDF {
                 albedo: [0.2, 0.9, 0.7].into(),
+                ..Default::default()
             }),
+            alpha: None,
         });
         let diffuse_blue = scene.insert_material(MaterialDescriptor {
             label: None,
             material: Box::new(DiffuseBxDF {
                 albedo: [0.2, 0.4, 0.8].into(),
+                ..Default::default()
             }),
+            alpha: None,
         });
         let glass = scene.insert_material(MaterialDescriptor {
             label: None,
             material: Box::new(DielectricBxDF {
                 ior: 1.5,
                 roughness: 0.01,
+                transmittance_color: WHITE,
             }),
+            alpha: None,
         });
         // let light = scene.insert_material(MaterialDescriptor {
         //     label: None,
@@ -53,15 +61,24 @@ impl SpheresScene {
 
         scene.insert_light(LightDescriptor {
             label: None,
-            light_pos: Point::new(0.0, 0., -0.5),
+            light: Box::new(PointLight {
+                position: Point::new(0.0, 0., -0.5),
+                intensity: WHITE,
+            }),
         });
         scene.insert_light(LightDescriptor {
             label: None,
-            light_pos: Point::new(0.4, -0., -0.6),
+            light: Box::new(PointLight {
+                position: Point::new(0.4, -0., -0.6),
+                intensity: WHITE,
+            }),
         });
         scene.insert_light(LightDescriptor {
             label: None,
-            light_pos: Point::new(-0.1, -0.1, 0.6),
+            light: Box::new(PointLight {
+                position: Point::new(-0.1, -0.1, 0.6),
+                intensity: WHITE,
+            }),
         });
     }
 }
@@ -14,7 +14,9 @@ impl StandfordBunnyScene {
             label: Some("Goosh - Default".to_string()),
             material: Box::new(DiffuseBxDF {
                 albedo: [1.0, 1.0, 0.5].into(),
+                ..Default::default()
             }),
+            alpha: None,
         });
 
         scene.load_obj(
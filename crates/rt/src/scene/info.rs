@@ -0,0 +1,210 @@
+//! A summary of a scene, to check that a model was loaded as expected before a long render.
+use std::{fmt::Display, mem::size_of};
+
+use glam::Vec3;
+
+use crate::{
+    aggregate::triangle_mesh::skip_degenerate_triangles,
+    color::Rgb,
+    material::{LightDescriptor, MaterialDescriptor, MaterialId},
+    math::{bounds::Bounds, point::Point},
+};
+
+use super::{quad_mesh, SceneT};
+
+/// A rough size of the acceleration structure for each primitive, Embree's BVH being built with a
+/// high quality
+const BVH_BYTES_PER_PRIMITIVE: usize = 64;
+
+/// The statistics of a scene, gathered by inserting the scene in it as in any other [SceneT]
+#[derive(Debug, Clone, Copy)]
+pub struct SceneInfo {
+    /// Without the degenerate ones, which are skipped
+    pub triangles: usize,
+    pub vertices: usize,
+    pub meshes: usize,
+    pub spheres: usize,
+    pub curves: usize,
+    pub lights: usize,
+    /// Only the inserted ones, the scene may add the one of the sky
+    pub materials: usize,
+    /// Bounds of the geometry, the lights excluded
+    pub bounds: Bounds,
+    /// Bytes of the vertices, indices, centers and radii of the geometry
+    pub geometry_bytes: usize,
+}
+
+impl Default for SceneInfo {
+    fn default() -> Self {
+        Self {
+            triangles: 0,
+            vertices: 0,
+            meshes: 0,
+            spheres: 0,
+            curves: 0,
+            lights: 0,
+            materials: 0,
+            bounds: Bounds::EMPTY,
+            geometry_bytes: 0,
+        }
+    }
+}
+
+impl SceneInfo {
+    pub fn primitives(&self) -> usize {
+        self.triangles + self.spheres + self.curves
+    }
+
+    /// The geometry and a guess of the size of its acceleration structure
+    pub fn estimated_memory(&self) -> usize {
+        self.geometry_bytes + BVH_BYTES_PER_PRIMITIVE * self.primitives()
+    }
+}
+
+impl Display for SceneInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "triangles: {} in {} meshes, {} vertices",
+            self.triangles, self.meshes, self.vertices
+        )?;
+        writeln!(f, "spheres: {}", self.spheres)?;
+        writeln!(f, "curves: {}", self.curves)?;
+        writeln!(f, "lights: {}", self.lights)?;
+        writeln!(f, "materials: {}", self.materials)?;
+        if self.bounds.is_empty() {
+            writeln!(f, "bounds: empty")?;
+        } else {
+            writeln!(
+                f,
+                "bounds: {} to {}",
+                self.bounds.origin.vec(),
+                self.bounds.end.vec()
+            )?;
+        }
+        write!(
+            f,
+            "estimated memory: {:.1} MiB",
+            self.estimated_memory() as f64 / (1024.0 * 1024.0)
+        )
+    }
+}
+
+impl SceneT for SceneInfo {
+    type GeometryHandle = ();
+
+    fn insert_material(&mut self, _mat: MaterialDescriptor) -> MaterialId {
+        self.materials += 1;
+        MaterialId(self.materials - 1)
+    }
+
+    fn insert_light(&mut self, _light: LightDescriptor) {
+        self.lights += 1;
+    }
+
+    fn insert_mesh(&mut self, _material: MaterialId, vertices: &[[f32; 3]], indices: &[[u32; 3]]) {
+        let positions = vertices
+            .iter()
+            .map(|&p| Vec3::from_array(p))
+            .collect::<Vec<_>>();
+        let mut indices = indices.to_vec();
+        skip_degenerate_triangles(&positions, &mut indices);
+
+        self.meshes += 1;
+        self.triangles += indices.len();
+        self.vertices += vertices.len();
+        self.geometry_bytes += size_of_val(vertices) + size_of_val(&indices[..]);
+        for &p in &positions {
+            self.bounds = self.bounds.union_point(Point(p));
+        }
+    }
+
+    fn insert_sphere(&mut self, _material: MaterialId, origin: Point, radius: f32) {
+        self.spheres += 1;
+        self.geometry_bytes += size_of::<[f32; 4]>();
+        self.bounds = self
+            .bounds
+            .union_point(origin - Vec3::splat(radius))
+            .union_point(origin + Vec3::splat(radius));
+    }
+
+    fn insert_sphere_light(&mut self, _le: Rgb, center: Point, radius: f32) {
+        self.materials += 1;
+        self.lights += 1;
+        self.insert_sphere(MaterialId(self.materials - 1), center, radius);
+    }
+
+    fn insert_quad_light(&mut self, _le: Rgb, corner: Point, edges: [Vec3; 2]) {
+        self.materials += 1;
+        self.lights += 1;
+        let (vertices, indices) = quad_mesh(corner, edges);
+        self.insert_mesh(MaterialId(self.materials - 1), &vertices, &indices);
+    }
+
+    fn insert_curve(&mut self, _material: MaterialId, control_points: &[[f32; 3]], widths: &[f32]) {
+        // Embree splits the curves in one segment per control point, the ends excluded
+        self.curves += control_points.len().saturating_sub(3);
+        self.geometry_bytes += control_points.len() * size_of::<[f32; 4]>();
+        for (&p, &width) in control_points.iter().zip(widths) {
+            let p = Point(Vec3::from_array(p));
+            let radius = Vec3::splat(width / 2.0);
+            self.bounds = self.bounds.union_point(p - radius).union_point(p + radius);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        material::MaterialId,
+        math::point::Point,
+        scene::{examples::SpheresScene, SceneT},
+    };
+
+    use super::SceneInfo;
+
+    #[test]
+    fn scene_info() {
+        let mut info = SceneInfo::default();
+        SpheresScene::insert_into(&mut info);
+        assert_eq!(
+            (info.spheres, info.lights, info.materials, info.triangles),
+            (3, 3, 3, 0)
+        );
+
+        // A quad and a degenerate triangle
+        info.insert_mesh(
+            MaterialId(0),
+            &[
+                [-2.0, -1.0, -3.0],
+                [2.0, -1.0, -3.0],
+                [2.0, -1.0, 1.0],
+                [-2.0, -1.0, 1.0],
+            ],
+            &[[0, 1, 2], [0, 2, 3], [0, 1, 1]],
+        );
+        info.insert_curve(
+            MaterialId(0),
+            &[
+                [0.0, 0.0, 0.0],
+                [0.0, 1.0, 0.0],
+                [0.0, 2.0, 0.0],
+                [0.0, 3.0, 0.0],
+            ],
+            &[0.1; 4],
+        );
+        assert_eq!((info.meshes, info.triangles, info.vertices), (1, 2, 4));
+        assert_eq!(info.curves, 1);
+        assert_eq!(info.primitives(), 6);
+        assert!(info.estimated_memory() > info.geometry_bytes);
+
+        let Point(origin) = info.bounds.origin;
+        let Point(end) = info.bounds.end;
+        assert_eq!(origin.to_array(), [-2.0, -1.0, -3.0]);
+        assert!(
+            (end - glam::Vec3::new(2.0, 3.05, 1.2)).abs().max_element() < 1e-6,
+            "{end}"
+        );
+        assert!(info.to_string().contains("spheres: 3"));
+    }
+}
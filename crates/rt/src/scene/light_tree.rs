@@ -0,0 +1,297 @@
+//! A bounding volume hierarchy of the lights, to pick the ones that matter at a point among many.
+//!
+//! A light is chosen by descending the tree from its root, going down each child with a
+//! probability proportional to its importance: the power of the lights it holds, divided by the
+//! squared distance to them and weighted by how much they can face the normal. The lights with an
+//! area are bounded as a whole, a point of the surface that some of them light is never given a
+//! zero importance for them.
+use glam::Vec3;
+
+use crate::math::{bounds::Bounds, point::Point};
+
+/// A light as the tree sees it
+#[derive(Debug, Clone, Copy)]
+pub struct LightInfo {
+    /// Where the light emits from, a single point for the infinitely small lights
+    pub bounds: Bounds,
+    /// Power of the light, any positive measure of its brightness
+    pub power: f32,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum NodeKind {
+    /// Index of the light
+    Leaf(usize),
+    /// Index of the second child, the first one is right after the node
+    Interior(usize),
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Node {
+    bounds: Bounds,
+    power: f32,
+    kind: NodeKind,
+}
+
+pub struct LightTree {
+    /// In depth first order, the root first
+    nodes: Vec<Node>,
+    /// The choices at each level of the tree leading to each light, from the root in the lowest
+    /// bit, 1 for the second child
+    paths: Vec<(u64, u32)>,
+}
+
+impl LightTree {
+    /// The lights without power can't be chosen
+    pub fn new(lights: &[LightInfo]) -> Self {
+        let mut tree = Self {
+            nodes: Vec::with_capacity(2 * lights.len()),
+            paths: vec![(0, 0); lights.len()],
+        };
+        let mut indices = (0..lights.len())
+            .filter(|&i| lights[i].power > 0.0)
+            .collect::<Vec<_>>();
+        if !indices.is_empty() {
+            tree.build(lights, &mut indices, 0, 0);
+        }
+        tree
+    }
+
+    fn build(&mut self, lights: &[LightInfo], indices: &mut [usize], path: u64, depth: u32) {
+        let bounds = indices
+            .iter()
+            .fold(Bounds::EMPTY, |b, &i| b.union(lights[i].bounds));
+        let power = indices.iter().map(|&i| lights[i].power).sum();
+
+        if let [light] = *indices {
+            self.paths[light] = (path, depth);
+            self.nodes.push(Node {
+                bounds,
+                power,
+                kind: NodeKind::Leaf(light),
+            });
+            return;
+        }
+        assert!(depth < u64::BITS, "the light tree is too deep");
+
+        // Split the centers at the median along the widest axis
+        let axis = bounds.longest_axis();
+        let middle = indices.len() / 2;
+        let center = |i: usize| lights[i].bounds.centroid().vec()[axis];
+        indices.select_nth_unstable_by(middle, |&a, &b| center(a).total_cmp(&center(b)));
+
+        let node = self.nodes.len();
+        self.nodes.push(Node {
+            bounds,
+            power,
+            kind: NodeKind::Interior(0),
+        });
+        let (first, second) = indices.split_at_mut(middle);
+        self.build(lights, first, path, depth + 1);
+        self.nodes[node].kind = NodeKind::Interior(self.nodes.len());
+        self.build(lights, second, path | 1 << depth, depth + 1);
+    }
+
+    /// How much the lights of a node can light `p`, on the side of the surface that `normal`
+    /// points to if there is one
+    fn importance(node: &Node, p: Point, normal: Option<Vec3>) -> f32 {
+        let center = node.bounds.centroid();
+        let radius = node.bounds.diag().length() / 2.0;
+        let to_center = center - p;
+        let distance = to_center.length();
+        // Inside of the bounds, the lights can be anywhere around
+        if distance <= radius {
+            return node.power / radius.max(f32::EPSILON).powi(2);
+        }
+
+        // The closest the lights can be
+        let closest = (distance - radius).max(radius).max(f32::EPSILON);
+        let Some(normal) = normal else {
+            return node.power / (closest * closest);
+        };
+
+        // The smallest angle between the normal and the bounding sphere of the lights
+        let cos_center = normal.dot(to_center / distance).clamp(-1.0, 1.0);
+        let half_angle = (radius / distance).asin();
+        let angle = (cos_center.acos() - half_angle).max(0.0);
+        if angle >= std::f32::consts::FRAC_PI_2 {
+            return 0.0;
+        }
+        node.power * angle.cos() / (closest * closest)
+    }
+
+    /// The importances of the two children of an interior node
+    fn children_importance(
+        &self,
+        node: usize,
+        second: usize,
+        p: Point,
+        normal: Option<Vec3>,
+    ) -> [f32; 2] {
+        [node + 1, second].map(|child| Self::importance(&self.nodes[child], p, normal))
+    }
+
+    /// Choose a light to light `p` with `u` in [0, 1), returns its index and the probability to
+    /// choose it. Only the lights on the side of the surface `normal` points to can be chosen, any
+    /// of them without a normal
+    pub fn sample(&self, p: Point, normal: Option<Vec3>, mut u: f32) -> Option<(usize, f32)> {
+        let root = self.nodes.first()?;
+        if Self::importance(root, p, normal) == 0.0 {
+            return None;
+        }
+
+        let (mut node, mut pmf) = (0, 1.0);
+        loop {
+            match self.nodes[node].kind {
+                NodeKind::Leaf(light) => return Some((light, pmf)),
+                NodeKind::Interior(second) => {
+                    let [first_importance, second_importance] =
+                        self.children_importance(node, second, p, normal);
+                    let total = first_importance + second_importance;
+                    if total == 0.0 {
+                        return None;
+                    }
+
+                    let p_first = first_importance / total;
+                    if u < p_first {
+                        u /= p_first;
+                        pmf *= p_first;
+                        node += 1;
+                    } else {
+                        u = ((u - p_first) / (1.0 - p_first)).min(1.0 - f32::EPSILON);
+                        pmf *= 1.0 - p_first;
+                        node = second;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Probability that [Self::sample] chooses the light `light` for `p`
+    pub fn pmf(&self, p: Point, normal: Option<Vec3>, light: usize) -> f32 {
+        let Some(root) = self.nodes.first() else {
+            return 0.0;
+        };
+        if Self::importance(root, p, normal) == 0.0 {
+            return 0.0;
+        }
+
+        let (path, depth) = self.paths[light];
+        let (mut node, mut pmf) = (0, 1.0);
+        for level in 0..depth {
+            let NodeKind::Interior(second) = self.nodes[node].kind else {
+                unreachable!("the path of a light goes through its leaf");
+            };
+            let importance = self.children_importance(node, second, p, normal);
+            let total = importance[0] + importance[1];
+            if total == 0.0 {
+                return 0.0;
+            }
+            let choice = (path >> level & 1) as usize;
+            pmf *= importance[choice] / total;
+            node = [node + 1, second][choice];
+        }
+        match self.nodes[node].kind {
+            NodeKind::Leaf(leaf) if leaf == light => pmf,
+            // A light without power is in no leaf
+            _ => 0.0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::Vec3;
+    use rand::{Rng, SeedableRng};
+
+    use crate::math::{bounds::Bounds, point::Point};
+
+    use super::{LightInfo, LightTree};
+
+    #[test]
+    fn light_tree_is_unbiased() {
+        let mut rng = crate::Rng::seed_from_u64(3);
+        let lights = (0..300)
+            .map(|_| {
+                let position = Point::new(
+                    rng.gen_range(-10.0..10.0),
+                    rng.gen_range(0.5..10.0),
+                    rng.gen_range(-10.0..10.0),
+                );
+                LightInfo {
+                    bounds: Bounds::new(position, position),
+                    power: rng.gen_range(0.1..5.0),
+                }
+            })
+            .collect::<Vec<_>>();
+        let tree = LightTree::new(&lights);
+
+        // The irradiance of a few points of a diffuse floor lit by the point lights
+        for (p, normal) in [
+            (Point::ORIGIN, Vec3::Y),
+            (Point::new(4.0, 0.0, -3.0), Vec3::Y),
+            (
+                Point::new(-2.0, 3.0, 1.0),
+                Vec3::new(1.0, 1.0, 0.0).normalize(),
+            ),
+        ] {
+            let f = |light: &LightInfo| {
+                let to_light = light.bounds.origin - p;
+                light.power * normal.dot(to_light.normalize()).max(0.0) / to_light.length_squared()
+            };
+            let expected = lights.iter().map(f).sum::<f32>();
+
+            // The lights behind the surface may never be chosen, every other one can be
+            let pmfs = (0..lights.len())
+                .map(|i| tree.pmf(p, Some(normal), i))
+                .collect::<Vec<_>>();
+            assert!(pmfs.iter().sum::<f32>() <= 1.0 + 1e-4);
+            for (light, pmf) in lights.iter().zip(&pmfs) {
+                assert!(f(light) == 0.0 || *pmf > 0.0);
+            }
+
+            let samples = 20000;
+            let (mut tree_mean, mut uniform_mean) = (0.0, 0.0);
+            for _ in 0..samples {
+                if let Some((light, pmf)) = tree.sample(p, Some(normal), rng.gen()) {
+                    assert!((pmf - pmfs[light]).abs() <= 1e-5 * pmf);
+                    tree_mean += f(&lights[light]) / pmf / samples as f32;
+                }
+
+                let light = rng.gen_range(0..lights.len());
+                uniform_mean += f(&lights[light]) * lights.len() as f32 / samples as f32;
+            }
+            for mean in [tree_mean, uniform_mean] {
+                assert!(
+                    (mean - expected).abs() < 0.05 * expected,
+                    "{mean} != {expected}"
+                );
+            }
+        }
+
+        // Nothing lights the back of the floor, unless it lets the light through
+        let above = Point::new(0.0, 20.0, 0.0);
+        assert!(tree.sample(above, Some(Vec3::Y), 0.5).is_none());
+        assert!(tree.sample(above, None, 0.5).is_some());
+    }
+
+    #[test]
+    fn light_tree_bounds_the_areas() {
+        // A wide light whose center is below the horizon of the point, its edge above
+        let lights = [
+            LightInfo {
+                bounds: Bounds::new(Point::new(-5.0, -0.5, -1.0), Point::new(5.0, 0.5, 1.0)),
+                power: 1.0,
+            },
+            LightInfo {
+                bounds: Bounds::new(Point::new(20.0, 5.0, 0.0), Point::new(20.0, 5.0, 0.0)),
+                power: 1.0,
+            },
+        ];
+        let tree = LightTree::new(&lights);
+        let p = Point::new(0.0, 0.3, 8.0);
+        assert!(tree.pmf(p, Some(Vec3::Y), 0) > 0.0);
+        let pmfs = [0, 1].map(|light| tree.pmf(p, Some(Vec3::Y), light));
+        assert!((pmfs.iter().sum::<f32>() - 1.0).abs() < 1e-5, "{pmfs:?}");
+    }
+}
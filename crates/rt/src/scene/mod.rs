@@ -1,8 +1,11 @@
 pub mod examples;
+pub mod info;
+pub mod light_tree;
 
 use crate::{
-    material::{LightDescriptor, MaterialDescriptor, MaterialId},
-    math::point::Point,
+    color::Rgb,
+    material::{EmitBxDF, LightDescriptor, MaterialDescriptor, MaterialId},
+    math::{point::Point, vec::Vec3},
 };
 
 pub trait SceneT {
@@ -17,10 +20,83 @@ pub trait SceneT {
         indices: &[[u32; 3]],
     ) -> Self::GeometryHandle;
 
+    /// A mesh shaded with the normals of its vertices, interpolated over its triangles. The
+    /// scenes that don't shade get it flat
+    fn insert_mesh_with_normals(
+        &mut self,
+        material: MaterialId,
+        vertices: &[[f32; 3]],
+        _normals: &[[f32; 3]],
+        indices: &[[u32; 3]],
+    ) -> Self::GeometryHandle {
+        self.insert_mesh(material, vertices, indices)
+    }
+
     fn insert_sphere(
         &mut self,
         material: MaterialId,
         origin: Point,
         radius: f32,
     ) -> Self::GeometryHandle;
+
+    /// A sphere emitting `le` everywhere on its surface. The scenes that are rendered also
+    /// register it as a light, so that it is sampled from the surfaces it lights
+    fn insert_sphere_light(&mut self, le: Rgb, center: Point, radius: f32) -> Self::GeometryHandle {
+        let material = self.insert_material(emissive_sphere_material(le));
+        self.insert_sphere(material, center, radius)
+    }
+
+    /// A parallelogram emitting `le` on the side of the cross product of its edges, as a mesh of
+    /// two triangles. The scenes that are rendered also register it as a light, see
+    /// [SceneT::insert_sphere_light]
+    fn insert_quad_light(
+        &mut self,
+        le: Rgb,
+        corner: Point,
+        edges: [Vec3; 2],
+    ) -> Self::GeometryHandle {
+        let material = self.insert_material(emissive_quad_material(le));
+        let (vertices, indices) = quad_mesh(corner, edges);
+        self.insert_mesh(material, &vertices, &indices)
+    }
+
+    /// A round cubic B-spline curve, for hair and grass, `widths` being the width of the curve at
+    /// each of the control points
+    fn insert_curve(
+        &mut self,
+        material: MaterialId,
+        control_points: &[[f32; 3]],
+        widths: &[f32],
+    ) -> Self::GeometryHandle;
+}
+
+/// The material of the spheres inserted with [SceneT::insert_sphere_light]
+pub(crate) fn emissive_sphere_material(le: Rgb) -> MaterialDescriptor {
+    MaterialDescriptor {
+        label: Some("Light".into()),
+        material: Box::new(EmitBxDF {
+            le,
+            two_sided: true,
+        }),
+        alpha: None,
+    }
+}
+
+/// The material of the quads inserted with [SceneT::insert_quad_light]
+pub(crate) fn emissive_quad_material(le: Rgb) -> MaterialDescriptor {
+    MaterialDescriptor {
+        label: Some("Light".into()),
+        material: Box::new(EmitBxDF {
+            le,
+            two_sided: false,
+        }),
+        alpha: None,
+    }
+}
+
+/// The two triangles of a parallelogram, wound so that their normal is the cross product of the
+/// edges
+pub(crate) fn quad_mesh(corner: Point, [u, v]: [Vec3; 2]) -> ([[f32; 3]; 4], [[u32; 3]; 2]) {
+    let vertices = [corner, corner + u, corner + u + v, corner + v].map(|p| p.vec().to_array());
+    (vertices, [[0, 1, 2], [0, 2, 3]])
 }
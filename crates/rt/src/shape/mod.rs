@@ -3,6 +3,15 @@ use crate::{math::bounds::Bounds, ray::Ray};
 pub trait Shape: Sync + Send {
     fn intersection_full(&self, ray: Ray) -> FullIntersectionResult;
 
+    /// Intersect a whole batch of rays at once.
+    ///
+    /// Shapes that can do better than one ray at a time should override it
+    fn intersection_stream(&self, rays: &[Ray]) -> Vec<FullIntersectionResult> {
+        rays.iter()
+            .map(|&ray| self.intersection_full(ray))
+            .collect()
+    }
+
     fn intersect_bare(&self, ray: Ray) -> MinIntersectionResult;
 
     fn bounding_box(&self) -> Bounds;
@@ -21,9 +30,15 @@ pub mod local_info {
     #[derive(Debug)]
     pub struct Full {
         pub pos: Point,
+        /// Geometric normal of the surface
         pub normal: Vec3,
+        /// Normal the BxDF frame is built around: the interpolated one on the meshes with vertex
+        /// normals, the geometric one otherwise
+        pub shading_normal: Vec3,
         pub material: MaterialId,
         pub uv: Uv,
+        /// Id of the geometry that was hit, the same from a render to the next
+        pub object: u32,
     }
 
     /// Contains only the pure geometrical information needed to locate the point.
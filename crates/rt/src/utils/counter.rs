@@ -1,7 +1,7 @@
 use std::{
     collections::HashMap,
     sync::{
-        atomic::{AtomicU64, Ordering},
+        atomic::{AtomicBool, AtomicU64, Ordering},
         Arc, Mutex,
     },
 };
@@ -63,11 +63,46 @@ impl CounterTime {
     }
 }
 
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Counters are only incremented once enabled, so that they cost next to nothing otherwise
+pub fn enable_counters() {
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+pub fn counters_enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// The value of the counter `descr`, None if it was never incremented or is not a count
+pub fn counter_value(descr: &str) -> Option<u64> {
+    match &**__COUNTERS.lock().unwrap().get(descr)? {
+        Counter::CounterU64(counter) => Some(counter.value()),
+        Counter::CounterTime(_) => None,
+    }
+}
+
+/// Log a table of all the counters, sorted by name
 pub fn report_counters() {
     let counters = __COUNTERS.lock().unwrap();
-    for (counter_name, counter) in counters.iter() {
-        log::log!(target: "counter_report", log::Level::Info, "{}: {} ", counter_name, counter.format())
+    let mut counters = counters
+        .iter()
+        .map(|(name, counter)| (*name, counter.format()))
+        .collect::<Vec<_>>();
+    counters.sort();
+
+    let name_width = counters
+        .iter()
+        .map(|(name, _)| name.len())
+        .max()
+        .unwrap_or(0);
+    let value_width = counters.iter().map(|(_, v)| v.len()).max().unwrap_or(0);
+    let rule = "-".repeat(name_width + value_width + 3);
+    log::log!(target: "counter_report", log::Level::Info, "{rule}");
+    for (name, value) in counters {
+        log::log!(target: "counter_report", log::Level::Info, "{name:<name_width$} | {value:>value_width$}")
     }
+    log::log!(target: "counter_report", log::Level::Info, "{rule}");
 }
 
 lazy_static::lazy_static! {
@@ -84,7 +119,7 @@ pub fn insert_counter(descr: &'static str, counter: Counter) -> Arc<Counter> {
 #[macro_export]
 macro_rules! counter {
     ($descr:literal) => {
-        if cfg!(feature = "counter") {
+        if cfg!(feature = "counter") && $crate::utils::counter::counters_enabled() {
             use $crate::utils::counter::{insert_counter, lazy_static, Counter, CounterU64};
             lazy_static::lazy_static! {
                 static ref COUNTER_REF: std::sync::Arc<Counter> = {
@@ -1,3 +1,4 @@
 pub mod counter;
 pub mod log_once;
+pub mod stable_hash;
 pub mod timer;
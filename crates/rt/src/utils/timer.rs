@@ -69,7 +69,7 @@ pub fn timed_scope_accumulate_<R, F: FnOnce() -> R>(timer: &CounterTime, f: F) -
 #[macro_export]
 macro_rules! timed_scope_accumulate {
     ($descr:literal, $($arg: tt)+) => {
-        if cfg!(feature = "counter_time") {
+        if cfg!(feature = "counter_time") && $crate::utils::counter::counters_enabled() {
             use $crate::utils::counter::{Counter, CounterTime, insert_counter};
             use $crate::utils::timer::{timed_scope_accumulate_, lazy_static};
             lazy_static::lazy_static! {
@@ -0,0 +1,106 @@
+//! The shadow rays cast by the integrators, as counted by the statistics of the renders.
+//!
+//! The counters are global: the test has its own process so that no other test adds to them.
+#![cfg(feature = "counter")]
+
+use embree4_rs::device::Device;
+use glam::Vec3;
+use rt::{
+    aggregate::embree::EmbreeScene,
+    color::{linear::WHITE, Rgb},
+    integrators::{Integrator, WhittedIntegrator},
+    light::PointLight,
+    material::{BxDF, BxDFFlags, BxDFSample, DiffuseBxDF, LightDescriptor, MaterialDescriptor},
+    math::{distributions::Samples, point::Point},
+    memory::{Arena, ArenaInner},
+    ray::Ray,
+    sampler::DummyPixelSampler,
+    scene::SceneT,
+    utils::counter::{counter_value, enable_counters},
+    Ctx, Seed,
+};
+
+/// A perfect mirror
+struct Mirror;
+
+impl BxDF for Mirror {
+    fn flags(&self) -> BxDFFlags {
+        BxDFFlags::Reflection | BxDFFlags::Specular
+    }
+    fn f(&self, _wo: Vec3, _wi: Vec3) -> Rgb {
+        Rgb::from_array([0.0; 3])
+    }
+    fn pdf(&self, _wo: Vec3, _wi: Vec3) -> f32 {
+        0.0
+    }
+    fn sample_f(&self, wo: Vec3, _uv: Samples<2>, _w: Samples<1>) -> Option<BxDFSample> {
+        Some(BxDFSample {
+            wi: Vec3::new(-wo.x, -wo.y, wo.z),
+            f: (1.0 / wo.z.abs()) * WHITE,
+            pdf: 1.0,
+            flags: self.flags(),
+        })
+    }
+}
+
+#[test]
+fn no_shadow_rays_on_mirrors() {
+    enable_counters();
+
+    // A mirror and a diffuse sphere side by side, both lit by the light
+    let device = Device::try_new(None).unwrap();
+    let mut scene = EmbreeScene::new(&device);
+    let mirror = scene.insert_material(MaterialDescriptor {
+        label: None,
+        material: Box::new(Mirror),
+        alpha: None,
+    });
+    let diffuse = scene.insert_material(MaterialDescriptor {
+        label: None,
+        material: Box::new(DiffuseBxDF {
+            albedo: [0.5, 0.5, 0.5].into(),
+            ..Default::default()
+        }),
+        alpha: None,
+    });
+    scene.insert_sphere(mirror, Point::new(-1.5, 0.0, -3.0), 1.0);
+    scene.insert_sphere(diffuse, Point::new(1.5, 0.0, -3.0), 1.0);
+    scene.insert_light(LightDescriptor {
+        label: None,
+        light: Box::new(PointLight {
+            position: Point::new(0.0, 3.0, 0.0),
+            intensity: WHITE,
+        }),
+    });
+    let scene = scene.commit().unwrap();
+    let world = scene.into_world().unwrap();
+
+    let integrator = WhittedIntegrator::new(4);
+    let arena = ArenaInner::new(1024);
+    let mut sampler = DummyPixelSampler;
+    let mut shadow_rays = |target: Point| {
+        let seed = Seed {
+            seed: 0,
+            x: 0,
+            y: 0,
+            sample_idx: 0,
+        };
+        let mut ctx = Ctx {
+            rng: seed.into_rng(0),
+            world: &world,
+            arena: Arena::new(&arena),
+            seed,
+            sampler: &mut sampler,
+            debug: false,
+        };
+        let before = counter_value("Shadow rays").unwrap_or(0);
+        let ray = Ray::new(Point::ORIGIN, target.vec().normalize());
+        integrator.ray_cast(&mut ctx, ray, 0);
+        counter_value("Shadow rays").unwrap_or(0) - before
+    };
+
+    // The delta lobe of the mirror can't be lit directly, the reflected ray escapes to the sky
+    // without any shadow ray
+    assert_eq!(shadow_rays(Point::new(-1.2, 0.5, -2.2)), 0);
+    assert_eq!(shadow_rays(Point::new(1.2, 0.5, -2.2)), 1);
+}